@@ -1,37 +1,395 @@
-use std::collections::HashMap;
-use std::fs;
-use std::io;
+use std::convert::Infallible;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Read, Write};
 use std::path::{PathBuf, Path};
+use std::sync::Arc;
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use crate::binary_detection::{is_binary, read_prefix};
+use crate::encoding::{DecodeOptions, DecodedText, UTF8_NAME, decode};
+use crate::truncation::{SizeLimitAction, SizeLimits, Truncation, read_truncated};
+use crate::formatter::{DocumentWriter, Footer, FormatOptions, Header, OutputFormatter, render_document};
+use crate::git_changes::FileDiff;
+use crate::content_cache::{CachedRead, ContentCache};
+use crate::redaction::{Redactor, SecretFinding};
 
-/// Aggregates the contents of the given files into a HashMap.
+/// The contents of a single aggregated file.
+///
+/// - `path`: The path of the file, relative to the input directory.
+/// - `content`: The text content of the file.
+/// - `segment`: The range of lines `content` holds, if it is only a segment of the file.
+/// - `encoding`: The encoding the file was decoded from, see `DecodedText::encoding`.
+/// - `truncation`: How the file was truncated, if it exceeded a size limit.
+/// - `diff`: The diff of the file in diff mode. `content` is then either the full content
+///   after the change, or empty if only the diff is shown.
+/// - `redactions`: The secrets that were redacted from `content` and `diff`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileContent {
+    pub path: PathBuf,
+    pub content: String,
+    pub segment: Option<FileSegment>,
+    pub encoding: String,
+    pub truncation: Option<Truncation>,
+    pub diff: Option<FileDiff>,
+    pub redactions: Vec<SecretFinding>,
+}
+
+/// A range of lines of a file that was split across several output parts.
+///
+/// - `start_line`: The first line of the segment, starting at 1.
+/// - `end_line`: The last line of the segment, inclusive.
+/// - `total_lines`: The number of lines in the whole file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FileSegment {
+    pub start_line: usize,
+    pub end_line: usize,
+    pub total_lines: usize,
+}
+
+impl FileContent {
+    /// Creates a new `FileContent` from a relative path and its UTF-8 content.
+    pub fn new(path: impl Into<PathBuf>, content: impl Into<String>) -> Self {
+        FileContent {
+            path: path.into(),
+            content: content.into(),
+            segment: None,
+            encoding: UTF8_NAME.to_string(),
+            truncation: None,
+            diff: None,
+            redactions: Vec::new(),
+        }
+    }
+
+    /// Returns the encoding the file was decoded from, if it was not plain UTF-8.
+    pub fn encoding_note(&self) -> Option<&str> {
+        (self.encoding != UTF8_NAME).then_some(self.encoding.as_str())
+    }
+
+    /// Returns the label of the file used in headers: its path, followed by the range of
+    /// lines it holds if it is a segment of a larger file.
+    pub fn label(&self) -> String {
+        match self.segment {
+            Some(segment) if segment.start_line > 1 => format!(
+                "{} (continued, lines {}-{} of {})",
+                self.path.display(), segment.start_line, segment.end_line, segment.total_lines
+            ),
+            Some(segment) => format!(
+                "{} (lines {}-{} of {})",
+                self.path.display(), segment.start_line, segment.end_line, segment.total_lines
+            ),
+            None => self.path.display().to_string(),
+        }
+    }
+
+    /// Returns the number in the original file of a line of the content, both starting at 1.
+    ///
+    /// The lines of a segment start at its first line, and the lines after the elision marker
+    /// of a truncated file are numbered from the end of the file.
+    ///
+    /// # Returns
+    ///
+    /// Returns `None` for the elision marker, which is not a line of the original file.
+    pub fn original_line(&self, line: usize) -> Option<usize> {
+        let line = line + self.segment.map_or(0, |segment| segment.start_line - 1);
+        match self.truncation {
+            Some(truncation) if line == truncation.head_lines + 1 => None,
+            Some(truncation) if line > truncation.head_lines + 1 => {
+                Some(line - truncation.head_lines - 1 + truncation.original_lines - truncation.tail_lines)
+            }
+            _ => Some(line),
+        }
+    }
+
+    /// Returns the highest number `original_line` can return, used to align line numbers.
+    pub fn last_original_line(&self) -> usize {
+        match (self.truncation, self.segment) {
+            (Some(truncation), _) => truncation.original_lines,
+            (None, Some(segment)) => segment.total_lines,
+            (None, None) => self.content.lines().count(),
+        }
+    }
+}
+
+/// The reason a file's content was left out of the output.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub enum SkipReason {
+    /// The file looks like binary data.
+    Binary,
+    /// The file is larger than `max_file_size`.
+    TooLarge { size: u64, limit: u64 },
+    /// The file doesn't fit in the space left under `max_total_size`.
+    TotalSizeLimit { size: u64, remaining: u64 },
+    /// The file could not be opened or read due to its permissions.
+    PermissionDenied,
+    /// Reading the file failed for another reason, described by the message.
+    Io(String),
+    /// The file is text, but neither valid UTF-8 nor valid in the fallback encoding.
+    NonUtf8,
+}
+
+impl fmt::Display for SkipReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SkipReason::Binary => write!(f, "binary"),
+            SkipReason::TooLarge { size, limit } => write!(f, "too large ({} bytes, limit {} bytes)", size, limit),
+            SkipReason::TotalSizeLimit { size, remaining } => {
+                write!(f, "total size limit reached ({} bytes, {} bytes left)", size, remaining)
+            }
+            SkipReason::PermissionDenied => write!(f, "permission denied"),
+            SkipReason::Io(message) => write!(f, "I/O error: {}", message),
+            SkipReason::NonUtf8 => write!(f, "invalid UTF-8"),
+        }
+    }
+}
+
+impl From<io::Error> for SkipReason {
+    fn from(err: io::Error) -> Self {
+        match err.kind() {
+            io::ErrorKind::PermissionDenied => SkipReason::PermissionDenied,
+            _ => SkipReason::Io(err.to_string()),
+        }
+    }
+}
+
+/// The number of files per thread read at once by `stream_contents`.
+const STREAM_BATCH_FILES_PER_THREAD: usize = 16;
+
+/// The result of reading a single file: its decoded text, how it was truncated and the
+/// number of bytes it counts against `max_total_size`, or the reason it was skipped.
+pub(crate) type FileRead = Result<(DecodedText, Option<Truncation>, u64), SkipReason>;
+
+/// Options controlling how files are read.
+///
+/// - `decode`: How text that isn't valid UTF-8 is decoded.
+/// - `size_limits`: The limits on the size of files, and what to do with files exceeding them.
+/// - `redactor`: The `Redactor` replacing secrets in the content of each file, or `None` to
+///   keep secrets. The default redactor uses the built-in rules only.
+#[derive(Debug, Clone)]
+pub struct ReadOptions {
+    pub decode: DecodeOptions,
+    pub size_limits: SizeLimits,
+    pub redactor: Option<Arc<Redactor>>,
+}
+
+impl Default for ReadOptions {
+    fn default() -> Self {
+        ReadOptions {
+            decode: DecodeOptions::default(),
+            size_limits: SizeLimits::default(),
+            redactor: Some(Arc::new(Redactor::default())),
+        }
+    }
+}
+
+/// A file that passed the ignore rules but whose content was left out of the output.
+///
+/// - `path`: The path of the file, relative to the input directory.
+/// - `reason`: Why the file was skipped.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SkippedFile {
+    pub path: PathBuf,
+    pub reason: SkipReason,
+}
+
+/// Aggregates the contents of the given files, preserving their order.
+///
+/// The start of each file is sniffed first, so binary files are skipped without reading
+/// them whole. Text that isn't UTF-8 is transcoded to UTF-8 as described in `encoding::decode`.
+/// Secrets are then replaced by the `redactor` of the options, and listed in the `redactions`
+/// of each file.
+/// Files that exceed `max_file_size`, or the space left under `max_total_size`, are skipped or
+/// truncated as described in `truncation::read_truncated`, without reading them whole. Files
+/// that can't be read or decoded are skipped as well, and every skipped file is reported with
+/// the reason it was skipped.
+///
+/// Files are read in parallel on the current rayon thread pool, so the number of threads can be
+/// bounded by calling this within `ThreadPool::install`. The result doesn't depend on the number
+/// of threads. With a `max_total_size`, each file is limited by the space the files before it
+/// left, so the files are read one after another.
+///
+/// # Arguments
+///
+/// * `files` - A slice of PathBuf representing the files to aggregate, in output order.
+/// * `base_dir` - The base directory path used to calculate relative paths.
+/// * `options` - A reference to the `ReadOptions` to use.
+///
+/// # Returns
+///
+/// Returns a tuple of:
+/// - A `Vec` of `FileContent`s, in the same order as `files`, each holding the relative path
+///   of a file and its contents.
+/// - A `Vec` of the `SkippedFile`s, in the same order as `files`. Failures to read single
+///   files are reported here rather than as errors.
+pub fn aggregate_contents(
+    files: &[PathBuf],
+    base_dir: &Path,
+    options: &ReadOptions,
+) -> (Vec<FileContent>, Vec<SkippedFile>) {
+    aggregate_contents_cached(files, base_dir, options, &mut ContentCache::disabled())
+}
+
+/// Aggregates the contents of the given files like `aggregate_contents`, reusing the reads
+/// of unchanged files found in the cache and caching the files it reads.
+///
+/// With a `max_total_size`, whether and how a file is read depends on the files before it,
+/// so the cache is bypassed and every file is read, one after another.
 ///
 /// # Arguments
 ///
-/// * `files` - A slice of PathBuf representing the files to aggregate.
+/// * `files` - A slice of PathBuf representing the files to aggregate, in output order.
 /// * `base_dir` - The base directory path used to calculate relative paths.
+/// * `options` - A reference to the `ReadOptions` to use. The cache must be cleared when
+///   they change.
+/// * `cache` - The `ContentCache` to reuse and update.
 ///
 /// # Returns
 ///
-/// Returns a `Result` containing a `HashMap` where:
-/// - Keys are relative `PathBuf`s of the files.
-/// - Values are the contents of the files as `String`s.
+/// Returns the same as `aggregate_contents`.
+pub fn aggregate_contents_cached(
+    files: &[PathBuf],
+    base_dir: &Path,
+    options: &ReadOptions,
+    cache: &mut ContentCache,
+) -> (Vec<FileContent>, Vec<SkippedFile>) {
+    let mut contents = Vec::with_capacity(files.len());
+    let Ok(skipped) = read_contents(files, base_dir, options, cache, |file, _| {
+        contents.push(file);
+        Ok::<_, Infallible>(())
+    });
+    (contents, skipped)
+}
+
+/// Reads the given files like `aggregate_contents_cached`, but hands each file to `write_file`
+/// as soon as it is read instead of collecting them, so the files don't all need to be held in
+/// memory at once.
+///
+/// Files are read in parallel in small batches, and handed over in the same order as `files`.
+/// Only one batch is held in memory at a time, apart from the content a `ContentCache::new`
+/// cache keeps; use an on-disk cache from `ContentCache::load` or `ContentCache::disabled` to
+/// keep memory use flat.
+///
+/// # Arguments
+///
+/// * `files` - A slice of PathBuf representing the files to aggregate, in output order.
+/// * `base_dir` - The base directory path used to calculate relative paths.
+/// * `options` - A reference to the `ReadOptions` to use.
+/// * `cache` - The `ContentCache` to reuse and update.
+/// * `write_file` - A function receiving each `FileContent` that wasn't skipped, along with
+///   the cache, e.g. to look up cached token counts.
 ///
-/// If a file cannot be read or contains invalid UTF-8, it is skipped and an error message is printed.
+/// # Returns
+///
+/// Returns a `Vec` of the `SkippedFile`s, in the same order as `files`.
 ///
 /// # Errors
 ///
-/// This function will return an `io::Error` if there are issues reading the files.
-pub fn aggregate_contents(files: &[PathBuf], base_dir: &Path) -> io::Result<HashMap<PathBuf, String>> {
-    let mut contents = HashMap::new();
-    for file in files {
-        if let Ok(content) = fs::read_to_string(file) {
+/// This function will return the first error returned by `write_file`, without reading the
+/// remaining files. Failures to read single files are reported as skipped files instead.
+pub fn stream_contents(
+    files: &[PathBuf],
+    base_dir: &Path,
+    options: &ReadOptions,
+    cache: &mut ContentCache,
+    write_file: impl FnMut(FileContent, &mut ContentCache) -> io::Result<()>,
+) -> io::Result<Vec<SkippedFile>> {
+    read_contents(files, base_dir, options, cache, write_file)
+}
+
+/// Reads the given files like `stream_contents`, for a `write_file` with any error type.
+fn read_contents<E>(
+    files: &[PathBuf],
+    base_dir: &Path,
+    options: &ReadOptions,
+    cache: &mut ContentCache,
+    mut write_file: impl FnMut(FileContent, &mut ContentCache) -> Result<(), E>,
+) -> Result<Vec<SkippedFile>, E> {
+    let mut skipped = Vec::new();
+    let mut remaining_total = options.size_limits.max_total_size;
+    let batch_size = rayon::current_num_threads() * STREAM_BATCH_FILES_PER_THREAD;
+    for batch in files.chunks(batch_size) {
+        for (file, read) in batch.iter().zip(read_files(batch, options, cache, &mut remaining_total)) {
             let relative_path = file.strip_prefix(base_dir).unwrap_or(file);
-            contents.insert(relative_path.to_path_buf(), content);
-        } else {
-            eprintln!("Skipping file {:?} due to invalid UTF-8", file);
+            match read {
+                Ok((decoded, truncation, _)) => {
+                    let mut content = FileContent {
+                        encoding: decoded.encoding,
+                        truncation,
+                        ..FileContent::new(relative_path, decoded.content)
+                    };
+                    // The cache holds the content as it was read, so it is redacted every time
+                    if let Some(redactor) = &options.redactor {
+                        content.redactions = redactor.redact_file(&mut content);
+                    }
+                    write_file(content, cache)?;
+                }
+                Err(reason) => skipped.push(SkippedFile { path: relative_path.to_path_buf(), reason }),
+            }
         }
     }
-    Ok(contents)
+    Ok(skipped)
+}
+
+/// Reads the given files in parallel, or one after another with a `max_total_size`, and
+/// returns their reads in the same order.
+fn read_files(
+    files: &[PathBuf],
+    options: &ReadOptions,
+    cache: &mut ContentCache,
+    remaining_total: &mut Option<u64>,
+) -> Vec<FileRead> {
+    let Some(remaining) = remaining_total else {
+        let cache_reads: Vec<CachedRead> = files
+            .par_iter()
+            .map(|file| cache.read(file, || read_text_file(file, options, None)))
+            .collect();
+        return files.iter().zip(cache_reads).map(|(file, cache_read)| cache.update(file, cache_read)).collect();
+    };
+    // Each file is limited by the space the files before it left, so they are read in order
+    files
+        .iter()
+        .map(|file| {
+            let read = read_text_file(file, options, Some(*remaining));
+            if let Ok((_, _, bytes_read)) = &read {
+                *remaining = remaining.saturating_sub(*bytes_read);
+            }
+            read
+        })
+        .collect()
+}
+
+/// Reads and decodes a text file, sniffing its start to skip binary files before reading them whole.
+///
+/// Returns the decoded text, how it was truncated, and the number of bytes of the file it holds.
+fn read_text_file(
+    path: &Path,
+    options: &ReadOptions,
+    remaining_total: Option<u64>,
+) -> FileRead {
+    let mut file = File::open(path)?;
+    let size = file.metadata()?.len();
+    let mut bytes = read_prefix(&mut file)?;
+    if is_binary(&bytes) {
+        return Err(SkipReason::Binary);
+    }
+
+    let limits = &options.size_limits;
+    let limit = [limits.max_file_size, remaining_total].into_iter().flatten().min();
+    if let Some(limit) = limit.filter(|limit| size > *limit) {
+        if limits.action == SizeLimitAction::Skip || limit == 0 {
+            return Err(match limits.max_file_size {
+                Some(max_file_size) if size > max_file_size => SkipReason::TooLarge { size, limit: max_file_size },
+                _ => SkipReason::TotalSizeLimit { size, remaining: remaining_total.unwrap_or(0) },
+            });
+        }
+        let (decoded, truncation) = read_truncated(&mut file, size, limit, limits, &options.decode)?
+            .ok_or(SkipReason::NonUtf8)?;
+        return Ok((decoded, Some(truncation), limit));
+    }
+
+    file.read_to_end(&mut bytes)?;
+    let decoded = decode(bytes, &options.decode).ok_or(SkipReason::NonUtf8)?;
+    Ok((decoded, None, size))
 }
 
 /// Determines the language identifier for a given file based on its extension.
@@ -44,7 +402,7 @@ pub fn aggregate_contents(files: &[PathBuf], base_dir: &Path) -> io::Result<Hash
 ///
 /// Returns a `&str` containing the language identifier. If the file extension is not recognized,
 /// it returns "text" as a default value.
-fn get_language_identifier(file: &Path) -> &str {
+pub fn get_language_identifier(file: &Path) -> &str {
     match file.extension().and_then(|ext| ext.to_str()) {
         Some("rs") => "rust",
         Some("py") => "python",
@@ -89,8 +447,9 @@ fn get_language_identifier(file: &Path) -> &str {
 /// # Arguments
 ///
 /// * `project_name` - The name of the project to be included in the output.
-/// * `contents` - A `HashMap` containing file paths and their contents.
-/// * `markdown` - A boolean flag indicating whether to format the output as Markdown.
+/// * `contents` - A slice of `FileContent`s, in the order they should appear in the output.
+/// * `formatter` - The `OutputFormatter` producing the layout of the output.
+/// * `options` - A reference to the `FormatOptions` the files are written with.
 ///
 /// # Returns
 ///
@@ -98,31 +457,50 @@ fn get_language_identifier(file: &Path) -> &str {
 ///
 /// # Format
 ///
-/// The output includes:
+/// The layout depends on the formatter. The Markdown and plain text formats include:
 /// - Project name at the top
 /// - For each file:
 ///   - File path
 ///   - File contents in a code block with appropriate language identifier
-///
-/// If `markdown` is true, the output is formatted for Markdown compatibility.
-/// Otherwise, it uses a plain text format with separators.
-pub fn format_output(project_name: &str, contents: &HashMap<PathBuf, String>, markdown: bool) -> String {
-    let mut output = String::new();
-    
-    // Add project name at the top
-    if markdown {
-        output.push_str(&format!("# Project: {}\n\n", project_name));
-    } else {
-        output.push_str(&format!("# Project: {}\n{}\n\n", project_name, "=".repeat(40)));
-    }
+pub fn format_output(project_name: &str, contents: &[FileContent], formatter: &dyn OutputFormatter, options: &FormatOptions) -> String {
+    render_document(formatter, options, &Header::new(project_name), None, contents, &Footer::default())
+}
 
-    for (file, content) in contents {
-        let lang_id = get_language_identifier(file);
-        if markdown {
-            output.push_str(&format!("## File: {}\n\n```{}\n{}\n```\n\n", file.display(), lang_id, content));
-        } else {
-            output.push_str(&format!("## File: {}\n{}\n```{}\n{}\n```\n\n", file.display(), "=".repeat(40), lang_id, content));
-        }
-    }
-    output
-}
\ No newline at end of file
+/// Reads the given files and writes them to a writer as they are read, producing the same
+/// output as `format_output` on the contents returned by `aggregate_contents`.
+///
+/// Unlike aggregating and formatting the files separately, this never holds more than a small
+/// batch of files in memory, so memory use stays flat however many files there are.
+///
+/// # Arguments
+///
+/// * `project_name` - The name of the project to be included in the output.
+/// * `files` - A slice of PathBuf representing the files to aggregate, in output order.
+/// * `base_dir` - The base directory path used to calculate relative paths.
+/// * `options` - A reference to the `ReadOptions` to use.
+/// * `formatter` - The `OutputFormatter` producing the layout of the output.
+/// * `format_options` - A reference to the `FormatOptions` the files are written with.
+/// * `out` - The writer to write the output to, e.g. a file, stdout or a socket.
+///
+/// # Returns
+///
+/// Returns a `Vec` of the `SkippedFile`s, in the same order as `files`.
+///
+/// # Errors
+///
+/// This function will return an `io::Error` if writing to `out` fails.
+pub fn write_output_to(
+    project_name: &str,
+    files: &[PathBuf],
+    base_dir: &Path,
+    options: &ReadOptions,
+    formatter: &dyn OutputFormatter,
+    format_options: &FormatOptions,
+    out: &mut dyn Write,
+) -> io::Result<Vec<SkippedFile>> {
+    let mut document = DocumentWriter::new(formatter, out).with_options(*format_options);
+    document.write_header(&Header::new(project_name))?;
+    let skipped = stream_contents(files, base_dir, options, &mut ContentCache::disabled(), |file, _| document.write_file(&file))?;
+    document.finish(&Footer::default())?;
+    Ok(skipped)
+}
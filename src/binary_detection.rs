@@ -0,0 +1,76 @@
+use std::fs::File;
+use std::io::{self, Read};
+use crate::encoding::detect_bomless_utf16;
+
+/// The number of bytes read from the start of a file to decide whether it is binary.
+pub const SNIFF_LEN: usize = 8192;
+
+/// Signatures at the start of common binary file formats that may not contain NUL bytes early on.
+const MAGIC_NUMBERS: &[&[u8]] = &[
+    b"\x89PNG\r\n\x1a\n",
+    b"\xff\xd8\xff",       // JPEG
+    b"GIF87a",
+    b"GIF89a",
+    b"%PDF-",
+    b"PK\x03\x04",         // ZIP, JAR, DOCX, ...
+    b"\x1f\x8b",           // gzip
+    b"BZh",                // bzip2
+    b"\xfd7zXZ\x00",       // xz
+    b"7z\xbc\xaf\x27\x1c", // 7-Zip
+    b"\x7fELF",
+    b"\xfe\xed\xfa\xce",   // Mach-O
+    b"\xfe\xed\xfa\xcf",
+    b"\xce\xfa\xed\xfe",
+    b"\xcf\xfa\xed\xfe",
+    b"\xca\xfe\xba\xbe",   // Mach-O universal binary, Java class
+    b"\x00asm",            // WebAssembly
+    b"SQLite format 3\x00",
+    b"OggS",
+    b"ID3",                // MP3
+    b"fLaC",
+    b"wOFF",
+    b"wOF2",
+];
+
+/// Byte order marks of UTF-16 text, which contains NUL bytes but is not binary.
+const UTF16_BOMS: &[&[u8]] = &[b"\xff\xfe", b"\xfe\xff"];
+
+/// Determines whether the start of a file's content looks like binary data.
+///
+/// The content is considered binary if it starts with the signature of a known binary format,
+/// or if it contains a NUL byte, which text files practically never do. UTF-16 text, which
+/// is recognized by its byte order mark or by `encoding::detect_bomless_utf16`, is not
+/// considered binary.
+///
+/// # Arguments
+///
+/// * `prefix` - The first bytes of the file, usually up to `SNIFF_LEN` of them.
+///
+/// # Returns
+///
+/// Returns `true` if the content looks like binary data.
+pub fn is_binary(prefix: &[u8]) -> bool {
+    if UTF16_BOMS.iter().any(|bom| prefix.starts_with(bom)) || detect_bomless_utf16(prefix).is_some() {
+        return false;
+    }
+    MAGIC_NUMBERS.iter().any(|magic| prefix.starts_with(magic)) || prefix.contains(&0)
+}
+
+/// Reads up to `SNIFF_LEN` bytes from the start of a file.
+///
+/// # Arguments
+///
+/// * `file` - The open file to read from, positioned at its start.
+///
+/// # Returns
+///
+/// Returns the bytes read, which are fewer than `SNIFF_LEN` only if the file is shorter.
+///
+/// # Errors
+///
+/// This function will return an error if reading from the file fails.
+pub fn read_prefix(file: &mut File) -> io::Result<Vec<u8>> {
+    let mut prefix = Vec::with_capacity(SNIFF_LEN);
+    file.by_ref().take(SNIFF_LEN as u64).read_to_end(&mut prefix)?;
+    Ok(prefix)
+}
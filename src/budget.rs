@@ -0,0 +1,212 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::PathBuf;
+use clap::ValueEnum;
+use glob::Pattern;
+use serde::{Deserialize, Serialize};
+use crate::aggregator::FileContent;
+use crate::tokenizer::Tokenizer;
+
+/// The policy deciding which files are kept first when the token budget is exceeded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize, Serialize, ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum BudgetPriority {
+    /// Keep files in the configured output order until the budget is used up.
+    #[default]
+    Order,
+    /// Keep the smallest files first.
+    SmallestFirst,
+    /// Keep files matching the highest weighted `budget_priority_globs` first.
+    Globs,
+}
+
+/// A hard limit on the number of tokens in the output.
+///
+/// - `max_tokens`: The maximum number of tokens the output may contain.
+/// - `priority`: The policy deciding which files are kept first.
+/// - `priority_globs`: Glob patterns and their weights, used by `BudgetPriority::Globs`.
+///   Files matching no pattern have a weight of 0.
+#[derive(Debug, Clone)]
+pub struct TokenBudget {
+    pub max_tokens: usize,
+    pub priority: BudgetPriority,
+    pub priority_globs: BTreeMap<String, i64>,
+}
+
+/// A file that was left out of the output to stay within the token budget.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OmittedFile {
+    pub path: PathBuf,
+    pub tokens: usize,
+}
+
+/// Counts the tokens in the content of each file.
+///
+/// # Returns
+///
+/// Returns a `Vec` holding the token count of each file, in the same order as `files`.
+pub fn count_file_tokens(files: &[FileContent], tokenizer: &dyn Tokenizer) -> Vec<usize> {
+    files.iter().map(|file| tokenizer.count_tokens(&file.content)).collect()
+}
+
+/// The output doesn't fit in the token budget, even with every file omitted.
+///
+/// - `max_tokens`: The token budget.
+/// - `tokens`: The number of tokens of the output with every file omitted.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BudgetExceeded {
+    pub max_tokens: usize,
+    pub tokens: usize,
+}
+
+impl fmt::Display for BudgetExceeded {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "The output takes {} tokens with every file omitted, over the token budget of {}",
+            self.tokens, self.max_tokens
+        )
+    }
+}
+
+impl std::error::Error for BudgetExceeded {}
+
+/// Selects the files to include so that the rendered output stays within the token budget.
+///
+/// Files are considered in the order given by the budget's priority policy, and each file is
+/// kept if it still fits in the remaining budget. The cost of a file is measured by rendering
+/// it, so it includes its header and code fences. An omitted file is listed in the trailer,
+/// so keeping a file saves the tokens of its trailer line. Once the selection is made, the
+/// full output is rendered and checked against the budget, dropping the lowest priority
+/// files until it fits. Each file is rendered on its own once to measure its cost, so the
+/// full output is only rendered again after enough files were dropped.
+///
+/// # Arguments
+///
+/// * `files` - A `Vec` of `FileContent`s in output order.
+/// * `budget` - A reference to the `TokenBudget` to enforce.
+/// * `tokenizer` - The `Tokenizer` used to count tokens.
+/// * `render` - A function rendering the complete output for a set of included and omitted files.
+///
+/// # Returns
+///
+/// Returns a tuple of the included files, still in output order, and the omitted files.
+///
+/// # Errors
+///
+/// This function will return a `BudgetExceeded` error if the output is over the budget even
+/// with every file omitted, e.g. because the header and the list of omitted files alone
+/// take up more tokens.
+pub fn apply_token_budget(
+    files: Vec<FileContent>,
+    budget: &TokenBudget,
+    tokenizer: &dyn Tokenizer,
+    render: impl Fn(&[FileContent], &[OmittedFile]) -> String,
+) -> Result<(Vec<FileContent>, Vec<OmittedFile>), BudgetExceeded> {
+    let base_tokens = tokenizer.count_tokens(&render(&[], &[]));
+    let costs: Vec<usize> = files
+        .iter()
+        .map(|file| {
+            let rendered = render(std::slice::from_ref(file), &[]);
+            tokenizer.count_tokens(&rendered).saturating_sub(base_tokens)
+        })
+        .collect();
+    let line_costs = trailer_line_costs(&files, &costs, base_tokens, tokenizer, &render);
+
+    // Start from every file omitted, and keep the files that fit in priority order
+    let priority_order = priority_order(&files, &costs, budget);
+    let mut included = vec![false; files.len()];
+    let mut used = base_tokens + line_costs.iter().sum::<usize>();
+    for &index in &priority_order {
+        let with_file = (used + costs[index]).saturating_sub(line_costs[index]);
+        if with_file <= budget.max_tokens {
+            included[index] = true;
+            used = with_file;
+        }
+    }
+
+    // Token counts are not strictly additive. Dropping a file subtracts its cost from the
+    // last count and adds its trailer line, and the output is only rendered again once the
+    // estimate fits
+    let mut dropped = priority_order.iter().rev().copied();
+    loop {
+        let (kept, omitted) = split_files(&files, &included, &costs);
+        let mut tokens = tokenizer.count_tokens(&render(&kept, &omitted));
+        if tokens <= budget.max_tokens {
+            return Ok((kept, omitted));
+        }
+        let mut dropped_any = false;
+        while tokens > budget.max_tokens {
+            let Some(index) = dropped.find(|index| included[*index]) else { break };
+            included[index] = false;
+            tokens = (tokens + line_costs[index]).saturating_sub(costs[index]);
+            dropped_any = true;
+        }
+        if !dropped_any {
+            return Err(BudgetExceeded { max_tokens: budget.max_tokens, tokens });
+        }
+    }
+}
+
+/// Estimates the tokens each file's line in the trailer of omitted files takes up, by
+/// rendering the output with every file omitted and sharing out the tokens the trailer adds
+/// by the length of each line's path and token count.
+fn trailer_line_costs(
+    files: &[FileContent],
+    costs: &[usize],
+    base_tokens: usize,
+    tokenizer: &dyn Tokenizer,
+    render: impl Fn(&[FileContent], &[OmittedFile]) -> String,
+) -> Vec<usize> {
+    if files.is_empty() {
+        return Vec::new();
+    }
+    let all_omitted = split_files(files, &vec![false; files.len()], costs).1;
+    let trailer_tokens = tokenizer.count_tokens(&render(&[], &all_omitted)).saturating_sub(base_tokens);
+    let weights: Vec<usize> = all_omitted
+        .iter()
+        .map(|file| file.path.to_string_lossy().len() + file.tokens.to_string().len())
+        .collect();
+    let total_weight = weights.iter().sum::<usize>().max(1);
+    weights.iter().map(|weight| (trailer_tokens * weight).div_ceil(total_weight)).collect()
+}
+
+/// Returns the indices of the files, from highest to lowest priority.
+fn priority_order(files: &[FileContent], costs: &[usize], budget: &TokenBudget) -> Vec<usize> {
+    let mut order: Vec<usize> = (0..files.len()).collect();
+    match budget.priority {
+        BudgetPriority::Order => {}
+        BudgetPriority::SmallestFirst => order.sort_by_key(|index| costs[*index]),
+        BudgetPriority::Globs => {
+            let weighted_patterns: Vec<(Pattern, i64)> = budget
+                .priority_globs
+                .iter()
+                .filter_map(|(glob, weight)| Pattern::new(glob).ok().map(|pattern| (pattern, *weight)))
+                .collect();
+            order.sort_by_cached_key(|index| {
+                let weight = weighted_patterns
+                    .iter()
+                    .filter(|(pattern, _)| pattern.matches_path(&files[*index].path))
+                    .map(|(_, weight)| *weight)
+                    .max()
+                    .unwrap_or(0);
+                std::cmp::Reverse(weight)
+            });
+        }
+    }
+    order
+}
+
+/// Splits the files into the included files and the omitted files.
+fn split_files(files: &[FileContent], included: &[bool], costs: &[usize]) -> (Vec<FileContent>, Vec<OmittedFile>) {
+    let mut kept = Vec::new();
+    let mut omitted = Vec::new();
+    for (index, file) in files.iter().enumerate() {
+        if included[index] {
+            kept.push(file.clone());
+        } else {
+            omitted.push(OmittedFile { path: file.path.clone(), tokens: costs[index] });
+        }
+    }
+    (kept, omitted)
+}
@@ -0,0 +1,269 @@
+use std::fs;
+use std::io;
+use std::ops::Add;
+use std::path::{Path, PathBuf};
+use crate::aggregator::{FileContent, FileSegment};
+use crate::formatter::Footer;
+use crate::tokenizer::Tokenizer;
+
+/// Describes the position of a part in an output that was split into several parts.
+///
+/// - `number`: The number of this part, starting at 1.
+/// - `total`: The total number of parts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PartInfo {
+    pub number: usize,
+    pub total: usize,
+}
+
+/// The maximum size of each output part. Unset limits are not enforced.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ChunkLimit {
+    pub max_bytes: Option<usize>,
+    pub max_tokens: Option<usize>,
+}
+
+impl ChunkLimit {
+    /// Returns `true` if neither a byte nor a token limit is set.
+    pub fn is_unlimited(&self) -> bool {
+        self.max_bytes.is_none() && self.max_tokens.is_none()
+    }
+
+    fn fits(&self, size: Size) -> bool {
+        self.max_bytes.is_none_or(|max_bytes| size.bytes <= max_bytes)
+            && self.max_tokens.is_none_or(|max_tokens| size.tokens <= max_tokens)
+    }
+}
+
+/// The size of a piece of output, in bytes and tokens.
+#[derive(Debug, Clone, Copy, Default)]
+struct Size {
+    bytes: usize,
+    tokens: usize,
+}
+
+impl Size {
+    fn saturating_sub(self, other: Size) -> Size {
+        Size {
+            bytes: self.bytes.saturating_sub(other.bytes),
+            tokens: self.tokens.saturating_sub(other.tokens),
+        }
+    }
+}
+
+impl Add for Size {
+    type Output = Size;
+
+    fn add(self, other: Size) -> Size {
+        Size { bytes: self.bytes + other.bytes, tokens: self.tokens + other.tokens }
+    }
+}
+
+/// Splits the output into parts that each stay under the chunk limit.
+///
+/// Files are packed into parts in output order, and parts are only split at file boundaries.
+/// A file that does not fit in a part on its own is split at line boundaries into segments,
+/// which are rendered with continuation headers. A single line larger than the limit is never
+/// split, so it may still produce a part exceeding the limit.
+///
+/// # Arguments
+///
+/// * `files` - A slice of `FileContent`s, in output order.
+/// * `footer` - The lists of omitted and skipped files, written at the end of the last part.
+/// * `limit` - A reference to the `ChunkLimit` each part must stay under.
+/// * `tokenizer` - The `Tokenizer` used to measure parts when a token limit is set.
+/// * `render` - A function rendering a part, including its header and index, from its files
+///   and footer.
+///
+/// # Returns
+///
+/// Returns a `Vec` of the rendered parts, in order. There is always at least one part.
+pub fn split_into_parts(
+    files: &[FileContent],
+    footer: &Footer,
+    limit: &ChunkLimit,
+    tokenizer: &dyn Tokenizer,
+    render: impl Fn(&[FileContent], &PartInfo, &Footer) -> String,
+) -> Vec<String> {
+    let parts = pack_parts(files, footer, limit, tokenizer, &render);
+    let total = parts.len();
+    parts
+        .iter()
+        .enumerate()
+        .map(|(index, part_files)| {
+            let part_footer = if index + 1 == total { *footer } else { Footer::default() };
+            render(part_files, &PartInfo { number: index + 1, total }, &part_footer)
+        })
+        .collect()
+}
+
+/// Decides which files go into each part like `split_into_parts`, without rendering the parts,
+/// so the caller can render them itself, e.g. with `formatter::render_document_counted`.
+///
+/// The footer belongs at the end of the last part. See `split_into_parts` for the arguments.
+///
+/// # Returns
+///
+/// Returns a `Vec` of the files of each part, in order. There is always at least one part.
+pub fn pack_parts(
+    files: &[FileContent],
+    footer: &Footer,
+    limit: &ChunkLimit,
+    tokenizer: &dyn Tokenizer,
+    render: impl Fn(&[FileContent], &PartInfo, &Footer) -> String,
+) -> Vec<Vec<FileContent>> {
+    let measure = |text: &str| Size {
+        bytes: text.len(),
+        tokens: if limit.max_tokens.is_some() { tokenizer.count_tokens(text) } else { 0 },
+    };
+    // Measure with the widest part numbers that could plausibly be rendered
+    let placeholder = PartInfo { number: 9999, total: 9999 };
+    let no_footer = Footer::default();
+    let base = measure(&render(&[], &placeholder, &no_footer));
+    let cost = |file: &FileContent| measure(&render(std::slice::from_ref(file), &placeholder, &no_footer)).saturating_sub(base);
+    let trailer = measure(&render(&[], &placeholder, footer)).saturating_sub(base);
+
+    let mut parts: Vec<Vec<FileContent>> = Vec::new();
+    let mut current: Vec<FileContent> = Vec::new();
+    let mut used = base;
+    for file in files {
+        let file_cost = cost(file);
+        if !limit.fits(used + file_cost) && !current.is_empty() {
+            parts.push(std::mem::take(&mut current));
+            used = base;
+        }
+        if limit.fits(used + file_cost) {
+            current.push(file.clone());
+            used = used + file_cost;
+            continue;
+        }
+        for segment in split_file(file, base, limit, &measure, &cost) {
+            let segment_cost = cost(&segment);
+            if !limit.fits(used + segment_cost) && !current.is_empty() {
+                parts.push(std::mem::take(&mut current));
+                used = base;
+            }
+            current.push(segment);
+            used = used + segment_cost;
+        }
+    }
+    if !footer.is_empty() && !limit.fits(used + trailer) && !current.is_empty() {
+        parts.push(std::mem::take(&mut current));
+    }
+    parts.push(current);
+    parts
+}
+
+/// Splits a file at line boundaries into segments that each fit in a part of their own.
+fn split_file(
+    file: &FileContent,
+    base: Size,
+    limit: &ChunkLimit,
+    measure: &dyn Fn(&str) -> Size,
+    cost: &dyn Fn(&FileContent) -> Size,
+) -> Vec<FileContent> {
+    if file.diff.is_some() {
+        // A diff is only meaningful whole
+        return vec![file.clone()];
+    }
+    let lines: Vec<&str> = file.content.split_inclusive('\n').collect();
+    let total_lines = lines.len();
+    let first_line = file.segment.map_or(1, |segment| segment.start_line);
+    let total_file_lines = file.segment.map_or(total_lines, |segment| segment.total_lines);
+    let segment = |start: usize, end: usize| FileContent {
+        path: file.path.clone(),
+        content: lines[start..end].concat(),
+        segment: Some(FileSegment {
+            start_line: first_line + start,
+            end_line: first_line + end - 1,
+            total_lines: total_file_lines,
+        }),
+        encoding: file.encoding.clone(),
+        truncation: file.truncation,
+        diff: None,
+        redactions: Vec::new(),
+    };
+    // Measure the header with the widest line numbers the segments can have
+    let last_line = first_line + total_lines;
+    let empty_segment = FileContent {
+        path: file.path.clone(),
+        content: String::new(),
+        segment: Some(FileSegment { start_line: last_line, end_line: last_line, total_lines: total_file_lines }),
+        encoding: file.encoding.clone(),
+        truncation: file.truncation,
+        diff: None,
+        redactions: Vec::new(),
+    };
+    let overhead = base + cost(&empty_segment);
+
+    let mut segments = Vec::new();
+    let mut start = 0;
+    let mut size = overhead;
+    for (index, line) in lines.iter().enumerate() {
+        let line_size = measure(line);
+        if !limit.fits(size + line_size) && index > start {
+            segments.push(segment(start, index));
+            start = index;
+            size = overhead;
+        }
+        size = size + line_size;
+    }
+    if start == 0 {
+        // The file can't be split any further, so keep it whole
+        return vec![file.clone()];
+    }
+    segments.push(segment(start, total_lines));
+    segments
+}
+
+/// Returns the path of a single part of the output.
+///
+/// Part numbers are zero-padded to at least two digits, e.g. `project_conag_output.part01.md`.
+///
+/// # Arguments
+///
+/// * `output_path` - A reference to the `Path` the output would be written to if it was not split.
+/// * `part` - A reference to the `PartInfo` of the part.
+pub fn part_path(output_path: &Path, part: &PartInfo) -> PathBuf {
+    let width = part.total.to_string().len().max(2);
+    let stem = output_path.file_stem().and_then(|stem| stem.to_str()).unwrap_or("conag_output");
+    let file_name = match output_path.extension().and_then(|ext| ext.to_str()) {
+        Some(extension) => format!("{}.part{:0width$}.{}", stem, part.number, extension, width = width),
+        None => format!("{}.part{:0width$}", stem, part.number, width = width),
+    };
+    output_path.with_file_name(file_name)
+}
+
+/// Removes the part files left over from a previous run that was split into parts.
+///
+/// # Arguments
+///
+/// * `output_path` - A reference to the `Path` the output would be written to if it was not split.
+///
+/// # Errors
+///
+/// This function will return an error if the output directory cannot be read or a part
+/// file cannot be removed.
+pub fn remove_stale_parts(output_path: &Path) -> io::Result<()> {
+    let Some(output_dir) = output_path.parent() else { return Ok(()) };
+    // A bare file name is in the current directory
+    let output_dir = if output_dir.as_os_str().is_empty() { Path::new(".") } else { output_dir };
+    let stem = output_path.file_stem().and_then(|stem| stem.to_str()).unwrap_or("conag_output");
+    let suffix = match output_path.extension().and_then(|ext| ext.to_str()) {
+        Some(extension) => format!(".{}", extension),
+        None => String::new(),
+    };
+    let prefix = format!("{}.part", stem);
+    for entry in fs::read_dir(output_dir)? {
+        let path = entry?.path();
+        let Some(file_name) = path.file_name().and_then(|name| name.to_str()) else { continue };
+        let is_part = file_name
+            .strip_prefix(&prefix)
+            .and_then(|rest| rest.strip_suffix(&suffix))
+            .is_some_and(|number| !number.is_empty() && number.chars().all(|c| c.is_ascii_digit()));
+        if is_part {
+            fs::remove_file(path)?;
+        }
+    }
+    Ok(())
+}
@@ -1,19 +1,53 @@
-use std::fs::{self, File};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::env;
-use std::io::Write;
-use clap::Parser;
-use anyhow::Result;
+use clap::{Parser, Subcommand};
+use clap::builder::RangedU64ValueParser;
+use rayon::ThreadPoolBuilder;
+use anyhow::{bail, Context, Result};
 use std::collections::HashSet;
-use crate::config::{read_config, generate_default_config};
+use crate::config::{Config, generate_default_config};
+use crate::config_layers::{LayeredConfig, load_layered_config};
+use crate::config_validation::{ConfigIssue, Severity, validate_config};
+use crate::aggregator::{FileContent, ReadOptions, SkippedFile, stream_contents};
+use crate::content_cache::{ContentCache, clear_cache, default_cache_dir};
+use crate::budget::{BudgetPriority, OmittedFile, apply_token_budget};
+use crate::chunking::{PartInfo, pack_parts};
+use crate::output::{OutputTarget, write_output, write_stream};
+use crate::listing::Listing;
+use crate::sorting::SortMode;
+use crate::formatter::{DocumentWriter, Footer, Header, OutputFormat, render_document, render_document_counted};
+use crate::tree::{DirectoryTree, TreeMark};
+use crate::git_changes::{ChangeSelection, Diffs, GitChanges, attach_diff, attach_diffs, collect_changes, collect_diffs};
+use crate::git_tracked::{SubmoduleMode, SubmoduleStub, list_tracked_files};
+use crate::ignore_rules::{FileExplanation, IgnoreRules, explain_file};
+use crate::explain::{render_explanation, render_table};
+use crate::presets::{PRESETS, find_preset, render_preset, render_preset_list};
+use crate::redaction::{Redactor, SecretFinding};
+use crate::tokenizer::TokenizerKind;
+use crate::truncation::SizeLimitAction;
 
 #[derive(Parser)]
 #[command(author, version, about)]
 pub struct Cli {
-    /// Path to the configuration file
-    #[arg(short, long)]
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
+    /// Path to the configuration file, used instead of the global config file
+    #[arg(short, long, global = true)]
     pub config: Option<String>,
 
+    /// Where to write the output; `-` writes to stdout
+    #[arg(short, long, help = "Write the output to this file, or to stdout if it is \"-\"")]
+    pub output: Option<String>,
+
+    /// Silence status and warning messages
+    #[arg(short, long, global = true, help = "Silence status and warning messages")]
+    pub quiet: bool,
+
+    /// Profiles to apply, from the `[profiles.<name>]` tables of the config files
+    #[arg(short, long, global = true, value_delimiter = ',', help = "Apply these profiles from the config files, e.g. api,tests; later profiles take precedence")]
+    pub profile: Vec<String>,
+
     /// Generate default config file
     #[arg(long)]
     pub generate_config: bool,
@@ -22,8 +56,12 @@ pub struct Cli {
     #[arg(long)]
     pub include_hidden: Option<Vec<String>>,
 
-    /// Flag to use plain text output format instead of Markdown.
-    #[arg(long, help = "Use plain text output format instead of Markdown")]
+    /// Output format
+    #[arg(long, value_enum, help = "Output format")]
+    pub format: Option<OutputFormat>,
+
+    /// Deprecated alias for `--format plain`.
+    #[arg(long, help = "Deprecated: use --format plain instead")]
     pub plain_text: bool,
 
     /// Files to include, overriding ignore rules
@@ -33,6 +71,208 @@ pub struct Cli {
     /// Directories to include, overriding ignore rules
     #[arg(long, value_delimiter = ',', name = "include_dir", help = "Directories to include, overriding ignore rules")]
     pub include_dir: Option<Vec<String>>,
+
+    /// Project types whose preset ignores to apply, in addition to the detected ones
+    #[arg(long, value_delimiter = ',', help = "Project types whose preset ignores to apply, e.g. rust,node, in addition to the detected ones")]
+    pub project_type: Option<Vec<String>>,
+
+    /// Don't detect the project types from marker files such as Cargo.toml
+    #[arg(long, help = "Don't detect the project types from marker files such as Cargo.toml")]
+    pub no_detect_project_types: bool,
+
+    /// Don't honor .gitignore, .ignore and git exclude files
+    #[arg(long, help = "Don't honor .gitignore, .ignore and git exclude files")]
+    pub no_gitignore: bool,
+
+    /// Only aggregate files tracked in the git index
+    #[arg(long, help = "Only aggregate files tracked in the git index")]
+    pub tracked_only: bool,
+
+    /// Also aggregate untracked files that aren't ignored, with --tracked-only
+    #[arg(long, help = "Also aggregate untracked files that aren't ignored, with --tracked-only")]
+    pub include_untracked: bool,
+
+    /// How to handle git submodules with --tracked-only
+    #[arg(long, value_enum, help = "How to handle git submodules with --tracked-only")]
+    pub submodules: Option<SubmoduleMode>,
+
+    /// Order of the files in the output
+    #[arg(long, value_enum, help = "Order of the files in the output")]
+    pub sort: Option<SortMode>,
+
+    /// Tokenizer used to count tokens
+    #[arg(long, value_enum, help = "Tokenizer used to count tokens")]
+    pub tokenizer: Option<TokenizerKind>,
+
+    /// Maximum number of tokens in the output
+    #[arg(long, help = "Maximum number of tokens in the output; files beyond the budget are omitted")]
+    pub max_tokens: Option<usize>,
+
+    /// Which files to keep first when the token budget is exceeded
+    #[arg(long, value_enum, help = "Which files to keep first when the token budget is exceeded")]
+    pub budget_priority: Option<BudgetPriority>,
+
+    /// Maximum size of each output file in bytes
+    #[arg(long, help = "Split the output into parts of at most this many bytes")]
+    pub max_chunk_bytes: Option<usize>,
+
+    /// Maximum size of each output file in tokens
+    #[arg(long, help = "Split the output into parts of at most this many tokens")]
+    pub max_chunk_tokens: Option<usize>,
+
+    /// Maximum size of a single file in bytes
+    #[arg(long, help = "Maximum size of a single file in bytes; larger files are skipped or truncated")]
+    pub max_file_size: Option<u64>,
+
+    /// Maximum size of all files together in bytes
+    #[arg(long, help = "Maximum size of all files together in bytes")]
+    pub max_total_size: Option<u64>,
+
+    /// What to do with files exceeding a size limit
+    #[arg(long, value_enum, help = "What to do with files exceeding a size limit")]
+    pub size_limit_action: Option<SizeLimitAction>,
+
+    /// Prefix each line of code with its line number in the original file
+    #[arg(long, help = "Prefix each line of code with its line number in the original file")]
+    pub line_numbers: bool,
+
+    /// Include a directory tree overview at the top of the output
+    #[arg(long, help = "Include a directory tree overview at the top of the output")]
+    pub tree: bool,
+
+    /// Number of directory levels to expand in the tree overview
+    #[arg(long, help = "Number of directory levels to expand in the tree overview")]
+    pub tree_depth: Option<usize>,
+
+    /// Only aggregate files changed since the merge base of this git revision and HEAD
+    #[arg(long, value_name = "REV", conflicts_with_all = ["staged", "uncommitted", "diff"], help = "Only aggregate files changed since this git revision, e.g. main")]
+    pub changed_since: Option<String>,
+
+    /// Only aggregate files with staged changes
+    #[arg(long, conflicts_with = "uncommitted", help = "Only aggregate files with staged changes")]
+    pub staged: bool,
+
+    /// Only aggregate files with uncommitted changes, including untracked files
+    #[arg(long, help = "Only aggregate files with uncommitted changes, including untracked files")]
+    pub uncommitted: bool,
+
+    /// Show the diff of each file changed since this git revision instead of its content
+    #[arg(long, value_name = "BASE", conflicts_with_all = ["staged", "uncommitted"], help = "Show the diff of each file changed since this git revision instead of its content")]
+    pub diff: Option<String>,
+
+    /// Number of unchanged lines shown around each change in diff mode
+    #[arg(long, value_name = "LINES", help = "Number of unchanged lines shown around each change in diff mode")]
+    pub diff_context: Option<u32>,
+
+    /// Also include the full content of changed files up to this size in diff mode
+    #[arg(long, value_name = "BYTES", help = "Also include the full content of changed files up to this many bytes in diff mode")]
+    pub diff_full_content_max_size: Option<u64>,
+
+    /// Number of threads reading files
+    #[arg(short, long, value_name = "N", value_parser = RangedU64ValueParser::<usize>::new().range(1..), help = "Number of threads reading files; defaults to one per CPU")]
+    pub jobs: Option<usize>,
+
+    /// Print the token count of each file
+    #[arg(long, help = "Print the token count of each file")]
+    pub token_report: bool,
+
+    /// Keep running and regenerate the output whenever files change
+    #[arg(short, long, help = "Keep running and regenerate the output whenever files change")]
+    pub watch: bool,
+
+    /// Milliseconds to wait for changes to settle before regenerating in watch mode
+    #[arg(long, value_name = "MS", help = "Milliseconds to wait for changes to settle before regenerating in watch mode")]
+    pub debounce: Option<u64>,
+
+    /// Don't use the on-disk content cache
+    #[arg(long, help = "Read every file instead of reusing unchanged files from the on-disk cache")]
+    pub no_cache: bool,
+
+    /// Print the files that would be aggregated instead of generating the output
+    #[arg(long, conflicts_with = "watch", help = "Print the files that would be aggregated, without reading them or writing the output")]
+    pub list: bool,
+
+    /// With --list, also print the size, estimated tokens and language of each file
+    #[arg(long, requires = "list", help = "With --list, also print the size, estimated tokens and language of each file")]
+    pub details: bool,
+
+    /// With --list, print the files and totals as JSON
+    #[arg(long, requires = "list", help = "With --list, print the files and totals as JSON")]
+    pub json: bool,
+
+    /// Abort with a report of the secrets found instead of redacting them
+    #[arg(long, help = "Abort with a report of file:line locations when a secret is found, instead of redacting it")]
+    pub fail_on_secret: bool,
+}
+
+impl Cli {
+    /// Returns the git changes selected by `--changed-since`, `--diff`, `--staged` or
+    /// `--uncommitted`, if any.
+    pub fn change_selection(&self) -> Option<ChangeSelection> {
+        if let Some(revision) = self.changed_since.as_ref().or(self.diff.as_ref()) {
+            Some(ChangeSelection::Since(revision.clone()))
+        } else if self.staged {
+            Some(ChangeSelection::Staged)
+        } else if self.uncommitted {
+            Some(ChangeSelection::Uncommitted)
+        } else {
+            None
+        }
+    }
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Inspect the configuration
+    Config {
+        #[command(subcommand)]
+        action: ConfigCommand,
+    },
+    /// Show why files are included or excluded
+    Explain {
+        /// The files to explain. Without any, every file in the input directory is listed
+        /// with its decision
+        paths: Vec<PathBuf>,
+    },
+    /// Inspect the built-in project presets
+    Presets {
+        #[command(subcommand)]
+        action: PresetsCommand,
+    },
+    /// Manage the on-disk content cache
+    Cache {
+        #[command(subcommand)]
+        action: CacheCommand,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum ConfigCommand {
+    /// Print the effective configuration after merging every layer
+    Show {
+        /// Annotate each value with the layer it came from
+        #[arg(long)]
+        origin: bool,
+    },
+    /// Check the config files for invalid glob patterns and unknown keys
+    Check,
+}
+
+#[derive(Subcommand)]
+pub enum PresetsCommand {
+    /// List the presets, marking the active ones
+    List,
+    /// Print the marker files and ignore patterns of a preset
+    Show {
+        /// The name of the preset, e.g. rust
+        name: String,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum CacheCommand {
+    /// Remove every cached file
+    Clear,
 }
 
 
@@ -40,12 +280,21 @@ pub struct Cli {
 ///
 /// This function handles the following operations:
 /// - Generating a default configuration file if requested
-/// - Reading and applying the configuration
-/// - Applying CLI overrides to the configuration
+/// - Reading and merging the global, project, environment and CLI configuration layers
+/// - Running a subcommand, if one was given
+/// - Loading the on-disk content cache, and saving it after generating the output
+/// - Watching the input directory and regenerating the output on changes, in watch mode
+/// - Otherwise generating the output once (see `generate`) and reporting on it:
+/// - Listing the files in the git index instead of the file system, in tracked-only mode
+/// - Selecting the files changed in git, in git-aware mode, and attaching their diffs in diff mode
 /// - Processing input files according to the configuration and ignore rules
-/// - Aggregating content from the filtered files
-/// - Formatting the output (as Markdown or plain text)
-/// - Writing the output to a file
+/// - Sorting the filtered files and aggregating their content, reading the files in parallel
+/// - Rendering the directory tree overview, if enabled
+/// - Enforcing the token budget, if one is configured
+/// - Formatting the output (as Markdown, plain text, XML, JSON or JSON Lines)
+/// - Splitting the output into parts, if a chunk limit is configured
+/// - Writing the output to stdout, or to one file per part
+/// - Reporting the files written, skipped and omitted, and the total tokens
 ///
 /// # Arguments
 ///
@@ -56,82 +305,713 @@ pub struct Cli {
 /// Returns a `Result<()>` which is `Ok(())` if the operation was successful,
 /// or an `Err` containing the error information if any step failed.
 pub fn run(cli: Cli) -> Result<()> {
+    crate::status::set_quiet(cli.quiet);
+    if cli.plain_text {
+        crate::warning!("--plain-text is deprecated, use --format plain instead");
+    }
+
     if cli.generate_config {
         generate_default_config()?;
         return Ok(());
     }
 
-    let mut config = if cfg!(feature = "dev") {
-        read_config(cli.config.as_deref())?
-    } else {
-        if cli.config.is_some() {
-            eprintln!("Warning: Custom config path is ignored in release mode. Using default config location.");
+    // Clearing the cache doesn't need a configuration
+    if let Some(Command::Cache { action: CacheCommand::Clear }) = cli.command {
+        return clear_content_cache();
+    }
+
+    let layered_config = load_layered_config(&cli)?;
+    // A jobs setting of 0 lets rayon pick one thread per CPU. The pool is started once, so
+    // watch mode keeps the number of threads it started with. It is local to this call rather
+    // than rayon's global pool, so `run` can be called more than once in the same process.
+    let pool = ThreadPoolBuilder::new()
+        .num_threads(layered_config.config.jobs.unwrap_or(0))
+        .build()
+        .context("Failed to start the threads reading files")?;
+    pool.install(|| run_with_config(&cli, layered_config))
+}
+
+/// Runs the command once the configuration is loaded, on the thread pool reading files.
+fn run_with_config(cli: &Cli, layered_config: LayeredConfig) -> Result<()> {
+    match cli.command {
+        Some(Command::Config { action: ConfigCommand::Show { origin } }) => {
+            print!("{}", layered_config.render(origin));
+            return Ok(());
         }
-        read_config(None)?
+        Some(Command::Config { action: ConfigCommand::Check }) => return check_config_files(&layered_config),
+        _ => check_config(&layered_config)?,
+    }
+
+    if let Some(Command::Explain { paths }) = &cli.command {
+        return explain(&layered_config, paths);
+    }
+
+    if let Some(Command::Presets { action }) = &cli.command {
+        return print_presets(&layered_config.config, action);
+    }
+
+    if cli.list {
+        return list(cli, layered_config.config);
+    }
+
+    if cli.watch {
+        return crate::watch::watch(cli, layered_config);
+    }
+
+    let config = layered_config.config;
+    let mut cache = match config.disk_cache_options() {
+        Some(cache_options) => ContentCache::load(&cache_options, &input_path(&config)?, &config.read_options()?),
+        None => ContentCache::disabled(),
     };
+    let generation = generate(cli, config, &mut cache)?;
+    if let Err(err) = cache.save() {
+        crate::warning!("Failed to save the content cache: {}", err);
+    }
+    report(&generation);
+    Ok(())
+}
 
-    // Apply CLI overrides
-    config = config.with_cli_overrides(&cli);
+/// Reports the problems in the configuration before it is used: warnings are printed, and
+/// errors stop the run.
+///
+/// # Errors
+///
+/// This function will return an error listing every error found by `validate_config`.
+pub fn check_config(layered_config: &LayeredConfig) -> Result<()> {
+    let (errors, warnings): (Vec<ConfigIssue>, Vec<ConfigIssue>) = validate_config(layered_config)
+        .into_iter()
+        .partition(|issue| issue.severity == Severity::Error);
+    // The issues name their severity themselves
+    for warning in &warnings {
+        crate::status!("{}", warning);
+    }
+    if !errors.is_empty() {
+        let errors: Vec<String> = errors.iter().map(ToString::to_string).collect();
+        bail!("Invalid configuration:\n{}", errors.join("\n"));
+    }
+    Ok(())
+}
 
-    if let Some(include_hidden) = cli.include_hidden {
-        config.include_hidden_patterns = include_hidden;
+/// Prints every problem in the configuration, for `conag config check`.
+///
+/// # Errors
+///
+/// This function will return an error if any of the problems is an error.
+fn check_config_files(layered_config: &LayeredConfig) -> Result<()> {
+    let issues = validate_config(layered_config);
+    for issue in &issues {
+        println!("{}", issue);
+    }
+    let errors = issues.iter().filter(|issue| issue.severity == Severity::Error).count();
+    if errors > 0 {
+        bail!("Found {} errors and {} warnings in the configuration", errors, issues.len() - errors);
+    }
+    for path in layered_config.config_files() {
+        crate::status!("Checked {}", path.display());
+    }
+    crate::status!("The configuration is valid, with {} warnings", issues.len());
+    Ok(())
+}
+
+/// Prints why files are included or excluded, for `conag explain`.
+///
+/// Each given file is explained with every rule evaluated for it. Without any files, every
+/// file in the input directory is listed in a table with its decision.
+///
+/// # Arguments
+///
+/// * `layered_config` - A reference to the `LayeredConfig`, whose origins name the layer of
+///   each rule.
+/// * `paths` - The files to explain, relative to the current directory.
+///
+/// # Errors
+///
+/// This function will return an error if the ignore rules are invalid or if listing the
+/// files fails.
+fn explain(layered_config: &LayeredConfig, paths: &[PathBuf]) -> Result<()> {
+    let config = &layered_config.config;
+    let input_path = std::path::absolute(input_path(config)?)?;
+    let mut ignore_rules = IgnoreRules::new(config)?;
+
+    if !paths.is_empty() {
+        let paths: Vec<PathBuf> = paths.iter().map(std::path::absolute).collect::<std::io::Result<_>>()?;
+        if config.respect_gitignore {
+            ignore_rules = ignore_rules.with_gitignore_for(&input_path, &paths);
+        }
+        for (index, path) in paths.iter().enumerate() {
+            if !path.exists() {
+                crate::warning!("{} does not exist", path.display());
+            }
+            if index > 0 {
+                println!();
+            }
+            let explanation = explain_file(&ignore_rules, path, &config.include_file_overrides, &config.include_dir_overrides, &input_path);
+            print!("{}", render_explanation(&explanation, &layered_config.origins, &input_path));
+        }
+        return Ok(());
     }
 
-    // Resolve output directory
-    let output_dir = config.output_dir.clone();
-    config = config.clone().with_output_dir(output_dir);
-    config.resolve_output_dir()?;
+    let mut files: Vec<PathBuf> = match config.tracked_options() {
+        Some(tracked_options) => list_tracked_files(&input_path, &tracked_options)?.files,
+        None => crate::file_system_ops::list_files(&input_path)?,
+    }
+    .into_iter()
+    .collect();
+    files.sort();
+    if config.respect_gitignore {
+        ignore_rules = ignore_rules.with_gitignore_for(&input_path, &files);
+    }
+    let explanations: Vec<FileExplanation> = files
+        .iter()
+        .map(|file| explain_file(&ignore_rules, file, &config.include_file_overrides, &config.include_dir_overrides, &input_path))
+        .collect();
+    print!("{}", render_table(&explanations, &input_path));
+    let included = explanations.iter().filter(|explanation| explanation.included).count();
+    crate::status!("{} files included, {} excluded", included, explanations.len() - included);
+    Ok(())
+}
+
+/// Prints the built-in presets, for `conag presets list` and `conag presets show`.
+///
+/// # Errors
+///
+/// This function will return an error if the preset to show doesn't exist.
+fn print_presets(config: &Config, action: &PresetsCommand) -> Result<()> {
+    match action {
+        PresetsCommand::List => {
+            let active = config.active_project_types();
+            print!("{}", render_preset_list(&active));
+            if active.is_empty() {
+                crate::status!("No project types are active");
+            } else {
+                crate::status!("Active project types: {}", active.join(", "));
+            }
+        }
+        PresetsCommand::Show { name } => {
+            let Some(preset) = find_preset(name) else {
+                let names: Vec<&str> = PRESETS.iter().map(|preset| preset.name).collect();
+                bail!("Unknown preset '{}'. The presets are: {}", name, names.join(", "));
+            };
+            let extra_ignores = config.project_specific_ignores.get(name).map(Vec::as_slice).unwrap_or_default();
+            print!("{}", render_preset(preset, extra_ignores));
+        }
+    }
+    Ok(())
+}
 
-    let input_path = if config.input_dir == "." {
+/// Removes every file from the on-disk content cache and reports how much space was freed.
+fn clear_content_cache() -> Result<()> {
+    let cache_dir = default_cache_dir().context("Could not find the cache directory")?;
+    let (files, bytes) = clear_cache(&cache_dir)
+        .with_context(|| format!("Failed to clear the cache in {:?}", cache_dir))?;
+    crate::status!("Removed {} cache files ({} bytes) from {:?}", files, bytes, cache_dir);
+    Ok(())
+}
+
+/// The outcome of generating the output once.
+///
+/// - `target`: Where the output was written.
+/// - `written`: The output files written, empty if the output went to stdout.
+/// - `files`: The number of files in the output.
+/// - `token_report`: The token count of each file in the output, if `--token-report` was given.
+/// - `redactions`: The secrets redacted from the output.
+/// - `skipped`: The files skipped as binary, unreadable or otherwise unusable.
+/// - `git_changes`: The changes that selected the files, in git-aware mode.
+/// - `omitted`: The number of files omitted to stay within the token budget.
+/// - `total_tokens`: The number of tokens in the output.
+#[derive(Debug)]
+pub struct Generation {
+    pub target: OutputTarget,
+    pub written: Vec<PathBuf>,
+    pub files: usize,
+    pub token_report: Vec<(PathBuf, usize)>,
+    pub redactions: Vec<SecretFinding>,
+    pub skipped: Vec<SkippedFile>,
+    pub git_changes: Option<GitChanges>,
+    pub omitted: usize,
+    pub total_tokens: usize,
+}
+
+/// Returns the input directory of the configuration, resolving `.` to the current directory.
+///
+/// # Errors
+///
+/// This function will return an error if `input_dir` is `.` and the current directory can't be determined.
+pub fn input_path(config: &Config) -> Result<PathBuf> {
+    Ok(if config.input_dir == "." {
         env::current_dir()?
     } else {
         PathBuf::from(&config.input_dir)
+    })
+}
+
+/// Resolves where the output goes: stdout, the `--output` path, or the default file in the
+/// output directory, named after the input directory.
+///
+/// # Errors
+///
+/// This function will return an error if the output directory can't be resolved.
+fn output_target(cli: &Cli, config: &mut Config, input_path: &Path) -> Result<OutputTarget> {
+    // The output directory is only needed for the default file
+    let file_extension = config.output_format.formatter().extension();
+    OutputTarget::resolve(cli.output.as_deref(), config.stdout_when_piped, || {
+        config.resolve_output_dir()?;
+
+        // Generate the output file name based on the root directory name
+        let root_dir_name = input_path.file_name()
+            .and_then(|name| name.to_str())
+            .unwrap_or("unknown");
+        // Outputs of different profiles don't overwrite each other
+        let profiles: String = cli.profile.iter().map(|name| format!("_{}", file_name_part(name))).collect();
+        let output_file_name = format!("{}{}_conag_output.{}", root_dir_name, profiles, file_extension);
+        Ok(PathBuf::from(&config.output_dir).join(&output_file_name))
+    })
+}
+
+/// Replaces the characters of a profile name that can't be used in a file name.
+fn file_name_part(name: &str) -> String {
+    name.chars()
+        .map(|c| if c.is_alphanumeric() || c == '-' || c == '.' { c } else { '-' })
+        .collect()
+}
+
+/// The files selected for the output, before any of them is read.
+///
+/// - `listed`: Every file listed in the input directory, in git-aware mode only the changed ones.
+/// - `files`: The listed files that pass the ignore rules, in output order, along with the
+///   paths of the submodule stubs.
+/// - `submodule_stubs`: The submodules listed as stubs that pass the ignore rules, in
+///   tracked-only mode.
+/// - `git_changes`: The changes that selected the files, in git-aware mode, without the
+///   deleted and renamed files the ignore rules exclude.
+/// - `diffs`: The diffs of the changed files that pass the ignore rules, in diff mode.
+pub struct FileSelection {
+    pub listed: HashSet<PathBuf>,
+    pub files: Vec<PathBuf>,
+    pub submodule_stubs: Vec<SubmoduleStub>,
+    pub git_changes: Option<GitChanges>,
+    pub diffs: Option<Diffs>,
+}
+
+/// Lists the files in the input directory, or in the git index in tracked-only mode, keeps
+/// the changed ones in git-aware mode, applies the ignore rules and sorts them.
+///
+/// # Arguments
+///
+/// * `cli` - A reference to the parsed command-line arguments.
+/// * `config` - A reference to the effective `Config`.
+/// * `input_path` - The input directory.
+/// * `target` - Where the output goes, so a previous output is never selected, or `None`
+///   when no output is written.
+///
+/// # Errors
+///
+/// This function will return an error if the ignore rules are invalid, or if listing the
+/// files or reading the git state fails.
+pub fn select_files(cli: &Cli, config: &Config, input_path: &Path, target: Option<&OutputTarget>) -> Result<FileSelection> {
+    let mut ignore_rules = IgnoreRules::new(config)?;
+    // The tree marks the ignored files, so they are listed too when it shows them
+    let list_ignored = config.tree_options().is_some_and(|tree_options| tree_options.show_ignored);
+
+    // In tracked-only mode, the git index lists the files instead of the file system
+    let (mut files, mut submodule_stubs): (HashSet<PathBuf>, _) = match config.tracked_options() {
+        Some(tracked_options) => {
+            let tracked = list_tracked_files(input_path, &tracked_options)?;
+            (tracked.files, tracked.submodules)
+        }
+        None if list_ignored => (crate::file_system_ops::list_files(input_path)?, Vec::new()),
+        None => {
+            let files = ignore_rules.list_candidate_files(
+                &config.include_file_overrides,
+                &config.include_dir_overrides,
+                input_path,
+                config.respect_gitignore,
+            )?;
+            (files, Vec::new())
+        }
     };
-    
+    // Never aggregate a previous output, e.g. when it is written inside the input directory
+    if let Some(target) = target {
+        files.retain(|path| !target.is_output_file(path));
+    }
+    let mut stub_paths: HashSet<PathBuf> = submodule_stubs.iter().map(|stub| input_path.join(&stub.path)).collect();
+
+    // In git-aware mode, only the changed files go through the ignore rules. In diff mode,
+    // the diffs select the files too, so the repository is only diffed once
+    let mut diffs = match &cli.diff {
+        Some(base) => Some(collect_diffs(input_path, base, config.diff_context_lines)?),
+        None => None,
+    };
+    let mut git_changes = match (cli.change_selection(), &diffs) {
+        (Some(ChangeSelection::Since(revision)), Some(diffs)) if revision == diffs.summary.base => {
+            Some(diffs.changes(input_path))
+        }
+        (Some(selection), _) => Some(collect_changes(input_path, &selection)?),
+        (None, _) => None,
+    };
+    if let Some(changes) = &git_changes {
+        files.retain(|path| changes.changed.contains(path));
+        stub_paths.retain(|path| changes.changed.contains(path));
+    }
+    // The deleted and renamed files are listed in the output too, so they go through the
+    // ignore rules as well
+    let changed_paths: HashSet<PathBuf> = git_changes
+        .iter()
+        .flat_map(|changes| changes.summary.deleted.iter().chain(changes.summary.renamed.iter().map(|file| &file.to)))
+        .chain(diffs.iter().flat_map(|diffs| diffs.summary.files.iter().map(|stat| &stat.path)))
+        .map(|path| input_path.join(path))
+        .collect();
+
+    if config.respect_gitignore {
+        ignore_rules = ignore_rules.with_gitignore_for(input_path, files.iter().chain(&stub_paths).chain(&changed_paths));
+    }
+
+    let apply_ignore_rules = |files: &HashSet<PathBuf>| {
+        crate::ignore_rules::apply_ignore_rules(
+            &ignore_rules,
+            files,
+            &config.include_file_overrides,
+            &config.include_dir_overrides,
+            input_path,
+        )
+    };
+    let mut filtered_files: Vec<PathBuf> = apply_ignore_rules(&files);
+    // The submodule stubs are sorted with the files, so they are written in their place
+    let filtered_stubs: HashSet<PathBuf> = apply_ignore_rules(&stub_paths).into_iter().collect();
+    submodule_stubs.retain(|stub| filtered_stubs.contains(&input_path.join(&stub.path)));
+    filtered_files.extend(filtered_stubs);
+    let filtered_changes: HashSet<PathBuf> = apply_ignore_rules(&changed_paths).into_iter().collect();
+    let is_filtered_change = |path: &Path| filtered_changes.contains(&input_path.join(path));
+    if let Some(changes) = &mut git_changes {
+        changes.summary.retain(is_filtered_change);
+    }
+    if let Some(diffs) = &mut diffs {
+        diffs.retain(is_filtered_change);
+    }
+    crate::sorting::sort_files(&mut filtered_files, config.sort, input_path);
+    Ok(FileSelection { listed: files, files: filtered_files, submodule_stubs, git_changes, diffs })
+}
+
+/// Returns the submodule stub listed at the given path, if any.
+fn submodule_stub<'a>(stubs: &'a [SubmoduleStub], input_path: &Path, path: &Path) -> Option<&'a SubmoduleStub> {
+    stubs.iter().find(|stub| input_path.join(&stub.path) == path)
+}
+
+/// Reads the selected files like `stream_contents`, writing each submodule stub in its place
+/// among them.
+///
+/// # Arguments
+///
+/// * `files` - The selected files, in output order, along with the paths of the stubs.
+/// * `stubs` - The submodule stubs of the selection.
+/// * `input_path` - The input directory.
+/// * `read_options` - A reference to the `ReadOptions` to use.
+/// * `cache` - The `ContentCache` to reuse and update.
+/// * `write_file` - A function receiving each file and stub, in output order.
+///
+/// # Returns
+///
+/// Returns a `Vec` of the `SkippedFile`s, in output order.
+///
+/// # Errors
+///
+/// This function will return the first error returned by `write_file`.
+fn stream_selection(
+    files: &[PathBuf],
+    stubs: &[SubmoduleStub],
+    input_path: &Path,
+    read_options: &ReadOptions,
+    cache: &mut ContentCache,
+    mut write_file: impl FnMut(FileContent, &mut ContentCache) -> std::io::Result<()>,
+) -> std::io::Result<Vec<SkippedFile>> {
+    let mut skipped = Vec::new();
+    let mut start = 0;
+    for (index, file) in files.iter().enumerate() {
+        if let Some(stub) = submodule_stub(stubs, input_path, file) {
+            skipped.extend(stream_contents(&files[start..index], input_path, read_options, cache, &mut write_file)?);
+            write_file(stub.to_file_content(), cache)?;
+            start = index + 1;
+        }
+    }
+    skipped.extend(stream_contents(&files[start..], input_path, read_options, cache, &mut write_file)?);
+    Ok(skipped)
+}
+
+/// Prints the files that would be aggregated, for `--list`, without reading them or writing
+/// the output. Without `--details`, the totals go to stderr, so the list of paths can be
+/// piped on.
+///
+/// # Errors
+///
+/// This function will return an error if selecting the files fails, or if the metadata of a
+/// selected file can't be read.
+fn list(cli: &Cli, config: Config) -> Result<()> {
+    let input_path = input_path(&config)?;
+    // Nothing is written, so the output target isn't resolved, e.g. `{DESKTOP}` on a machine without one
+    let selection = select_files(cli, &config, &input_path, None)?;
+    // The submodule stubs aren't files, so they have no size to list
+    let files: Vec<PathBuf> = selection
+        .files
+        .into_iter()
+        .filter(|file| submodule_stub(&selection.submodule_stubs, &input_path, file).is_none())
+        .collect();
+    let listing = Listing::new(&files, &input_path)
+        .with_context(|| format!("Failed to list the files in {:?}", input_path))?;
+    if cli.json {
+        print!("{}", listing.render_json());
+    } else if cli.details {
+        print!("{}", listing.render(true));
+    } else {
+        print!("{}", listing.render(false));
+        let totals = &listing.totals;
+        crate::status!("{} files, {} bytes, about {} tokens", totals.files, totals.size, totals.estimated_tokens);
+    }
+    Ok(())
+}
+
+/// Generates the output once: lists, filters and aggregates the files, renders them and
+/// writes the output.
+///
+/// Each file is normally written as soon as it is read, so memory use stays flat. The output
+/// is built in memory first instead when a token budget, a chunk limit, the directory tree or
+/// `fail_on_secret` is set, since they all need every file to be read before anything is
+/// written. The token totals are counted the same way either way.
+///
+/// # Arguments
+///
+/// * `cli` - A reference to the parsed command-line arguments.
+/// * `config` - The effective `Config` after merging every layer.
+/// * `cache` - The `ContentCache` of files read by previous generations, which is reused
+///   and updated.
+///
+/// # Returns
+///
+/// Returns the `Generation` describing the output, for `report` to print.
+///
+/// # Errors
+///
+/// This function will return an error if the configuration is invalid, if git state is
+/// needed and can't be read, or if listing the files or writing the output fails.
+pub fn generate(cli: &Cli, mut config: Config, cache: &mut ContentCache) -> Result<Generation> {
+    let read_options = config.read_options()?;
+    let input_path = input_path(&config)?;
     // Get the project name from the root directory
     let project_name = input_path
         .file_name()
         .and_then(|name| name.to_str())
         .unwrap_or("Unknown Project");
     
-    let files: HashSet<PathBuf> = crate::file_system_ops::list_files(&input_path)?;
-    
-    let ignore_rules = crate::ignore_rules::IgnoreRules::new(&config);
+    let target = output_target(cli, &mut config, &input_path)?;
+    let FileSelection { listed: files, files: filtered_files, submodule_stubs, git_changes, diffs } =
+        select_files(cli, &config, &input_path, Some(&target))?;
+    // In diff mode, the diff summary at the top already lists the deleted and renamed files
+    let change_summary = git_changes.as_ref().filter(|_| diffs.is_none()).map(|changes| &changes.summary);
+
+    let (diff_files, diff_summary) = diffs.map(|diffs| (diffs.files, diffs.summary)).unzip();
+    let formatter = config.output_format.formatter();
+    let format_options = config.format_options();
+    let tokenizer = config.tokenizer.tokenizer();
+    // Secrets in the content are redacted while the files are read; the diffs are attached
+    // afterwards, so they are redacted here
+    let redactor = read_options.redactor.as_deref();
+    let file_tokens = |file: &FileContent, redacted: bool, cache: &mut ContentCache| {
+        let count = || tokenizer.count_tokens(&file.content);
+        // In diff mode or once secrets are redacted, the content no longer matches the cached
+        // read of the file
+        if file.diff.is_some() || redacted {
+            count()
+        } else {
+            cache.tokens(&input_path.join(&file.path), config.tokenizer, count)
+        }
+    };
 
+    // The token budget, the chunking and the skipped files marked in the directory tree all
+    // need every file to be read first, and so does checking for secrets before writing
+    // anything. Otherwise each file is written as soon as it is read.
+    let chunk_limit = config.chunk_limit();
+    let token_budget = config.token_budget();
+    let tree_options = config.tree_options();
+    if token_budget.is_none() && chunk_limit.is_unlimited() && tree_options.is_none() && !config.fail_on_secret {
+        let header = Header { project_name, tree: None, diff_summary: diff_summary.as_ref() };
+        let mut diff_files = diff_files.unwrap_or_default();
+        let mut token_report = Vec::new();
+        let mut redactions = Vec::new();
+        let mut skipped = Vec::new();
+        let mut files_written = 0;
+        let mut total_tokens = 0;
+        let written = write_stream(&target, |out| {
+            let mut document = DocumentWriter::new(formatter.as_ref(), out).with_options(format_options).with_tokenizer(tokenizer.as_ref());
+            document.write_header(&header)?;
+            let mut write_file = |mut file: FileContent, cache: &mut ContentCache| {
+                if let Some(diff) = diff_files.remove(&file.path) {
+                    attach_diff(&mut file, diff, config.diff_full_content_max_size);
+                    redact_diff(&mut file, redactor);
+                }
+                if cli.token_report {
+                    token_report.push((file.path.clone(), file_tokens(&file, !file.redactions.is_empty(), cache)));
+                }
+                redactions.append(&mut file.redactions);
+                files_written += 1;
+                document.write_file(&file)
+            };
+            skipped = stream_selection(&filtered_files, &submodule_stubs, &input_path, &read_options, cache, &mut write_file)?;
+            let embedded_skipped: &[SkippedFile] = if config.embed_skip_report { &skipped } else { &[] };
+            total_tokens = document.finish(&Footer { omitted: &[], skipped: embedded_skipped, changes: change_summary })?;
+            Ok(())
+        })?;
+        return Ok(Generation {
+            target,
+            written,
+            files: files_written,
+            token_report,
+            redactions,
+            skipped,
+            git_changes,
+            omitted: 0,
+            total_tokens,
+        });
+    }
 
-    let filtered_files: Vec<PathBuf> = crate::ignore_rules::apply_ignore_rules(
-        &ignore_rules,
-        &files,
-        &config.include_file_overrides,
-        &config.include_dir_overrides,
-        &input_path,
-    );
-    
-    let contents = crate::aggregator::aggregate_contents(&filtered_files, &input_path)?;
+    let mut contents = Vec::new();
+    let skipped = stream_selection(&filtered_files, &submodule_stubs, &input_path, &read_options, cache, |file, _| {
+        contents.push(file);
+        Ok(())
+    })?;
+    if let Some(diff_files) = diff_files {
+        attach_diffs(&mut contents, diff_files, config.diff_full_content_max_size);
+        for file in &mut contents {
+            redact_diff(file, redactor);
+        }
+    }
+    let redactions: Vec<SecretFinding> = contents.iter().flat_map(|file| file.redactions.iter().cloned()).collect();
+    if config.fail_on_secret && !redactions.is_empty() {
+        let locations: Vec<String> = redactions
+            .iter()
+            .map(|finding| format!("  {}:{}: {}", finding.path.display(), finding.line, finding.kind))
+            .collect();
+        bail!("Found {} possible secrets, so no output was written:\n{}", redactions.len(), locations.join("\n"));
+    }
+    let redacted_paths: HashSet<&PathBuf> = redactions.iter().map(|finding| &finding.path).collect();
 
-    // Use Markdown by default, unless --plain-text is specified
-    let use_markdown = !cli.plain_text;
-    let output = crate::aggregator::format_output(project_name, &contents, use_markdown);
+    let tree = tree_options.map(|tree_options| {
+        let mut tree = DirectoryTree::from_files(project_name, &input_path, &files, &filtered_files);
+        for file in &skipped {
+            tree.insert(&file.path, TreeMark::Skipped(file.reason.to_string()));
+        }
+        (tree, tree_options)
+    });
+    // The tree marks the files omitted by the token budget, so it depends on the files kept
+    let render_tree = |omitted: &[OmittedFile]| {
+        tree.as_ref().map(|(tree, tree_options)| {
+            let mut tree = tree.clone();
+            for file in omitted {
+                tree.insert(&file.path, TreeMark::Skipped("over the token budget".to_string()));
+            }
+            tree.render(tree_options)
+        })
+    };
 
-    // Ensure the output directory exists
-    let output_dir = PathBuf::from(&config.output_dir);
-    fs::create_dir_all(&output_dir)?;
+    let embedded_skipped: &[SkippedFile] = if config.embed_skip_report { &skipped } else { &[] };
+    let (contents, omitted) = match token_budget {
+        Some(budget) => {
+            let full_tree = render_tree(&[]);
+            let render = |contents: &[FileContent], omitted: &[OmittedFile]| {
+                let marked_tree = if omitted.is_empty() { None } else { render_tree(omitted) };
+                let header = Header { project_name, tree: marked_tree.as_deref().or(full_tree.as_deref()), diff_summary: diff_summary.as_ref() };
+                let footer = Footer { omitted, skipped: embedded_skipped, changes: change_summary };
+                render_document(formatter.as_ref(), &format_options, &header, None, contents, &footer)
+            };
+            apply_token_budget(contents, &budget, tokenizer.as_ref(), render)?
+        }
+        None => (contents, Vec::new()),
+    };
+    let tree = render_tree(&omitted);
+    let header = Header { project_name, tree: tree.as_deref(), diff_summary: diff_summary.as_ref() };
+    // The tokens are counted per file, the same way as when the output is streamed
+    let footer = Footer { omitted: &omitted, skipped: embedded_skipped, changes: change_summary };
+    let render_counted = |files: &[FileContent], part: Option<&PartInfo>, footer: &Footer| {
+        render_document_counted(formatter.as_ref(), &format_options, tokenizer.as_ref(), &header, part, files, footer)
+    };
+    let parts: Vec<(String, usize)> = if chunk_limit.is_unlimited() {
+        vec![render_counted(&contents, None, &footer)]
+    } else {
+        let parts = pack_parts(&contents, &footer, &chunk_limit, tokenizer.as_ref(), |files, part, footer| {
+            render_document(formatter.as_ref(), &format_options, &header, Some(part), files, footer)
+        });
+        let total = parts.len();
+        parts
+            .iter()
+            .enumerate()
+            .map(|(index, files)| {
+                let part_footer = if index + 1 == total { footer } else { Footer::default() };
+                render_counted(files, Some(&PartInfo { number: index + 1, total }), &part_footer)
+            })
+            .collect()
+    };
+    let (parts, part_tokens): (Vec<String>, Vec<usize>) = parts.into_iter().unzip();
 
-    // Generate the output file name based on the root directory name
-    let root_dir_name = input_path.file_name()
-        .and_then(|name| name.to_str())
-        .unwrap_or("unknown");
-    let file_extension = if use_markdown { "md" } else { "txt" };
-    let output_file_name = format!("{}_conag_output.{}", root_dir_name, file_extension);
-    let output_path = PathBuf::from(&config.output_dir).join(&output_file_name);
+    let written = write_output(&target, &parts)?;
 
-    // Open the file in write mode, which truncates the file if it already exists
-    let mut file = File::create(&output_path)?;
+    let token_report = if cli.token_report {
+        contents
+            .iter()
+            .map(|file| (file.path.clone(), file_tokens(file, redacted_paths.contains(&file.path), cache)))
+            .collect()
+    } else {
+        Vec::new()
+    };
+    Ok(Generation {
+        target,
+        written,
+        files: contents.len(),
+        token_report,
+        redactions,
+        skipped,
+        git_changes,
+        omitted: omitted.len(),
+        total_tokens: part_tokens.iter().sum(),
+    })
+}
 
-    // Write the new content
-    file.write_all(output.as_bytes())?;
+/// Redacts the diff attached to a file, adding the secrets found to those redacted from its
+/// content.
+fn redact_diff(file: &mut FileContent, redactor: Option<&Redactor>) {
+    if let Some(redactor) = redactor {
+        let findings = redactor.redact_diff(file);
+        file.redactions.extend(findings);
+    }
+}
 
-    println!("Output written to: {:?}", output_path);
+/// Prints the status messages describing a generation of the output.
+pub fn report(generation: &Generation) {
+    for path in &generation.written {
+        crate::status!("Output written to: {:?}", path);
+    }
 
-    Ok(())
-}
\ No newline at end of file
+    // The token report was asked for explicitly, so it is printed even with --quiet
+    for (path, tokens) in &generation.token_report {
+        eprintln!("{:>10}  {}", tokens, path.display());
+    }
+    if !generation.redactions.is_empty() {
+        crate::status!("Redacted {} possible secrets:", generation.redactions.len());
+        for finding in &generation.redactions {
+            crate::status!("  {}:{} ({})", finding.path.display(), finding.line, finding.kind);
+        }
+    }
+    if !generation.skipped.is_empty() {
+        crate::status!("Skipped {} files:", generation.skipped.len());
+        for file in &generation.skipped {
+            crate::status!("  {} ({})", file.path.display(), file.reason);
+        }
+    }
+    if let Some(changes) = &generation.git_changes {
+        crate::status!(
+            "Selected {} changed files ({} deleted, {} renamed)",
+            changes.changed.len(), changes.summary.deleted.len(), changes.summary.renamed.len()
+        );
+    }
+    if generation.omitted > 0 {
+        crate::status!("Omitted {} files to stay within the token budget", generation.omitted);
+    }
+    crate::status!("Total tokens: {}", generation.total_tokens);
+}
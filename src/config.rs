@@ -1,11 +1,27 @@
-use serde::Deserialize;
-use std::collections::HashMap;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::path::{PathBuf, Path};
+use std::sync::Arc;
 use anyhow::{Result, Context};
 use std::fs;
-use crate::cli::Cli;
+use crate::aggregator::ReadOptions;
+use crate::budget::{BudgetPriority, TokenBudget};
+use crate::chunking::ChunkLimit;
+use crate::content_cache::{DiskCacheOptions, default_cache_dir};
+use crate::encoding::{DecodeOptions, encoding_for_label};
+use crate::formatter::{FormatOptions, OutputFormat};
+use crate::presets::{DetectedPreset, DetectionCache, find_preset};
+use crate::redaction::Redactor;
+use crate::sorting::SortMode;
+use crate::tokenizer::TokenizerKind;
+use crate::tree::TreeOptions;
+use crate::git_tracked::{SubmoduleMode, TrackedOptions};
+use crate::truncation::{SizeLimitAction, SizeLimits};
 
-#[derive(Debug, Deserialize, Clone)]
+/// The configuration file shipped with conag, written by `generate_default_config`.
+pub const DEFAULT_CONFIG_TOML: &str = include_str!("../config/default_config.toml");
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct Config {
     #[serde(default = "default_input_dir")]
     pub input_dir: String,
@@ -17,6 +33,12 @@ pub struct Config {
     #[serde(default)]
     pub project_type: Option<String>,
 
+    #[serde(default)]
+    pub project_types: Vec<String>,
+
+    #[serde(default = "default_true")]
+    pub detect_project_types: bool,
+
     #[serde(default)]
     pub project_specific_ignores: HashMap<String, Vec<String>>,
 
@@ -28,19 +50,257 @@ pub struct Config {
 
     #[serde(default)]
     pub include_dir_overrides: Vec<String>,
+
+    #[serde(default = "default_true")]
+    pub respect_gitignore: bool,
+
+    #[serde(default)]
+    pub tracked_only: bool,
+
+    #[serde(default)]
+    pub include_untracked: bool,
+
+    #[serde(default)]
+    pub submodules: SubmoduleMode,
+
+    #[serde(default)]
+    pub sort: SortMode,
+
+    #[serde(default)]
+    pub tokenizer: TokenizerKind,
+
+    #[serde(default)]
+    pub max_tokens: Option<usize>,
+
+    #[serde(default)]
+    pub budget_priority: BudgetPriority,
+
+    #[serde(default)]
+    pub budget_priority_globs: BTreeMap<String, i64>,
+
+    #[serde(default)]
+    pub max_chunk_bytes: Option<usize>,
+
+    #[serde(default)]
+    pub max_chunk_tokens: Option<usize>,
+
+    #[serde(default)]
+    pub stdout_when_piped: bool,
+
+    #[serde(default)]
+    pub output_format: OutputFormat,
+
+    #[serde(default)]
+    pub line_numbers: bool,
+
+    #[serde(default)]
+    pub show_tree: bool,
+
+    #[serde(default)]
+    pub tree_max_depth: Option<usize>,
+
+    #[serde(default)]
+    pub tree_max_files_per_dir: Option<usize>,
+
+    #[serde(default = "default_true")]
+    pub tree_show_ignored: bool,
+
+    #[serde(default)]
+    pub embed_skip_report: bool,
+
+    #[serde(default)]
+    pub fallback_encoding: Option<String>,
+
+    #[serde(default)]
+    pub lossy_decoding: bool,
+
+    #[serde(default)]
+    pub max_file_size: Option<u64>,
+
+    #[serde(default)]
+    pub max_total_size: Option<u64>,
+
+    #[serde(default)]
+    pub size_limit_action: SizeLimitAction,
+
+    #[serde(default = "default_truncate_lines")]
+    pub truncate_lines: usize,
+
+    #[serde(default = "default_diff_context_lines")]
+    pub diff_context_lines: u32,
+
+    #[serde(default)]
+    pub diff_full_content_max_size: Option<u64>,
+
+    #[serde(default = "default_watch_debounce_ms")]
+    pub watch_debounce_ms: u64,
+
+    #[serde(default)]
+    pub jobs: Option<usize>,
+
+    #[serde(default = "default_true")]
+    pub cache: bool,
+
+    #[serde(default = "default_cache_max_size")]
+    pub cache_max_size: u64,
+
+    #[serde(default)]
+    pub cache_content_hash: bool,
+
+    #[serde(default = "default_true")]
+    pub redact_secrets: bool,
+
+    #[serde(default)]
+    pub redact_high_entropy: bool,
+
+    #[serde(default)]
+    pub redaction_rules: BTreeMap<String, String>,
+
+    #[serde(default)]
+    pub fail_on_secret: bool,
+
+    #[serde(default)]
+    pub profiles: BTreeMap<String, toml::Table>,
+
+    /// The presets detected in `input_dir`, detected once by `active_project_types`.
+    #[serde(skip)]
+    pub detected_presets: DetectionCache,
 }
 
 fn default_input_dir() -> String {
     ".".to_string()
 }
 
+fn default_output_dir() -> String {
+    "{DESKTOP}/conag_output".to_string()
+}
+
+fn default_true() -> bool {
+    true
+}
+
+fn default_truncate_lines() -> usize {
+    200
+}
+
+fn default_diff_context_lines() -> u32 {
+    3
+}
+
+fn default_watch_debounce_ms() -> u64 {
+    300
+}
+
+fn default_cache_max_size() -> u64 {
+    512 * 1024 * 1024
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            input_dir: default_input_dir(),
+            output_dir: default_output_dir(),
+            ignore_patterns: Vec::new(),
+            project_type: None,
+            project_types: Vec::new(),
+            detect_project_types: true,
+            project_specific_ignores: HashMap::new(),
+            include_hidden_patterns: Vec::new(),
+            include_file_overrides: Vec::new(),
+            include_dir_overrides: Vec::new(),
+            respect_gitignore: true,
+            tracked_only: false,
+            include_untracked: false,
+            submodules: SubmoduleMode::default(),
+            sort: SortMode::default(),
+            tokenizer: TokenizerKind::default(),
+            max_tokens: None,
+            budget_priority: BudgetPriority::default(),
+            budget_priority_globs: BTreeMap::new(),
+            max_chunk_bytes: None,
+            max_chunk_tokens: None,
+            stdout_when_piped: false,
+            output_format: OutputFormat::default(),
+            line_numbers: false,
+            show_tree: false,
+            tree_max_depth: None,
+            tree_max_files_per_dir: None,
+            tree_show_ignored: true,
+            embed_skip_report: false,
+            fallback_encoding: None,
+            lossy_decoding: false,
+            max_file_size: None,
+            max_total_size: None,
+            size_limit_action: SizeLimitAction::default(),
+            truncate_lines: default_truncate_lines(),
+            diff_context_lines: default_diff_context_lines(),
+            diff_full_content_max_size: None,
+            watch_debounce_ms: default_watch_debounce_ms(),
+            jobs: None,
+            cache: true,
+            cache_max_size: default_cache_max_size(),
+            cache_content_hash: false,
+            redact_secrets: true,
+            redact_high_entropy: false,
+            redaction_rules: BTreeMap::new(),
+            fail_on_secret: false,
+            profiles: BTreeMap::new(),
+            detected_presets: DetectionCache::default(),
+        }
+    }
+}
+
 impl Config {
-    /// Returns a vector of ignore patterns, including project-specific patterns if applicable.
+    /// Returns the active project types: `project_type`, `project_types` and, if
+    /// `detect_project_types` is set, the types detected from the marker files in `input_dir`.
+    ///
+    /// Each active type enables its built-in preset, if there is one, and its entry in
+    /// `project_specific_ignores`, if any.
+    pub fn active_project_types(&self) -> Vec<String> {
+        let mut types: Vec<String> = self.project_type.iter().chain(&self.project_types).cloned().collect();
+        types.extend(self.detected().into_iter().map(|detected| detected.preset.name.to_string()));
+        let mut seen = HashSet::new();
+        types.retain(|project_type| seen.insert(project_type.clone()));
+        types
+    }
+
+    /// Returns the presets of the active project types, with the directories their ignores
+    /// apply under. A type set in `project_type` or `project_types` applies to the whole input
+    /// directory, and a detected one under each directory holding one of its marker files.
+    pub fn active_presets(&self) -> Vec<DetectedPreset> {
+        let detected = self.detected();
+        self.active_project_types()
+            .iter()
+            .filter_map(|project_type| find_preset(project_type))
+            .map(|preset| {
+                let is_explicit = self.project_type.as_deref() == Some(preset.name) || self.project_types.iter().any(|name| name == preset.name);
+                let mut roots: Vec<PathBuf> = if is_explicit { vec![PathBuf::new()] } else { Vec::new() };
+                for found in detected.iter().filter(|found| found.preset == preset) {
+                    roots.extend(found.roots.iter().filter(|root| !(is_explicit && root.as_os_str().is_empty())).cloned());
+                }
+                DetectedPreset { preset, roots }
+            })
+            .collect()
+    }
+
+    /// Returns the presets detected in `input_dir`, or none if `detect_project_types` is off.
+    fn detected(&self) -> Vec<DetectedPreset> {
+        if self.detect_project_types {
+            self.detected_presets.get(Path::new(&self.input_dir))
+        } else {
+            Vec::new()
+        }
+    }
+
+    /// Returns a vector of ignore patterns, including the patterns of the active project types.
     /// Also adds a default pattern to ignore all hidden files unless explicitly included.
     pub fn get_ignore_patterns(&self) -> Vec<String> {
         let mut patterns = self.ignore_patterns.clone();
-        if let Some(project_type) = &self.project_type {
-            if let Some(specific_ignores) = self.project_specific_ignores.get(project_type) {
+        for active in self.active_presets() {
+            patterns.extend(active.roots.iter().flat_map(|root| active.preset.ignores_under(root)));
+        }
+        for project_type in self.active_project_types() {
+            if let Some(specific_ignores) = self.project_specific_ignores.get(&project_type) {
                 patterns.extend(specific_ignores.clone());
             }
         }
@@ -58,6 +318,90 @@ impl Config {
         })
     }
 
+    /// Returns the token budget to enforce, if `max_tokens` is set.
+    pub fn token_budget(&self) -> Option<TokenBudget> {
+        self.max_tokens.map(|max_tokens| TokenBudget {
+            max_tokens,
+            priority: self.budget_priority,
+            priority_globs: self.budget_priority_globs.clone(),
+        })
+    }
+
+    /// Returns the maximum size of each output part.
+    pub fn chunk_limit(&self) -> ChunkLimit {
+        ChunkLimit { max_bytes: self.max_chunk_bytes, max_tokens: self.max_chunk_tokens }
+    }
+
+    /// Returns the options for listing tracked files, if `tracked_only` is set.
+    pub fn tracked_options(&self) -> Option<TrackedOptions> {
+        self.tracked_only.then_some(TrackedOptions {
+            include_untracked: self.include_untracked,
+            submodules: self.submodules,
+        })
+    }
+
+    /// Returns the options for the on-disk content cache, if `cache` is set and the platform
+    /// has a cache directory.
+    pub fn disk_cache_options(&self) -> Option<DiskCacheOptions> {
+        let dir = default_cache_dir().filter(|_| self.cache)?;
+        Some(DiskCacheOptions { dir, max_size: self.cache_max_size, hash_contents: self.cache_content_hash })
+    }
+
+    /// Returns the redactor for secrets in file contents, if `redact_secrets` or
+    /// `fail_on_secret` is set.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if a regex in `redaction_rules` is invalid.
+    pub fn redactor(&self) -> Result<Option<Redactor>> {
+        if !self.redact_secrets && !self.fail_on_secret {
+            return Ok(None);
+        }
+        Redactor::new(&self.redaction_rules, self.redact_high_entropy)
+            .map(Some)
+            .map_err(|(kind, err)| anyhow::Error::new(err).context(format!("Invalid regex in redaction rule '{}'", kind)))
+    }
+
+    /// Returns the options for how the files are written, shared by every output format.
+    pub fn format_options(&self) -> FormatOptions {
+        FormatOptions { line_numbers: self.line_numbers }
+    }
+
+    /// Returns the options for rendering the directory tree, if `show_tree` is set.
+    pub fn tree_options(&self) -> Option<TreeOptions> {
+        self.show_tree.then_some(TreeOptions {
+            max_depth: self.tree_max_depth,
+            max_files_per_dir: self.tree_max_files_per_dir,
+            show_ignored: self.tree_show_ignored,
+        })
+    }
+
+    /// Returns the options for reading files: how to decode them, their size limits and the
+    /// redactor for secrets.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if `fallback_encoding` is not a known encoding label,
+    /// or if a regex in `redaction_rules` is invalid.
+    pub fn read_options(&self) -> Result<ReadOptions> {
+        let fallback_encoding = match &self.fallback_encoding {
+            Some(label) => Some(encoding_for_label(label).with_context(|| {
+                format!("Unknown fallback_encoding {:?}. Use a label such as \"windows-1252\" or \"latin1\"", label)
+            })?),
+            None => None,
+        };
+        Ok(ReadOptions {
+            decode: DecodeOptions { fallback_encoding, lossy: self.lossy_decoding },
+            size_limits: SizeLimits {
+                max_file_size: self.max_file_size,
+                max_total_size: self.max_total_size,
+                action: self.size_limit_action,
+                max_lines: self.truncate_lines,
+            },
+            redactor: self.redactor()?.map(Arc::new),
+        })
+    }
+
     /// Resolves the output directory path, replacing {DESKTOP} with the actual desktop path if present.
     pub fn resolve_output_dir(&mut self) -> Result<()> {
         if self.output_dir.contains("{DESKTOP}") {
@@ -85,16 +429,6 @@ impl Config {
         Ok(home_dir.join(".config").join("conag").join("config.toml"))
     }
 
-    pub fn with_cli_overrides(mut self, cli: &Cli) -> Self {
-        if let Some(include_files) = &cli.include_file {
-            self.include_file_overrides = include_files.clone();
-        }
-        if let Some(include_dirs) = &cli.include_dir {
-            self.include_dir_overrides = include_dirs.clone();
-        }
-        self
-    }
-
     pub fn with_output_dir(mut self, output_dir: String) -> Self {
         self.output_dir = output_dir;
         self
@@ -165,10 +499,10 @@ pub fn generate_default_config() -> Result<()> {
     if !config_path.exists() {
         let config_dir = config_path.parent().unwrap();
         fs::create_dir_all(config_dir)?;
-        fs::write(&config_path, include_str!("../config/default_config.toml"))?;
-        println!("Generated default config file at {:?}", config_path);
+        fs::write(&config_path, DEFAULT_CONFIG_TOML)?;
+        crate::status!("Generated default config file at {:?}", config_path);
     } else {
-        println!("Config file already exists at {:?}", config_path);
+        crate::status!("Config file already exists at {:?}", config_path);
     }
     Ok(())
 }
\ No newline at end of file
@@ -0,0 +1,578 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::env;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use anyhow::{Result, Context};
+use toml::{Table, Value};
+use crate::cli::Cli;
+use crate::config::{Config, DEFAULT_CONFIG_TOML};
+use crate::formatter::OutputFormat;
+
+/// The names of project-local configuration files, in order of preference.
+pub const PROJECT_CONFIG_FILE_NAMES: [&str; 2] = [".conag.toml", "conag.toml"];
+
+/// The prefix of environment variables that override configuration values, e.g. `CONAG_OUTPUT_DIR`.
+pub const ENV_PREFIX: &str = "CONAG_";
+
+/// The prefix of keys that append to a list instead of replacing it, e.g. `extend_ignore_patterns`.
+pub const EXTEND_PREFIX: &str = "extend_";
+
+/// The key of the table holding the named profiles, as in `[profiles.docs]`.
+pub const PROFILES_KEY: &str = "profiles";
+
+/// The integer keys that may have no default value, whose type can't be read from the defaults.
+const OPTIONAL_INTEGER_KEYS: [&str; 9] = [
+    "max_tokens",
+    "max_chunk_bytes",
+    "max_chunk_tokens",
+    "tree_max_depth",
+    "tree_max_files_per_dir",
+    "max_file_size",
+    "max_total_size",
+    "diff_full_content_max_size",
+    "jobs",
+];
+
+/// The source of a configuration layer.
+///
+/// Layers are merged in the order of this enum, so later sources take precedence:
+/// built-in defaults, then the global config file, the project-local config file, the
+/// profiles selected with `--profile`, `CONAG_*` environment variables and finally
+/// command-line flags.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum LayerSource {
+    Default,
+    Global,
+    Project,
+    Profile,
+    Environment,
+    Cli,
+}
+
+impl fmt::Display for LayerSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            LayerSource::Default => "default",
+            LayerSource::Global => "global",
+            LayerSource::Project => "project",
+            LayerSource::Profile => "profile",
+            LayerSource::Environment => "env",
+            LayerSource::Cli => "cli",
+        };
+        f.write_str(name)
+    }
+}
+
+/// Describes where a configuration value came from.
+///
+/// - `source`: The layer the value came from.
+/// - `path`: The config file the value was read from, if any.
+/// - `profile`: The name of the profile the value was set by, for profile layers.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Origin {
+    pub source: LayerSource,
+    pub path: Option<PathBuf>,
+    pub profile: Option<String>,
+}
+
+impl fmt::Display for Origin {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.source)?;
+        if let Some(profile) = &self.profile {
+            write!(f, " {}", profile)?;
+        }
+        match &self.path {
+            Some(path) => write!(f, " ({})", path.display()),
+            None => Ok(()),
+        }
+    }
+}
+
+/// A single layer of configuration values.
+///
+/// - `origin`: Where the values of this layer came from.
+/// - `values`: The raw TOML values of this layer.
+/// - `text`: The TOML text the values were parsed from, if the layer was read from a file.
+#[derive(Debug, Clone)]
+pub struct ConfigLayer {
+    pub origin: Origin,
+    pub values: Table,
+    pub text: Option<String>,
+}
+
+impl ConfigLayer {
+    /// Creates a new `ConfigLayer` from a source and a table of values.
+    pub fn new(source: LayerSource, values: Table) -> Self {
+        ConfigLayer { origin: Origin { source, path: None, profile: None }, values, text: None }
+    }
+
+    /// Creates the layer holding the built-in defaults of every configuration value.
+    ///
+    /// The values of the shipped default config file, such as its ignore patterns, always
+    /// apply, even without a global config file. The values it leaves out come from
+    /// `Config::default`.
+    pub fn defaults() -> Result<Self> {
+        let mut values = Table::try_from(Config::default())
+            .with_context(|| "Failed to serialize the default config")?;
+        let shipped: Table = toml::from_str(DEFAULT_CONFIG_TOML)
+            .with_context(|| "Failed to parse the shipped default config")?;
+        values.extend(shipped);
+        Ok(ConfigLayer::new(LayerSource::Default, values))
+    }
+
+    /// Reads a layer from a TOML configuration file.
+    ///
+    /// # Arguments
+    ///
+    /// * `source` - The `LayerSource` the file belongs to.
+    /// * `path` - A reference to the `Path` of the configuration file.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the file cannot be read or is not valid TOML.
+    pub fn from_file(source: LayerSource, path: &Path) -> Result<Self> {
+        let config_str = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read config file: {:?}", path))?;
+        let values: Table = toml::from_str(&config_str)
+            .with_context(|| format!("Failed to parse config file: {:?}", path))?;
+        Ok(ConfigLayer { origin: Origin { source, path: Some(path.to_path_buf()), profile: None }, values, text: Some(config_str) })
+    }
+
+    /// Builds the layer of a profile, from its `[profiles.<name>]` table in the given layers.
+    ///
+    /// A profile can set any configuration key, including `extend_` keys, which append to
+    /// the lists of the layers below it. If several layers define the same profile, the
+    /// highest precedence one is used.
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - The name of the profile.
+    /// * `layers` - The layers to look for the profile in.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error naming the defined profiles if no layer defines
+    /// the profile, or if it is not a table.
+    pub fn from_profile(name: &str, layers: &[ConfigLayer]) -> Result<Self> {
+        let profile_of = |layer: &'_ ConfigLayer| match layer.values.get(PROFILES_KEY) {
+            Some(Value::Table(profiles)) => profiles.get(name).cloned(),
+            _ => None,
+        };
+        let Some((layer, profile)) = layers.iter().rev().find_map(|layer| profile_of(layer).map(|profile| (layer, profile))) else {
+            let mut names: Vec<&String> = layers
+                .iter()
+                .filter_map(|layer| match layer.values.get(PROFILES_KEY) {
+                    Some(Value::Table(profiles)) => Some(profiles.keys()),
+                    _ => None,
+                })
+                .flatten()
+                .collect();
+            names.sort();
+            names.dedup();
+            if names.is_empty() {
+                anyhow::bail!("Unknown profile '{}'. No profiles are defined in the config files", name);
+            }
+            let names: Vec<&str> = names.into_iter().map(String::as_str).collect();
+            anyhow::bail!("Unknown profile '{}'. The profiles are: {}", name, names.join(", "));
+        };
+        let Value::Table(mut values) = profile else {
+            anyhow::bail!("Profile '{}' in {} must be a table", name, layer.origin);
+        };
+        // Profiles don't nest
+        values.remove(PROFILES_KEY);
+        let origin = Origin { source: LayerSource::Profile, path: layer.origin.path.clone(), profile: Some(name.to_string()) };
+        Ok(ConfigLayer { origin, values, text: None })
+    }
+
+    /// Builds a layer from `CONAG_*` environment variables.
+    ///
+    /// The variable name minus the prefix, lowercased, is the configuration key. Values are
+    /// parsed according to the type of the same key in `reference`: lists are comma-separated,
+    /// and booleans and integers are parsed as such. The integer keys without a default value
+    /// (such as `max_tokens`) are parsed as integers too, and any other key as a string.
+    ///
+    /// # Arguments
+    ///
+    /// * `vars` - An iterator of environment variable names and values.
+    /// * `reference` - A `Table` of already merged values, used to determine value types.
+    pub fn from_env_vars(vars: impl IntoIterator<Item = (String, String)>, reference: &Table) -> Self {
+        let mut values = Table::new();
+        for (name, raw) in vars {
+            let Some(key) = name.strip_prefix(ENV_PREFIX) else { continue };
+            let key = key.to_lowercase();
+            let value = match reference.get(&key) {
+                _ if key.starts_with(EXTEND_PREFIX) => parse_env_list(&raw),
+                Some(Value::Array(_)) => parse_env_list(&raw),
+                Some(Value::Boolean(_)) => match raw.trim().parse() {
+                    Ok(flag) => Value::Boolean(flag),
+                    Err(_) => Value::String(raw),
+                },
+                Some(Value::Integer(_)) => parse_env_integer(raw),
+                None if OPTIONAL_INTEGER_KEYS.contains(&key.as_str()) => parse_env_integer(raw),
+                _ => Value::String(raw),
+            };
+            values.insert(key, value);
+        }
+        ConfigLayer::new(LayerSource::Environment, values)
+    }
+
+    /// Builds a layer from the configuration flags given on the command line.
+    pub fn from_cli(cli: &Cli) -> Self {
+        let mut values = Table::new();
+        let string_list = |items: &[String]| Value::Array(items.iter().cloned().map(Value::String).collect());
+        if let Some(include_hidden) = &cli.include_hidden {
+            values.insert("include_hidden_patterns".to_string(), string_list(include_hidden));
+        }
+        if let Some(include_files) = &cli.include_file {
+            values.insert("include_file_overrides".to_string(), string_list(include_files));
+        }
+        if let Some(include_dirs) = &cli.include_dir {
+            values.insert("include_dir_overrides".to_string(), string_list(include_dirs));
+        }
+        if let Some(project_types) = &cli.project_type {
+            values.insert("project_types".to_string(), string_list(project_types));
+        }
+        if cli.no_detect_project_types {
+            values.insert("detect_project_types".to_string(), Value::Boolean(false));
+        }
+        if cli.no_gitignore {
+            values.insert("respect_gitignore".to_string(), Value::Boolean(false));
+        }
+        if cli.no_cache {
+            values.insert("cache".to_string(), Value::Boolean(false));
+        }
+        if cli.fail_on_secret {
+            values.insert("fail_on_secret".to_string(), Value::Boolean(true));
+        }
+        if cli.tracked_only {
+            values.insert("tracked_only".to_string(), Value::Boolean(true));
+        }
+        if cli.include_untracked {
+            values.insert("include_untracked".to_string(), Value::Boolean(true));
+        }
+        if let Some(submodules) = cli.submodules {
+            values.insert("submodules".to_string(), enum_value(submodules));
+        }
+        if let Some(sort) = cli.sort {
+            values.insert("sort".to_string(), enum_value(sort));
+        }
+        if let Some(tokenizer) = cli.tokenizer {
+            values.insert("tokenizer".to_string(), enum_value(tokenizer));
+        }
+        if let Some(max_tokens) = cli.max_tokens {
+            values.insert("max_tokens".to_string(), Value::Integer(max_tokens as i64));
+        }
+        if let Some(budget_priority) = cli.budget_priority {
+            values.insert("budget_priority".to_string(), enum_value(budget_priority));
+        }
+        if let Some(max_chunk_bytes) = cli.max_chunk_bytes {
+            values.insert("max_chunk_bytes".to_string(), Value::Integer(max_chunk_bytes as i64));
+        }
+        if let Some(max_chunk_tokens) = cli.max_chunk_tokens {
+            values.insert("max_chunk_tokens".to_string(), Value::Integer(max_chunk_tokens as i64));
+        }
+        if let Some(max_file_size) = cli.max_file_size {
+            values.insert("max_file_size".to_string(), Value::Integer(max_file_size as i64));
+        }
+        if let Some(max_total_size) = cli.max_total_size {
+            values.insert("max_total_size".to_string(), Value::Integer(max_total_size as i64));
+        }
+        if let Some(size_limit_action) = cli.size_limit_action {
+            values.insert("size_limit_action".to_string(), enum_value(size_limit_action));
+        }
+        if let Some(diff_context) = cli.diff_context {
+            values.insert("diff_context_lines".to_string(), Value::Integer(diff_context.into()));
+        }
+        if let Some(jobs) = cli.jobs {
+            values.insert("jobs".to_string(), Value::Integer(jobs as i64));
+        }
+        if let Some(debounce) = cli.debounce {
+            values.insert("watch_debounce_ms".to_string(), Value::Integer(debounce as i64));
+        }
+        if let Some(max_size) = cli.diff_full_content_max_size {
+            values.insert("diff_full_content_max_size".to_string(), Value::Integer(max_size as i64));
+        }
+        if cli.line_numbers {
+            values.insert("line_numbers".to_string(), Value::Boolean(true));
+        }
+        if cli.tree {
+            values.insert("show_tree".to_string(), Value::Boolean(true));
+        }
+        if let Some(tree_depth) = cli.tree_depth {
+            values.insert("tree_max_depth".to_string(), Value::Integer(tree_depth as i64));
+        }
+        if let Some(format) = cli.format {
+            values.insert("output_format".to_string(), enum_value(format));
+        } else if cli.plain_text {
+            values.insert("output_format".to_string(), enum_value(OutputFormat::Plain));
+        }
+        ConfigLayer::new(LayerSource::Cli, values)
+    }
+}
+
+/// Converts a unit enum, such as `SortMode`, into the TOML string it is configured with.
+fn enum_value(value: impl serde::Serialize) -> Value {
+    Value::try_from(value).expect("unit enums serialize to strings")
+}
+
+/// Splits a comma-separated environment variable into a list of strings.
+fn parse_env_list(raw: &str) -> Value {
+    Value::Array(
+        raw.split(',')
+            .map(str::trim)
+            .filter(|item| !item.is_empty())
+            .map(|item| Value::String(item.to_string()))
+            .collect(),
+    )
+}
+
+/// Parses an environment variable as an integer, keeping it as a string if it isn't one so
+/// the error names the key.
+fn parse_env_integer(raw: String) -> Value {
+    match raw.trim().parse() {
+        Ok(number) => Value::Integer(number),
+        Err(_) => Value::String(raw),
+    }
+}
+
+/// The result of merging every configuration layer.
+///
+/// - `config`: The effective configuration.
+/// - `values`: The merged TOML values the configuration was built from.
+/// - `origins`: The origin of every value, keyed by its dotted path. List items are keyed
+///   by index (`ignore_patterns[2]`) and table entries by name (`project_specific_ignores.rust`).
+/// - `layers`: The layers that were merged, from lowest to highest precedence.
+#[derive(Debug, Clone)]
+pub struct LayeredConfig {
+    pub config: Config,
+    pub values: Table,
+    pub origins: BTreeMap<String, Origin>,
+    pub layers: Vec<ConfigLayer>,
+}
+
+impl LayeredConfig {
+    /// Merges the given layers, in order, into an effective configuration.
+    ///
+    /// The merge semantics for each key are:
+    /// - Scalars and lists replace the value of lower layers.
+    /// - `extend_<key>` appends its items to the list `<key>` instead of replacing it.
+    /// - Tables (such as `project_specific_ignores`) are merged entry by entry, with each
+    ///   entry replacing the entry of the same name in lower layers.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the merged values are not a valid `Config`.
+    pub fn from_layers(layers: &[ConfigLayer]) -> Result<Self> {
+        let mut values = Table::new();
+        let mut origins = BTreeMap::new();
+        for layer in layers {
+            merge_layer(&mut values, &mut origins, layer);
+        }
+        let config: Config = Value::Table(values.clone())
+            .try_into()
+            .with_context(|| "Invalid configuration")?;
+        Ok(LayeredConfig { config, values, origins, layers: layers.to_vec() })
+    }
+
+    /// Returns the config files that contributed values to the configuration.
+    pub fn config_files(&self) -> BTreeSet<PathBuf> {
+        self.origins.values().filter_map(|origin| origin.path.clone()).collect()
+    }
+
+    /// Renders the effective configuration as TOML.
+    ///
+    /// # Arguments
+    ///
+    /// * `with_origin` - Whether to annotate each value with the layer it came from.
+    ///
+    /// # Returns
+    ///
+    /// Returns a `String` containing the configuration, one value (or list item) per line.
+    pub fn render(&self, with_origin: bool) -> String {
+        let mut output = String::new();
+        for (key, value) in &self.values {
+            self.render_value(&mut output, key, value, with_origin);
+        }
+        output
+    }
+
+    fn render_value(&self, output: &mut String, key: &str, value: &Value, with_origin: bool) {
+        let comment = |path: &str| match (with_origin, self.origins.get(path)) {
+            (true, Some(origin)) => format!("  # {}", origin),
+            _ => String::new(),
+        };
+        match value {
+            Value::Array(items) if !items.is_empty() => {
+                output.push_str(&format!("{} = [\n", key));
+                for (index, item) in items.iter().enumerate() {
+                    output.push_str(&format!("    {},{}\n", item, comment(&format!("{}[{}]", key, index))));
+                }
+                output.push_str("]\n");
+            }
+            Value::Table(entries) => {
+                for (name, entry) in entries {
+                    self.render_value(output, &format!("{}.{}", key, name), entry, with_origin);
+                }
+            }
+            _ => output.push_str(&format!("{} = {}{}\n", key, value, comment(key))),
+        }
+    }
+}
+
+/// Merges a single layer into the accumulated values, recording the origin of everything it sets.
+fn merge_layer(values: &mut Table, origins: &mut BTreeMap<String, Origin>, layer: &ConfigLayer) {
+    for (key, value) in &layer.values {
+        match (key.strip_prefix(EXTEND_PREFIX), value) {
+            (Some(base_key), Value::Array(items)) => {
+                let target = values
+                    .entry(base_key.to_string())
+                    .or_insert_with(|| Value::Array(Vec::new()));
+                if let Value::Array(existing) = target {
+                    for item in items {
+                        origins.insert(format!("{}[{}]", base_key, existing.len()), layer.origin.clone());
+                        existing.push(item.clone());
+                    }
+                }
+            }
+            (_, Value::Table(entries)) if matches!(values.get(key), Some(Value::Table(_))) => {
+                if let Some(Value::Table(existing)) = values.get_mut(key) {
+                    for (name, entry) in entries {
+                        let path = format!("{}.{}", key, name);
+                        clear_origins(origins, &path);
+                        record_origins(origins, &path, entry, &layer.origin);
+                        existing.insert(name.clone(), entry.clone());
+                    }
+                }
+            }
+            _ => {
+                clear_origins(origins, key);
+                record_origins(origins, key, value, &layer.origin);
+                values.insert(key.clone(), value.clone());
+            }
+        }
+    }
+}
+
+/// Removes the origins of a value and everything nested inside it.
+fn clear_origins(origins: &mut BTreeMap<String, Origin>, path: &str) {
+    origins.retain(|key, _| {
+        key != path && !key.starts_with(&format!("{}[", path)) && !key.starts_with(&format!("{}.", path))
+    });
+}
+
+/// Records the origin of a value and everything nested inside it.
+fn record_origins(origins: &mut BTreeMap<String, Origin>, path: &str, value: &Value, origin: &Origin) {
+    match value {
+        Value::Array(items) if !items.is_empty() => {
+            for index in 0..items.len() {
+                origins.insert(format!("{}[{}]", path, index), origin.clone());
+            }
+        }
+        Value::Table(entries) => {
+            for (name, entry) in entries {
+                record_origins(origins, &format!("{}.{}", path, name), entry, origin);
+            }
+        }
+        _ => {
+            origins.insert(path.to_string(), origin.clone());
+        }
+    }
+}
+
+/// Finds the project-local configuration file by walking up from the given directory.
+///
+/// In each directory, `.conag.toml` is preferred over `conag.toml`. The search stops at the
+/// first directory containing either file, and doesn't go above the root of the repository
+/// (the first directory containing `.git`) or the home directory.
+///
+/// # Arguments
+///
+/// * `start_dir` - A reference to the `Path` of the directory to start searching from.
+///
+/// # Returns
+///
+/// Returns the path of the project configuration file, or `None` if there is none.
+pub fn find_project_config(start_dir: &Path) -> Option<PathBuf> {
+    let start_dir = std::path::absolute(start_dir).unwrap_or_else(|_| start_dir.to_path_buf());
+    let home_dir = dirs::home_dir();
+    for dir in start_dir.ancestors() {
+        let config_path = PROJECT_CONFIG_FILE_NAMES
+            .iter()
+            .map(|name| dir.join(name))
+            .find(|path| path.is_file());
+        if config_path.is_some() {
+            return config_path;
+        }
+        if dir.join(".git").exists() || home_dir.as_deref() == Some(dir) {
+            return None;
+        }
+    }
+    None
+}
+
+/// Loads the effective configuration for a run, merging every layer.
+///
+/// The layers, from lowest to highest precedence, are:
+/// 1. The built-in defaults.
+/// 2. The global config file (`~/.config/conag/config.toml`, or the file given with `--config`).
+/// 3. The project config file (`.conag.toml` or `conag.toml`), found by walking up from `input_dir`.
+/// 4. The profiles selected with `--profile`, in the order given.
+/// 5. `CONAG_*` environment variables.
+/// 6. Command-line flags.
+///
+/// # Arguments
+///
+/// * `cli` - A reference to the parsed command-line arguments.
+///
+/// # Errors
+///
+/// This function will return an error if:
+/// - A config file given with `--config` does not exist.
+/// - Neither a global nor a project config file exists.
+/// - A config file cannot be read or parsed, or the merged values are not a valid `Config`.
+/// - A selected profile is not defined in any config file.
+pub fn load_layered_config(cli: &Cli) -> Result<LayeredConfig> {
+    let mut layers = vec![ConfigLayer::defaults()?];
+
+    let global_path = match &cli.config {
+        Some(path) => {
+            let path = PathBuf::from(path);
+            if !path.exists() {
+                anyhow::bail!("Config file not found at {:?}", path);
+            }
+            path
+        }
+        None => Config::default_config_path()?,
+    };
+    let has_global = global_path.exists();
+    if has_global {
+        layers.push(ConfigLayer::from_file(LayerSource::Global, &global_path)?);
+    }
+
+    let cli_layer = ConfigLayer::from_cli(cli);
+
+    // The input directory decides where to look for a project config, so resolve it from the other layers first
+    let pre_project = LayeredConfig::from_layers(&layers)?;
+    let env_layer = ConfigLayer::from_env_vars(env::vars(), &pre_project.values);
+    // Profiles of the global config file can set the input directory too
+    let global_profiles: Vec<ConfigLayer> = cli.profile.iter().filter_map(|name| ConfigLayer::from_profile(name, &layers).ok()).collect();
+    let input_dir = LayeredConfig::from_layers(&[layers.clone(), global_profiles, vec![env_layer.clone(), cli_layer.clone()]].concat())?
+        .config
+        .input_dir;
+
+    match find_project_config(Path::new(&input_dir)) {
+        Some(project_path) => layers.push(ConfigLayer::from_file(LayerSource::Project, &project_path)?),
+        None if !has_global => anyhow::bail!(
+            "Config file not found at {:?}. To generate a default config, run:\nconag --generate-config",
+            global_path
+        ),
+        None => {}
+    }
+
+    let profiles = cli.profile.iter().map(|name| ConfigLayer::from_profile(name, &layers)).collect::<Result<Vec<_>>>()?;
+    layers.extend(profiles);
+    layers.push(env_layer);
+    layers.push(cli_layer);
+    LayeredConfig::from_layers(&layers)
+}
@@ -0,0 +1,413 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::ops::Range;
+use serde::de::{self, Deserialize, Deserializer, MapAccess, SeqAccess, Visitor};
+use serde::forward_to_deserialize_any;
+use toml::{Spanned, Table, Value};
+use crate::config::Config;
+use crate::config_layers::{ConfigLayer, EXTEND_PREFIX, LayerSource, LayeredConfig, Origin, PROFILES_KEY};
+use crate::presets::find_preset;
+
+/// The list keys whose items are glob patterns.
+const GLOB_LIST_KEYS: [&str; 4] = [
+    "ignore_patterns",
+    "include_hidden_patterns",
+    "include_file_overrides",
+    "include_dir_overrides",
+];
+
+/// The table keys whose entries are lists of glob patterns.
+const GLOB_TABLE_KEYS: [&str; 1] = ["project_specific_ignores"];
+
+/// How serious a problem in the configuration is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    /// The configuration can't be used, e.g. because of an invalid glob pattern.
+    Error,
+    /// The configuration can be used, but probably doesn't do what was meant, e.g. because
+    /// of a misspelled key.
+    Warning,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Severity::Error => f.write_str("error"),
+            Severity::Warning => f.write_str("warning"),
+        }
+    }
+}
+
+/// A position in a config file, both starting at 1. The column counts characters.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Location {
+    pub line: usize,
+    pub column: usize,
+}
+
+/// A problem found in a configuration layer.
+///
+/// - `severity`: Whether the problem is an error or a warning.
+/// - `origin`: The layer the problem is in, including the path of its config file if any.
+/// - `location`: Where in the config file the problem is, if the layer was read from a file.
+/// - `key`: The dotted path of the value, e.g. `ignore_patterns[2]`.
+/// - `message`: The description of the problem.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConfigIssue {
+    pub severity: Severity,
+    pub origin: Origin,
+    pub location: Option<Location>,
+    pub key: String,
+    pub message: String,
+}
+
+impl fmt::Display for ConfigIssue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (&self.origin.path, self.location) {
+            (Some(path), Some(location)) => write!(f, "{}:{}:{}", path.display(), location.line, location.column)?,
+            (Some(path), None) => write!(f, "{}", path.display())?,
+            (None, _) => write!(f, "{}", self.origin)?,
+        }
+        write!(f, ": {}: {}", self.severity, self.message)
+    }
+}
+
+/// Checks every layer of a configuration for invalid glob patterns, unknown keys and unknown
+/// project types.
+///
+/// Glob patterns are checked in `ignore_patterns`, `include_hidden_patterns`, the override
+/// lists, their `extend_` variants and `project_specific_ignores`. Unknown keys are reported
+/// as warnings, with a suggestion if a known key is spelled similarly. The built-in defaults
+/// and the command-line flags only hold known keys, so only config files and environment
+/// variables are checked for them. A project type is unknown if it has no built-in preset and
+/// no entry in `project_specific_ignores`.
+///
+/// # Arguments
+///
+/// * `layered` - A reference to the `LayeredConfig` whose layers to check.
+///
+/// # Returns
+///
+/// Returns the problems found, in layer order and in the order of the keys in each layer.
+pub fn validate_config(layered: &LayeredConfig) -> Vec<ConfigIssue> {
+    let custom_types: Vec<&str> = layered.config.project_specific_ignores.keys().map(String::as_str).collect();
+    layered.layers.iter().flat_map(|layer| check_layer(layer, &custom_types)).collect()
+}
+
+/// Checks a single configuration layer. See `validate_config`.
+pub fn validate_layer(layer: &ConfigLayer) -> Vec<ConfigIssue> {
+    let custom_types: Vec<&str> = match layer.values.get("project_specific_ignores") {
+        Some(Value::Table(entries)) => entries.keys().map(String::as_str).collect(),
+        _ => Vec::new(),
+    };
+    check_layer(layer, &custom_types)
+}
+
+/// Checks a single configuration layer, given the project types that have ignores in
+/// `project_specific_ignores` besides the built-in presets.
+fn check_layer(layer: &ConfigLayer, custom_types: &[&str]) -> Vec<ConfigIssue> {
+    // The layer was parsed from this text already, so it parses again
+    let spans = layer.text.as_deref().and_then(|text| toml::from_str::<SpannedDocument>(text).ok());
+    let issue = |severity: Severity, path: &[Segment], message: String, key_span: bool| {
+        let span = spans.as_ref().and_then(|spans| spans.span(path, key_span));
+        let location = layer.text.as_deref().zip(span).map(|(text, span)| location(text, span.start));
+        ConfigIssue { severity, origin: layer.origin.clone(), location, key: dotted_path(path), message }
+    };
+
+    // The profiles of a config file are checked where they are defined, whether they are
+    // selected or not, so their layers aren't checked again
+    if layer.origin.source == LayerSource::Profile {
+        return Vec::new();
+    }
+    let mut tables: Vec<(Vec<Segment>, &Table)> = vec![(Vec::new(), &layer.values)];
+    if let Some(Value::Table(profiles)) = layer.values.get(PROFILES_KEY) {
+        // A profile that isn't a table fails to load as a whole, before the checks run
+        for (name, profile) in profiles {
+            if let Value::Table(profile) = profile {
+                tables.push((vec![Segment::Key(PROFILES_KEY), Segment::Key(name)], profile));
+            }
+        }
+    }
+
+    let mut issues = Vec::new();
+    let checks_keys = matches!(layer.origin.source, LayerSource::Global | LayerSource::Project | LayerSource::Environment);
+    for (prefix, values) in tables {
+        let mut custom_types = custom_types.to_vec();
+        if let (false, Some(Value::Table(entries))) = (prefix.is_empty(), values.get("project_specific_ignores")) {
+            custom_types.extend(entries.keys().map(String::as_str));
+        }
+        for (key, value) in values {
+            let base_key = key.strip_prefix(EXTEND_PREFIX).unwrap_or(key);
+            if checks_keys && !config_keys().contains(&base_key) {
+                let path = joined(&prefix, &[Segment::Key(key)]);
+                let message = match suggest_key(base_key) {
+                    Some(known) if base_key != key => format!("unknown key '{}', did you mean '{}{}'?", dotted_path(&path), EXTEND_PREFIX, known),
+                    Some(known) => format!("unknown key '{}', did you mean '{}'?", dotted_path(&path), known),
+                    None => format!("unknown key '{}'", dotted_path(&path)),
+                };
+                issues.push(issue(Severity::Warning, &path, message, true));
+                continue;
+            }
+
+            // A project type without a preset or ignores of its own does nothing
+            let project_types: Vec<(Vec<Segment>, &str)> = match (base_key, value) {
+                ("project_type", Value::String(project_type)) => vec![(joined(&prefix, &[Segment::Key(key)]), project_type.as_str())],
+                ("project_types", Value::Array(items)) => items
+                    .iter()
+                    .enumerate()
+                    .filter_map(|(index, item)| item.as_str().map(|project_type| (joined(&prefix, &[Segment::Key(key), Segment::Index(index)]), project_type)))
+                    .collect(),
+                _ => Vec::new(),
+            };
+            for (path, project_type) in project_types {
+                if find_preset(project_type).is_none() && !custom_types.contains(&project_type) {
+                    let message = format!(
+                        "unknown project type '{}' in {}, which has no preset and no project_specific_ignores",
+                        project_type, dotted_path(&path)
+                    );
+                    issues.push(issue(Severity::Warning, &path, message, false));
+                }
+            }
+
+            let mut lists = Vec::new();
+            if GLOB_LIST_KEYS.contains(&base_key) {
+                lists.push((joined(&prefix, &[Segment::Key(key)]), value));
+            } else if let (true, Value::Table(entries)) = (GLOB_TABLE_KEYS.contains(&base_key), value) {
+                lists.extend(entries.iter().map(|(name, entry)| (joined(&prefix, &[Segment::Key(key), Segment::Key(name)]), entry)));
+            }
+            for (path, list) in lists {
+                let Value::Array(items) = list else { continue };
+                for (index, item) in items.iter().enumerate() {
+                    let Value::String(pattern) = item else { continue };
+                    if let Err(err) = glob::Pattern::new(pattern) {
+                        let path = [path.as_slice(), &[Segment::Index(index)]].concat();
+                        let message = format!("invalid glob pattern '{}' in {}: {}", pattern, dotted_path(&path), err.msg);
+                        issues.push(issue(Severity::Error, &path, message, false));
+                    }
+                }
+            }
+        }
+    }
+    issues
+}
+
+/// Returns the names of every configuration key, as declared by `Config`.
+pub fn config_keys() -> &'static [&'static str] {
+    let mut fields: &'static [&'static str] = &[];
+    // Deserializing reports the fields of the struct to the deserializer, which stops there
+    let _ = Config::deserialize(FieldNames(&mut fields));
+    fields
+}
+
+/// Returns the known key spelled most like an unknown one, if any is close enough.
+pub fn suggest_key(unknown: &str) -> Option<&'static str> {
+    let max_distance = (unknown.chars().count() / 3).max(1);
+    config_keys()
+        .iter()
+        .map(|known| (strsim::levenshtein(unknown, known), *known))
+        .filter(|(distance, _)| *distance <= max_distance)
+        .min_by_key(|(distance, _)| *distance)
+        .map(|(_, known)| known)
+}
+
+/// Returns the path of a value inside the table at `prefix`.
+fn joined<'a>(prefix: &[Segment<'a>], rest: &[Segment<'a>]) -> Vec<Segment<'a>> {
+    [prefix, rest].concat()
+}
+
+/// A step in the path to a value: a key of a table, or an index of a list.
+#[derive(Debug, Clone, Copy)]
+enum Segment<'a> {
+    Key(&'a str),
+    Index(usize),
+}
+
+fn dotted_path(path: &[Segment]) -> String {
+    let mut dotted = String::new();
+    for segment in path {
+        match segment {
+            Segment::Key(key) if dotted.is_empty() => dotted.push_str(key),
+            Segment::Key(key) => dotted.push_str(&format!(".{}", key)),
+            Segment::Index(index) => dotted.push_str(&format!("[{}]", index)),
+        }
+    }
+    dotted
+}
+
+/// Converts a byte offset in a text into a line and column.
+fn location(text: &str, offset: usize) -> Location {
+    let before = &text[..offset.min(text.len())];
+    let line_start = before.rfind('\n').map_or(0, |index| index + 1);
+    Location { line: before.matches('\n').count() + 1, column: before[line_start..].chars().count() + 1 }
+}
+
+/// A config file holding the position of every key and value in the text it was parsed from.
+///
+/// The parser reports no position for the name of a profile in a `[profiles.<name>]` header,
+/// so the profiles are kept apart by name. A file setting a profile with dotted keys, such as
+/// `profiles.docs.sort = "tree"`, has no positions.
+struct SpannedDocument {
+    table: SpannedTable,
+    profiles: BTreeMap<String, Spanned<SpannedValue>>,
+}
+
+impl SpannedDocument {
+    /// Returns the position of the value at the given path, or of its key if `key_span` is set
+    /// and the path ends with a key.
+    fn span(&self, path: &[Segment], key_span: bool) -> Option<Range<usize>> {
+        match path {
+            [Segment::Key(PROFILES_KEY), Segment::Key(name), rest @ ..] => {
+                let profile = self.profiles.get(*name)?;
+                match profile.get_ref() {
+                    _ if rest.is_empty() => Some(profile.span()),
+                    SpannedValue::Table(table) => table.span(rest, key_span),
+                    _ => None,
+                }
+            }
+            _ => self.table.span(path, key_span),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for SpannedDocument {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_map(SpannedDocumentVisitor)
+    }
+}
+
+struct SpannedDocumentVisitor;
+
+impl<'de> Visitor<'de> for SpannedDocumentVisitor {
+    type Value = SpannedDocument;
+
+    fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("a TOML document")
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<SpannedDocument, A::Error> {
+        let mut document = SpannedDocument { table: SpannedTable(Vec::new()), profiles: BTreeMap::new() };
+        while let Some(key) = map.next_key::<Spanned<String>>()? {
+            if key.get_ref() == PROFILES_KEY {
+                document.profiles = map.next_value()?;
+            } else {
+                document.table.0.push((key, map.next_value()?));
+            }
+        }
+        Ok(document)
+    }
+}
+
+/// A TOML value holding the position of every key and value in the text it was parsed from.
+enum SpannedValue {
+    Table(SpannedTable),
+    Array(Vec<Spanned<SpannedValue>>),
+    Other,
+}
+
+/// A TOML table holding the position of every key and value in the text it was parsed from.
+struct SpannedTable(Vec<(Spanned<String>, Spanned<SpannedValue>)>);
+
+impl SpannedTable {
+    /// Returns the position of the value at the given path, or of its key if `key_span` is set
+    /// and the path ends with a key.
+    fn span(&self, path: &[Segment], key_span: bool) -> Option<Range<usize>> {
+        let (Segment::Key(first), rest) = path.split_first()? else { return None };
+        let entry = self.0.iter().find(|(key, _)| key.get_ref().as_str() == *first)?;
+        let (key, mut value) = (&entry.0, &entry.1);
+        if rest.is_empty() && key_span {
+            return Some(key.span());
+        }
+        for (index, segment) in rest.iter().enumerate() {
+            value = match (segment, value.get_ref()) {
+                (Segment::Key(name), SpannedValue::Table(table)) => {
+                    let (key, entry) = table.0.iter().find(|(key, _)| key.get_ref().as_str() == *name)?;
+                    if index + 1 == rest.len() && key_span {
+                        return Some(key.span());
+                    }
+                    entry
+                }
+                (Segment::Index(item), SpannedValue::Array(items)) => items.get(*item)?,
+                _ => return None,
+            };
+        }
+        Some(value.span())
+    }
+}
+
+impl<'de> Deserialize<'de> for SpannedValue {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_any(SpannedValueVisitor)
+    }
+}
+
+struct SpannedValueVisitor;
+
+impl<'de> Visitor<'de> for SpannedValueVisitor {
+    type Value = SpannedValue;
+
+    fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("a TOML value")
+    }
+
+    fn visit_bool<E>(self, _: bool) -> Result<SpannedValue, E> {
+        Ok(SpannedValue::Other)
+    }
+
+    fn visit_i64<E>(self, _: i64) -> Result<SpannedValue, E> {
+        Ok(SpannedValue::Other)
+    }
+
+    fn visit_u64<E>(self, _: u64) -> Result<SpannedValue, E> {
+        Ok(SpannedValue::Other)
+    }
+
+    fn visit_f64<E>(self, _: f64) -> Result<SpannedValue, E> {
+        Ok(SpannedValue::Other)
+    }
+
+    fn visit_str<E>(self, _: &str) -> Result<SpannedValue, E> {
+        Ok(SpannedValue::Other)
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<SpannedValue, A::Error> {
+        let mut items = Vec::new();
+        while let Some(item) = seq.next_element()? {
+            items.push(item);
+        }
+        Ok(SpannedValue::Array(items))
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<SpannedValue, A::Error> {
+        let mut entries = Vec::new();
+        while let Some(entry) = map.next_entry()? {
+            entries.push(entry);
+        }
+        Ok(SpannedValue::Table(SpannedTable(entries)))
+    }
+}
+
+/// A deserializer that records the field names a struct asks for, then stops.
+struct FieldNames<'a>(&'a mut &'static [&'static str]);
+
+impl<'de> Deserializer<'de> for FieldNames<'_> {
+    type Error = de::value::Error;
+
+    fn deserialize_any<V: Visitor<'de>>(self, _visitor: V) -> Result<V::Value, Self::Error> {
+        Err(de::Error::custom("expected a struct"))
+    }
+
+    fn deserialize_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        fields: &'static [&'static str],
+        _visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        *self.0 = fields;
+        Err(de::Error::custom("only the field names are read"))
+    }
+
+    forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string bytes byte_buf option
+        unit unit_struct newtype_struct seq tuple tuple_struct map enum identifier ignored_any
+    }
+}
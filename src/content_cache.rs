@@ -0,0 +1,501 @@
+use std::cmp::Reverse;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::SystemTime;
+use serde::{Deserialize, Serialize};
+use xxhash_rust::xxh3::xxh3_64;
+use crate::aggregator::{FileRead, ReadOptions, SkipReason};
+use crate::encoding::DecodedText;
+use crate::tokenizer::TokenizerKind;
+use crate::truncation::Truncation;
+
+/// The version of the on-disk cache format. Caches written with another version are discarded.
+const CACHE_FORMAT_VERSION: u32 = 2;
+
+/// The name of the file listing the cached files of an input directory.
+const INDEX_FILE_NAME: &str = "index.json";
+
+/// The name of the directory holding the content of the cached files, one file per content hash.
+const CONTENT_DIR_NAME: &str = "content";
+
+/// The extension of the single-file caches written by older versions, which are still removed
+/// by `clear_cache` and when trimming the cache directory.
+const LEGACY_CACHE_FILE_EXTENSION: &str = "json";
+
+/// Numbers the temporary files content is written to, so threads writing the same content
+/// don't collide.
+static TEMP_FILE_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/// Options for persisting a `ContentCache` on disk between runs.
+///
+/// - `dir`: The directory holding the cache of each input directory.
+/// - `max_size`: The maximum size in bytes of all caches together. The least recently
+///   written caches are removed to stay under it.
+/// - `hash_contents`: Whether files whose modification time changed but whose content hashes
+///   the same keep their cached data, such as their token counts, instead of being cached anew.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DiskCacheOptions {
+    pub dir: PathBuf,
+    pub max_size: u64,
+    pub hash_contents: bool,
+}
+
+/// Returns the default cache directory, `$XDG_CACHE_HOME/conag` on Linux, if the platform has one.
+pub fn default_cache_dir() -> Option<PathBuf> {
+    dirs::cache_dir().map(|dir| dir.join("conag"))
+}
+
+/// The modification time and size of a file when it was read.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+pub(crate) struct FileStamp {
+    modified: Option<SystemTime>,
+    size: u64,
+}
+
+impl FileStamp {
+    fn of(path: &Path) -> io::Result<Self> {
+        let metadata = fs::metadata(path)?;
+        Ok(FileStamp { modified: metadata.modified().ok(), size: metadata.len() })
+    }
+}
+
+/// A file that was read as text, as it is cached.
+///
+/// In an on-disk cache the content is stored in a content file of its own, named by its hash,
+/// so the index stays small and only the content of the files being output is loaded.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+struct CachedText {
+    encoding: String,
+    truncation: Option<Truncation>,
+    bytes_read: u64,
+    #[serde(skip)]
+    content: Option<String>,
+}
+
+/// A cached file: how it was read, and the data derived from its content.
+///
+/// The binary flag is part of `read`, as `SkipReason::Binary`. The language is derived from the
+/// file extension alone, so it isn't cached.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+struct CachedFile {
+    stamp: FileStamp,
+    hash: Option<u64>,
+    read: Result<CachedText, SkipReason>,
+    tokens: Vec<(TokenizerKind, usize)>,
+}
+
+/// The index of the cache of an input directory.
+#[derive(Debug, Deserialize, Serialize)]
+struct CacheIndex {
+    version: u32,
+    fingerprint: String,
+    entries: HashMap<PathBuf, CachedFile>,
+}
+
+/// The outcome of looking up a file with `ContentCache::read`, which `ContentCache::update`
+/// applies to the cache.
+#[derive(Debug)]
+pub(crate) enum CachedRead {
+    /// The cached read of the file is up to date.
+    Hit(FileRead),
+    /// The file was read, and its content hashes the same as the cached read, but its stamp changed.
+    Restamped { stamp: FileStamp, read: FileRead },
+    /// The file was read. It is cached unless its stamp couldn't be taken.
+    Read { stamp: Option<FileStamp>, hash: Option<u64>, read: FileRead },
+}
+
+/// Where a `ContentCache` is persisted: a directory per input directory, holding the index
+/// and the content files.
+#[derive(Debug)]
+struct CacheStore {
+    dir: PathBuf,
+    fingerprint: String,
+    max_size: u64,
+}
+
+impl CacheStore {
+    fn index_path(&self) -> PathBuf {
+        self.dir.join(INDEX_FILE_NAME)
+    }
+
+    fn content_path(&self, hash: u64) -> PathBuf {
+        self.dir.join(CONTENT_DIR_NAME).join(format!("{:016x}", hash))
+    }
+
+    /// Writes a content file, unless one with the same hash already exists.
+    fn write_content(&self, hash: u64, content: &str) -> io::Result<()> {
+        let path = self.content_path(hash);
+        if path.exists() {
+            return Ok(());
+        }
+        fs::create_dir_all(self.dir.join(CONTENT_DIR_NAME))?;
+        // Write to a temporary file first, so a content file is never read while partially written
+        let counter = TEMP_FILE_COUNTER.fetch_add(1, Ordering::Relaxed);
+        let temp_path = path.with_extension(format!("{}-{}.tmp", std::process::id(), counter));
+        fs::write(&temp_path, content)?;
+        fs::rename(&temp_path, &path)
+    }
+
+    /// Reads a content file, checking that it still hashes the same.
+    fn read_content(&self, hash: u64) -> Option<String> {
+        fs::read_to_string(self.content_path(hash))
+            .ok()
+            .filter(|content| xxh3_64(content.as_bytes()) == hash)
+    }
+
+    /// Removes the content files no cached file refers to anymore.
+    ///
+    /// # Returns
+    ///
+    /// Returns the size in bytes of the remaining content files.
+    fn remove_unused_content(&self, used: &HashSet<u64>) -> io::Result<u64> {
+        let entries = match fs::read_dir(self.dir.join(CONTENT_DIR_NAME)) {
+            Ok(entries) => entries,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(0),
+            Err(err) => return Err(err),
+        };
+        let mut size = 0;
+        for entry in entries {
+            let entry = entry?;
+            let is_used = entry
+                .file_name()
+                .to_str()
+                .and_then(|name| u64::from_str_radix(name, 16).ok())
+                .is_some_and(|hash| used.contains(&hash));
+            if is_used {
+                size += entry.metadata()?.len();
+            } else {
+                remove_if_exists(&entry.path())?;
+            }
+        }
+        Ok(size)
+    }
+}
+
+/// Remembers how each file was read, so unchanged files aren't read again when the output
+/// is regenerated, e.g. in watch mode or by the next run.
+///
+/// Entries are keyed by the path of the file as passed to `aggregate_contents_cached`, and
+/// hold the modification time and size of the file when it was read. An entry is only reused
+/// while both are unchanged, or, with `hash_contents`, while the content hashes the same. The
+/// cache must still be cleared when the options for reading files change.
+///
+/// A cache created with `new` lives in memory only, including the content of every file it
+/// read. A cache created with `load` is read from the cache directory, and `save` writes it
+/// back. It only keeps an index of the files in memory, and writes the content of each file it
+/// reads to the cache directory right away. A cache created with `disabled` remembers nothing.
+#[derive(Debug, Default)]
+pub struct ContentCache {
+    entries: HashMap<PathBuf, CachedFile>,
+    hash_contents: bool,
+    store: Option<CacheStore>,
+    dirty: bool,
+    disabled: bool,
+}
+
+impl ContentCache {
+    /// Creates an empty cache kept in memory only.
+    pub fn new() -> Self {
+        ContentCache::default()
+    }
+
+    /// Creates a cache that remembers nothing, so files read through it aren't held in memory
+    /// after they were handed over.
+    pub fn disabled() -> Self {
+        ContentCache { disabled: true, ..ContentCache::default() }
+    }
+
+    /// Loads the on-disk cache of an input directory.
+    ///
+    /// A missing, unreadable or outdated cache results in an empty cache. Caches written with
+    /// other `ReadOptions` or by another version of conag are outdated.
+    ///
+    /// # Arguments
+    ///
+    /// * `options` - A reference to the `DiskCacheOptions` to use.
+    /// * `input_dir` - A reference to the `Path` of the input directory. Each input directory
+    ///   has its own cache.
+    /// * `read_options` - A reference to the `ReadOptions` files are read with.
+    pub fn load(options: &DiskCacheOptions, input_dir: &Path, read_options: &ReadOptions) -> Self {
+        let input_dir = fs::canonicalize(input_dir).unwrap_or_else(|_| input_dir.to_path_buf());
+        let store = CacheStore {
+            dir: options.dir.join(format!("{:016x}", xxh3_64(input_dir.as_os_str().as_encoded_bytes()))),
+            // The content is cached before it is redacted, so the redactor doesn't matter
+            fingerprint: format!("{} {:?} {:?}", env!("CARGO_PKG_VERSION"), read_options.decode, read_options.size_limits),
+            max_size: options.max_size,
+        };
+        let entries = fs::read(store.index_path())
+            .ok()
+            .and_then(|bytes| serde_json::from_slice::<CacheIndex>(&bytes).ok())
+            .filter(|index| index.version == CACHE_FORMAT_VERSION && index.fingerprint == store.fingerprint)
+            .map(|index| index.entries)
+            .unwrap_or_default();
+        ContentCache {
+            entries,
+            hash_contents: options.hash_contents,
+            store: Some(store),
+            ..ContentCache::default()
+        }
+    }
+
+    /// Checks whether the cached read of a file is still up to date, and otherwise reads the
+    /// file with `read`. The cache isn't changed, so files can be looked up from several
+    /// threads at once; pass the outcome to `update` afterwards.
+    ///
+    /// The content of an on-disk cache is loaded here, and the content of a file that was read
+    /// is written to the cache directory here, so both happen on the threads reading files.
+    pub(crate) fn read(&self, path: &Path, read: impl FnOnce() -> FileRead) -> CachedRead {
+        if self.disabled {
+            return CachedRead::Read { stamp: None, hash: None, read: read() };
+        }
+        // The stamp is taken before reading, so a change made while reading is noticed next time
+        let mut stamp = FileStamp::of(path).ok();
+        let entry = self.entries.get(path);
+        if let Some(cached) = entry.filter(|entry| Some(entry.stamp) == stamp).and_then(|entry| self.cached_read(entry)) {
+            return CachedRead::Hit(cached);
+        }
+
+        let read = read();
+        // The hash is taken of the content that was just read, so files are never read twice
+        let hash = read.as_ref().ok().map(|(decoded, _, _)| xxh3_64(decoded.content.as_bytes()));
+        if let (Some(store), Some(hash), Ok((decoded, _, _))) = (&self.store, hash, &read) {
+            if stamp.is_some() && store.write_content(hash, &decoded.content).is_err() {
+                // The content couldn't be cached, so neither can the file
+                stamp = None;
+            }
+        }
+        match (stamp, entry) {
+            (Some(stamp), Some(entry)) if self.hash_contents && hash.is_some() && entry.hash == hash => {
+                CachedRead::Restamped { stamp, read }
+            }
+            _ => CachedRead::Read { stamp, hash, read },
+        }
+    }
+
+    /// Returns the cached read of a file, or `None` if its content is no longer in the cache.
+    fn cached_read(&self, entry: &CachedFile) -> Option<FileRead> {
+        let text = match &entry.read {
+            Ok(text) => text,
+            Err(reason) => return Some(Err(reason.clone())),
+        };
+        let content = match (&text.content, &self.store) {
+            (Some(content), _) => content.clone(),
+            (None, Some(store)) => store.read_content(entry.hash?)?,
+            (None, None) => return None,
+        };
+        let decoded = DecodedText { content, encoding: text.encoding.clone() };
+        Some(Ok((decoded, text.truncation, text.bytes_read)))
+    }
+
+    /// Applies the outcome of `read` to the cache.
+    ///
+    /// # Returns
+    ///
+    /// Returns the read of the file, either from the cache or as it was just read.
+    pub(crate) fn update(&mut self, path: &Path, cached_read: CachedRead) -> FileRead {
+        match cached_read {
+            CachedRead::Hit(read) | CachedRead::Read { stamp: None, read, .. } => read,
+            CachedRead::Restamped { stamp, read } => {
+                self.dirty = true;
+                if let Some(entry) = self.entries.get_mut(path) {
+                    entry.stamp = stamp;
+                }
+                read
+            }
+            CachedRead::Read { stamp: Some(stamp), hash, read } => {
+                self.dirty = true;
+                // An on-disk cache already wrote the content to the cache directory
+                let keep_content = self.store.is_none();
+                let cached_read = match &read {
+                    Ok((decoded, truncation, bytes_read)) => Ok(CachedText {
+                        encoding: decoded.encoding.clone(),
+                        truncation: *truncation,
+                        bytes_read: *bytes_read,
+                        content: keep_content.then(|| decoded.content.clone()),
+                    }),
+                    Err(reason) => Err(reason.clone()),
+                };
+                let entry = CachedFile { stamp, hash, read: cached_read, tokens: Vec::new() };
+                self.entries.insert(path.to_path_buf(), entry);
+                read
+            }
+        }
+    }
+
+    /// Returns the cached token count of a file for the given tokenizer, and otherwise counts
+    /// its tokens with `count` and caches the result. Files that aren't cached are counted
+    /// every time.
+    pub(crate) fn tokens(&mut self, path: &Path, tokenizer: TokenizerKind, count: impl FnOnce() -> usize) -> usize {
+        let Some(entry) = self.entries.get_mut(path) else { return count() };
+        if let Some((_, tokens)) = entry.tokens.iter().find(|(kind, _)| *kind == tokenizer) {
+            return *tokens;
+        }
+        let tokens = count();
+        entry.tokens.push((tokenizer, tokens));
+        self.dirty = true;
+        tokens
+    }
+
+    /// Forgets a file, so it is read again the next time it is aggregated.
+    ///
+    /// # Returns
+    ///
+    /// Returns `true` if the file was cached.
+    pub fn invalidate(&mut self, path: &Path) -> bool {
+        self.dirty = true;
+        self.entries.remove(path).is_some()
+    }
+
+    /// Forgets every file.
+    pub fn clear(&mut self) {
+        self.dirty = true;
+        self.entries.clear();
+    }
+
+    /// Returns the number of cached files.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Returns `true` if no files are cached.
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Writes the index of a cache created with `load` back to the cache directory. Files that
+    /// no longer exist are forgotten, along with content no file refers to anymore, while the
+    /// files this run didn't read, e.g. with `--changed`, are kept. Then removes the least
+    /// recently written caches of other input directories until all caches fit in `max_size`.
+    ///
+    /// Nothing is written if the cache lives in memory only, or if no file changed.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the cache directory can't be written, or if the
+    /// cache of this input directory alone exceeds `max_size`, in which case it is removed.
+    pub fn save(&mut self) -> io::Result<()> {
+        let Some(store) = &self.store else { return Ok(()) };
+        let cached_files = self.entries.len();
+        self.entries.retain(|path, _| path.exists());
+        if !self.dirty && self.entries.len() == cached_files {
+            return Ok(());
+        }
+
+        let index = CacheIndex {
+            version: CACHE_FORMAT_VERSION,
+            fingerprint: store.fingerprint.clone(),
+            entries: std::mem::take(&mut self.entries),
+        };
+        let bytes = serde_json::to_vec(&index).map_err(io::Error::other);
+        self.entries = index.entries;
+        let bytes = bytes?;
+        fs::create_dir_all(&store.dir)?;
+        let used_content: HashSet<u64> = self.entries.values().filter_map(|entry| entry.hash).collect();
+        let size = bytes.len() as u64 + store.remove_unused_content(&used_content)?;
+        if size > store.max_size {
+            remove_cache(&store.dir)?;
+            return Err(io::Error::other(format!(
+                "the cache of this directory takes {} bytes, more than cache_max_size ({} bytes)",
+                size, store.max_size
+            )));
+        }
+
+        // Write to a temporary file first, so a concurrent run never reads a partial index
+        let index_path = store.index_path();
+        let temp_path = index_path.with_extension("tmp");
+        fs::write(&temp_path, &bytes)?;
+        fs::rename(&temp_path, &index_path)?;
+        self.dirty = false;
+        trim_cache_dir(store.dir.parent().unwrap_or(Path::new(".")), store.max_size)
+    }
+}
+
+/// Removes every cache from the cache directory.
+///
+/// # Arguments
+///
+/// * `dir` - A reference to the `Path` of the cache directory.
+///
+/// # Returns
+///
+/// Returns the number of caches removed and the number of bytes they took up.
+///
+/// # Errors
+///
+/// This function will return an error if the cache directory exists but can't be read, or a
+/// cache can't be removed.
+pub fn clear_cache(dir: &Path) -> io::Result<(usize, u64)> {
+    let mut removed = (0, 0);
+    for (path, size, _) in caches(dir)? {
+        remove_cache(&path)?;
+        removed.0 += 1;
+        removed.1 += size;
+    }
+    Ok(removed)
+}
+
+/// Removes the least recently written caches until the rest fit in `max_size`.
+fn trim_cache_dir(dir: &Path, max_size: u64) -> io::Result<()> {
+    let mut caches = caches(dir)?;
+    caches.sort_by_key(|(_, _, modified)| Reverse(*modified));
+    let mut total = 0;
+    for (path, size, _) in caches {
+        total += size;
+        if total > max_size {
+            remove_cache(&path)?;
+        }
+    }
+    Ok(())
+}
+
+/// Lists the caches in the cache directory with their size and the time their index was
+/// last written. Single-file caches written by older versions are listed too.
+fn caches(dir: &Path) -> io::Result<Vec<(PathBuf, u64, Option<SystemTime>)>> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(err) => return Err(err),
+    };
+    let mut caches = Vec::new();
+    for entry in entries {
+        let path = entry?.path();
+        if path.is_dir() {
+            let modified = fs::metadata(path.join(INDEX_FILE_NAME)).and_then(|metadata| metadata.modified()).ok();
+            caches.push((path.clone(), dir_size(&path)?, modified));
+        } else if path.extension().is_some_and(|extension| extension == LEGACY_CACHE_FILE_EXTENSION) {
+            let metadata = fs::metadata(&path)?;
+            caches.push((path, metadata.len(), metadata.modified().ok()));
+        }
+    }
+    Ok(caches)
+}
+
+/// Returns the total size of the files in a directory and its subdirectories.
+fn dir_size(dir: &Path) -> io::Result<u64> {
+    let mut size = 0;
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let metadata = entry.metadata()?;
+        size += if metadata.is_dir() { dir_size(&entry.path())? } else { metadata.len() };
+    }
+    Ok(size)
+}
+
+/// Removes a cache, either the directory of an input directory or a legacy cache file.
+fn remove_cache(path: &Path) -> io::Result<()> {
+    let result = if path.is_dir() { fs::remove_dir_all(path) } else { fs::remove_file(path) };
+    match result {
+        Err(err) if err.kind() != io::ErrorKind::NotFound => Err(err),
+        _ => Ok(()),
+    }
+}
+
+fn remove_if_exists(path: &Path) -> io::Result<()> {
+    match fs::remove_file(path) {
+        Err(err) if err.kind() != io::ErrorKind::NotFound => Err(err),
+        _ => Ok(()),
+    }
+}
@@ -0,0 +1,172 @@
+use encoding_rs::{Encoding, UTF_16BE, UTF_16LE, UTF_8};
+use serde::{Deserialize, Serialize};
+
+/// The name of the encoding of text that is valid UTF-8.
+pub const UTF8_NAME: &str = "UTF-8";
+
+/// The number of bytes needed to recognize UTF-16 text without a byte order mark.
+const MIN_UTF16_SNIFF_LEN: usize = 8;
+
+/// Text decoded from a file, along with how it was decoded.
+///
+/// - `content`: The decoded text.
+/// - `encoding`: The name of the detected encoding, e.g. `UTF-8`, `UTF-16LE` or `windows-1252`,
+///   followed by ` (lossy)` if invalid sequences were replaced.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct DecodedText {
+    pub content: String,
+    pub encoding: String,
+}
+
+/// Options controlling how text that isn't valid UTF-8 is decoded.
+///
+/// - `fallback_encoding`: The encoding used for files that have no byte order mark and aren't
+///   valid UTF-8, e.g. `windows-1252` for legacy Latin-1 files.
+/// - `lossy`: Whether to replace invalid sequences with U+FFFD instead of rejecting the file.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct DecodeOptions {
+    pub fallback_encoding: Option<&'static Encoding>,
+    pub lossy: bool,
+}
+
+/// Looks up an encoding by one of its WHATWG labels, e.g. `windows-1252`, `latin1` or `shift_jis`.
+///
+/// # Returns
+///
+/// Returns the encoding, or `None` if the label is unknown.
+pub fn encoding_for_label(label: &str) -> Option<&'static Encoding> {
+    Encoding::for_label(label.trim().as_bytes())
+}
+
+/// Decodes the content of a text file into UTF-8.
+///
+/// The encoding is detected in this order:
+/// 1. A UTF-8, UTF-16LE or UTF-16BE byte order mark selects that encoding, and is removed.
+/// 2. Content that looks like UTF-16 without a byte order mark, see `detect_bomless_utf16`.
+/// 3. Content that is valid UTF-8 is used as-is.
+/// 4. The fallback encoding, if one is configured.
+/// 5. UTF-8 with invalid sequences replaced, in lossy mode.
+///
+/// # Arguments
+///
+/// * `bytes` - The raw content of the file.
+/// * `options` - A reference to the `DecodeOptions` to use.
+///
+/// # Returns
+///
+/// Returns the `DecodedText`, or `None` if the content can't be decoded without errors and
+/// lossy mode is off.
+pub fn decode(bytes: Vec<u8>, options: &DecodeOptions) -> Option<DecodedText> {
+    if let Some((encoding, bom_length)) = Encoding::for_bom(&bytes) {
+        let (content, had_errors) = encoding.decode_without_bom_handling(&bytes[bom_length..]);
+        return finish(content.into_owned(), encoding, had_errors, options);
+    }
+    if let Some(encoding) = detect_bomless_utf16(&bytes) {
+        let (content, had_errors) = encoding.decode_without_bom_handling(&bytes);
+        return finish(content.into_owned(), encoding, had_errors, options);
+    }
+    let bytes = match String::from_utf8(bytes) {
+        Ok(content) => return Some(DecodedText { content, encoding: UTF8_NAME.to_string() }),
+        Err(err) => err.into_bytes(),
+    };
+    let encoding = options.fallback_encoding.unwrap_or(UTF_8);
+    let (content, had_errors) = encoding.decode_without_bom_handling(&bytes);
+    finish(content.into_owned(), encoding, had_errors, options)
+}
+
+fn finish(content: String, encoding: &'static Encoding, had_errors: bool, options: &DecodeOptions) -> Option<DecodedText> {
+    if had_errors && !options.lossy {
+        return None;
+    }
+    Some(DecodedText { content, encoding: encoding_label(encoding, had_errors) })
+}
+
+/// Detects the encoding of content from its first bytes, the same way `decode` does, for
+/// content that is decoded in fragments because it is too large to read whole.
+///
+/// # Arguments
+///
+/// * `prefix` - The first bytes of the content. A multi-byte sequence cut off at the end of
+///   the prefix does not count as invalid.
+/// * `options` - A reference to the `DecodeOptions` to use.
+///
+/// # Returns
+///
+/// Returns the detected encoding and the length of its byte order mark, or `None` if the
+/// prefix is not valid UTF-8, no fallback encoding is configured and lossy mode is off.
+pub fn detect_encoding(prefix: &[u8], options: &DecodeOptions) -> Option<(&'static Encoding, usize)> {
+    if let Some(detected) = Encoding::for_bom(prefix) {
+        return Some(detected);
+    }
+    if let Some(encoding) = detect_bomless_utf16(prefix) {
+        return Some((encoding, 0));
+    }
+    match std::str::from_utf8(prefix) {
+        Ok(_) => Some((UTF_8, 0)),
+        Err(err) if err.error_len().is_none() => Some((UTF_8, 0)),
+        Err(_) => options.fallback_encoding.or(options.lossy.then_some(UTF_8)).map(|encoding| (encoding, 0)),
+    }
+}
+
+/// Detects UTF-16 text without a byte order mark from the NUL bytes of its first code units.
+///
+/// In UTF-16 text that is mostly ASCII, the high byte of nearly every code unit is NUL and
+/// the low byte practically never is, so the NUL bytes alternate. Text in scripts outside
+/// Latin-1 isn't recognized this way, and is still treated as binary.
+///
+/// # Arguments
+///
+/// * `prefix` - The first bytes of the content.
+///
+/// # Returns
+///
+/// Returns `UTF_16LE` or `UTF_16BE`, or `None` if the content doesn't look like UTF-16 or
+/// isn't valid in it.
+pub fn detect_bomless_utf16(prefix: &[u8]) -> Option<&'static Encoding> {
+    if prefix.len() < MIN_UTF16_SNIFF_LEN {
+        return None;
+    }
+    let units = prefix.len() / 2;
+    let nul_count = |offset: usize| prefix.iter().skip(offset).step_by(2).take(units).filter(|byte| **byte == 0).count();
+    let (even_nuls, odd_nuls) = (nul_count(0), nul_count(1));
+    let encoding = if even_nuls == 0 && odd_nuls * 10 >= units * 9 {
+        UTF_16LE
+    } else if odd_nuls == 0 && even_nuls * 10 >= units * 9 {
+        UTF_16BE
+    } else {
+        return None;
+    };
+    let (_, had_errors) = decode_fragment(&prefix[..units * 2], encoding);
+    (!had_errors).then_some(encoding)
+}
+
+/// Returns `true` if the encoding is UTF-16, whose code units are two bytes long.
+pub fn is_utf16(encoding: &'static Encoding) -> bool {
+    encoding == UTF_16LE || encoding == UTF_16BE
+}
+
+/// Decodes a fragment of content cut out of a larger file.
+///
+/// A multi-byte sequence cut off at the end of the fragment is dropped rather than treated
+/// as invalid. The caller is responsible for starting the fragment at a character boundary.
+///
+/// # Returns
+///
+/// Returns the decoded text, and whether invalid sequences were replaced with U+FFFD.
+pub fn decode_fragment(bytes: &[u8], encoding: &'static Encoding) -> (String, bool) {
+    let mut decoder = encoding.new_decoder_without_bom_handling();
+    let capacity = decoder.max_utf8_buffer_length(bytes.len()).unwrap_or(bytes.len() * 3);
+    let mut content = String::with_capacity(capacity);
+    let (_, _, had_errors) = decoder.decode_to_string(bytes, &mut content, false);
+    (content, had_errors)
+}
+
+/// Returns the name an encoding is reported with, marking it as lossy if invalid sequences
+/// were replaced.
+pub fn encoding_label(encoding: &'static Encoding, lossy: bool) -> String {
+    if lossy {
+        format!("{} (lossy)", encoding.name())
+    } else {
+        encoding.name().to_string()
+    }
+}
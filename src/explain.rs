@@ -0,0 +1,152 @@
+use std::collections::BTreeMap;
+use std::path::Path;
+use crate::config_layers::Origin;
+use crate::ignore_rules::{DecisionStep, FileExplanation, RuleCheck, RuleKind, RuleOutcome, RuleSource};
+
+/// The decision steps, in order of precedence, with the kinds of rules evaluated in each.
+const STEPS: [(DecisionStep, &[RuleKind]); 4] = [
+    (DecisionStep::FileOverride, &[RuleKind::FileOverride]),
+    (DecisionStep::HiddenFile, &[RuleKind::HiddenFiles, RuleKind::IncludeHidden]),
+    (DecisionStep::IgnorePattern, &[RuleKind::DirOverride, RuleKind::IgnorePattern]),
+    (DecisionStep::GitIgnore, &[RuleKind::GitIgnore]),
+];
+
+/// Renders why a single file is included or excluded, listing every rule evaluated for it.
+///
+/// The rules are grouped by the decision step they belong to, in order of precedence. Each
+/// rule is shown with whether it matched, its config key and the layer it came from.
+///
+/// # Arguments
+///
+/// * `explanation` - A reference to the `FileExplanation` to render.
+/// * `origins` - The origin of every configuration value, as in `LayeredConfig::origins`.
+/// * `input_dir` - The input directory, which paths of git ignore files are shown relative to.
+///
+/// # Returns
+///
+/// Returns a `String` with the decision on the first line, followed by the rules.
+pub fn render_explanation(explanation: &FileExplanation, origins: &BTreeMap<String, Origin>, input_dir: &Path) -> String {
+    let decision = if explanation.included { "included" } else { "excluded" };
+    let mut output = format!("{}: {}\n", explanation.path.display(), decision);
+    let decided_by = match explanation.deciding_rule.map(|index| &explanation.checks[index]) {
+        Some(check) => format!(": {} {} {}", rule_key(check), check.pattern, source_note(check, origins, input_dir)).trim_end().to_string(),
+        None if explanation.step == DecisionStep::Default => ": no rule excludes the file".to_string(),
+        None => String::new(),
+    };
+    output.push_str(&format!(
+        "Decided by step {} of {} ({}){}\n",
+        explanation.step.number(), DecisionStep::Default.number(), explanation.step, decided_by
+    ));
+
+    let keys: Vec<String> = explanation.checks.iter().map(|check| rule_key(check)).collect();
+    let key_width = keys.iter().map(String::len).max().unwrap_or(0);
+    let pattern_width = explanation.checks.iter().map(|check| check.pattern.len()).max().unwrap_or(0);
+    for (step, kinds) in STEPS {
+        output.push_str(&format!("\n{}. {}\n", step.number(), step));
+        let mut any = false;
+        for (check, key) in explanation.checks.iter().zip(&keys).filter(|(check, _)| kinds.contains(&check.kind)) {
+            let outcome = match check.outcome {
+                RuleOutcome::Matched => "matched",
+                RuleOutcome::NoMatch => "no match",
+                RuleOutcome::Reincluded => "re-included",
+                RuleOutcome::Skipped => "skipped",
+            };
+            let line = format!(
+                "   {:<11}  {:<key_width$}  {:<pattern_width$}  {}",
+                outcome, key, check.pattern, source_note(check, origins, input_dir)
+            );
+            output.push_str(line.trim_end());
+            output.push('\n');
+            any = true;
+        }
+        if !any {
+            output.push_str("   (no rules)\n");
+        }
+    }
+    output
+}
+
+/// Renders the decision for many files as a table, one file per line.
+///
+/// # Arguments
+///
+/// * `explanations` - The explanations of the files, in the order to list them.
+/// * `input_dir` - The input directory, which paths of git ignore files are shown relative to.
+///
+/// # Returns
+///
+/// Returns a `String` with a header line, followed by the decision, the deciding step and
+/// rule and the path of each file.
+pub fn render_table(explanations: &[FileExplanation], input_dir: &Path) -> String {
+    let rows: Vec<[String; 4]> = explanations
+        .iter()
+        .map(|explanation| {
+            let decision = if explanation.included { "included" } else { "excluded" };
+            let rule = explanation
+                .deciding_rule
+                .map(|index| rule_name(&explanation.checks[index], input_dir))
+                .unwrap_or_default();
+            [decision.to_string(), explanation.step.to_string(), rule, explanation.path.display().to_string()]
+        })
+        .collect();
+    let header = ["DECISION", "STEP", "RULE", "PATH"].map(String::from);
+    let width = |column: usize| rows.iter().chain([&header]).map(|row| row[column].chars().count()).max().unwrap_or(0);
+    let widths = [width(0), width(1), width(2)];
+
+    let mut output = String::new();
+    for row in [&header].into_iter().chain(&rows) {
+        let line = format!(
+            "{:<w0$}  {:<w1$}  {:<w2$}  {}",
+            row[0], row[1], row[2], row[3], w0 = widths[0], w1 = widths[1], w2 = widths[2]
+        );
+        output.push_str(line.trim_end());
+        output.push('\n');
+    }
+    output
+}
+
+/// Returns the config key of a rule, e.g. `ignore_patterns[3]`, or a description of a rule
+/// that doesn't come from the configuration.
+fn rule_key(check: &RuleCheck) -> String {
+    match (&check.source, check.kind) {
+        (RuleSource::Config(list, index), _) => format!("{}[{}]", list, index),
+        (RuleSource::Preset(name, index), _) => format!("presets.{}[{}]", name, index),
+        (RuleSource::BuiltIn, RuleKind::HiddenFiles) => "hidden files".to_string(),
+        (RuleSource::BuiltIn, _) => "built-in".to_string(),
+        (RuleSource::IgnoreFile(_), _) if check.pattern.is_empty() && check.outcome == RuleOutcome::Skipped => {
+            "git ignore files, disabled by a directory override".to_string()
+        }
+        (RuleSource::IgnoreFile(_), _) if check.pattern.is_empty() => "git ignore files, no rule matches".to_string(),
+        (RuleSource::IgnoreFile(_), _) => "git ignore rule".to_string(),
+    }
+}
+
+/// Returns the short name of the rule that decided, as shown in the table.
+fn rule_name(check: &RuleCheck, input_dir: &Path) -> String {
+    match &check.source {
+        RuleSource::Config(list, index) => format!("{} ({}[{}])", check.pattern, list, index),
+        RuleSource::BuiltIn => format!("{} (built-in)", check.pattern),
+        RuleSource::Preset(name, _) => format!("{} ({} preset)", check.pattern, name),
+        RuleSource::IgnoreFile(Some(file)) => format!("{} ({})", check.pattern, relative(file, input_dir)),
+        RuleSource::IgnoreFile(None) => check.pattern.to_string(),
+    }
+}
+
+/// Returns where a rule came from: the config layer of a configured rule, or the ignore
+/// file of a git ignore rule.
+fn source_note(check: &RuleCheck, origins: &BTreeMap<String, Origin>, input_dir: &Path) -> String {
+    match &check.source {
+        RuleSource::Config(list, index) => match origins.get(&format!("{}[{}]", list, index)) {
+            Some(origin) => format!("from {}", origin),
+            None => String::new(),
+        },
+        RuleSource::BuiltIn => "built-in".to_string(),
+        RuleSource::Preset(name, _) => format!("from the built-in {} preset", name),
+        RuleSource::IgnoreFile(Some(file)) => format!("from {}", relative(file, input_dir)),
+        RuleSource::IgnoreFile(None) => String::new(),
+    }
+}
+
+fn relative(path: &Path, base: &Path) -> String {
+    path.strip_prefix(base).unwrap_or(path).display().to_string()
+}
@@ -2,6 +2,7 @@ use std::collections::HashSet;
 use std::fs;
 use std::io;
 use std::path::{Path, PathBuf};
+use ignore::WalkBuilder;
 
 /// Lists all files in the given directory and its subdirectories.
 ///
@@ -22,12 +23,55 @@ use std::path::{Path, PathBuf};
 /// This function will return an error if there are issues accessing the file system or
 /// if there are permission problems.
 pub fn list_files(dir: &Path) -> io::Result<HashSet<PathBuf>> {
+    let mut builder = WalkBuilder::new(dir);
+    builder.standard_filters(false);
+    walk_files(dir, &builder)
+}
+
+/// Lists the files in the given directory and its subdirectories, without walking the
+/// directories that are skipped.
+///
+/// Unlike `list_files`, whole directories are left out in a single pass: those for which
+/// `skip_dir` returns `true` and, if enabled, those ignored by the `.gitignore` and `.ignore`
+/// files found along the way, which leave out the files they ignore as well. Only the ignore
+/// files inside `dir` are honored here, so callers still apply the complete rules to the files
+/// listed.
+///
+/// # Arguments
+///
+/// * `dir` - A reference to a Path representing the directory to start the search from.
+/// * `respect_gitignore` - Whether to skip the directories ignored by `.gitignore` and `.ignore` files.
+/// * `skip_dir` - Returns `true` for a directory whose files should not be listed.
+///
+/// # Returns
+///
+/// Returns an `io::Result` containing a `HashSet` of `PathBuf`s, each representing a file path.
+/// The function returns an empty set if the given path is not a directory.
+///
+/// # Errors
+///
+/// This function will return an error if there are issues accessing the file system or
+/// if there are permission problems.
+pub fn list_files_pruned<F>(dir: &Path, respect_gitignore: bool, skip_dir: F) -> io::Result<HashSet<PathBuf>>
+where
+    F: Fn(&Path) -> bool + Send + Sync + 'static,
+{
+    let mut builder = WalkBuilder::new(dir);
+    builder
+        .standard_filters(false)
+        .ignore(respect_gitignore)
+        .git_ignore(respect_gitignore)
+        .require_git(false)
+        .filter_entry(move |entry| !(entry.file_type().is_some_and(|file_type| file_type.is_dir()) && skip_dir(entry.path())));
+    walk_files(dir, &builder)
+}
+
+fn walk_files(dir: &Path, builder: &WalkBuilder) -> io::Result<HashSet<PathBuf>> {
     let mut files = HashSet::new();
     if dir.is_dir() {
-        for entry in walkdir::WalkDir::new(dir).follow_links(false).into_iter().filter_map(|e| e.ok()) {
-            let path = entry.path().to_path_buf();
-            if path.is_file() {
-                files.insert(path);
+        for entry in builder.build().filter_map(|e| e.ok()) {
+            if entry.path().is_file() {
+                files.insert(entry.into_path());
             }
         }
     }
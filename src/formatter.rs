@@ -0,0 +1,834 @@
+use std::borrow::Cow;
+use std::io::{self, Write};
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use crate::aggregator::{FileContent, SkippedFile, get_language_identifier};
+use crate::budget::OmittedFile;
+use crate::chunking::PartInfo;
+use crate::git_changes::{ChangeStatus, ChangeSummary, DiffSummary, FileDiff, RenamedFile};
+use crate::tokenizer::Tokenizer;
+
+/// Formats the aggregated output, one piece at a time.
+///
+/// An output document is written as a header, followed by each file in output order, followed
+/// by a footer. Every formatter produces a complete document from these pieces, so an output
+/// split into parts is a set of complete documents.
+pub trait OutputFormatter {
+    /// Returns the file extension of the output, without the leading dot.
+    fn extension(&self) -> &'static str;
+
+    /// Writes the start of the document: the project name, the directory tree and the diff
+    /// summary if there are any and, for a part of an output that was split into several
+    /// parts, the index of the files in the part.
+    fn write_header(&self, out: &mut dyn Write, header: &Header, part: Option<&PartIndex>) -> io::Result<()>;
+
+    /// Writes a single file with the given options. `index` is the position of the file in the
+    /// document, starting at 0.
+    fn write_file(&self, out: &mut dyn Write, file: &FileContent, index: usize, options: &FormatOptions) -> io::Result<()>;
+
+    /// Writes the end of the document, including the lists of omitted and skipped files and
+    /// the summary of deleted and renamed files.
+    fn write_footer(&self, out: &mut dyn Write, footer: &Footer) -> io::Result<()>;
+}
+
+/// The project-wide information at the top of every output document.
+///
+/// - `project_name`: The name of the project.
+/// - `tree`: The rendered directory tree overview, if enabled.
+/// - `diff_summary`: The insertions and deletions of each changed file, in diff mode.
+#[derive(Debug, Clone, Copy)]
+pub struct Header<'a> {
+    pub project_name: &'a str,
+    pub tree: Option<&'a str>,
+    pub diff_summary: Option<&'a DiffSummary>,
+}
+
+impl<'a> Header<'a> {
+    /// Creates a header for the given project, without a directory tree or diff summary.
+    pub fn new(project_name: &'a str) -> Self {
+        Header { project_name, tree: None, diff_summary: None }
+    }
+}
+
+/// The lists of files left out of the output, written at the end of the last document.
+///
+/// - `omitted`: The files omitted to stay within the token budget.
+/// - `skipped`: The files skipped as binary, unreadable or otherwise unusable, if the skip
+///   report is embedded in the output.
+/// - `changes`: The deleted and renamed files, if the files were selected from git changes.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Footer<'a> {
+    pub omitted: &'a [OmittedFile],
+    pub skipped: &'a [SkippedFile],
+    pub changes: Option<&'a ChangeSummary>,
+}
+
+impl Footer<'_> {
+    /// Returns `true` if there are no omitted, skipped, deleted or renamed files to list.
+    pub fn is_empty(&self) -> bool {
+        self.omitted.is_empty() && self.skipped.is_empty() && self.changes.is_none_or(ChangeSummary::is_empty)
+    }
+
+    /// Returns the change summary, if there are deleted or renamed files to list.
+    fn changes(&self) -> Option<&ChangeSummary> {
+        self.changes.filter(|changes| !changes.is_empty())
+    }
+}
+
+/// The position of a part in a split output, and the files it contains.
+///
+/// - `info`: The number of the part and the total number of parts.
+/// - `files`: The files in the part, in output order.
+#[derive(Debug, Clone, Copy)]
+pub struct PartIndex<'a> {
+    pub info: &'a PartInfo,
+    pub files: &'a [FileContent],
+}
+
+/// The output formats available.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize, Serialize, ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum OutputFormat {
+    /// Markdown with a fenced code block per file.
+    #[default]
+    Markdown,
+    /// Plain text with separator lines.
+    Plain,
+    /// `<document>` XML tags, as recommended for Anthropic models.
+    Xml,
+    /// A single JSON object holding every file.
+    Json,
+    /// One JSON object per file, per line.
+    Jsonl,
+}
+
+impl OutputFormat {
+    /// Returns the `OutputFormatter` implementation for this format.
+    pub fn formatter(&self) -> Box<dyn OutputFormatter + Send + Sync> {
+        match self {
+            OutputFormat::Markdown => Box::new(MarkdownFormatter),
+            OutputFormat::Plain => Box::new(PlainTextFormatter),
+            OutputFormat::Xml => Box::new(XmlFormatter),
+            OutputFormat::Json => Box::new(JsonFormatter),
+            OutputFormat::Jsonl => Box::new(JsonLinesFormatter),
+        }
+    }
+}
+
+/// Options for how the files are written, shared by every format.
+///
+/// - `line_numbers`: Prefix each line of the text formats with its number in the original
+///   file, and give each file of the JSON formats a `start_line` field.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FormatOptions {
+    pub line_numbers: bool,
+}
+
+/// Writes a complete document with the given formatter.
+///
+/// # Arguments
+///
+/// * `formatter` - The `OutputFormatter` to use.
+/// * `options` - A reference to the `FormatOptions` the files are written with.
+/// * `out` - The writer to write the document to.
+/// * `header` - A reference to the `Header` with the project name and directory tree.
+/// * `part` - The position of the document in a split output, if the output was split.
+/// * `files` - A slice of `FileContent`s, in the order they should appear in the output.
+/// * `footer` - A reference to the `Footer` with the omitted and skipped files.
+///
+/// # Errors
+///
+/// This function will return an `io::Error` if writing to `out` fails.
+pub fn write_document(
+    formatter: &dyn OutputFormatter,
+    options: &FormatOptions,
+    out: &mut dyn Write,
+    header: &Header,
+    part: Option<&PartInfo>,
+    files: &[FileContent],
+    footer: &Footer,
+) -> io::Result<()> {
+    let part = part.map(|info| PartIndex { info, files });
+    formatter.write_header(out, header, part.as_ref())?;
+    for (index, file) in files.iter().enumerate() {
+        formatter.write_file(out, file, index, options)?;
+    }
+    formatter.write_footer(out, footer)
+}
+
+/// Renders a complete document with the given formatter into a `String`.
+///
+/// See `write_document` for the arguments.
+pub fn render_document(
+    formatter: &dyn OutputFormatter,
+    options: &FormatOptions,
+    header: &Header,
+    part: Option<&PartInfo>,
+    files: &[FileContent],
+    footer: &Footer,
+) -> String {
+    let mut output = Vec::new();
+    write_document(formatter, options, &mut output, header, part, files, footer)
+        .expect("writing to a Vec can't fail");
+    String::from_utf8(output).expect("formatters only write UTF-8")
+}
+
+/// Renders a complete document like `render_document`, and counts its tokens the same way as
+/// `DocumentWriter::with_tokenizer`, so the totals of rendered and streamed output agree.
+///
+/// # Returns
+///
+/// Returns the rendered document and its number of tokens.
+pub fn render_document_counted(
+    formatter: &dyn OutputFormatter,
+    options: &FormatOptions,
+    tokenizer: &dyn Tokenizer,
+    header: &Header,
+    part: Option<&PartInfo>,
+    files: &[FileContent],
+    footer: &Footer,
+) -> (String, usize) {
+    let mut output = Vec::new();
+    let mut document = DocumentWriter::new(formatter, &mut output).with_options(*options).with_tokenizer(tokenizer);
+    let tokens = match part {
+        Some(part) => document.write_part_header(header, part, files),
+        None => document.write_header(header),
+    }
+    .and_then(|_| files.iter().try_for_each(|file| document.write_file(file)))
+    .and_then(|_| document.finish(footer))
+    .expect("writing to a Vec can't fail");
+    (String::from_utf8(output).expect("formatters only write UTF-8"), tokens)
+}
+
+/// Writes a document with the given formatter one file at a time, so the files can be
+/// written while they are still being read instead of being collected first.
+///
+/// Each piece is formatted into a buffer before it is written to the sink, so its tokens can
+/// be counted with `with_tokenizer`. Only one file is buffered at a time.
+pub struct DocumentWriter<'a> {
+    formatter: &'a dyn OutputFormatter,
+    out: &'a mut dyn Write,
+    options: FormatOptions,
+    tokenizer: Option<&'a dyn Tokenizer>,
+    buffer: Vec<u8>,
+    files: usize,
+    tokens: usize,
+}
+
+impl<'a> DocumentWriter<'a> {
+    /// Creates a writer of a document to the given sink, writing the files with the default
+    /// options.
+    pub fn new(formatter: &'a dyn OutputFormatter, out: &'a mut dyn Write) -> Self {
+        DocumentWriter { formatter, out, options: FormatOptions::default(), tokenizer: None, buffer: Vec::new(), files: 0, tokens: 0 }
+    }
+
+    /// Writes the files with the given options.
+    pub fn with_options(mut self, options: FormatOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    /// Counts the tokens of everything written with the given tokenizer. Each piece is
+    /// counted on its own, so the total can differ slightly from counting the whole document;
+    /// `render_document_counted` counts rendered documents the same way.
+    pub fn with_tokenizer(mut self, tokenizer: &'a dyn Tokenizer) -> Self {
+        self.tokenizer = Some(tokenizer);
+        self
+    }
+
+    /// Writes the start of the document. See `OutputFormatter::write_header`.
+    pub fn write_header(&mut self, header: &Header) -> io::Result<()> {
+        self.formatter.write_header(&mut self.buffer, header, None)?;
+        self.flush_buffer()
+    }
+
+    /// Writes the start of a part of a document, including the index of the files in the part.
+    /// See `OutputFormatter::write_header`.
+    pub fn write_part_header(&mut self, header: &Header, part: &PartInfo, files: &[FileContent]) -> io::Result<()> {
+        let part = PartIndex { info: part, files };
+        self.formatter.write_header(&mut self.buffer, header, Some(&part))?;
+        self.flush_buffer()
+    }
+
+    /// Writes the next file of the document. See `OutputFormatter::write_file`.
+    pub fn write_file(&mut self, file: &FileContent) -> io::Result<()> {
+        self.formatter.write_file(&mut self.buffer, file, self.files, &self.options)?;
+        self.files += 1;
+        self.flush_buffer()
+    }
+
+    /// Writes the end of the document and flushes the sink. See `OutputFormatter::write_footer`.
+    ///
+    /// # Returns
+    ///
+    /// Returns the number of tokens in the document, or 0 if no tokenizer was given.
+    pub fn finish(mut self, footer: &Footer) -> io::Result<usize> {
+        self.formatter.write_footer(&mut self.buffer, footer)?;
+        self.flush_buffer()?;
+        self.out.flush()?;
+        Ok(self.tokens)
+    }
+
+    /// Returns the number of files written so far.
+    pub fn files(&self) -> usize {
+        self.files
+    }
+
+    fn flush_buffer(&mut self) -> io::Result<()> {
+        if let Some(tokenizer) = self.tokenizer {
+            let text = std::str::from_utf8(&self.buffer).expect("formatters only write UTF-8");
+            self.tokens += tokenizer.count_tokens(text);
+        }
+        self.out.write_all(&self.buffer)?;
+        self.buffer.clear();
+        Ok(())
+    }
+}
+
+const SEPARATOR_WIDTH: usize = 40;
+
+/// Markdown output, with a fenced code block per file.
+pub struct MarkdownFormatter;
+
+impl OutputFormatter for MarkdownFormatter {
+    fn extension(&self) -> &'static str {
+        "md"
+    }
+
+    fn write_header(&self, out: &mut dyn Write, header: &Header, part: Option<&PartIndex>) -> io::Result<()> {
+        write!(out, "# Project: {}\n\n", header.project_name)?;
+        if let Some(tree) = header.tree {
+            write!(out, "## Directory Tree\n\n```\n{}```\n\n", tree)?;
+        }
+        if let Some(summary) = header.diff_summary {
+            out.write_all(b"## Diff Summary\n\n")?;
+            write_diff_summary(out, summary)?;
+        }
+        write_part_index(out, part)
+    }
+
+    fn write_file(&self, out: &mut dyn Write, file: &FileContent, _index: usize, options: &FormatOptions) -> io::Result<()> {
+        let lang_id = get_language_identifier(&file.path);
+        let Some(diff) = &file.diff else {
+            return write!(out, "## File: {}\n\n```{}\n{}\n```\n\n", file_heading(file), lang_id, text_content(file, options));
+        };
+        write!(out, "## File: {}\n\n", file_heading(file))?;
+        // A pure rename has no hunks
+        if !diff.hunks.is_empty() {
+            write!(out, "```diff\n{}```\n\n", diff.unified())?;
+        }
+        if !file.content.is_empty() {
+            write!(out, "Full content after the change:\n\n```{}\n{}\n```\n\n", lang_id, text_content(file, options))?;
+        }
+        Ok(())
+    }
+
+    fn write_footer(&self, out: &mut dyn Write, footer: &Footer) -> io::Result<()> {
+        if !footer.omitted.is_empty() {
+            out.write_all(b"## Omitted Files\n\n")?;
+            write_omitted_list(out, footer.omitted)?;
+        }
+        if !footer.skipped.is_empty() {
+            out.write_all(b"## Skipped Files\n\n")?;
+            write_skipped_list(out, footer.skipped)?;
+        }
+        if let Some(changes) = footer.changes() {
+            out.write_all(b"## Deleted and Renamed Files\n\n")?;
+            write_change_summary(out, changes)?;
+        }
+        Ok(())
+    }
+}
+
+/// Plain text output, with separator lines under each header.
+pub struct PlainTextFormatter;
+
+impl OutputFormatter for PlainTextFormatter {
+    fn extension(&self) -> &'static str {
+        "txt"
+    }
+
+    fn write_header(&self, out: &mut dyn Write, header: &Header, part: Option<&PartIndex>) -> io::Result<()> {
+        write!(out, "# Project: {}\n{}\n\n", header.project_name, "=".repeat(SEPARATOR_WIDTH))?;
+        if let Some(tree) = header.tree {
+            write!(out, "## Directory Tree\n{}\n{}\n", "=".repeat(SEPARATOR_WIDTH), tree)?;
+        }
+        if let Some(summary) = header.diff_summary {
+            write!(out, "## Diff Summary\n{}\n", "=".repeat(SEPARATOR_WIDTH))?;
+            write_diff_summary(out, summary)?;
+        }
+        write_part_index(out, part)
+    }
+
+    fn write_file(&self, out: &mut dyn Write, file: &FileContent, _index: usize, options: &FormatOptions) -> io::Result<()> {
+        let lang_id = get_language_identifier(&file.path);
+        let separator = "=".repeat(SEPARATOR_WIDTH);
+        let Some(diff) = &file.diff else {
+            return write!(out, "## File: {}\n{}\n```{}\n{}\n```\n\n", file_heading(file), separator, lang_id, text_content(file, options));
+        };
+        write!(out, "## File: {}\n{}\n", file_heading(file), separator)?;
+        if !diff.hunks.is_empty() {
+            write!(out, "```diff\n{}```\n\n", diff.unified())?;
+        }
+        if !file.content.is_empty() {
+            write!(out, "Full content after the change:\n```{}\n{}\n```\n\n", lang_id, text_content(file, options))?;
+        }
+        Ok(())
+    }
+
+    fn write_footer(&self, out: &mut dyn Write, footer: &Footer) -> io::Result<()> {
+        if !footer.omitted.is_empty() {
+            write!(out, "## Omitted Files\n{}\n", "=".repeat(SEPARATOR_WIDTH))?;
+            write_omitted_list(out, footer.omitted)?;
+        }
+        if !footer.skipped.is_empty() {
+            write!(out, "## Skipped Files\n{}\n", "=".repeat(SEPARATOR_WIDTH))?;
+            write_skipped_list(out, footer.skipped)?;
+        }
+        if let Some(changes) = footer.changes() {
+            write!(out, "## Deleted and Renamed Files\n{}\n", "=".repeat(SEPARATOR_WIDTH))?;
+            write_change_summary(out, changes)?;
+        }
+        Ok(())
+    }
+}
+
+/// Returns the heading of a file in the text formats: its label, followed by the encoding it
+/// was decoded from if that was not UTF-8, its original size if it was truncated, and its
+/// inserted and deleted lines in diff mode.
+fn file_heading(file: &FileContent) -> String {
+    let mut notes = Vec::new();
+    if let Some(diff) = &file.diff {
+        notes.push(format!("+{} -{}", diff.insertions, diff.deletions));
+    }
+    if let Some(encoding) = file.encoding_note() {
+        notes.push(format!("encoding: {}", encoding));
+    }
+    if let Some(truncation) = file.truncation {
+        notes.push(format!(
+            "truncated, originally {} bytes and {} lines",
+            truncation.original_bytes, truncation.original_lines
+        ));
+    }
+    if notes.is_empty() {
+        file.label()
+    } else {
+        format!("{} ({})", file.label(), notes.join(", "))
+    }
+}
+
+/// Returns the content of a file as written by the text formats: as-is, or with each line
+/// prefixed by its number in the original file if `line_numbers` is set. The numbers are
+/// right-aligned to the width of the highest line number of the file, and the elision
+/// marker of a truncated file gets no number.
+fn text_content<'a>(file: &'a FileContent, options: &FormatOptions) -> Cow<'a, str> {
+    if !options.line_numbers {
+        return Cow::Borrowed(&file.content);
+    }
+    let width = file.last_original_line().to_string().len();
+    let mut numbered = String::with_capacity(file.content.len() + file.content.lines().count() * (width + 3));
+    for (index, line) in file.content.split_inclusive('\n').enumerate() {
+        let number = file.original_line(index + 1).map(|number| number.to_string()).unwrap_or_default();
+        if line.trim_end_matches(['\r', '\n']).is_empty() {
+            numbered.push_str(&format!("{:>width$} |{}", number, line));
+        } else {
+            numbered.push_str(&format!("{:>width$} | {}", number, line));
+        }
+    }
+    Cow::Owned(numbered)
+}
+
+/// Writes the insertions and deletions of each changed file and in total, shared by the text formats.
+fn write_diff_summary(out: &mut dyn Write, summary: &DiffSummary) -> io::Result<()> {
+    writeln!(out, "Changes since {}:\n", summary.base)?;
+    for file in &summary.files {
+        let status = match &file.old_path {
+            Some(old_path) => format!(" (renamed from {})", old_path.display()),
+            None if file.status == ChangeStatus::Modified => String::new(),
+            None => format!(" ({})", file.status),
+        };
+        writeln!(out, "- {}{}: +{} -{}", file.path.display(), status, file.insertions, file.deletions)?;
+    }
+    write!(
+        out,
+        "\n{} files changed, {} insertions(+), {} deletions(-)\n\n",
+        summary.files.len(), summary.insertions(), summary.deletions()
+    )
+}
+
+/// Writes the part number and the files in the part, shared by the text formats.
+fn write_part_index(out: &mut dyn Write, part: Option<&PartIndex>) -> io::Result<()> {
+    let Some(part) = part else { return Ok(()) };
+    write!(out, "Part {} of {}\n\nFiles in this part:\n", part.info.number, part.info.total)?;
+    for file in part.files {
+        writeln!(out, "- {}", file.label())?;
+    }
+    out.write_all(b"\n")
+}
+
+/// Writes the list of omitted files, shared by the text formats.
+fn write_omitted_list(out: &mut dyn Write, omitted: &[OmittedFile]) -> io::Result<()> {
+    out.write_all(b"The following files were omitted to stay within the token budget:\n\n")?;
+    for file in omitted {
+        writeln!(out, "- {} ({} tokens)", file.path.display(), file.tokens)?;
+    }
+    Ok(())
+}
+
+/// Writes the list of skipped files, shared by the text formats.
+fn write_skipped_list(out: &mut dyn Write, skipped: &[SkippedFile]) -> io::Result<()> {
+    out.write_all(b"The following files were skipped:\n\n")?;
+    for file in skipped {
+        writeln!(out, "- {} ({})", file.path.display(), file.reason)?;
+    }
+    Ok(())
+}
+
+/// Writes the lists of deleted and renamed files, shared by the text formats.
+fn write_change_summary(out: &mut dyn Write, changes: &ChangeSummary) -> io::Result<()> {
+    if !changes.deleted.is_empty() {
+        out.write_all(b"The following files were deleted:\n\n")?;
+        for path in &changes.deleted {
+            writeln!(out, "- {}", path.display())?;
+        }
+    }
+    if !changes.renamed.is_empty() {
+        if !changes.deleted.is_empty() {
+            out.write_all(b"\n")?;
+        }
+        out.write_all(b"The following files were renamed:\n\n")?;
+        for file in &changes.renamed {
+            writeln!(out, "- {} → {}", file.from.display(), file.to.display())?;
+        }
+    }
+    Ok(())
+}
+
+/// XML output using `<document>` tags, as recommended for long documents in Anthropic's prompting guides.
+///
+/// File contents and diffs are wrapped in CDATA sections instead of being escaped, so code
+/// reads naturally to the model and the document stays well-formed. Attribute values and
+/// file paths are escaped.
+pub struct XmlFormatter;
+
+impl OutputFormatter for XmlFormatter {
+    fn extension(&self) -> &'static str {
+        "xml"
+    }
+
+    fn write_header(&self, out: &mut dyn Write, header: &Header, part: Option<&PartIndex>) -> io::Result<()> {
+        write!(out, "<documents project=\"{}\"", escape_xml(header.project_name))?;
+        if let Some(part) = part {
+            write!(out, " part=\"{}\" total_parts=\"{}\"", part.info.number, part.info.total)?;
+        }
+        out.write_all(b">\n")?;
+        if let Some(tree) = header.tree {
+            write!(out, "<directory_tree>\n{}</directory_tree>\n", escape_xml(tree))?;
+        }
+        if let Some(summary) = header.diff_summary {
+            writeln!(
+                out,
+                "<diff_summary base=\"{}\" insertions=\"{}\" deletions=\"{}\">",
+                escape_xml(&summary.base), summary.insertions(), summary.deletions()
+            )?;
+            for file in &summary.files {
+                write!(out, "<file status=\"{}\" insertions=\"{}\" deletions=\"{}\"", file.status, file.insertions, file.deletions)?;
+                if let Some(old_path) = &file.old_path {
+                    write!(out, " from=\"{}\"", escape_xml(&old_path.display().to_string()))?;
+                }
+                writeln!(out, ">{}</file>", escape_xml(&file.path.display().to_string()))?;
+            }
+            out.write_all(b"</diff_summary>\n")?;
+        }
+        Ok(())
+    }
+
+    fn write_file(&self, out: &mut dyn Write, file: &FileContent, index: usize, options: &FormatOptions) -> io::Result<()> {
+        writeln!(out, "<document index=\"{}\">", index + 1)?;
+        writeln!(out, "<source>{}</source>", escape_xml(&file.label()))?;
+        if let Some(encoding) = file.encoding_note() {
+            writeln!(out, "<encoding>{}</encoding>", escape_xml(encoding))?;
+        }
+        if let Some(truncation) = file.truncation {
+            writeln!(
+                out,
+                "<truncated original_bytes=\"{}\" original_lines=\"{}\" elided_lines=\"{}\"/>",
+                truncation.original_bytes, truncation.original_lines, truncation.elided_lines()
+            )?;
+        }
+        if let Some(diff) = &file.diff {
+            write_xml_diff(out, diff)?;
+            if file.content.is_empty() {
+                return out.write_all(b"</document>\n");
+            }
+        }
+        writeln!(out, "<document_content>{}</document_content>", cdata(&format!("\n{}\n", text_content(file, options))))?;
+        out.write_all(b"</document>\n")
+    }
+
+    fn write_footer(&self, out: &mut dyn Write, footer: &Footer) -> io::Result<()> {
+        if !footer.omitted.is_empty() {
+            out.write_all(b"<omitted_files>\n")?;
+            for file in footer.omitted {
+                writeln!(out, "<file tokens=\"{}\">{}</file>", file.tokens, escape_xml(&file.path.display().to_string()))?;
+            }
+            out.write_all(b"</omitted_files>\n")?;
+        }
+        if !footer.skipped.is_empty() {
+            out.write_all(b"<skipped_files>\n")?;
+            for file in footer.skipped {
+                let reason = escape_xml(&file.reason.to_string());
+                writeln!(out, "<file reason=\"{}\">{}</file>", reason, escape_xml(&file.path.display().to_string()))?;
+            }
+            out.write_all(b"</skipped_files>\n")?;
+        }
+        if let Some(changes) = footer.changes() {
+            if !changes.deleted.is_empty() {
+                out.write_all(b"<deleted_files>\n")?;
+                for path in &changes.deleted {
+                    writeln!(out, "<file>{}</file>", escape_xml(&path.display().to_string()))?;
+                }
+                out.write_all(b"</deleted_files>\n")?;
+            }
+            if !changes.renamed.is_empty() {
+                out.write_all(b"<renamed_files>\n")?;
+                for file in &changes.renamed {
+                    let from = escape_xml(&file.from.display().to_string());
+                    writeln!(out, "<file from=\"{}\">{}</file>", from, escape_xml(&file.to.display().to_string()))?;
+                }
+                out.write_all(b"</renamed_files>\n")?;
+            }
+        }
+        out.write_all(b"</documents>\n")
+    }
+}
+
+/// Writes the hunks of a diff as `<hunk>` elements in a `<diff>` element. Like file contents,
+/// the lines of each hunk are wrapped in a CDATA section.
+fn write_xml_diff(out: &mut dyn Write, diff: &FileDiff) -> io::Result<()> {
+    writeln!(out, "<diff insertions=\"{}\" deletions=\"{}\">", diff.insertions, diff.deletions)?;
+    for hunk in &diff.hunks {
+        writeln!(
+            out,
+            "<hunk old_start=\"{}\" old_lines=\"{}\" new_start=\"{}\" new_lines=\"{}\">",
+            hunk.old_start, hunk.old_lines, hunk.new_start, hunk.new_lines
+        )?;
+        let mut lines = format!("{}\n", hunk.header);
+        for line in &hunk.lines {
+            lines.push_str(line);
+            lines.push('\n');
+        }
+        writeln!(out, "{}</hunk>", cdata(&lines))?;
+    }
+    out.write_all(b"</diff>\n")
+}
+
+/// Wraps text in a CDATA section. A `]]>` in the text would end the section, so it is split
+/// across two sections.
+fn cdata(text: &str) -> String {
+    format!("<![CDATA[{}]]>", text.replace("]]>", "]]]]><![CDATA[>"))
+}
+
+/// Escapes the characters that are special in XML text and attribute values.
+fn escape_xml(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// A single JSON object holding the project name, the directory tree, the files and the
+/// omitted and skipped files. If the files were selected from git changes, it also holds
+/// `deleted` paths and `renamed` objects with their `from` and `to` paths.
+///
+/// Each file is an object with its `path`, `language`, `encoding` and `content`, plus a `lines` object
+/// (`start`, `end`, `total`) if it is a segment of a larger file, and a `truncated` object
+/// (`original_bytes`, `original_lines`, `head_lines`, `tail_lines`) if it was truncated. In
+/// diff mode, each file also has a `diff` object with its `insertions`, `deletions` and
+/// `hunks`, and the top-level object has a `diff_summary`. With `line_numbers`, each file
+/// has a `start_line` field with the number of its first line in the original file.
+pub struct JsonFormatter;
+
+impl OutputFormatter for JsonFormatter {
+    fn extension(&self) -> &'static str {
+        "json"
+    }
+
+    fn write_header(&self, out: &mut dyn Write, header: &Header, part: Option<&PartIndex>) -> io::Result<()> {
+        writeln!(out, "{{\n  \"project\": {},", json!(header.project_name))?;
+        if let Some(tree) = header.tree {
+            writeln!(out, "  \"tree\": {},", json!(tree))?;
+        }
+        if let Some(summary) = header.diff_summary {
+            writeln!(out, "  \"diff_summary\": {},", diff_summary_json(summary))?;
+        }
+        if let Some(part) = part {
+            let part = json!({ "number": part.info.number, "total": part.info.total });
+            writeln!(out, "  \"part\": {},", part)?;
+        }
+        out.write_all(b"  \"files\": [")
+    }
+
+    fn write_file(&self, out: &mut dyn Write, file: &FileContent, index: usize, options: &FormatOptions) -> io::Result<()> {
+        let separator = if index == 0 { "\n" } else { ",\n" };
+        write!(out, "{}    {}", separator, file_json(file, options))
+    }
+
+    fn write_footer(&self, out: &mut dyn Write, footer: &Footer) -> io::Result<()> {
+        out.write_all(b"\n  ]")?;
+        write_json_list(out, "omitted", footer.omitted.iter().map(omitted_json))?;
+        write_json_list(out, "skipped", footer.skipped.iter().map(skipped_json))?;
+        if let Some(changes) = footer.changes {
+            write_json_list(out, "deleted", changes.deleted.iter().map(|path| json!(path.display().to_string())))?;
+            write_json_list(out, "renamed", changes.renamed.iter().map(renamed_json))?;
+        }
+        out.write_all(b"\n}\n")
+    }
+}
+
+/// One JSON object per line for each file, for pipelines that process files individually.
+///
+/// File objects have the same fields as in `JsonFormatter`. Omitted files are written as
+/// objects with their `path`, `tokens` and `"omitted": true`, and skipped files as objects
+/// with their `path`, `reason` and `"skipped": true`. Deleted files are written as objects
+/// with their `path` and `"deleted": true`, and renamed files as objects with their `from`
+/// and `to` paths and `"renamed": true`. The directory tree, if enabled,
+/// is written first as an object with a single `tree` field, followed by the diff summary
+/// as an object with a single `diff_summary` field in diff mode.
+pub struct JsonLinesFormatter;
+
+impl OutputFormatter for JsonLinesFormatter {
+    fn extension(&self) -> &'static str {
+        "jsonl"
+    }
+
+    fn write_header(&self, out: &mut dyn Write, header: &Header, _part: Option<&PartIndex>) -> io::Result<()> {
+        if let Some(tree) = header.tree {
+            writeln!(out, "{}", json!({ "tree": tree }))?;
+        }
+        if let Some(summary) = header.diff_summary {
+            writeln!(out, "{}", json!({ "diff_summary": diff_summary_json(summary) }))?;
+        }
+        Ok(())
+    }
+
+    fn write_file(&self, out: &mut dyn Write, file: &FileContent, _index: usize, options: &FormatOptions) -> io::Result<()> {
+        writeln!(out, "{}", file_json(file, options))
+    }
+
+    fn write_footer(&self, out: &mut dyn Write, footer: &Footer) -> io::Result<()> {
+        for file in footer.omitted {
+            let mut value = omitted_json(file);
+            value["omitted"] = json!(true);
+            writeln!(out, "{}", value)?;
+        }
+        for file in footer.skipped {
+            let mut value = skipped_json(file);
+            value["skipped"] = json!(true);
+            writeln!(out, "{}", value)?;
+        }
+        if let Some(changes) = footer.changes {
+            for path in &changes.deleted {
+                writeln!(out, "{}", json!({ "path": path.display().to_string(), "deleted": true }))?;
+            }
+            for file in &changes.renamed {
+                let mut value = renamed_json(file);
+                value["renamed"] = json!(true);
+                writeln!(out, "{}", value)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn file_json(file: &FileContent, options: &FormatOptions) -> serde_json::Value {
+    let mut value = json!({
+        "path": file.path.display().to_string(),
+        "language": get_language_identifier(&file.path),
+        "encoding": file.encoding,
+        "content": file.content,
+    });
+    if let Some(truncation) = file.truncation {
+        value["truncated"] = json!({
+            "original_bytes": truncation.original_bytes,
+            "original_lines": truncation.original_lines,
+            "head_lines": truncation.head_lines,
+            "tail_lines": truncation.tail_lines,
+        });
+    }
+    if let Some(diff) = &file.diff {
+        let hunks: Vec<serde_json::Value> = diff
+            .hunks
+            .iter()
+            .map(|hunk| json!({
+                "old_start": hunk.old_start,
+                "old_lines": hunk.old_lines,
+                "new_start": hunk.new_start,
+                "new_lines": hunk.new_lines,
+                "header": hunk.header,
+                "lines": hunk.lines,
+            }))
+            .collect();
+        value["diff"] = json!({ "insertions": diff.insertions, "deletions": diff.deletions, "hunks": hunks });
+    }
+    if let Some(segment) = file.segment {
+        value["lines"] = json!({ "start": segment.start_line, "end": segment.end_line, "total": segment.total_lines });
+    }
+    if options.line_numbers {
+        value["start_line"] = json!(file.original_line(1));
+    }
+    value
+}
+
+fn diff_summary_json(summary: &DiffSummary) -> serde_json::Value {
+    let files: Vec<serde_json::Value> = summary
+        .files
+        .iter()
+        .map(|file| {
+            let mut value = json!({
+                "path": file.path.display().to_string(),
+                "status": file.status.to_string(),
+                "insertions": file.insertions,
+                "deletions": file.deletions,
+            });
+            if let Some(old_path) = &file.old_path {
+                value["old_path"] = json!(old_path.display().to_string());
+            }
+            value
+        })
+        .collect();
+    json!({
+        "base": summary.base,
+        "insertions": summary.insertions(),
+        "deletions": summary.deletions(),
+        "files": files,
+    })
+}
+
+fn omitted_json(file: &OmittedFile) -> serde_json::Value {
+    json!({ "path": file.path.display().to_string(), "tokens": file.tokens })
+}
+
+fn skipped_json(file: &SkippedFile) -> serde_json::Value {
+    json!({ "path": file.path.display().to_string(), "reason": file.reason.to_string() })
+}
+
+fn renamed_json(file: &RenamedFile) -> serde_json::Value {
+    json!({ "from": file.from.display().to_string(), "to": file.to.display().to_string() })
+}
+
+/// Writes a `"key": [...]` entry of the top-level JSON object, one value per line.
+fn write_json_list(out: &mut dyn Write, key: &str, values: impl Iterator<Item = serde_json::Value>) -> io::Result<()> {
+    write!(out, ",\n  {}: [", json!(key))?;
+    let mut is_empty = true;
+    for (index, value) in values.enumerate() {
+        let separator = if index == 0 { "\n" } else { ",\n" };
+        write!(out, "{}    {}", separator, value)?;
+        is_empty = false;
+    }
+    if !is_empty {
+        out.write_all(b"\n  ")?;
+    }
+    out.write_all(b"]")
+}
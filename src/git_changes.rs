@@ -0,0 +1,419 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use anyhow::{Result, Context};
+use git2::{Delta, Diff, DiffFindOptions, DiffOptions, Patch, Repository, Tree};
+use crate::aggregator::FileContent;
+
+/// Which changes in the git repository select the files to aggregate.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ChangeSelection {
+    /// Files changed since the merge base of the given revision and `HEAD`, including
+    /// uncommitted changes to tracked files.
+    Since(String),
+    /// Files with changes staged in the index.
+    Staged,
+    /// Files with staged or unstaged changes, and untracked files.
+    Uncommitted,
+}
+
+/// A file that was renamed, with both paths relative to the input directory.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RenamedFile {
+    pub from: PathBuf,
+    pub to: PathBuf,
+}
+
+/// The changes that can't be shown as file contents, listed in a summary section.
+///
+/// - `deleted`: The deleted files, relative to the input directory.
+/// - `renamed`: The renamed files. Their content is aggregated under the new path.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ChangeSummary {
+    pub deleted: Vec<PathBuf>,
+    pub renamed: Vec<RenamedFile>,
+}
+
+impl ChangeSummary {
+    /// Returns `true` if no files were deleted or renamed.
+    pub fn is_empty(&self) -> bool {
+        self.deleted.is_empty() && self.renamed.is_empty()
+    }
+
+    /// Keeps only the deleted and renamed files for which `keep` returns `true`. Renamed
+    /// files are kept by their new path.
+    pub fn retain(&mut self, mut keep: impl FnMut(&Path) -> bool) {
+        self.deleted.retain(|path| keep(path));
+        self.renamed.retain(|file| keep(&file.to));
+    }
+}
+
+/// The files selected by a `ChangeSelection`.
+///
+/// - `changed`: The added, modified and renamed files that still exist, as paths under the
+///   input directory in the same form `list_files` returns them.
+/// - `summary`: The deleted and renamed files.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct GitChanges {
+    pub changed: HashSet<PathBuf>,
+    pub summary: ChangeSummary,
+}
+
+/// Reads the changes selected by `selection` from the git repository containing the input
+/// directory.
+///
+/// The repository is read with libgit2, so no `git` binary is needed. Changes outside the
+/// input directory are ignored, and renames are detected by content similarity.
+///
+/// # Arguments
+///
+/// * `input_dir` - A reference to the `Path` of the input directory.
+/// * `selection` - A reference to the `ChangeSelection` to read.
+///
+/// # Returns
+///
+/// Returns the `GitChanges`, with paths under or relative to `input_dir`.
+///
+/// # Errors
+///
+/// This function will return an error if `input_dir` is not inside a git repository with a
+/// working directory, if the revision of `ChangeSelection::Since` can't be resolved, or if
+/// reading the repository fails.
+pub fn collect_changes(input_dir: &Path, selection: &ChangeSelection) -> Result<GitChanges> {
+    let repo = open_repository(input_dir)?;
+    let relative_to_input = path_mapper(&repo, input_dir)?;
+    let diff = changes_diff(&repo, selection, &mut DiffOptions::new())?;
+
+    let mut changes = GitChanges::default();
+    for delta in diff.deltas() {
+        let old_path = relative_to_input(delta.old_file().path());
+        let new_path = relative_to_input(delta.new_file().path());
+        match delta.status() {
+            Delta::Deleted => changes.summary.deleted.extend(old_path),
+            Delta::Renamed => {
+                if let Some(to) = new_path {
+                    changes.changed.insert(input_dir.join(&to));
+                    if let Some(from) = old_path {
+                        changes.summary.renamed.push(RenamedFile { from, to });
+                    }
+                }
+            }
+            Delta::Added | Delta::Modified | Delta::Copied | Delta::Typechange | Delta::Untracked => {
+                changes.changed.extend(new_path.map(|path| input_dir.join(path)));
+            }
+            _ => {}
+        }
+    }
+    changes.summary.deleted.sort();
+    changes.summary.renamed.sort_by(|a, b| a.to.cmp(&b.to));
+    Ok(changes)
+}
+
+/// Opens the git repository containing the input directory.
+pub(crate) fn open_repository(input_dir: &Path) -> Result<Repository> {
+    Repository::discover(input_dir)
+        .with_context(|| format!("{} is not inside a git repository", input_dir.display()))
+}
+
+/// Returns a function mapping paths in a diff, which are relative to the working directory,
+/// to paths relative to the input directory. Paths outside the input directory map to `None`.
+fn path_mapper(repo: &Repository, input_dir: &Path) -> Result<impl Fn(Option<&Path>) -> Option<PathBuf>> {
+    let workdir = repo.workdir().context("Git-aware mode needs a repository with a working directory")?;
+    let workdir = fs::canonicalize(workdir)?;
+    let canonical_input = fs::canonicalize(input_dir)?;
+    Ok(move |path: Option<&Path>| {
+        path.and_then(|path| workdir.join(path).strip_prefix(&canonical_input).ok().map(Path::to_path_buf))
+    })
+}
+
+/// Computes the diff holding the changes selected by `selection`, with renames detected.
+fn changes_diff<'repo>(repo: &'repo Repository, selection: &ChangeSelection, options: &mut DiffOptions) -> Result<Diff<'repo>> {
+    let mut diff = match selection {
+        ChangeSelection::Since(revision) => {
+            let base = repo
+                .revparse_single(revision)
+                .and_then(|object| object.peel_to_commit())
+                .with_context(|| format!("Unknown git revision '{}'", revision))?;
+            let base_tree = match repo.head().and_then(|head| head.peel_to_commit()) {
+                Ok(head) => repo.find_commit(repo.merge_base(base.id(), head.id())?)?.tree()?,
+                Err(_) => base.tree()?,
+            };
+            repo.diff_tree_to_workdir_with_index(Some(&base_tree), Some(options))?
+        }
+        ChangeSelection::Staged => repo.diff_tree_to_index(head_tree(repo).as_ref(), None, Some(options))?,
+        ChangeSelection::Uncommitted => {
+            options.include_untracked(true).recurse_untracked_dirs(true);
+            repo.diff_tree_to_workdir_with_index(head_tree(repo).as_ref(), Some(options))?
+        }
+    };
+    diff.find_similar(Some(DiffFindOptions::new().renames(true).for_untracked(true)))?;
+    Ok(diff)
+}
+
+/// Returns the tree of `HEAD`, or `None` if the repository has no commits yet.
+fn head_tree(repo: &Repository) -> Option<Tree<'_>> {
+    repo.head().and_then(|head| head.peel_to_tree()).ok()
+}
+
+/// How a file changed in a diff.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChangeStatus {
+    Added,
+    Modified,
+    Deleted,
+    Renamed,
+}
+
+impl fmt::Display for ChangeStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ChangeStatus::Added => write!(f, "added"),
+            ChangeStatus::Modified => write!(f, "modified"),
+            ChangeStatus::Deleted => write!(f, "deleted"),
+            ChangeStatus::Renamed => write!(f, "renamed"),
+        }
+    }
+}
+
+/// The number of lines a diff inserts and deletes in a single file.
+///
+/// - `path`: The path of the file, relative to the input directory. For a deleted file,
+///   this is the path it was deleted from.
+/// - `old_path`: The previous path of a renamed file.
+/// - `status`: How the file changed.
+/// - `insertions`: The number of inserted lines.
+/// - `deletions`: The number of deleted lines.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DiffStat {
+    pub path: PathBuf,
+    pub old_path: Option<PathBuf>,
+    pub status: ChangeStatus,
+    pub insertions: usize,
+    pub deletions: usize,
+}
+
+/// The summary of a diff, written at the top of the output in diff mode.
+///
+/// - `base`: The revision the diff was taken against.
+/// - `files`: The insertions and deletions of each changed file, sorted by path.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DiffSummary {
+    pub base: String,
+    pub files: Vec<DiffStat>,
+}
+
+impl DiffSummary {
+    /// Returns the total number of inserted lines.
+    pub fn insertions(&self) -> usize {
+        self.files.iter().map(|file| file.insertions).sum()
+    }
+
+    /// Returns the total number of deleted lines.
+    pub fn deletions(&self) -> usize {
+        self.files.iter().map(|file| file.deletions).sum()
+    }
+}
+
+/// A hunk of a unified diff.
+///
+/// - `old_start`, `old_lines`: The range of lines the hunk covers before the change.
+/// - `new_start`, `new_lines`: The range of lines the hunk covers after the change.
+/// - `header`: The `@@ -a,b +c,d @@` line of the hunk, including any function context.
+/// - `lines`: The lines of the hunk, each starting with ` `, `+` or `-`, without their newline.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DiffHunk {
+    pub old_start: u32,
+    pub old_lines: u32,
+    pub new_start: u32,
+    pub new_lines: u32,
+    pub header: String,
+    pub lines: Vec<String>,
+}
+
+/// The diff of a single file, shown instead of or along with its content in diff mode.
+///
+/// - `insertions`: The number of inserted lines.
+/// - `deletions`: The number of deleted lines.
+/// - `hunks`: The hunks of the diff.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FileDiff {
+    pub insertions: usize,
+    pub deletions: usize,
+    pub hunks: Vec<DiffHunk>,
+}
+
+impl FileDiff {
+    /// Returns the diff as unified diff text, without the file header lines.
+    pub fn unified(&self) -> String {
+        let mut text = String::new();
+        for hunk in &self.hunks {
+            text.push_str(&hunk.header);
+            text.push('\n');
+            for line in &hunk.lines {
+                text.push_str(line);
+                text.push('\n');
+            }
+        }
+        text
+    }
+}
+
+/// The diffs of the files changed since a revision.
+///
+/// - `summary`: The insertions and deletions of every changed file, including deleted files.
+/// - `files`: The diff of each file that still exists, by path relative to the input directory.
+#[derive(Debug, Clone, Default)]
+pub struct Diffs {
+    pub summary: DiffSummary,
+    pub files: HashMap<PathBuf, FileDiff>,
+}
+
+impl Diffs {
+    /// Returns the files the diffs select, the same as `collect_changes` returns for
+    /// `ChangeSelection::Since` with the same base, so the repository is only diffed once.
+    ///
+    /// # Arguments
+    ///
+    /// * `input_dir` - A reference to the `Path` of the input directory.
+    pub fn changes(&self, input_dir: &Path) -> GitChanges {
+        let mut changes = GitChanges::default();
+        for stat in &self.summary.files {
+            match (stat.status, &stat.old_path) {
+                (ChangeStatus::Deleted, _) => changes.summary.deleted.push(stat.path.clone()),
+                (ChangeStatus::Renamed, Some(from)) => {
+                    changes.changed.insert(input_dir.join(&stat.path));
+                    changes.summary.renamed.push(RenamedFile { from: from.clone(), to: stat.path.clone() });
+                }
+                _ => {
+                    changes.changed.insert(input_dir.join(&stat.path));
+                }
+            }
+        }
+        changes
+    }
+
+    /// Keeps only the changed files for which `keep` returns `true`, by path relative to the
+    /// input directory.
+    pub fn retain(&mut self, mut keep: impl FnMut(&Path) -> bool) {
+        self.summary.files.retain(|stat| keep(&stat.path));
+        self.files.retain(|path, _| keep(path));
+    }
+}
+
+/// Computes the diff of each file changed since the merge base of `base` and `HEAD`,
+/// including uncommitted changes to tracked files, in the same way as
+/// `ChangeSelection::Since`.
+///
+/// # Arguments
+///
+/// * `input_dir` - A reference to the `Path` of the input directory.
+/// * `base` - The revision to diff against, e.g. `main`.
+/// * `context_lines` - The number of unchanged lines shown around each change.
+///
+/// # Returns
+///
+/// Returns the `Diffs` of the files under `input_dir`.
+///
+/// # Errors
+///
+/// This function will return an error if `input_dir` is not inside a git repository with a
+/// working directory, if `base` can't be resolved, or if reading the repository fails.
+pub fn collect_diffs(input_dir: &Path, base: &str, context_lines: u32) -> Result<Diffs> {
+    let repo = open_repository(input_dir)?;
+    let relative_to_input = path_mapper(&repo, input_dir)?;
+    let selection = ChangeSelection::Since(base.to_string());
+    let diff = changes_diff(&repo, &selection, DiffOptions::new().context_lines(context_lines))?;
+
+    let mut diffs = Diffs { summary: DiffSummary { base: base.to_string(), files: Vec::new() }, files: HashMap::new() };
+    for (index, delta) in diff.deltas().enumerate() {
+        let status = match delta.status() {
+            Delta::Added | Delta::Untracked | Delta::Copied => ChangeStatus::Added,
+            Delta::Modified | Delta::Typechange => ChangeStatus::Modified,
+            Delta::Deleted => ChangeStatus::Deleted,
+            Delta::Renamed => ChangeStatus::Renamed,
+            _ => continue,
+        };
+        let old_path = relative_to_input(delta.old_file().path());
+        let path = match status {
+            ChangeStatus::Deleted => old_path.clone(),
+            _ => relative_to_input(delta.new_file().path()),
+        };
+        let Some(path) = path else { continue };
+
+        let file_diff = match Patch::from_diff(&diff, index)? {
+            Some(patch) => file_diff(&patch)?,
+            None => FileDiff::default(),
+        };
+        diffs.summary.files.push(DiffStat {
+            path: path.clone(),
+            old_path: old_path.filter(|_| status == ChangeStatus::Renamed),
+            status,
+            insertions: file_diff.insertions,
+            deletions: file_diff.deletions,
+        });
+        if status != ChangeStatus::Deleted {
+            diffs.files.insert(path, file_diff);
+        }
+    }
+    diffs.summary.files.sort_by(|a, b| a.path.cmp(&b.path));
+    Ok(diffs)
+}
+
+/// Converts a libgit2 patch into a `FileDiff`.
+fn file_diff(patch: &Patch) -> Result<FileDiff> {
+    let (_, insertions, deletions) = patch.line_stats()?;
+    let mut hunks = Vec::with_capacity(patch.num_hunks());
+    for hunk_index in 0..patch.num_hunks() {
+        let (hunk, line_count) = patch.hunk(hunk_index)?;
+        let mut lines = Vec::with_capacity(line_count);
+        for line_index in 0..line_count {
+            let line = patch.line_in_hunk(hunk_index, line_index)?;
+            let content = String::from_utf8_lossy(line.content());
+            match line.origin() {
+                origin @ (' ' | '+' | '-') => lines.push(format!("{}{}", origin, content.trim_end_matches(['\n', '\r']))),
+                // The "no newline at end of file" markers
+                '=' | '>' | '<' => lines.push(content.trim_matches(['\n', '\r']).to_string()),
+                _ => {}
+            }
+        }
+        hunks.push(DiffHunk {
+            old_start: hunk.old_start(),
+            old_lines: hunk.old_lines(),
+            new_start: hunk.new_start(),
+            new_lines: hunk.new_lines(),
+            header: String::from_utf8_lossy(hunk.header()).trim_end().to_string(),
+            lines,
+        });
+    }
+    Ok(FileDiff { insertions, deletions, hunks })
+}
+
+/// Attaches the diff of each changed file to its aggregated content.
+///
+/// Only files smaller than `full_content_max_size` keep their full content along with
+/// their diff; the others are shown as a diff only.
+///
+/// # Arguments
+///
+/// * `contents` - The aggregated files, with paths relative to the input directory.
+/// * `diffs` - The diff of each changed file, as returned in `Diffs::files`.
+/// * `full_content_max_size` - The size in bytes up to which a file keeps its full content.
+pub fn attach_diffs(contents: &mut [FileContent], mut diffs: HashMap<PathBuf, FileDiff>, full_content_max_size: Option<u64>) {
+    for file in contents {
+        if let Some(diff) = diffs.remove(&file.path) {
+            attach_diff(file, diff, full_content_max_size);
+        }
+    }
+}
+
+/// Attaches the diff of a changed file to its aggregated content, like `attach_diffs` does
+/// for each file.
+pub fn attach_diff(file: &mut FileContent, diff: FileDiff, full_content_max_size: Option<u64>) {
+    if full_content_max_size.is_none_or(|max_size| file.content.len() as u64 > max_size) {
+        // The secrets redacted from the content are no longer part of the output
+        file.content.clear();
+        file.redactions.clear();
+    }
+    file.diff = Some(diff);
+}
@@ -0,0 +1,154 @@
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+use anyhow::Result;
+use clap::ValueEnum;
+use git2::{Repository, Status, StatusOptions};
+use serde::{Deserialize, Serialize};
+use crate::aggregator::FileContent;
+use crate::git_changes::open_repository;
+
+/// The file mode git records for a submodule in the index.
+const GITLINK_MODE: u32 = 0o160000;
+
+/// How submodules are handled when only tracked files are aggregated.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize, Serialize, ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum SubmoduleMode {
+    /// List each submodule as a stub with its URL and commit, without its files.
+    #[default]
+    Stub,
+    /// Include the tracked files of each checked-out submodule. Submodules that aren't
+    /// checked out are listed as stubs.
+    Recurse,
+}
+
+/// Options controlling which files `list_tracked_files` returns.
+///
+/// - `include_untracked`: Whether to also return untracked files that aren't ignored.
+/// - `submodules`: How to handle submodules.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TrackedOptions {
+    pub include_untracked: bool,
+    pub submodules: SubmoduleMode,
+}
+
+/// A submodule listed as a stub instead of its files.
+///
+/// - `path`: The path of the submodule, relative to the input directory.
+/// - `url`: The URL of the submodule, if it is configured in `.gitmodules`.
+/// - `commit`: The commit of the submodule recorded in the index.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SubmoduleStub {
+    pub path: PathBuf,
+    pub url: Option<String>,
+    pub commit: String,
+}
+
+impl SubmoduleStub {
+    /// Returns the stub as a `FileContent`, so it is listed with the files in the output.
+    pub fn to_file_content(&self) -> FileContent {
+        let url = self.url.as_deref().unwrap_or("unknown");
+        FileContent::new(&self.path, format!("Git submodule\nURL: {}\nCommit: {}", url, self.commit))
+    }
+}
+
+/// The files tracked by git under the input directory.
+///
+/// - `files`: The tracked files that exist in the working directory, as paths under the
+///   input directory in the same form `list_files` returns them.
+/// - `submodules`: The submodules listed as stubs, sorted by path.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TrackedFiles {
+    pub files: HashSet<PathBuf>,
+    pub submodules: Vec<SubmoduleStub>,
+}
+
+/// Lists the files in the git index of the repository containing the input directory.
+///
+/// This is used instead of `list_files` in tracked-only mode, so build artifacts and other
+/// untracked files never need to be ignored by patterns. Files in the index that were
+/// deleted from the working directory are left out.
+///
+/// # Arguments
+///
+/// * `input_dir` - A reference to the `Path` of the input directory.
+/// * `options` - A reference to the `TrackedOptions` to use.
+///
+/// # Returns
+///
+/// Returns the `TrackedFiles` under `input_dir`.
+///
+/// # Errors
+///
+/// This function will return an error if `input_dir` is not inside a git repository with a
+/// working directory, or if reading the repository fails.
+pub fn list_tracked_files(input_dir: &Path, options: &TrackedOptions) -> Result<TrackedFiles> {
+    let repo = open_repository(input_dir)?;
+    let canonical_input = fs::canonicalize(input_dir)?;
+
+    let mut files = Vec::new();
+    let mut submodules = Vec::new();
+    collect_repository(&repo, options, &mut files, &mut submodules)?;
+
+    // Keep the paths under the input directory, in the form list_files returns them
+    let under_input = |path: &Path| path.strip_prefix(&canonical_input).ok().map(Path::to_path_buf);
+    let mut tracked = TrackedFiles {
+        files: files.iter().filter_map(|path| under_input(path)).map(|path| input_dir.join(path)).collect(),
+        submodules: submodules
+            .into_iter()
+            .filter_map(|stub| Some(SubmoduleStub { path: under_input(&stub.path)?, ..stub }))
+            .collect(),
+    };
+    tracked.submodules.sort_by(|a, b| a.path.cmp(&b.path));
+    Ok(tracked)
+}
+
+/// Collects the tracked files of a repository, and of its submodules when recursing, as
+/// canonical absolute paths. Stubs are collected with their canonical absolute path.
+fn collect_repository(
+    repo: &Repository,
+    options: &TrackedOptions,
+    files: &mut Vec<PathBuf>,
+    submodules: &mut Vec<SubmoduleStub>,
+) -> Result<()> {
+    let Some(workdir) = repo.workdir() else { return Ok(()) };
+    let workdir = fs::canonicalize(workdir)?;
+
+    for entry in repo.index()?.iter() {
+        let relative_path = PathBuf::from(String::from_utf8_lossy(&entry.path).into_owned());
+        let path = workdir.join(&relative_path);
+        if entry.mode != GITLINK_MODE {
+            if path.is_file() {
+                files.push(path);
+            }
+            continue;
+        }
+
+        let submodule = repo.find_submodule(&relative_path.to_string_lossy()).ok();
+        if options.submodules == SubmoduleMode::Recurse {
+            if let Some(submodule_repo) = submodule.as_ref().and_then(|submodule| submodule.open().ok()) {
+                collect_repository(&submodule_repo, options, files, submodules)?;
+                continue;
+            }
+        }
+        submodules.push(SubmoduleStub {
+            path,
+            url: submodule.as_ref().and_then(|submodule| submodule.url().map(str::to_string)),
+            commit: entry.id.to_string(),
+        });
+    }
+
+    if options.include_untracked {
+        let mut status_options = StatusOptions::new();
+        status_options.include_untracked(true).recurse_untracked_dirs(true).exclude_submodules(true);
+        for status in repo.statuses(Some(&mut status_options))?.iter() {
+            if status.status().contains(Status::WT_NEW) {
+                if let Some(path) = status.path() {
+                    files.push(workdir.join(path));
+                }
+            }
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,251 @@
+use std::collections::{HashMap, HashSet};
+use std::ffi::OsStr;
+use std::fs;
+use std::path::{Path, PathBuf};
+use ignore::gitignore::{Glob, Gitignore, GitignoreBuilder};
+use ignore::Match;
+
+/// The per-directory ignore files that are honored, in order of precedence.
+///
+/// `.ignore` files take precedence over `.gitignore` files in the same directory,
+/// matching the behaviour of tools such as ripgrep and fd.
+const IGNORE_FILE_NAMES: [&str; 2] = [".ignore", ".gitignore"];
+
+/// Matches paths against the ignore files git itself would honor.
+///
+/// This includes:
+/// - The `.gitignore` and `.ignore` files of the directories inside the input directory that
+///   hold the files to match, as well as those in parent directories up to the root of the
+///   enclosing repository.
+/// - The repository's `.git/info/exclude` file.
+/// - The user's global excludes file (`core.excludesFile`, or `$XDG_CONFIG_HOME/git/ignore`).
+///
+/// Matching follows gitignore semantics: patterns are anchored to the directory of the file
+/// that declares them, `!` negates an earlier match, a trailing `/` only matches directories,
+/// and rules in deeper directories take precedence over rules in their parents.
+pub struct GitIgnoreMatcher {
+    root: PathBuf,
+    dir_matchers: HashMap<PathBuf, Vec<Gitignore>>,
+    repo_excludes: Option<Gitignore>,
+    global_excludes: Option<Gitignore>,
+}
+
+impl GitIgnoreMatcher {
+    /// Creates a new `GitIgnoreMatcher` for the given input directory.
+    ///
+    /// This method lists the files of the input directory tree, skipping the directories that
+    /// are ignored as a whole, and loads their ignore files as `for_files` does.
+    ///
+    /// # Arguments
+    ///
+    /// * `input_dir` - A reference to the `Path` representing the directory being aggregated.
+    ///
+    /// # Returns
+    ///
+    /// Returns a new `GitIgnoreMatcher`. Ignore files that cannot be read or parsed are skipped
+    /// with a warning, mirroring how git treats them.
+    pub fn new(input_dir: &Path) -> Self {
+        let files = crate::file_system_ops::list_files_pruned(input_dir, true, |dir| dir.file_name() == Some(OsStr::new(".git")))
+            .unwrap_or_default();
+        Self::for_files(input_dir, &files)
+    }
+
+    /// Creates a new `GitIgnoreMatcher` able to match the given files of the input directory.
+    ///
+    /// This method locates the enclosing git repository (if any), then loads the ignore files
+    /// found in the directories of the files, up to the input directory, and in every directory
+    /// between it and the repository root. The input directory is not walked again.
+    ///
+    /// # Arguments
+    ///
+    /// * `input_dir` - A reference to the `Path` representing the directory being aggregated.
+    /// * `files` - The files that will be matched, e.g. as listed by `list_files`.
+    ///
+    /// # Returns
+    ///
+    /// Returns a new `GitIgnoreMatcher`. Ignore files that cannot be read or parsed are skipped
+    /// with a warning, mirroring how git treats them.
+    pub fn for_files<'a>(input_dir: &Path, files: impl IntoIterator<Item = &'a PathBuf>) -> Self {
+        let input_dir = std::path::absolute(input_dir).unwrap_or_else(|_| input_dir.to_path_buf());
+        let repo_root = find_repo_root(&input_dir);
+        let root = repo_root.clone().unwrap_or_else(|| input_dir.clone());
+
+        let mut dir_matchers = HashMap::new();
+
+        // Ignore files between the repository root and the input directory still apply
+        for dir in input_dir.ancestors().skip(1) {
+            if !dir.starts_with(&root) {
+                break;
+            }
+            load_dir_matchers(dir, &mut dir_matchers);
+        }
+
+        let mut loaded_dirs = HashSet::new();
+        for file in files {
+            let file = std::path::absolute(file).unwrap_or_else(|_| file.clone());
+            for dir in file.ancestors().skip(1) {
+                if !dir.starts_with(&input_dir) || !loaded_dirs.insert(dir.to_path_buf()) {
+                    break;
+                }
+                load_dir_matchers(dir, &mut dir_matchers);
+            }
+        }
+
+        let repo_excludes = repo_root
+            .as_ref()
+            .and_then(|repo_root| git_dir(repo_root))
+            .map(|git_dir| git_dir.join("info").join("exclude"))
+            .filter(|exclude| exclude.is_file())
+            .and_then(|exclude| build_matcher(&root, &exclude));
+
+        let (global, err) = GitignoreBuilder::new(&root).build_global();
+        if let Some(err) = err {
+            crate::warning!("Failed to load global git excludes: {}", err);
+        }
+        let global_excludes = if global.is_empty() { None } else { Some(global) };
+
+        GitIgnoreMatcher { root, dir_matchers, repo_excludes, global_excludes }
+    }
+
+    /// Determines whether a path is ignored by git's ignore rules.
+    ///
+    /// A path is ignored if any of its parent directories is ignored, or if the path itself
+    /// is ignored. As in git, a negated pattern cannot re-include a file whose parent
+    /// directory has been excluded.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - A reference to the `Path` of the file to check.
+    ///
+    /// # Returns
+    ///
+    /// Returns `true` if the path should be ignored, `false` otherwise.
+    pub fn is_ignored(&self, path: &Path) -> bool {
+        self.deciding_rule(path).is_some_and(|rule| rule.ignored)
+    }
+
+    /// Returns the rule deciding whether a path is ignored, if any rule matches it.
+    ///
+    /// This is the rule ignoring the first ignored parent directory of the path if there is
+    /// one, and the highest precedence rule matching the path itself otherwise.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - A reference to the `Path` of the file to check.
+    ///
+    /// # Returns
+    ///
+    /// Returns the deciding `GitIgnoreRule`, or `None` if no rule matches the path or its parents.
+    pub fn deciding_rule(&self, path: &Path) -> Option<GitIgnoreRule<'_>> {
+        let path = std::path::absolute(path).unwrap_or_else(|_| path.to_path_buf());
+        let path = path.as_path();
+        if !path.starts_with(&self.root) {
+            return None;
+        }
+        let mut parents: Vec<&Path> = path
+            .ancestors()
+            .skip(1)
+            .take_while(|dir| *dir != self.root)
+            .collect();
+        parents.reverse();
+        for dir in parents {
+            if let Match::Ignore(glob) = self.matched(dir, true) {
+                return Some(GitIgnoreRule::new(glob, Some(dir)));
+            }
+        }
+        match self.matched(path, false) {
+            Match::None => None,
+            Match::Ignore(glob) | Match::Whitelist(glob) => Some(GitIgnoreRule::new(glob, None)),
+        }
+    }
+
+    /// Returns the highest precedence rule matching the path itself.
+    fn matched(&self, path: &Path, is_dir: bool) -> Match<&Glob> {
+        for dir in path.ancestors().skip(1) {
+            if let Some(matchers) = self.dir_matchers.get(dir) {
+                for matcher in matchers {
+                    let matched = matcher.matched(path, is_dir);
+                    if !matched.is_none() {
+                        return matched;
+                    }
+                }
+            }
+            if dir == self.root {
+                break;
+            }
+        }
+        for matcher in self.repo_excludes.iter().chain(self.global_excludes.iter()) {
+            let matched = matcher.matched(path, is_dir);
+            if !matched.is_none() {
+                return matched;
+            }
+        }
+        Match::None
+    }
+}
+
+/// A git ignore rule matching a path.
+///
+/// - `pattern`: The pattern as written in the ignore file, e.g. `/target` or `!keep.log`.
+/// - `source`: The ignore file declaring the pattern, if known.
+/// - `ignored`: Whether the rule ignores the path, rather than re-including it with `!`.
+/// - `parent`: The parent directory the rule matched, if it ignored a parent of the path
+///   rather than the path itself.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GitIgnoreRule<'a> {
+    pub pattern: &'a str,
+    pub source: Option<&'a Path>,
+    pub ignored: bool,
+    pub parent: Option<PathBuf>,
+}
+
+impl<'a> GitIgnoreRule<'a> {
+    fn new(glob: &'a Glob, parent: Option<&Path>) -> Self {
+        GitIgnoreRule {
+            pattern: glob.original(),
+            source: glob.from(),
+            ignored: !glob.is_whitelist(),
+            parent: parent.map(Path::to_path_buf),
+        }
+    }
+}
+
+/// Finds the root of the git repository containing `dir`, if any.
+fn find_repo_root(dir: &Path) -> Option<PathBuf> {
+    dir.ancestors()
+        .find(|ancestor| ancestor.join(".git").exists())
+        .map(Path::to_path_buf)
+}
+
+/// Resolves the git directory of a repository, following `gitdir:` files used by worktrees.
+fn git_dir(repo_root: &Path) -> Option<PathBuf> {
+    let dot_git = repo_root.join(".git");
+    if dot_git.is_dir() {
+        return Some(dot_git);
+    }
+    let contents = fs::read_to_string(&dot_git).ok()?;
+    let git_dir = PathBuf::from(contents.strip_prefix("gitdir:")?.trim());
+    Some(if git_dir.is_absolute() { git_dir } else { repo_root.join(git_dir) })
+}
+
+/// Loads the ignore files declared directly in `dir`, if any.
+fn load_dir_matchers(dir: &Path, dir_matchers: &mut HashMap<PathBuf, Vec<Gitignore>>) {
+    let matchers: Vec<Gitignore> = IGNORE_FILE_NAMES
+        .iter()
+        .map(|name| dir.join(name))
+        .filter(|file| file.is_file())
+        .filter_map(|file| build_matcher(dir, &file))
+        .collect();
+    if !matchers.is_empty() {
+        dir_matchers.insert(dir.to_path_buf(), matchers);
+    }
+}
+
+/// Builds a matcher for a single ignore file, anchored at `root`.
+fn build_matcher(root: &Path, file: &Path) -> Option<Gitignore> {
+    let mut builder = GitignoreBuilder::new(root);
+    if let Some(err) = builder.add(file) {
+        crate::warning!("Failed to parse ignore file {:?}: {}", file, err);
+    }
+    builder.build().ok()
+}
@@ -1,18 +1,27 @@
 use std::path::{PathBuf, Path};
 use glob::Pattern;
+use anyhow::{Context, Result};
 use crate::config::Config;
+use crate::file_system_ops::list_files_pruned;
+use crate::gitignore::GitIgnoreMatcher;
 use std::collections::HashSet;
+use std::fmt;
 
 /// Represents a set of rules for ignoring files and directories.
 ///
-/// This struct contains three types of rules:
+/// This struct contains four types of rules:
 /// - `rules`: A list of patterns for files and directories to ignore.
 /// - `include_hidden`: A list of patterns for hidden files or directories to include, despite being hidden.
 /// - `ignore_hidden`: A pattern to match all hidden files and directories.
+/// - `gitignore`: An optional matcher for `.gitignore`, `.ignore` and git exclude files.
+///
+/// `rule_sources` holds where each rule came from: a list in the configuration or a built-in preset.
 pub struct IgnoreRules {
     pub rules: Vec<Pattern>,
+    pub rule_sources: Vec<PatternSource>,
     pub include_hidden: Vec<Pattern>,
     pub ignore_hidden: Pattern,
+    pub gitignore: Option<GitIgnoreMatcher>,
 }
 
 impl IgnoreRules {
@@ -28,24 +37,156 @@ impl IgnoreRules {
     /// # Returns
     ///
     /// Returns a new `IgnoreRules` instance with the processed patterns.
-    pub fn new(config: &Config) -> Self {
-        let patterns = config.get_ignore_patterns();
-        let rules: Vec<Pattern> = patterns.into_iter()
-            .filter(|p| p != ".*")  // Filter out the ".*" pattern
-            .map(|r| Pattern::new(&r).expect("Invalid ignore pattern"))
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error naming the first pattern that is not a valid glob.
+    /// `config_validation::validate_config` reports every invalid pattern with its location.
+    pub fn new(config: &Config) -> Result<Self> {
+        let compile = |pattern: &str| {
+            Pattern::new(pattern).with_context(|| format!("Invalid glob pattern '{}'", pattern))
+        };
+        // The same patterns as `Config::get_ignore_patterns`, whose ".*" is handled by `ignore_hidden`
+        let mut keyed_patterns: Vec<(PatternSource, String)> = config.ignore_patterns.iter()
+            .enumerate()
+            .map(|(index, pattern)| (PatternSource::Config("ignore_patterns".to_string(), index), pattern.clone()))
             .collect();
+        for active in config.active_presets() {
+            for root in &active.roots {
+                let patterns = active.preset.ignores_under(root).into_iter().enumerate();
+                keyed_patterns.extend(patterns.map(|(index, pattern)| (PatternSource::Preset(active.preset.name, index), pattern)));
+            }
+        }
+        for project_type in config.active_project_types() {
+            if let Some(specific_ignores) = config.project_specific_ignores.get(&project_type) {
+                let list = format!("project_specific_ignores.{}", project_type);
+                keyed_patterns.extend(specific_ignores.iter().enumerate().map(|(index, pattern)| (PatternSource::Config(list.clone(), index), pattern.clone())));
+            }
+        }
+        keyed_patterns.retain(|(_, pattern)| pattern != ".*");
+        let rules: Vec<Pattern> = keyed_patterns.iter()
+            .map(|(_, pattern)| compile(pattern))
+            .collect::<Result<_>>()?;
+        let rule_sources = keyed_patterns.into_iter().map(|(source, _)| source).collect();
         let include_hidden: Vec<Pattern> = config.include_hidden_patterns.iter()
-            .map(|p| Pattern::new(p).expect("Invalid include hidden pattern"))
-            .collect();
+            .map(|pattern| compile(pattern))
+            .collect::<Result<_>>()?;
         let ignore_hidden = Pattern::new(".*").expect("Invalid ignore hidden pattern");
-        IgnoreRules { rules, include_hidden, ignore_hidden }
+        Ok(IgnoreRules { rules, rule_sources, include_hidden, ignore_hidden, gitignore: None })
+    }
+
+    /// Adds git's ignore rules for the given input directory to this `IgnoreRules` instance.
+    ///
+    /// # Arguments
+    ///
+    /// * `input_dir` - A reference to the `Path` representing the base input directory.
+    ///
+    /// # Returns
+    ///
+    /// Returns the `IgnoreRules` instance with a `GitIgnoreMatcher` attached.
+    pub fn with_gitignore(mut self, input_dir: &Path) -> Self {
+        self.gitignore = Some(GitIgnoreMatcher::new(input_dir));
+        self
     }
+
+    /// Adds git's ignore rules for the given files of the input directory to this `IgnoreRules`
+    /// instance, without walking the input directory again.
+    ///
+    /// # Arguments
+    ///
+    /// * `input_dir` - A reference to the `Path` representing the base input directory.
+    /// * `files` - The files the rules will be applied to.
+    ///
+    /// # Returns
+    ///
+    /// Returns the `IgnoreRules` instance with a `GitIgnoreMatcher` attached.
+    pub fn with_gitignore_for<'a>(mut self, input_dir: &Path, files: impl IntoIterator<Item = &'a PathBuf>) -> Self {
+        self.gitignore = Some(GitIgnoreMatcher::for_files(input_dir, files));
+        self
+    }
+
+    /// Lists the files in the input directory that the ignore rules may include, without
+    /// walking the directories they exclude as a whole.
+    ///
+    /// A directory is skipped when an ignore pattern ending in `/**` matches it, when a pattern
+    /// ending in `/*` matches it outside the directory overrides, or, with `respect_gitignore`,
+    /// when git's ignore files ignore it, as are the files they ignore. The directory overrides are then walked without
+    /// git's ignore files, and the file overrides are added as they are. The listed files still
+    /// go through `apply_ignore_rules`.
+    ///
+    /// # Arguments
+    ///
+    /// * `include_file_overrides` - A slice of `String`s representing specific files to include.
+    /// * `include_dir_overrides` - A slice of `String`s representing directories to include.
+    /// * `input_dir` - A reference to the `Path` representing the base input directory.
+    /// * `respect_gitignore` - Whether git's ignore files apply.
+    ///
+    /// # Returns
+    ///
+    /// Returns a `HashSet` of the listed files, in the form `list_files` returns them.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if listing the files fails.
+    pub fn list_candidate_files(
+        &self,
+        include_file_overrides: &[String],
+        include_dir_overrides: &[String],
+        input_dir: &Path,
+        respect_gitignore: bool,
+    ) -> Result<HashSet<PathBuf>> {
+        // The part of the patterns before the trailing `/**` or `/*`, matching the directories
+        // whose files they all match
+        let dir_prefixes = |suffix: &str| -> Vec<Pattern> {
+            self.rules.iter()
+                .filter_map(|rule| rule.as_str().strip_suffix(suffix))
+                .filter_map(|prefix| Pattern::new(prefix).ok())
+                .collect()
+        };
+        let tree_rules = dir_prefixes("/**");
+        let dir_rules = dir_prefixes("/*");
+        let dir_overrides: Vec<PathBuf> = include_dir_overrides.iter().map(PathBuf::from).collect();
+
+        let base = input_dir.to_path_buf();
+        let skipped_tree_rules = tree_rules.clone();
+        let mut files = list_files_pruned(input_dir, respect_gitignore, move |dir| {
+            let relative_dir = dir.strip_prefix(&base).unwrap_or(dir);
+            // Directory-level ignores don't apply inside an included directory, or to its parents
+            let overridden = dir_overrides.iter()
+                .any(|dir_override| relative_dir.starts_with(dir_override) || dir_override.starts_with(relative_dir));
+            skipped_tree_rules.iter().any(|rule| rule.matches_path(relative_dir))
+                || (!overridden && dir_rules.iter().any(|rule| rule.matches_path(relative_dir)))
+        })?;
+
+        for dir_override in include_dir_overrides {
+            let base = input_dir.to_path_buf();
+            let tree_rules = tree_rules.clone();
+            let override_files = list_files_pruned(&input_dir.join(dir_override), false, move |dir| {
+                let relative_dir = dir.strip_prefix(&base).unwrap_or(dir);
+                tree_rules.iter().any(|rule| rule.matches_path(relative_dir))
+            })?;
+            files.extend(override_files);
+        }
+        files.extend(include_file_overrides.iter().map(|file| input_dir.join(file)).filter(|file| file.is_file()));
+        Ok(files)
+    }
+}
+
+/// Where an ignore pattern of `IgnoreRules` came from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PatternSource {
+    /// An item of a list in the configuration, as the name of the list and the index, e.g.
+    /// `("project_specific_ignores.rust", 0)`.
+    Config(String, usize),
+    /// A pattern of a built-in preset, as the name of the preset and the index.
+    Preset(&'static str, usize),
 }
 
 /// Applies ignore rules to a set of files, considering file and directory overrides.
 ///
 /// This function filters a set of files based on the provided ignore rules, while also
-/// taking into account any explicitly included files or directories.
+/// taking into account any explicitly included files or directories. Files ignored by git's
+/// ignore rules are dropped as well, unless they are explicitly included by a file or directory override.
 ///
 /// # Arguments
 ///
@@ -68,37 +209,254 @@ pub fn apply_ignore_rules(
 ) -> Vec<PathBuf> {
     files
         .iter()
-        .filter(|file| {
-            let relative_file = file.strip_prefix(input_dir).unwrap_or(file);
-            let file_str = relative_file.to_string_lossy();
-            let file_name = relative_file.file_name().and_then(|s| s.to_str()).unwrap_or("");
-            let is_hidden = ignore_rules.ignore_hidden.matches(file_name);
-            let should_include_hidden = ignore_rules.include_hidden.iter().any(|pattern| pattern.matches(file_name));
-            let should_include_file = include_file_overrides.iter().any(|override_path| {
-                file_str == *override_path
-            });
-            let should_include_dir = include_dir_overrides.iter().any(|dir| {
-                relative_file.starts_with(dir)
-            });
-
-            // Adjust should_ignore to skip directory-level ignores if directory is included
-            let should_ignore = ignore_rules.rules.iter().any(|rule| {
-                let rule_str = rule.as_str();
-                let is_dir_pattern = rule_str.ends_with("/*");
-                if is_dir_pattern && should_include_dir {
-                    // Skip directory-level ignore if directory is included
-                    false
-                } else {
-                    rule.matches_path(relative_file)
-                }
-            });
-
-            if should_include_file {
-                true
-            } else {
-                (!is_hidden || should_include_hidden) && !should_ignore
-            }
-        })
+        .filter(|file| is_included(ignore_rules, file, include_file_overrides, include_dir_overrides, input_dir))
         .cloned()
         .collect()
-}
\ No newline at end of file
+}
+
+/// Checks whether a single file passes the ignore rules, the same way `apply_ignore_rules`
+/// filters a set of files.
+///
+/// # Arguments
+///
+/// * `ignore_rules` - A reference to the `IgnoreRules` struct containing ignore patterns.
+/// * `file` - A reference to the `Path` of the file to check.
+/// * `include_file_overrides` - A slice of `String`s representing specific files to include, overriding ignore rules.
+/// * `include_dir_overrides` - A slice of `String`s representing directories to include, overriding ignore rules.
+/// * `input_dir` - A reference to the `Path` representing the base input directory.
+///
+/// # Returns
+///
+/// Returns `true` if the file should be included.
+pub fn is_included(
+    ignore_rules: &IgnoreRules,
+    file: &Path,
+    include_file_overrides: &[String],
+    include_dir_overrides: &[String],
+    input_dir: &Path,
+) -> bool {
+    let relative_file = file.strip_prefix(input_dir).unwrap_or(file);
+    let file_str = relative_file.to_string_lossy();
+    if include_file_overrides.iter().any(|override_path| file_str == *override_path) {
+        return true;
+    }
+
+    let file_name = relative_file.file_name().and_then(|s| s.to_str()).unwrap_or("");
+    let is_hidden = ignore_rules.ignore_hidden.matches(file_name);
+    if is_hidden && !ignore_rules.include_hidden.iter().any(|pattern| pattern.matches(file_name)) {
+        return false;
+    }
+
+    let should_include_dir = include_dir_overrides.iter().any(|dir| relative_file.starts_with(dir));
+    let should_ignore = ignore_rules.rules.iter().any(|rule| {
+        // Directory-level ignores don't apply inside an included directory
+        let is_dir_pattern = rule.as_str().ends_with("/*");
+        !(is_dir_pattern && should_include_dir) && rule.matches_path(relative_file)
+    });
+    if should_ignore {
+        return false;
+    }
+
+    let is_git_ignored = !should_include_dir && ignore_rules.gitignore.as_ref().is_some_and(|gitignore| {
+        let absolute_file = if file.is_absolute() { file.to_path_buf() } else { input_dir.join(file) };
+        gitignore.is_ignored(&absolute_file)
+    });
+    !is_git_ignored
+}
+
+/// The steps deciding whether a file is included, in order of precedence. The first step
+/// that applies to a file decides.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DecisionStep {
+    /// The file is listed in `include_file_overrides`, so it is included.
+    FileOverride,
+    /// The file is hidden and no `include_hidden_patterns` matches it, so it is excluded.
+    HiddenFile,
+    /// An ignore pattern matches the file, so it is excluded.
+    IgnorePattern,
+    /// Git's ignore files ignore the file, so it is excluded.
+    GitIgnore,
+    /// No rule excludes the file, so it is included.
+    Default,
+}
+
+impl DecisionStep {
+    /// Returns the position of the step in the order of precedence, starting at 1.
+    pub fn number(&self) -> usize {
+        match self {
+            DecisionStep::FileOverride => 1,
+            DecisionStep::HiddenFile => 2,
+            DecisionStep::IgnorePattern => 3,
+            DecisionStep::GitIgnore => 4,
+            DecisionStep::Default => 5,
+        }
+    }
+}
+
+impl fmt::Display for DecisionStep {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            DecisionStep::FileOverride => "file override",
+            DecisionStep::HiddenFile => "hidden file",
+            DecisionStep::IgnorePattern => "ignore pattern",
+            DecisionStep::GitIgnore => "git ignore",
+            DecisionStep::Default => "default",
+        };
+        f.write_str(name)
+    }
+}
+
+/// The kinds of rules evaluated for a file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RuleKind {
+    /// An entry of `include_file_overrides`, matching the exact relative path.
+    FileOverride,
+    /// The built-in `.*` pattern matching hidden files.
+    HiddenFiles,
+    /// An entry of `include_hidden_patterns`, matching the file name.
+    IncludeHidden,
+    /// An entry of `include_dir_overrides`, matching a directory containing the file. It
+    /// disables the ignore patterns ending in `/*` and git's ignore files.
+    DirOverride,
+    /// An entry of `ignore_patterns` or of the project-specific ignores, matching the path.
+    IgnorePattern,
+    /// A pattern of git's ignore files.
+    GitIgnore,
+}
+
+/// Where a rule came from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RuleSource<'a> {
+    /// An item of a list in the configuration, as the name of the list and the index.
+    Config(&'a str, usize),
+    /// A rule built into conag.
+    BuiltIn,
+    /// A pattern of a built-in preset, as the name of the preset and the index.
+    Preset(&'static str, usize),
+    /// A git ignore file, if known.
+    IgnoreFile(Option<&'a Path>),
+}
+
+impl<'a> From<&'a PatternSource> for RuleSource<'a> {
+    fn from(source: &'a PatternSource) -> Self {
+        match source {
+            PatternSource::Config(list, index) => RuleSource::Config(list, *index),
+            PatternSource::Preset(name, index) => RuleSource::Preset(name, *index),
+        }
+    }
+}
+
+/// The outcome of evaluating a rule for a file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RuleOutcome {
+    Matched,
+    NoMatch,
+    /// The git ignore rule matched, but re-includes the file with `!`.
+    Reincluded,
+    /// The rule was not evaluated, because a directory override disables it.
+    Skipped,
+}
+
+/// A rule evaluated for a file.
+///
+/// - `kind`: The kind of rule.
+/// - `pattern`: The pattern of the rule.
+/// - `source`: Where the rule came from.
+/// - `outcome`: Whether the rule matched the file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RuleCheck<'a> {
+    pub kind: RuleKind,
+    pub pattern: &'a str,
+    pub source: RuleSource<'a>,
+    pub outcome: RuleOutcome,
+}
+
+/// Why a file is included or excluded.
+///
+/// - `path`: The path of the file, relative to the input directory.
+/// - `checks`: Every rule evaluated for the file, grouped by the step they belong to.
+/// - `included`: Whether the file is included.
+/// - `step`: The step that decided.
+/// - `deciding_rule`: The index in `checks` of the rule that decided, if a single rule did.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileExplanation<'a> {
+    pub path: PathBuf,
+    pub checks: Vec<RuleCheck<'a>>,
+    pub included: bool,
+    pub step: DecisionStep,
+    pub deciding_rule: Option<usize>,
+}
+
+/// Evaluates every rule deciding whether a file is included, as `apply_ignore_rules` does.
+///
+/// Unlike `apply_ignore_rules`, which stops at the first rule that decides, every rule is
+/// evaluated and recorded, so this is meant for `conag explain` rather than for filtering.
+///
+/// # Arguments
+///
+/// See `apply_ignore_rules`. `file` is the path of the file, absolute or relative to `input_dir`.
+///
+/// # Returns
+///
+/// Returns a `FileExplanation` with every rule evaluated and the decision.
+pub fn explain_file<'a>(
+    ignore_rules: &'a IgnoreRules,
+    file: &Path,
+    include_file_overrides: &'a [String],
+    include_dir_overrides: &'a [String],
+    input_dir: &Path,
+) -> FileExplanation<'a> {
+    let relative_file = file.strip_prefix(input_dir).unwrap_or(file);
+    let file_str = relative_file.to_string_lossy();
+    let file_name = relative_file.file_name().and_then(|s| s.to_str()).unwrap_or("");
+    let outcome = |matched: bool| if matched { RuleOutcome::Matched } else { RuleOutcome::NoMatch };
+    let mut checks = Vec::new();
+
+    for (index, override_path) in include_file_overrides.iter().enumerate() {
+        let source = RuleSource::Config("include_file_overrides", index);
+        checks.push(RuleCheck { kind: RuleKind::FileOverride, pattern: override_path, source, outcome: outcome(file_str == *override_path) });
+    }
+    let is_hidden = ignore_rules.ignore_hidden.matches(file_name);
+    let hidden = ignore_rules.ignore_hidden.as_str();
+    checks.push(RuleCheck { kind: RuleKind::HiddenFiles, pattern: hidden, source: RuleSource::BuiltIn, outcome: outcome(is_hidden) });
+    for (index, pattern) in ignore_rules.include_hidden.iter().enumerate() {
+        let source = RuleSource::Config("include_hidden_patterns", index);
+        checks.push(RuleCheck { kind: RuleKind::IncludeHidden, pattern: pattern.as_str(), source, outcome: outcome(pattern.matches(file_name)) });
+    }
+    let mut should_include_dir = false;
+    for (index, dir) in include_dir_overrides.iter().enumerate() {
+        let matched = relative_file.starts_with(dir);
+        should_include_dir |= matched;
+        checks.push(RuleCheck { kind: RuleKind::DirOverride, pattern: dir, source: RuleSource::Config("include_dir_overrides", index), outcome: outcome(matched) });
+    }
+    for (rule, pattern_source) in ignore_rules.rules.iter().zip(&ignore_rules.rule_sources) {
+        // Directory-level ignores don't apply inside an included directory
+        let is_dir_pattern = rule.as_str().ends_with("/*");
+        let outcome = if is_dir_pattern && should_include_dir { RuleOutcome::Skipped } else { outcome(rule.matches_path(relative_file)) };
+        checks.push(RuleCheck { kind: RuleKind::IgnorePattern, pattern: rule.as_str(), source: pattern_source.into(), outcome });
+    }
+    if let Some(gitignore) = &ignore_rules.gitignore {
+        let absolute_file = if file.is_absolute() { file.to_path_buf() } else { input_dir.join(file) };
+        let check = match gitignore.deciding_rule(&absolute_file) {
+            _ if should_include_dir => RuleCheck { kind: RuleKind::GitIgnore, pattern: "", source: RuleSource::IgnoreFile(None), outcome: RuleOutcome::Skipped },
+            Some(rule) => RuleCheck { kind: RuleKind::GitIgnore, pattern: rule.pattern, source: RuleSource::IgnoreFile(rule.source), outcome: if rule.ignored { RuleOutcome::Matched } else { RuleOutcome::Reincluded } },
+            None => RuleCheck { kind: RuleKind::GitIgnore, pattern: "", source: RuleSource::IgnoreFile(None), outcome: RuleOutcome::NoMatch },
+        };
+        checks.push(check);
+    }
+
+    let first_match = |kind: RuleKind| checks.iter().position(|check| check.kind == kind && check.outcome == RuleOutcome::Matched);
+    let (step, deciding_rule) = if let Some(index) = first_match(RuleKind::FileOverride) {
+        (DecisionStep::FileOverride, Some(index))
+    } else if is_hidden && first_match(RuleKind::IncludeHidden).is_none() {
+        (DecisionStep::HiddenFile, first_match(RuleKind::HiddenFiles))
+    } else if let Some(index) = first_match(RuleKind::IgnorePattern) {
+        (DecisionStep::IgnorePattern, Some(index))
+    } else if let Some(index) = first_match(RuleKind::GitIgnore) {
+        (DecisionStep::GitIgnore, Some(index))
+    } else {
+        (DecisionStep::Default, None)
+    };
+    let included = matches!(step, DecisionStep::FileOverride | DecisionStep::Default);
+    FileExplanation { path: relative_file.to_path_buf(), checks, included, step, deciding_rule }
+}
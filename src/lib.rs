@@ -1,6 +1,28 @@
+pub mod status;
 pub mod config;
+pub mod config_layers;
+pub mod config_validation;
 pub mod file_system_ops;
+pub mod presets;
 pub mod ignore_rules;
+pub mod explain;
+pub mod gitignore;
+pub mod git_changes;
+pub mod git_tracked;
+pub mod binary_detection;
+pub mod encoding;
+pub mod truncation;
+pub mod redaction;
+pub mod content_cache;
 pub mod aggregator;
+pub mod formatter;
+pub mod sorting;
+pub mod tree;
+pub mod tokenizer;
+pub mod budget;
+pub mod chunking;
+pub mod output;
+pub mod listing;
+pub mod watch;
 pub mod cli;
 
@@ -0,0 +1,140 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use serde::Serialize;
+use crate::aggregator::get_language_identifier;
+
+/// The number of bytes counted as one token when estimating tokens without reading a file.
+const BYTES_PER_TOKEN: u64 = 4;
+
+/// A file selected for the output, described without reading its content.
+///
+/// - `path`: The path of the file, relative to the input directory.
+/// - `size`: The size of the file in bytes.
+/// - `estimated_tokens`: The number of tokens the file is estimated to take, from its size.
+/// - `language`: The language detected from the file extension, as in the output's code blocks.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ListedFile {
+    pub path: PathBuf,
+    pub size: u64,
+    pub estimated_tokens: u64,
+    pub language: String,
+}
+
+/// The number of files, bytes and estimated tokens of a group of listed files.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct ListingTotals {
+    pub files: usize,
+    pub size: u64,
+    pub estimated_tokens: u64,
+}
+
+impl ListingTotals {
+    fn add(&mut self, file: &ListedFile) {
+        self.files += 1;
+        self.size += file.size;
+        self.estimated_tokens += file.estimated_tokens;
+    }
+}
+
+/// The files selected for the output, as printed by `--list`.
+///
+/// - `files`: The selected files, in output order.
+/// - `totals`: The totals of every file.
+/// - `languages`: The totals of the files of each language, largest first.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Listing {
+    pub files: Vec<ListedFile>,
+    pub totals: ListingTotals,
+    pub languages: Vec<(String, ListingTotals)>,
+}
+
+impl Listing {
+    /// Describes the selected files from their metadata, without reading them.
+    ///
+    /// Tokens are estimated as one per four bytes, which is close to what the tokenizers count
+    /// for source code and much cheaper than reading every file.
+    ///
+    /// # Arguments
+    ///
+    /// * `files` - The selected files, in output order.
+    /// * `input_dir` - The input directory, which the listed paths are relative to.
+    ///
+    /// # Returns
+    ///
+    /// Returns the `Listing` of the files with their totals.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the metadata of a file can't be read.
+    pub fn new(files: &[PathBuf], input_dir: &Path) -> io::Result<Self> {
+        let mut listing = Listing::default();
+        let mut languages: BTreeMap<String, ListingTotals> = BTreeMap::new();
+        for file in files {
+            let size = fs::metadata(input_dir.join(file))?.len();
+            let path = file.strip_prefix(input_dir).unwrap_or(file).to_path_buf();
+            let language = get_language_identifier(&path).to_string();
+            let listed = ListedFile { estimated_tokens: size.div_ceil(BYTES_PER_TOKEN), path, size, language };
+            listing.totals.add(&listed);
+            languages.entry(listed.language.clone()).or_default().add(&listed);
+            listing.files.push(listed);
+        }
+        listing.languages = languages.into_iter().collect();
+        listing.languages.sort_by_key(|(_, totals)| std::cmp::Reverse(totals.size));
+        Ok(listing)
+    }
+
+    /// Renders the listing as text: one path per line, or a table with the size, estimated
+    /// tokens and language of each file followed by the totals if `details` is set.
+    pub fn render(&self, details: bool) -> String {
+        if !details {
+            return self.files.iter().map(|file| format!("{}\n", file.path.display())).collect();
+        }
+        let rows: Vec<[String; 4]> = self
+            .files
+            .iter()
+            .map(|file| [file.size.to_string(), file.estimated_tokens.to_string(), file.language.clone(), file.path.display().to_string()])
+            .chain(std::iter::once(total_row("total", &self.totals)))
+            .chain(self.languages.iter().map(|(language, totals)| total_row(language, totals)))
+            .collect();
+        let header = ["SIZE", "TOKENS", "LANGUAGE", "PATH"].map(String::from);
+        let width = |column: usize| rows.iter().chain([&header]).map(|row| row[column].chars().count()).max().unwrap_or(0);
+        let widths = [width(0), width(1), width(2)];
+
+        let mut output = String::new();
+        for (index, row) in [&header].into_iter().chain(&rows).enumerate() {
+            // The totals are set apart from the files
+            if index == self.files.len() + 1 {
+                output.push('\n');
+            }
+            let line = format!(
+                "{:>w0$}  {:>w1$}  {:<w2$}  {}",
+                row[0], row[1], row[2], row[3], w0 = widths[0], w1 = widths[1], w2 = widths[2]
+            );
+            output.push_str(line.trim_end());
+            output.push('\n');
+        }
+        output
+    }
+
+    /// Renders the listing as a JSON document, for `--list --json`.
+    pub fn render_json(&self) -> String {
+        let languages: serde_json::Map<String, serde_json::Value> = self
+            .languages
+            .iter()
+            .map(|(language, totals)| (language.clone(), serde_json::json!(totals)))
+            .collect();
+        let document = serde_json::json!({
+            "files": self.files,
+            "totals": self.totals,
+            "languages": languages,
+        });
+        format!("{:#}\n", document)
+    }
+}
+
+/// Returns a table row with the totals of a group of files, labelled in the language column.
+fn total_row(label: &str, totals: &ListingTotals) -> [String; 4] {
+    [totals.size.to_string(), totals.estimated_tokens.to_string(), label.to_string(), format!("({} files)", totals.files)]
+}
@@ -0,0 +1,181 @@
+use std::fs::{self, File};
+use std::io::{self, BufWriter, IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use anyhow::{Result, Context};
+use crate::chunking::{PartInfo, part_path, remove_stale_parts};
+
+/// The value of `--output` that selects stdout.
+pub const STDOUT_PATH: &str = "-";
+
+/// Where the aggregated output is written.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum OutputTarget {
+    /// Write the output to stdout, e.g. to pipe it into another program.
+    Stdout,
+    /// Write the output to the given file.
+    File(PathBuf),
+}
+
+impl OutputTarget {
+    /// Determines where the output should be written.
+    ///
+    /// The target is chosen in this order:
+    /// 1. `--output -` selects stdout, and `--output <path>` selects that file.
+    /// 2. If `stdout_when_piped` is enabled and stdout is not a terminal, stdout is selected.
+    /// 3. Otherwise the default output file is used.
+    ///
+    /// # Arguments
+    ///
+    /// * `explicit` - The value of `--output`, if given.
+    /// * `stdout_when_piped` - Whether to write to stdout when it is not a terminal.
+    /// * `default_path` - A function returning the default output file path. It is only called
+    ///   when no other target applies.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if `default_path` is called and fails.
+    pub fn resolve(
+        explicit: Option<&str>,
+        stdout_when_piped: bool,
+        default_path: impl FnOnce() -> Result<PathBuf>,
+    ) -> Result<Self> {
+        match explicit {
+            Some(STDOUT_PATH) => Ok(OutputTarget::Stdout),
+            Some(path) => Ok(OutputTarget::File(PathBuf::from(path))),
+            None if stdout_when_piped && !io::stdout().is_terminal() => Ok(OutputTarget::Stdout),
+            None => Ok(OutputTarget::File(default_path()?)),
+        }
+    }
+
+    /// Returns `true` if the path is where the output is written, either as a single file
+    /// or as one of its parts. Relative paths are compared as absolute paths.
+    pub fn is_output_file(&self, path: &Path) -> bool {
+        let OutputTarget::File(output_path) = self else { return false };
+        let (Ok(output_path), Ok(path)) = (std::path::absolute(output_path), std::path::absolute(path)) else { return false };
+        if output_path == path {
+            return true;
+        }
+        if output_path.parent() != path.parent() {
+            return false;
+        }
+        let stem = output_path.file_stem().and_then(|stem| stem.to_str()).unwrap_or_default();
+        let suffix = match output_path.extension().and_then(|ext| ext.to_str()) {
+            Some(extension) => format!(".{}", extension),
+            None => String::new(),
+        };
+        path.file_name()
+            .and_then(|name| name.to_str())
+            .and_then(|name| name.strip_prefix(&format!("{}.part", stem)))
+            .and_then(|rest| rest.strip_suffix(&suffix))
+            .is_some_and(|number| !number.is_empty() && number.bytes().all(|byte| byte.is_ascii_digit()))
+    }
+}
+
+/// Writes the rendered output parts to the output target.
+///
+/// When writing to a file, parts left over from a previous run are removed first. A single
+/// part is written to the target path itself; several parts are written to numbered part
+/// files next to it (see `chunking::part_path`).
+///
+/// # Arguments
+///
+/// * `target` - A reference to the `OutputTarget` to write to.
+/// * `parts` - A slice of the rendered parts, in order.
+///
+/// # Returns
+///
+/// Returns a `Vec` of the paths that were written, which is empty when writing to stdout.
+///
+/// # Errors
+///
+/// This function will return an error if:
+/// - The output is split into several parts and the target is stdout.
+/// - The output directory or an output file cannot be created or written.
+pub fn write_output(target: &OutputTarget, parts: &[String]) -> Result<Vec<PathBuf>> {
+    match target {
+        OutputTarget::Stdout if parts.len() > 1 => anyhow::bail!(
+            "The output was split into {} parts, which can't be written to stdout. Use --output <path> instead.",
+            parts.len()
+        ),
+        OutputTarget::File(output_path) if parts.len() > 1 => write_files(output_path, parts),
+        _ => write_stream(target, |out| parts.iter().try_for_each(|part| out.write_all(part.as_bytes()))),
+    }
+}
+
+/// Writes a single output to the output target as it is produced, instead of rendering it
+/// first.
+///
+/// When writing to a file, parts left over from a previous run are removed first. The
+/// reading end of stdout closing early, e.g. with `conag | head`, stops the output without
+/// an error.
+///
+/// # Arguments
+///
+/// * `target` - A reference to the `OutputTarget` to write to.
+/// * `write` - A function writing the output to the buffered writer it is given.
+///
+/// # Returns
+///
+/// Returns a `Vec` of the paths that were written, which is empty when writing to stdout.
+///
+/// # Errors
+///
+/// This function will return an error if the output directory or the output file cannot be
+/// created, or if `write` fails.
+pub fn write_stream(target: &OutputTarget, write: impl FnOnce(&mut dyn Write) -> io::Result<()>) -> Result<Vec<PathBuf>> {
+    match target {
+        OutputTarget::Stdout => {
+            let mut stdout = BufWriter::new(io::stdout().lock());
+            match write(&mut stdout).and_then(|_| stdout.flush()) {
+                // The reading end of a pipe closing early (e.g. `conag | head`) is not an error
+                Err(err) if err.kind() == io::ErrorKind::BrokenPipe => Ok(Vec::new()),
+                result => result.map(|_| Vec::new()).with_context(|| "Failed to write output to stdout"),
+            }
+        }
+        OutputTarget::File(output_path) => {
+            prepare_output_path(output_path)?;
+            // Open the file in write mode, which truncates the file if it already exists
+            let file = File::create(output_path)
+                .with_context(|| format!("Failed to create output file: {:?}", output_path))?;
+            let mut file = BufWriter::new(file);
+            write(&mut file)
+                .and_then(|_| file.flush())
+                .with_context(|| format!("Failed to write output file: {:?}", output_path))?;
+            Ok(vec![output_path.clone()])
+        }
+    }
+}
+
+/// Creates the output directory, and removes the outputs left over from a previous run.
+fn prepare_output_path(output_path: &Path) -> Result<()> {
+    // Ensure the output directory exists
+    if let Some(output_dir) = output_path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+        fs::create_dir_all(output_dir)
+            .with_context(|| format!("Failed to create output directory: {:?}", output_dir))?;
+    }
+
+    // Remove outputs left over from a previous run, so they aren't mistaken for this run's output
+    remove_stale_parts(output_path)?;
+    Ok(())
+}
+
+/// Writes the parts to numbered part files next to the output path.
+fn write_files(output_path: &Path, parts: &[String]) -> Result<Vec<PathBuf>> {
+    prepare_output_path(output_path)?;
+    let total = parts.len();
+    if output_path.exists() {
+        fs::remove_file(output_path)?;
+    }
+
+    let mut written = Vec::with_capacity(total);
+    for (index, output) in parts.iter().enumerate() {
+        let part_output_path = part_path(output_path, &PartInfo { number: index + 1, total });
+
+        // Open the file in write mode, which truncates the file if it already exists
+        let mut file = File::create(&part_output_path)
+            .with_context(|| format!("Failed to create output file: {:?}", part_output_path))?;
+        file.write_all(output.as_bytes())?;
+        written.push(part_output_path);
+    }
+    Ok(written)
+}
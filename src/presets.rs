@@ -0,0 +1,265 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use glob::Pattern;
+
+/// A built-in set of ignore patterns for a kind of project.
+///
+/// - `name`: The project type the preset is activated by, e.g. `rust`.
+/// - `description`: The kinds of projects the preset is for.
+/// - `markers`: File name patterns whose presence marks a project of this type.
+/// - `ignores`: The glob patterns ignored in projects of this type, relative to the directory
+///   holding the marker file.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Preset {
+    pub name: &'static str,
+    pub description: &'static str,
+    pub markers: &'static [&'static str],
+    pub ignores: &'static [&'static str],
+}
+
+/// The built-in presets, in the order they are listed.
+///
+/// The ignores are relative to the directory holding a marker file, so a preset detected in
+/// `backend/` only ignores `backend/target/**`, not every `target` directory in the project.
+/// Patterns starting with `**/` apply anywhere below that directory.
+pub const PRESETS: &[Preset] = &[
+    Preset {
+        name: "rust",
+        description: "Rust crates and Cargo workspaces",
+        markers: &["Cargo.toml"],
+        ignores: &["target/**", "**/*.rlib", "**/*.rmeta", "Cargo.lock"],
+    },
+    Preset {
+        name: "python",
+        description: "Python packages and scripts",
+        markers: &["pyproject.toml", "setup.py", "setup.cfg", "requirements.txt", "Pipfile", "poetry.lock"],
+        ignores: &[
+            "**/__pycache__/**",
+            "**/*.pyc",
+            "**/*.pyo",
+            "**/*.egg-info/**",
+            "pip-wheel-metadata/**",
+            "**/.pytest_cache/**",
+            ".mypy_cache/**",
+            ".tox/**",
+            ".venv/**",
+            "venv/**",
+            "poetry.lock",
+        ],
+    },
+    Preset {
+        name: "node",
+        description: "JavaScript and TypeScript projects using npm, yarn or pnpm",
+        markers: &["package.json"],
+        ignores: &[
+            "**/node_modules/**",
+            "**/*.js.map",
+            "**/*.d.ts",
+            "package-lock.json",
+            "yarn.lock",
+            "pnpm-lock.yaml",
+            ".next/**",
+            "coverage/**",
+        ],
+    },
+    Preset {
+        name: "go",
+        description: "Go modules",
+        markers: &["go.mod"],
+        ignores: &["vendor/**", "**/*.out", "go.sum"],
+    },
+    Preset {
+        name: "java",
+        description: "Java and Kotlin projects built with Gradle or Maven",
+        markers: &["build.gradle", "build.gradle.kts", "settings.gradle", "settings.gradle.kts", "pom.xml"],
+        ignores: &["build/**", ".gradle/**", "target/**", "**/*.class", "**/*.jar", "gradlew", "gradlew.bat"],
+    },
+    Preset {
+        name: "dotnet",
+        description: ".NET projects in C#, F# or Visual Basic",
+        markers: &["*.sln", "*.csproj", "*.fsproj", "*.vbproj", "global.json"],
+        ignores: &["bin/**", "obj/**", "**/*.dll", "**/*.pdb", "packages/**"],
+    },
+    Preset {
+        name: "ruby",
+        description: "Ruby gems and Rails applications",
+        markers: &["Gemfile", "*.gemspec"],
+        ignores: &["vendor/bundle/**", ".bundle/**", "Gemfile.lock", "tmp/**", "log/**"],
+    },
+    Preset {
+        name: "php",
+        description: "PHP projects using Composer",
+        markers: &["composer.json"],
+        ignores: &["vendor/**", "composer.lock"],
+    },
+    Preset {
+        name: "cpp",
+        description: "C and C++ projects built with CMake or Meson",
+        markers: &["CMakeLists.txt", "meson.build"],
+        ignores: &["build/**", "cmake-build-*/**", "**/CMakeFiles/**", "**/*.o", "**/*.a", "**/*.so"],
+    },
+    Preset {
+        name: "swift",
+        description: "Swift packages and Xcode projects",
+        markers: &["Package.swift", "*.xcodeproj"],
+        ignores: &[".build/**", "DerivedData/**", "Package.resolved", "**/*.xcodeproj/**"],
+    },
+];
+
+/// The number of directory levels below the input directory looked into for marker files.
+const MAX_MARKER_DEPTH: usize = 3;
+
+/// The dependency and build output directories not looked into for marker files, as the
+/// packages they hold have markers of their own.
+const SKIPPED_DIRS: &[&str] = &["node_modules", "target", "vendor", "venv", "build", "dist", "out", "bin", "obj", "__pycache__"];
+
+impl Preset {
+    /// Returns the ignores of the preset for a project in the given directory, relative to the
+    /// input directory. An empty `root` is the input directory itself.
+    pub fn ignores_under(&self, root: &Path) -> Vec<String> {
+        let prefix = root
+            .components()
+            .map(|component| component.as_os_str().to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("/");
+        self.ignores
+            .iter()
+            .map(|pattern| if prefix.is_empty() { pattern.to_string() } else { format!("{}/{}", Pattern::escape(&prefix), pattern) })
+            .collect()
+    }
+}
+
+/// Returns the built-in preset with the given name, if there is one.
+pub fn find_preset(name: &str) -> Option<&'static Preset> {
+    PRESETS.iter().find(|preset| preset.name == name)
+}
+
+/// A preset detected in the input directory.
+///
+/// - `preset`: The preset detected.
+/// - `roots`: The directories holding a marker file of the preset, relative to the input
+///   directory, in the order they were found. The input directory itself is the empty path.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DetectedPreset {
+    pub preset: &'static Preset,
+    pub roots: Vec<PathBuf>,
+}
+
+/// Detects the project types of a directory from the marker files it contains.
+///
+/// Markers are looked for in the directory itself and in the directories below it, down to
+/// `MAX_MARKER_DEPTH` levels, so a repository with e.g. a `backend/Cargo.toml` and a
+/// `services/web/package.json` activates both the `rust` and the `node` presets. Hidden
+/// directories and dependency directories such as `node_modules` are not looked into.
+///
+/// # Arguments
+///
+/// * `dir` - A reference to the `Path` of the directory to look into.
+///
+/// # Returns
+///
+/// Returns the presets detected with the directories of their markers, in the order of
+/// `PRESETS`. A directory that can't be read has no presets.
+pub fn detect_presets(dir: &Path) -> Vec<DetectedPreset> {
+    let markers: Vec<(&'static Preset, Vec<Pattern>)> = PRESETS
+        .iter()
+        .map(|preset| (preset, preset.markers.iter().filter_map(|marker| Pattern::new(marker).ok()).collect()))
+        .collect();
+    let mut detected: Vec<DetectedPreset> = Vec::new();
+    let mut pending = vec![(PathBuf::new(), 0)];
+    while let Some((relative_dir, depth)) = pending.pop() {
+        let Ok(entries) = fs::read_dir(dir.join(&relative_dir)) else { continue };
+        let mut names: Vec<(String, bool)> = entries
+            .filter_map(|entry| entry.ok())
+            .map(|entry| (entry.file_name().to_string_lossy().into_owned(), entry.path().is_dir()))
+            .collect();
+        // Directories are visited in name order, so the roots are found in the same order every time
+        names.sort();
+        for (preset, patterns) in &markers {
+            if !names.iter().any(|(name, _)| patterns.iter().any(|pattern| pattern.matches(name))) {
+                continue;
+            }
+            match detected.iter_mut().find(|found| found.preset == *preset) {
+                Some(found) => found.roots.push(relative_dir.clone()),
+                None => detected.push(DetectedPreset { preset, roots: vec![relative_dir.clone()] }),
+            }
+        }
+        if depth < MAX_MARKER_DEPTH {
+            let subdirs = names
+                .iter()
+                .rev()
+                .filter(|(name, is_dir)| *is_dir && !name.starts_with('.') && !SKIPPED_DIRS.contains(&name.as_str()));
+            pending.extend(subdirs.map(|(name, _)| (relative_dir.join(name), depth + 1)));
+        }
+    }
+    detected.sort_by_key(|found| PRESETS.iter().position(|preset| preset == found.preset));
+    detected
+}
+
+/// The presets detected in a directory, detected once when first needed so that looking up
+/// the active project types doesn't walk the directory every time.
+#[derive(Debug, Clone, Default)]
+pub struct DetectionCache(OnceLock<(PathBuf, Vec<DetectedPreset>)>);
+
+impl DetectionCache {
+    /// Returns the presets detected in `dir`, detecting them on the first call. A different
+    /// directory than the cached one is detected again without being cached.
+    pub fn get(&self, dir: &Path) -> Vec<DetectedPreset> {
+        let (cached_dir, detected) = self.0.get_or_init(|| (dir.to_path_buf(), detect_presets(dir)));
+        if cached_dir == dir {
+            detected.clone()
+        } else {
+            detect_presets(dir)
+        }
+    }
+}
+
+/// Renders the list of presets as a table, marking the active ones with `*`.
+///
+/// # Arguments
+///
+/// * `active` - The active project types, as returned by `Config::active_project_types`.
+///
+/// # Returns
+///
+/// Returns a `String` with a header line, followed by the name, markers and description of
+/// each preset.
+pub fn render_preset_list(active: &[String]) -> String {
+    let rows: Vec<[String; 4]> = PRESETS
+        .iter()
+        .map(|preset| {
+            let mark = if active.iter().any(|name| name == preset.name) { "*" } else { "" };
+            [mark.to_string(), preset.name.to_string(), preset.markers.join(", "), preset.description.to_string()]
+        })
+        .collect();
+    let header = ["", "NAME", "MARKERS", "DESCRIPTION"].map(String::from);
+    let width = |column: usize| rows.iter().chain([&header]).map(|row| row[column].chars().count()).max().unwrap_or(0);
+    let widths = [width(1), width(2)];
+
+    let mut output = String::new();
+    for row in [&header].into_iter().chain(&rows) {
+        let line = format!("{:<1} {:<w1$}  {:<w2$}  {}", row[0], row[1], row[2], row[3], w1 = widths[0], w2 = widths[1]);
+        output.push_str(line.trim_end());
+        output.push('\n');
+    }
+    output
+}
+
+/// Renders a preset: its description, the marker files detecting it and its ignore patterns,
+/// followed by the patterns added to it in `project_specific_ignores`, if any.
+pub fn render_preset(preset: &Preset, extra_ignores: &[String]) -> String {
+    let mut output = format!("{}: {}\n", preset.name, preset.description);
+    output.push_str(&format!("Detected by: {}\n", preset.markers.join(", ")));
+    output.push_str("Ignores:\n");
+    for pattern in preset.ignores {
+        output.push_str(&format!("  {}\n", pattern));
+    }
+    if !extra_ignores.is_empty() {
+        output.push_str(&format!("Also ignored, from project_specific_ignores.{}:\n", preset.name));
+        for pattern in extra_ignores {
+            output.push_str(&format!("  {}\n", pattern));
+        }
+    }
+    output
+}
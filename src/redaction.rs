@@ -0,0 +1,277 @@
+use std::collections::BTreeMap;
+use std::ops::Range;
+use std::path::PathBuf;
+use regex::Regex;
+use crate::aggregator::FileContent;
+
+/// The built-in rules, as the kind of secret and the regex matching it. If the regex has a
+/// capture group, only the group is redacted, so e.g. `password = "hunter2"` keeps its key.
+const BUILTIN_RULES: [(&str, &str); 7] = [
+    ("private-key", r"-----BEGIN[A-Z ]* PRIVATE KEY( BLOCK)?-----[\s\S]*?-----END[A-Z ]* PRIVATE KEY( BLOCK)?-----"),
+    ("aws-access-key", r"\b(?:AKIA|ASIA)[0-9A-Z]{16}\b"),
+    ("aws-secret-key", r#"(?i)aws_?secret_?(?:access_?)?key["']?\s*[:=]\s*["']?([A-Za-z0-9/+=]{40})\b"#),
+    ("jwt", r"\beyJ[A-Za-z0-9_-]{8,}\.eyJ[A-Za-z0-9_-]{8,}\.[A-Za-z0-9_-]{8,}"),
+    ("github-token", r"\b(?:gh[pousr]_[A-Za-z0-9]{36,}|github_pat_[A-Za-z0-9_]{22,})\b"),
+    ("slack-token", r"\bxox[abeoprs]-[A-Za-z0-9-]{10,}"),
+    ("password", r#"(?i)(?:password|passwd|pwd|secret|api_?key|access_?token|auth_?token)["']?\s*[:=]\s*["']([^"'\s]{4,})["']"#),
+];
+
+/// The kind of secret reported for high-entropy strings.
+const HIGH_ENTROPY_KIND: &str = "high-entropy";
+
+/// The shortest string checked for high entropy.
+const HIGH_ENTROPY_MIN_LENGTH: usize = 32;
+
+/// The Shannon entropy in bits per character above which a string is considered random.
+const HIGH_ENTROPY_THRESHOLD: f64 = 4.0;
+
+/// A rule detecting one kind of secret.
+///
+/// - `kind`: The name of the kind of secret, shown as `[REDACTED:<kind>]`.
+/// - `regex`: The regex matching the secret. If it has a capture group, only the first group
+///   is redacted.
+#[derive(Debug, Clone)]
+pub struct RedactionRule {
+    pub kind: String,
+    pub regex: Regex,
+}
+
+/// A secret found in a file.
+///
+/// - `path`: The path of the file, relative to the input directory.
+/// - `line`: The line of the file the secret starts on, starting at 1.
+/// - `kind`: The kind of secret.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SecretFinding {
+    pub path: PathBuf,
+    pub line: usize,
+    pub kind: String,
+}
+
+/// A secret found in a piece of text: its kind and its byte range in the text.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SecretMatch {
+    pub kind: String,
+    pub range: Range<usize>,
+}
+
+/// Detects credentials in file contents and replaces them with `[REDACTED:<kind>]`.
+///
+/// The built-in rules detect AWS keys, private key PEM blocks, JWTs, GitHub and Slack tokens,
+/// and values assigned to keys such as `password` or `api_key`. Long strings that look random,
+/// such as API keys of unknown shapes, can be detected by their entropy as well; this is
+/// opt-in, since hashes, lock files and base64 data look random too.
+#[derive(Debug, Clone)]
+pub struct Redactor {
+    rules: Vec<RedactionRule>,
+    high_entropy: bool,
+    high_entropy_candidate: Regex,
+}
+
+impl Redactor {
+    /// Creates a redactor with the built-in rules followed by custom rules.
+    ///
+    /// # Arguments
+    ///
+    /// * `custom_rules` - The custom rules, as the kind of secret and the regex matching it.
+    /// * `high_entropy` - Whether to also redact long strings that look random.
+    ///
+    /// # Errors
+    ///
+    /// This function will return the kind and the error of the first custom rule whose regex
+    /// is invalid.
+    pub fn new(custom_rules: &BTreeMap<String, String>, high_entropy: bool) -> Result<Self, (String, regex::Error)> {
+        let builtin = BUILTIN_RULES.iter().map(|(kind, pattern)| (kind.to_string(), pattern.to_string()));
+        let rules = builtin
+            .chain(custom_rules.iter().map(|(kind, pattern)| (kind.clone(), pattern.clone())))
+            .map(|(kind, pattern)| match Regex::new(&pattern) {
+                Ok(regex) => Ok(RedactionRule { kind, regex }),
+                Err(err) => Err((kind, err)),
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(Redactor {
+            rules,
+            high_entropy,
+            high_entropy_candidate: Regex::new(&format!(r"[A-Za-z0-9+/=_-]{{{},}}", HIGH_ENTROPY_MIN_LENGTH))
+                .expect("the high-entropy pattern is valid"),
+        })
+    }
+
+    /// Finds the secrets in a piece of text.
+    ///
+    /// # Returns
+    ///
+    /// Returns the secrets in the order they appear. When secrets overlap, the one starting
+    /// first is kept, and of those starting at the same position the longest.
+    pub fn find(&self, text: &str) -> Vec<SecretMatch> {
+        let mut matches = Vec::new();
+        for rule in &self.rules {
+            for captures in rule.regex.captures_iter(text) {
+                let secret = captures.get(1).or_else(|| captures.get(0)).expect("a match has a group 0");
+                matches.push(SecretMatch { kind: rule.kind.clone(), range: secret.range() });
+            }
+        }
+        if self.high_entropy {
+            let candidates = self.high_entropy_candidate.find_iter(text).filter(|candidate| looks_random(candidate.as_str()));
+            matches.extend(candidates.map(|candidate| SecretMatch { kind: HIGH_ENTROPY_KIND.to_string(), range: candidate.range() }));
+        }
+
+        matches.sort_by_key(|secret| (secret.range.start, std::cmp::Reverse(secret.range.end)));
+        let mut end = 0;
+        matches.retain(|secret| {
+            let keep = secret.range.start >= end;
+            if keep {
+                end = secret.range.end;
+            }
+            keep
+        });
+        matches
+    }
+
+    /// Replaces the secrets in a piece of text with `[REDACTED:<kind>]`.
+    ///
+    /// # Returns
+    ///
+    /// Returns the redacted text, and the line each secret started on and its kind.
+    pub fn redact(&self, text: &str) -> (String, Vec<(usize, String)>) {
+        let matches = self.find(text);
+        if matches.is_empty() {
+            return (text.to_string(), Vec::new());
+        }
+        let mut redacted = String::with_capacity(text.len());
+        let mut found = Vec::with_capacity(matches.len());
+        let mut position = 0;
+        let mut line = 1;
+        for secret in matches {
+            line += text[position..secret.range.start].matches('\n').count();
+            redacted.push_str(&text[position..secret.range.start]);
+            redacted.push_str(&format!("[REDACTED:{}]", secret.kind));
+            found.push((line, secret.kind));
+            // Secrets spanning several lines keep their line breaks, so the lines after them
+            // keep their numbers
+            let line_breaks = text[secret.range.clone()].matches('\n').count();
+            redacted.push_str(&"\n".repeat(line_breaks));
+            line += line_breaks;
+            position = secret.range.end;
+        }
+        redacted.push_str(&text[position..]);
+        (redacted, found)
+    }
+
+    /// Redacts the content and the diff of an aggregated file.
+    ///
+    /// # Returns
+    ///
+    /// Returns the secrets found, with their line in the original file. Secrets in the diff
+    /// are reported as described in `redact_diff`.
+    pub fn redact_file(&self, file: &mut FileContent) -> Vec<SecretFinding> {
+        let (content, found) = self.redact(&file.content);
+        let mut findings: Vec<SecretFinding> = found
+            .into_iter()
+            .map(|(line, kind)| SecretFinding { path: file.path.clone(), line: file.original_line(line).unwrap_or(line), kind })
+            .collect();
+        file.content = content;
+        findings.extend(self.redact_diff(file));
+        findings
+    }
+
+    /// Redacts the diff of an aggregated file, e.g. after it was attached to a file whose
+    /// content was already redacted.
+    ///
+    /// Secrets may span several lines, such as private key blocks, so the rules run over each
+    /// side of a hunk as a whole: the added and context lines, and the removed and context
+    /// lines. A secret is replaced with `[REDACTED:<kind>]` on its first line, and removed
+    /// from the lines after it, which are kept so the hunk still adds up.
+    ///
+    /// # Returns
+    ///
+    /// Returns the secrets found, with their line in the new version of the file, or in the
+    /// old version for secrets in removed lines.
+    pub fn redact_diff(&self, file: &mut FileContent) -> Vec<SecretFinding> {
+        let mut findings = Vec::new();
+        let Some(diff) = &mut file.diff else { return findings };
+        for hunk in &mut diff.hunks {
+            // The replaced byte range of each line, by index of the line in the hunk
+            let mut edits: Vec<(usize, Range<usize>, String)> = Vec::new();
+            for (old_side, first_line) in [(false, hunk.new_start), (true, hunk.old_start)] {
+                let excluded = if old_side { '+' } else { '-' };
+                let lines: Vec<usize> = (0..hunk.lines.len())
+                    .filter(|index| !hunk.lines[*index].starts_with([excluded, '\\']))
+                    .collect();
+                let mut text = String::new();
+                let mut starts = Vec::with_capacity(lines.len());
+                for index in &lines {
+                    starts.push(text.len());
+                    text.push_str(hunk.lines[*index].get(1..).unwrap_or_default());
+                    text.push('\n');
+                }
+                for secret in self.find(&text) {
+                    let first = starts.partition_point(|start| *start <= secret.range.start) - 1;
+                    let last = starts.partition_point(|start| *start < secret.range.end) - 1;
+                    let touched = &lines[first..=last];
+                    // Context lines are on both sides, so their secrets are only reported once
+                    if old_side && !touched.iter().any(|index| hunk.lines[*index].starts_with('-')) {
+                        continue;
+                    }
+                    findings.push(SecretFinding { path: file.path.clone(), line: first_line as usize + first, kind: secret.kind.clone() });
+                    for (offset, index) in touched.iter().enumerate() {
+                        let line_start = starts[first + offset];
+                        let line = &hunk.lines[*index];
+                        let prefix_len = line.len() - line.get(1..).unwrap_or_default().len();
+                        let body_len = line.len() - prefix_len;
+                        let start = secret.range.start.max(line_start) - line_start;
+                        let end = secret.range.end.min(line_start + body_len) - line_start;
+                        let replacement = if offset == 0 { format!("[REDACTED:{}]", secret.kind) } else { String::new() };
+                        edits.push((*index, start + prefix_len..end + prefix_len, replacement));
+                    }
+                }
+            }
+
+            // A secret in context lines next to removed lines may be found on both sides
+            edits.sort_by_key(|(index, range, _)| (*index, range.start, std::cmp::Reverse(range.end)));
+            let mut kept: Vec<(usize, Range<usize>, String)> = Vec::with_capacity(edits.len());
+            for edit in edits {
+                let overlaps = kept.last().is_some_and(|(index, range, _)| *index == edit.0 && range.end > edit.1.start);
+                if !overlaps {
+                    kept.push(edit);
+                }
+            }
+            for (index, range, replacement) in kept.into_iter().rev() {
+                hunk.lines[index].replace_range(range, &replacement);
+            }
+        }
+        findings
+    }
+}
+
+impl Default for Redactor {
+    /// Creates a redactor with the built-in rules only, without the high-entropy check.
+    fn default() -> Self {
+        Redactor::new(&BTreeMap::new(), false).expect("the built-in rules are valid")
+    }
+}
+
+/// Returns `true` if a string looks like a random key: it mixes upper and lower case letters
+/// and digits, and its characters are spread evenly enough.
+fn looks_random(candidate: &str) -> bool {
+    let has = |predicate: fn(&char) -> bool| candidate.chars().any(|c| predicate(&c));
+    has(char::is_ascii_uppercase) && has(char::is_ascii_lowercase) && has(char::is_ascii_digit)
+        && shannon_entropy(candidate) >= HIGH_ENTROPY_THRESHOLD
+}
+
+/// Returns the Shannon entropy of a string in bits per character.
+fn shannon_entropy(text: &str) -> f64 {
+    let mut counts = [0usize; 256];
+    for byte in text.bytes() {
+        counts[byte as usize] += 1;
+    }
+    let length = text.len() as f64;
+    counts
+        .iter()
+        .filter(|count| **count > 0)
+        .map(|count| {
+            let probability = *count as f64 / length;
+            -probability * probability.log2()
+        })
+        .sum()
+}
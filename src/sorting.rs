@@ -0,0 +1,101 @@
+use std::cmp::Ordering;
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+use std::time::SystemTime;
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+
+/// File names treated as project entry points, in the order they are emitted.
+///
+/// Names without an extension (like `README`) also match any extension, case-insensitively.
+const ENTRY_POINTS: [&str; 8] = [
+    "main.rs",
+    "lib.rs",
+    "main.go",
+    "main.py",
+    "__main__.py",
+    "index.js",
+    "index.ts",
+    "README",
+];
+
+/// The order in which files appear in the aggregated output.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize, Serialize, ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum SortMode {
+    /// Sort by relative path.
+    #[default]
+    Path,
+    /// Sort like a directory tree, listing subdirectories before the files next to them.
+    Tree,
+    /// Sort by file size, smallest first.
+    Size,
+    /// Sort by modification time, most recently modified first.
+    Mtime,
+    /// List entry points (`main.rs`, `lib.rs`, `README`, ...) first, then the rest by path.
+    EntryPoints,
+}
+
+/// Sorts the given files in place according to the sort mode.
+///
+/// Every mode falls back to the relative path to break ties, so the resulting order is
+/// fully deterministic for a given set of files.
+///
+/// # Arguments
+///
+/// * `files` - A mutable slice of `PathBuf`s representing the files to sort.
+/// * `mode` - The `SortMode` to apply.
+/// * `base_dir` - The base directory path used to calculate relative paths.
+pub fn sort_files(files: &mut [PathBuf], mode: SortMode, base_dir: &Path) {
+    let relative = |file: &Path| file.strip_prefix(base_dir).unwrap_or(file).to_path_buf();
+    match mode {
+        SortMode::Path => files.sort_by_cached_key(|file| relative(file)),
+        SortMode::Tree => files.sort_by(|a, b| compare_tree_order(&relative(a), &relative(b))),
+        SortMode::Size => files.sort_by_cached_key(|file| {
+            let size = fs::metadata(file).map(|metadata| metadata.len()).unwrap_or(0);
+            (size, relative(file))
+        }),
+        SortMode::Mtime => files.sort_by_cached_key(|file| {
+            let modified = fs::metadata(file)
+                .and_then(|metadata| metadata.modified())
+                .unwrap_or(SystemTime::UNIX_EPOCH);
+            (std::cmp::Reverse(modified), relative(file))
+        }),
+        SortMode::EntryPoints => files.sort_by_cached_key(|file| {
+            let relative_file = relative(file);
+            let rank = entry_point_rank(&relative_file);
+            (rank, relative_file.components().count(), relative_file)
+        }),
+    }
+}
+
+/// Compares two relative paths in directory-first tree order.
+fn compare_tree_order(a: &Path, b: &Path) -> Ordering {
+    let a_components: Vec<Component> = a.components().collect();
+    let b_components: Vec<Component> = b.components().collect();
+    for (index, (a_part, b_part)) in a_components.iter().zip(&b_components).enumerate() {
+        if a_part == b_part {
+            continue;
+        }
+        let a_is_dir = index + 1 < a_components.len();
+        let b_is_dir = index + 1 < b_components.len();
+        return b_is_dir.cmp(&a_is_dir).then_with(|| a_part.cmp(b_part));
+    }
+    a_components.len().cmp(&b_components.len())
+}
+
+/// Returns the position of the file in `ENTRY_POINTS`, or `ENTRY_POINTS.len()` if it is not an entry point.
+fn entry_point_rank(file: &Path) -> usize {
+    let file_name = file.file_name().and_then(|name| name.to_str()).unwrap_or("");
+    let file_stem = file.file_stem().and_then(|stem| stem.to_str()).unwrap_or("");
+    ENTRY_POINTS
+        .iter()
+        .position(|entry_point| {
+            if entry_point.contains('.') {
+                file_name == *entry_point
+            } else {
+                file_stem.eq_ignore_ascii_case(entry_point)
+            }
+        })
+        .unwrap_or(ENTRY_POINTS.len())
+}
@@ -0,0 +1,36 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static QUIET: AtomicBool = AtomicBool::new(false);
+
+/// Silences (or re-enables) status and warning messages for the rest of the process.
+pub fn set_quiet(quiet: bool) {
+    QUIET.store(quiet, Ordering::Relaxed);
+}
+
+/// Returns `true` if status and warning messages are silenced.
+pub fn is_quiet() -> bool {
+    QUIET.load(Ordering::Relaxed)
+}
+
+/// Prints a status message to stderr, unless messages are silenced with `--quiet`.
+///
+/// Status messages never go to stdout, so they can't end up mixed into output that is
+/// piped to another program.
+#[macro_export]
+macro_rules! status {
+    ($($arg:tt)*) => {
+        if !$crate::status::is_quiet() {
+            eprintln!($($arg)*);
+        }
+    };
+}
+
+/// Prints a warning to stderr, unless messages are silenced with `--quiet`.
+#[macro_export]
+macro_rules! warning {
+    ($($arg:tt)*) => {
+        if !$crate::status::is_quiet() {
+            eprintln!("Warning: {}", format_args!($($arg)*));
+        }
+    };
+}
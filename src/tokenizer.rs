@@ -0,0 +1,128 @@
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+
+/// Counts the tokens a piece of text will take up in an LLM's context window.
+pub trait Tokenizer {
+    /// Returns the number of tokens in the given text.
+    fn count_tokens(&self, text: &str) -> usize;
+}
+
+/// The tokenizers available for counting tokens.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize, Serialize, ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum TokenizerKind {
+    /// Estimate tokens the way a BPE tokenizer like `cl100k_base` splits text.
+    #[default]
+    Bpe,
+    /// Estimate one token per four characters.
+    Chars,
+}
+
+impl TokenizerKind {
+    /// Returns the `Tokenizer` implementation for this kind.
+    pub fn tokenizer(&self) -> Box<dyn Tokenizer + Send + Sync> {
+        match self {
+            TokenizerKind::Bpe => Box::new(BpeEstimator),
+            TokenizerKind::Chars => Box::new(CharsHeuristic),
+        }
+    }
+}
+
+/// A cheap heuristic that counts one token per four characters.
+pub struct CharsHeuristic;
+
+impl Tokenizer for CharsHeuristic {
+    fn count_tokens(&self, text: &str) -> usize {
+        text.chars().count().div_ceil(4)
+    }
+}
+
+/// An estimator compatible with BPE tokenizers such as `cl100k_base`.
+///
+/// BPE tokenizers first split text into pre-tokens (words with their leading space or
+/// punctuation, runs of up to three digits, punctuation runs and whitespace runs), then
+/// merge the bytes of each pre-token into vocabulary entries. This estimator performs the
+/// same pre-tokenization and estimates how many vocabulary entries each pre-token needs:
+/// - Words are split at `camelCase` boundaries; common-length pieces are a single token,
+///   longer pieces take one token per six letters.
+/// - Non-ASCII letters take roughly one token each.
+/// - Digits take one token per three digits.
+/// - Punctuation takes one token per two characters.
+/// - Whitespace containing a newline is one token, other whitespace one per eight characters.
+pub struct BpeEstimator;
+
+/// The longest word piece that is assumed to be a single vocabulary entry.
+const MAX_SINGLE_TOKEN_WORD: usize = 10;
+
+impl Tokenizer for BpeEstimator {
+    fn count_tokens(&self, text: &str) -> usize {
+        let chars: Vec<char> = text.chars().collect();
+        let mut tokens = 0;
+        let mut index = 0;
+        while index < chars.len() {
+            let c = chars[index];
+            if c.is_alphabetic() {
+                let end = run_end(&chars, index, |c| c.is_alphabetic());
+                tokens += word_tokens(&chars[index..end]);
+                index = end;
+            } else if c.is_numeric() {
+                let end = run_end(&chars, index, |c| c.is_numeric());
+                tokens += (end - index).div_ceil(3);
+                index = end;
+            } else if c.is_whitespace() {
+                let end = run_end(&chars, index, |c| c.is_whitespace());
+                let run = &chars[index..end];
+                // A single space before a word or punctuation is merged into that pre-token
+                let merges_forward = end < chars.len() && run.last() == Some(&' ') && !chars[end].is_numeric();
+                let run_len = if merges_forward { run.len() - 1 } else { run.len() };
+                tokens += if run.iter().any(|c| *c == '\n' || *c == '\r') {
+                    1
+                } else {
+                    run_len.div_ceil(8)
+                };
+                index = end;
+            } else {
+                let end = run_end(&chars, index, |c| !c.is_alphanumeric() && !c.is_whitespace());
+                tokens += (end - index).div_ceil(2);
+                index = end;
+            }
+        }
+        tokens
+    }
+}
+
+/// Returns the index just past the run of characters matching the predicate.
+fn run_end(chars: &[char], start: usize, predicate: impl Fn(char) -> bool) -> usize {
+    chars[start..]
+        .iter()
+        .position(|c| !predicate(*c))
+        .map_or(chars.len(), |offset| start + offset)
+}
+
+/// Estimates the tokens of a run of letters.
+fn word_tokens(word: &[char]) -> usize {
+    if !word.iter().all(char::is_ascii) {
+        let ascii_letters = word.iter().filter(|c| c.is_ascii()).count();
+        return word.len() - ascii_letters + ascii_letters.div_ceil(4);
+    }
+    let mut tokens = 0;
+    let mut piece_len = 0;
+    for (index, c) in word.iter().enumerate() {
+        let starts_piece = index > 0 && c.is_ascii_uppercase() && word[index - 1].is_ascii_lowercase();
+        if starts_piece {
+            tokens += piece_tokens(piece_len);
+            piece_len = 0;
+        }
+        piece_len += 1;
+    }
+    tokens + piece_tokens(piece_len)
+}
+
+/// Estimates the tokens of a single word piece of the given length.
+fn piece_tokens(len: usize) -> usize {
+    match len {
+        0 => 0,
+        len if len <= MAX_SINGLE_TOKEN_WORD => 1,
+        len => len.div_ceil(6),
+    }
+}
@@ -0,0 +1,187 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+/// How a file in the directory tree was handled.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TreeMark {
+    /// The file's content is included in the output.
+    Included,
+    /// The file was excluded by the ignore rules.
+    Ignored,
+    /// The file passed the ignore rules but its content was left out, for the given reason.
+    Skipped(String),
+}
+
+/// Options controlling how much of the directory tree is rendered.
+///
+/// - `max_depth`: The number of directory levels below the root to expand. Deeper directories
+///   are collapsed into a single line with their file count.
+/// - `max_files_per_dir`: The number of files listed per directory before the rest are
+///   collapsed into a "… N more files" line.
+/// - `show_ignored`: Whether to show ignored files. Directories holding only ignored files are
+///   always shown as a single line when enabled.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TreeOptions {
+    pub max_depth: Option<usize>,
+    pub max_files_per_dir: Option<usize>,
+    pub show_ignored: bool,
+}
+
+impl Default for TreeOptions {
+    fn default() -> Self {
+        TreeOptions { max_depth: None, max_files_per_dir: None, show_ignored: true }
+    }
+}
+
+/// An overview of the files in the input directory, rendered like the output of `tree`.
+#[derive(Debug, Clone, Default)]
+pub struct DirectoryTree {
+    root_name: String,
+    root: Node,
+}
+
+#[derive(Debug, Clone, Default)]
+struct Node {
+    dirs: BTreeMap<String, Node>,
+    files: BTreeMap<String, TreeMark>,
+}
+
+impl Node {
+    /// Returns the number of files in this directory and its subdirectories that are shown.
+    fn file_count(&self, options: &TreeOptions) -> usize {
+        let files = self.files.values().filter(|mark| options.show_ignored || **mark != TreeMark::Ignored).count();
+        files + self.dirs.values().map(|dir| dir.file_count(options)).sum::<usize>()
+    }
+
+    /// Returns `true` if every file in this directory and its subdirectories is ignored.
+    fn is_ignored(&self) -> bool {
+        self.files.values().all(|mark| *mark == TreeMark::Ignored) && self.dirs.values().all(Node::is_ignored)
+    }
+}
+
+/// A single line of a directory listing.
+enum Entry<'a> {
+    Dir(&'a str, &'a Node),
+    File(&'a str, &'a TreeMark),
+    More(usize),
+}
+
+impl DirectoryTree {
+    /// Creates an empty tree whose root is labeled with the given name.
+    pub fn new(root_name: impl Into<String>) -> Self {
+        DirectoryTree { root_name: root_name.into(), root: Node::default() }
+    }
+
+    /// Builds a tree from every file found in the input directory, marking the included files
+    /// as `Included` and the rest as `Ignored`.
+    ///
+    /// # Arguments
+    ///
+    /// * `root_name` - The label of the root directory.
+    /// * `base_dir` - The input directory, which the paths are made relative to.
+    /// * `all_files` - Every file found in the input directory.
+    /// * `included` - The files that passed the ignore rules.
+    pub fn from_files<'a>(
+        root_name: impl Into<String>,
+        base_dir: &Path,
+        all_files: impl IntoIterator<Item = &'a PathBuf>,
+        included: &[PathBuf],
+    ) -> Self {
+        let mut tree = DirectoryTree::new(root_name);
+        for file in all_files {
+            tree.insert(file.strip_prefix(base_dir).unwrap_or(file), TreeMark::Ignored);
+        }
+        for file in included {
+            tree.insert(file.strip_prefix(base_dir).unwrap_or(file), TreeMark::Included);
+        }
+        tree
+    }
+
+    /// Adds a file to the tree, replacing its mark if it was already added.
+    ///
+    /// # Arguments
+    ///
+    /// * `relative_path` - The path of the file, relative to the root of the tree.
+    /// * `mark` - How the file was handled.
+    pub fn insert(&mut self, relative_path: &Path, mark: TreeMark) {
+        let components: Vec<String> = relative_path
+            .components()
+            .map(|component| component.as_os_str().to_string_lossy().into_owned())
+            .collect();
+        let Some((file_name, dirs)) = components.split_last() else { return };
+        let mut node = &mut self.root;
+        for dir in dirs {
+            node = node.dirs.entry(dir.clone()).or_default();
+        }
+        node.files.insert(file_name.clone(), mark);
+    }
+
+    /// Renders the tree as text, one entry per line.
+    ///
+    /// Directories are listed before files, both in alphabetical order. Ignored and skipped
+    /// files are marked with `[ignored]` and `[skipped: <reason>]`, and a directory holding
+    /// only ignored files is shown as a single `[ignored]` line.
+    ///
+    /// # Arguments
+    ///
+    /// * `options` - A reference to the `TreeOptions` controlling the depth and collapsing.
+    ///
+    /// # Returns
+    ///
+    /// Returns a `String` holding the rendered tree, ending with a newline.
+    pub fn render(&self, options: &TreeOptions) -> String {
+        let mut output = format!("{}/\n", self.root_name);
+        render_dir(&self.root, "", 1, options, &mut output);
+        output
+    }
+}
+
+fn render_dir(node: &Node, prefix: &str, depth: usize, options: &TreeOptions, output: &mut String) {
+    let mut entries: Vec<Entry> = node
+        .dirs
+        .iter()
+        .filter(|(_, dir)| options.show_ignored || !dir.is_ignored())
+        .map(|(name, dir)| Entry::Dir(name, dir))
+        .collect();
+    let files: Vec<(&String, &TreeMark)> = node
+        .files
+        .iter()
+        .filter(|(_, mark)| options.show_ignored || **mark != TreeMark::Ignored)
+        .collect();
+    let shown_files = options.max_files_per_dir.map_or(files.len(), |max| max.min(files.len()));
+    entries.extend(files[..shown_files].iter().map(|(name, mark)| Entry::File(name, mark)));
+    if shown_files < files.len() {
+        entries.push(Entry::More(files.len() - shown_files));
+    }
+
+    let count = entries.len();
+    for (index, entry) in entries.into_iter().enumerate() {
+        let is_last = index + 1 == count;
+        let connector = if is_last { "└── " } else { "├── " };
+        match entry {
+            Entry::Dir(name, dir) if dir.is_ignored() => {
+                output.push_str(&format!("{}{}{}/  [ignored]\n", prefix, connector, name));
+            }
+            Entry::Dir(name, dir) if options.max_depth.is_some_and(|max_depth| depth >= max_depth) => {
+                output.push_str(&format!("{}{}{}/  … {} files\n", prefix, connector, name, dir.file_count(options)));
+            }
+            Entry::Dir(name, dir) => {
+                output.push_str(&format!("{}{}{}/\n", prefix, connector, name));
+                let child_prefix = format!("{}{}", prefix, if is_last { "    " } else { "│   " });
+                render_dir(dir, &child_prefix, depth + 1, options, output);
+            }
+            Entry::File(name, TreeMark::Included) => {
+                output.push_str(&format!("{}{}{}\n", prefix, connector, name));
+            }
+            Entry::File(name, TreeMark::Ignored) => {
+                output.push_str(&format!("{}{}{}  [ignored]\n", prefix, connector, name));
+            }
+            Entry::File(name, TreeMark::Skipped(reason)) => {
+                output.push_str(&format!("{}{}{}  [skipped: {}]\n", prefix, connector, name, reason));
+            }
+            Entry::More(remaining) => {
+                output.push_str(&format!("{}{}… {} more files\n", prefix, connector, remaining));
+            }
+        }
+    }
+}
@@ -0,0 +1,211 @@
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Read, Seek, SeekFrom};
+use clap::ValueEnum;
+use encoding_rs::{Encoding, UTF_16BE, UTF_16LE, UTF_8};
+use serde::{Deserialize, Serialize};
+use crate::encoding::{DecodeOptions, DecodedText, decode_fragment, detect_encoding, encoding_label, is_utf16};
+
+/// What to do with a file that exceeds `max_file_size`, or the space left under `max_total_size`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize, Serialize, ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum SizeLimitAction {
+    /// Leave the file out of the output.
+    #[default]
+    Skip,
+    /// Keep the start of the file, up to the limit.
+    Head,
+    /// Keep the start and the end of the file, half of the limit each, with an elision marker between them.
+    HeadTail,
+    /// Keep the first `truncate_lines` lines of the file, up to the limit.
+    Lines,
+}
+
+/// Limits on the size of the files read, in bytes on disk. Unset limits are not enforced.
+///
+/// - `max_file_size`: The maximum size of a single file.
+/// - `max_total_size`: The maximum size of all files together.
+/// - `action`: What to do with a file that exceeds a limit.
+/// - `max_lines`: The number of lines kept by `SizeLimitAction::Lines`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SizeLimits {
+    pub max_file_size: Option<u64>,
+    pub max_total_size: Option<u64>,
+    pub action: SizeLimitAction,
+    pub max_lines: usize,
+}
+
+/// Describes how a file was truncated to stay within a size limit.
+///
+/// - `original_bytes`: The size of the whole file in bytes.
+/// - `original_lines`: The number of lines in the whole file.
+/// - `head_lines`: The number of lines kept from the start of the file.
+/// - `tail_lines`: The number of lines kept from the end of the file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+pub struct Truncation {
+    pub original_bytes: u64,
+    pub original_lines: usize,
+    pub head_lines: usize,
+    pub tail_lines: usize,
+}
+
+impl Truncation {
+    /// Returns the number of lines that were left out.
+    pub fn elided_lines(&self) -> usize {
+        self.original_lines.saturating_sub(self.head_lines + self.tail_lines)
+    }
+
+    /// Returns the marker line inserted where content was left out.
+    pub fn marker(&self) -> String {
+        match self.elided_lines() {
+            0 => "[… line truncated …]\n".to_string(),
+            1 => "[… 1 line elided …]\n".to_string(),
+            elided => format!("[… {} lines elided …]\n", elided),
+        }
+    }
+}
+
+/// The length of the longest byte order mark, which is read in addition to the head of a file.
+const MAX_BOM_LENGTH: u64 = 3;
+
+/// Reads the part of a file that stays within a size limit, as chosen by the action.
+///
+/// Only the kept bytes are held in memory; the rest of the file is streamed through to count
+/// its lines. Kept content is cut at line boundaries where possible, and an elision marker
+/// (see `Truncation::marker`) is inserted where content was left out.
+///
+/// # Arguments
+///
+/// * `file` - The open file to read.
+/// * `size` - The size of the file in bytes.
+/// * `limit` - The maximum number of bytes to keep.
+/// * `limits` - A reference to the `SizeLimits` holding the action to take.
+/// * `decode` - A reference to the `DecodeOptions` used to decode the kept content.
+///
+/// # Returns
+///
+/// Returns the decoded kept content and a `Truncation` describing what was kept, or `None`
+/// if the content can't be decoded.
+///
+/// # Errors
+///
+/// This function will return an error if reading from the file fails.
+pub fn read_truncated(
+    file: &mut File,
+    size: u64,
+    limit: u64,
+    limits: &SizeLimits,
+    decode: &DecodeOptions,
+) -> io::Result<Option<(DecodedText, Truncation)>> {
+    let (head_budget, tail_budget) = match limits.action {
+        SizeLimitAction::HeadTail => (limit / 2, limit - limit / 2),
+        _ => (limit, 0),
+    };
+
+    file.seek(SeekFrom::Start(0))?;
+    let mut head_bytes = Vec::new();
+    file.by_ref().take(head_budget + MAX_BOM_LENGTH).read_to_end(&mut head_bytes)?;
+    let Some((encoding, bom_length)) = detect_encoding(&head_bytes, decode) else { return Ok(None) };
+    head_bytes.truncate(bom_length + head_budget as usize);
+    let (mut head, head_errors) = decode_fragment(&head_bytes[bom_length..], encoding);
+    cut_after_last_newline(&mut head);
+    if limits.action == SizeLimitAction::Lines {
+        keep_lines(&mut head, limits.max_lines);
+    }
+
+    let (tail, tail_errors) = if tail_budget > 0 {
+        read_tail(file, size, tail_budget, bom_length, encoding)?
+    } else {
+        (String::new(), false)
+    };
+
+    let had_errors = head_errors || tail_errors;
+    if had_errors && !decode.lossy {
+        return Ok(None);
+    }
+
+    let truncation = Truncation {
+        original_bytes: size,
+        original_lines: count_lines(file, encoding)?,
+        head_lines: head.lines().count(),
+        tail_lines: tail.lines().count(),
+    };
+    let mut content = head;
+    if !content.is_empty() && !content.ends_with('\n') {
+        content.push('\n');
+    }
+    content.push_str(&truncation.marker());
+    content.push_str(&tail);
+    Ok(Some((DecodedText { content, encoding: encoding_label(encoding, had_errors) }, truncation)))
+}
+
+/// Reads and decodes the last `budget` bytes of a file, starting at the next line boundary.
+fn read_tail(file: &mut File, size: u64, budget: u64, bom_length: usize, encoding: &'static Encoding) -> io::Result<(String, bool)> {
+    // The tail never reaches back into the byte order mark
+    let mut start = (size - budget).max(bom_length as u64);
+    if is_utf16(encoding) && (start - bom_length as u64) % 2 == 1 {
+        start += 1;
+    }
+    file.seek(SeekFrom::Start(start))?;
+    let mut bytes = Vec::with_capacity(budget as usize);
+    file.read_to_end(&mut bytes)?;
+    let mut offset = 0;
+    if encoding == UTF_8 {
+        // Skip the rest of a character cut off at the start
+        offset = bytes.iter().take_while(|byte| (**byte & 0xC0) == 0x80).count();
+    }
+    let (mut tail, had_errors) = decode_fragment(&bytes[offset..], encoding);
+    if let Some(newline) = tail.find('\n') {
+        tail.drain(..=newline);
+    }
+    Ok((tail, had_errors))
+}
+
+/// Cuts the text after its last newline, so only whole lines are kept.
+fn cut_after_last_newline(text: &mut String) {
+    if let Some(newline) = text.rfind('\n') {
+        text.truncate(newline + 1);
+    }
+}
+
+/// Keeps only the first `max_lines` lines of the text.
+fn keep_lines(text: &mut String, max_lines: usize) {
+    if max_lines == 0 {
+        text.clear();
+    } else if let Some((newline, _)) = text.match_indices('\n').nth(max_lines - 1) {
+        text.truncate(newline + 1);
+    }
+}
+
+/// Counts the lines in a whole file without holding it in memory.
+fn count_lines(file: &mut File, encoding: &'static Encoding) -> io::Result<usize> {
+    let newline: &[u8] = if encoding == UTF_16LE {
+        b"\n\0"
+    } else if encoding == UTF_16BE {
+        b"\0\n"
+    } else {
+        b"\n"
+    };
+    file.seek(SeekFrom::Start(0))?;
+    let mut reader = BufReader::with_capacity(64 * 1024, file);
+    let mut pending = Vec::new();
+    let mut lines = 0;
+    let mut ends_with_newline = true;
+    loop {
+        let buffer = reader.fill_buf()?;
+        if buffer.is_empty() {
+            break;
+        }
+        pending.extend_from_slice(buffer);
+        let length = buffer.len();
+        reader.consume(length);
+        let whole_units = pending.len() / newline.len() * newline.len();
+        for unit in pending[..whole_units].chunks_exact(newline.len()) {
+            ends_with_newline = unit == newline;
+            if ends_with_newline {
+                lines += 1;
+            }
+        }
+        pending.drain(..whole_units);
+    }
+    Ok(if ends_with_newline { lines } else { lines + 1 })
+}
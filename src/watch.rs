@@ -0,0 +1,312 @@
+use std::collections::BTreeSet;
+use std::path::{self, Component, Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::time::Duration;
+use anyhow::Result;
+use notify::event::ModifyKind;
+use notify::{Event, EventKind, RecursiveMode, Watcher};
+use crate::cli::{Cli, Generation, check_config, generate, input_path, report};
+use crate::config::Config;
+use crate::config_layers::{LayeredConfig, PROJECT_CONFIG_FILE_NAMES, load_layered_config};
+use crate::content_cache::ContentCache;
+use crate::ignore_rules::{IgnoreRules, is_included};
+use crate::output::OutputTarget;
+
+/// The names of files whose changes affect the ignore rules.
+const IGNORE_FILE_NAMES: [&str; 2] = [".gitignore", ".ignore"];
+
+/// The number of changed paths named in the summary of a regeneration.
+const SUMMARY_PATHS: usize = 3;
+
+/// How a change to a path affects the output.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PathKind {
+    /// The change doesn't affect the output, e.g. the output file itself or git internals.
+    Ignored,
+    /// A config or ignore file changed, so the configuration and ignore rules are rebuilt
+    /// and every file is read again.
+    RebuildTrigger,
+    /// A file that may be in the output changed, so only it is read again.
+    Source,
+}
+
+/// The rules deciding which changed files may be in the output.
+///
+/// - `ignore_rules`: The ignore rules of the configuration, with git's ignore rules if
+///   `respect_gitignore` is set.
+/// - `include_file_overrides`, `include_dir_overrides`: The overrides of the configuration.
+/// - `input_dir`: The absolute input directory, which the changed paths are under.
+pub struct SourceRules {
+    pub ignore_rules: IgnoreRules,
+    pub include_file_overrides: Vec<String>,
+    pub include_dir_overrides: Vec<String>,
+    pub input_dir: PathBuf,
+}
+
+impl SourceRules {
+    /// Builds the rules of the given configuration.
+    ///
+    /// # Arguments
+    ///
+    /// * `config` - A reference to the effective `Config`.
+    /// * `input_dir` - The absolute input directory.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the ignore rules are invalid.
+    pub fn new(config: &Config, input_dir: &Path) -> Result<Self> {
+        let mut ignore_rules = IgnoreRules::new(config)?;
+        if config.respect_gitignore {
+            ignore_rules = ignore_rules.with_gitignore(input_dir);
+        }
+        Ok(SourceRules {
+            ignore_rules,
+            include_file_overrides: config.include_file_overrides.clone(),
+            include_dir_overrides: config.include_dir_overrides.clone(),
+            input_dir: input_dir.to_path_buf(),
+        })
+    }
+
+    /// Returns `true` if the file passes the ignore rules.
+    pub fn includes(&self, path: &Path) -> bool {
+        is_included(&self.ignore_rules, path, &self.include_file_overrides, &self.include_dir_overrides, &self.input_dir)
+    }
+}
+
+/// The paths watch mode needs to tell apart when files change.
+///
+/// - `config_files`: The config files the configuration was loaded from.
+/// - `output`: Where the output was written last.
+/// - `sources`: The rules deciding which files may be in the output. Without them, every
+///   file outside `.git` counts as a source file.
+#[derive(Default)]
+pub struct WatchedPaths {
+    pub config_files: BTreeSet<PathBuf>,
+    pub output: Option<OutputTarget>,
+    pub sources: Option<SourceRules>,
+}
+
+impl WatchedPaths {
+    /// Classifies a changed path by how it affects the output.
+    pub fn classify(&self, path: &Path) -> PathKind {
+        if self.output.as_ref().is_some_and(|output| output.is_output_file(path)) {
+            return PathKind::Ignored;
+        }
+        let file_name = path.file_name().and_then(|name| name.to_str()).unwrap_or_default();
+        if self.config_files.contains(path)
+            || PROJECT_CONFIG_FILE_NAMES.contains(&file_name)
+            || IGNORE_FILE_NAMES.contains(&file_name)
+            || path.ends_with(".git/info/exclude")
+        {
+            return PathKind::RebuildTrigger;
+        }
+        if path.components().any(|component| component == Component::Normal(".git".as_ref())) {
+            return PathKind::Ignored;
+        }
+        if self.sources.as_ref().is_some_and(|sources| !sources.includes(path)) {
+            return PathKind::Ignored;
+        }
+        PathKind::Source
+    }
+}
+
+/// The changes collected during one debounce interval.
+///
+/// - `added`, `modified`, `removed`: The changed source files, by how they changed.
+/// - `rebuild_triggers`: The config and ignore files that changed.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ChangeBatch {
+    pub added: BTreeSet<PathBuf>,
+    pub modified: BTreeSet<PathBuf>,
+    pub removed: BTreeSet<PathBuf>,
+    pub rebuild_triggers: BTreeSet<PathBuf>,
+}
+
+impl ChangeBatch {
+    /// Records a file system event, merging it with the earlier events for the same paths.
+    /// Events that don't change file contents, such as reads and metadata changes, are ignored.
+    pub fn record(&mut self, event: &Event, watched: &WatchedPaths) {
+        for path in &event.paths {
+            let exists = path.exists();
+            match watched.classify(path) {
+                PathKind::Ignored => {}
+                PathKind::RebuildTrigger => {
+                    if !matches!(event.kind, EventKind::Access(_) | EventKind::Modify(ModifyKind::Metadata(_))) {
+                        self.rebuild_triggers.insert(path.clone());
+                    }
+                }
+                PathKind::Source => match event.kind {
+                    EventKind::Create(_) => self.record_added(path),
+                    EventKind::Modify(ModifyKind::Name(_)) if exists => self.record_added(path),
+                    EventKind::Modify(ModifyKind::Name(_)) | EventKind::Remove(_) => self.record_removed(path),
+                    EventKind::Modify(ModifyKind::Metadata(_)) => {}
+                    EventKind::Modify(_) if !self.added.contains(path) => {
+                        self.modified.insert(path.clone());
+                    }
+                    _ => {}
+                },
+            }
+        }
+    }
+
+    fn record_added(&mut self, path: &Path) {
+        if self.removed.remove(path) {
+            self.modified.insert(path.to_path_buf());
+        } else {
+            self.added.insert(path.to_path_buf());
+        }
+    }
+
+    fn record_removed(&mut self, path: &Path) {
+        self.modified.remove(path);
+        // A file added and removed within the same interval never affected the output
+        if !self.added.remove(path) {
+            self.removed.insert(path.to_path_buf());
+        }
+    }
+
+    /// Returns `true` if nothing that affects the output changed.
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.modified.is_empty() && self.removed.is_empty() && self.rebuild_triggers.is_empty()
+    }
+
+    /// Returns every changed source file.
+    pub fn source_paths(&self) -> impl Iterator<Item = &PathBuf> {
+        self.added.iter().chain(&self.modified).chain(&self.removed)
+    }
+
+    /// Returns the one-line summary logged after regenerating the output for these changes.
+    ///
+    /// # Arguments
+    ///
+    /// * `input_dir` - The input directory, which the changed paths are shown relative to.
+    /// * `generation` - A reference to the `Generation` of the regenerated output.
+    pub fn summary(&self, input_dir: &Path, generation: &Generation) -> String {
+        let relative = |path: &PathBuf| path.strip_prefix(input_dir).unwrap_or(path).display().to_string();
+        let (action, changes): (&str, Vec<String>) = if self.rebuild_triggers.is_empty() {
+            let changes = [(&self.added, "added"), (&self.modified, "modified"), (&self.removed, "removed")]
+                .into_iter()
+                .flat_map(|(paths, kind)| paths.iter().map(move |path| format!("{} ({})", relative(path), kind)))
+                .collect();
+            ("Regenerated", changes)
+        } else {
+            ("Rebuilt ignore rules and config", self.rebuild_triggers.iter().map(relative).collect())
+        };
+        let mut named = changes.iter().take(SUMMARY_PATHS).cloned().collect::<Vec<_>>().join(", ");
+        if changes.len() > SUMMARY_PATHS {
+            named.push_str(&format!(" and {} more", changes.len() - SUMMARY_PATHS));
+        }
+        format!(
+            "{} after changes to {}: {} files, {} tokens",
+            action, named, generation.files, generation.total_tokens
+        )
+    }
+}
+
+/// Generates the output, then watches the input directory and regenerates the output
+/// whenever files change, until the process is interrupted.
+///
+/// Changes are collected until none arrive for `watch_debounce_ms`, and then handled at
+/// once. Only the changed files are read again; the others are reused from a
+/// `ContentCache`. Changes to config or ignore files reload the configuration, rebuild the
+/// ignore rules and read every file again. Each regeneration logs a one-line summary.
+///
+/// # Arguments
+///
+/// * `cli` - A reference to the parsed command-line arguments.
+/// * `layered_config` - The configuration loaded for the first generation.
+///
+/// # Errors
+///
+/// This function will return an error if the first generation fails or the input directory
+/// can't be watched. Later failures, such as an invalid config file, are logged as warnings
+/// and the previous output is kept.
+pub fn watch(cli: &Cli, layered_config: LayeredConfig) -> Result<()> {
+    let config_files = absolute_paths(layered_config.config_files());
+    let mut config = layered_config.config;
+    let input_dir = input_path(&config)?;
+    let absolute_input_dir = path::absolute(&input_dir)?;
+    let mut cache = ContentCache::new();
+    let generation = generate(cli, config.clone(), &mut cache)?;
+    report(&generation);
+
+    let mut watched = WatchedPaths {
+        config_files,
+        output: Some(generation.target),
+        sources: Some(SourceRules::new(&config, &absolute_input_dir)?),
+    };
+
+    let (sender, receiver) = mpsc::channel();
+    let mut watcher = notify::recommended_watcher(sender)?;
+    watcher.watch(&absolute_input_dir, RecursiveMode::Recursive)?;
+    // Config files outside the input directory are watched through their directory, so
+    // editors that save by replacing the file are noticed too
+    for config_file in &watched.config_files {
+        if let Some(config_dir) = config_file.parent().filter(|dir| !dir.starts_with(&absolute_input_dir)) {
+            watcher.watch(config_dir, RecursiveMode::NonRecursive)?;
+        }
+    }
+    crate::status!("Watching {} for changes", input_dir.display());
+
+    let debounce = Duration::from_millis(config.watch_debounce_ms);
+    while let Some(events) = next_batch(&receiver, debounce) {
+        let mut batch = ChangeBatch::default();
+        for event in events {
+            match event {
+                Ok(event) => batch.record(&event, &watched),
+                Err(err) => crate::warning!("Watch error: {}", err),
+            }
+        }
+        if batch.is_empty() {
+            continue;
+        }
+
+        if batch.rebuild_triggers.is_empty() {
+            for path in batch.source_paths() {
+                // The cache is keyed by paths as generate lists them, under the configured input directory
+                let relative_path = path.strip_prefix(&absolute_input_dir).unwrap_or(path);
+                cache.invalidate(&input_dir.join(relative_path));
+            }
+        } else {
+            match load_layered_config(cli).and_then(|layered_config| check_config(&layered_config).map(|_| layered_config)) {
+                Ok(layered_config) => {
+                    watched.config_files = absolute_paths(layered_config.config_files());
+                    config = layered_config.config;
+                }
+                Err(err) => crate::warning!("Keeping the previous configuration: {:#}", err),
+            }
+            // The ignore files may have changed too, so the rules are rebuilt either way
+            match SourceRules::new(&config, &absolute_input_dir) {
+                Ok(sources) => watched.sources = Some(sources),
+                Err(err) => crate::warning!("Keeping the previous ignore rules: {:#}", err),
+            }
+            cache.clear();
+        }
+
+        match generate(cli, config.clone(), &mut cache) {
+            Ok(generation) => {
+                crate::status!("{}", batch.summary(&absolute_input_dir, &generation));
+                watched.output = Some(generation.target);
+            }
+            Err(err) => crate::warning!("Regenerating the output failed: {:#}", err),
+        }
+    }
+    Ok(())
+}
+
+/// Waits for the next event, then collects events until none arrive for the debounce interval.
+///
+/// Returns `None` when the watcher has stopped.
+fn next_batch<T>(receiver: &Receiver<T>, debounce: Duration) -> Option<Vec<T>> {
+    let mut events = vec![receiver.recv().ok()?];
+    loop {
+        match receiver.recv_timeout(debounce) {
+            Ok(event) => events.push(event),
+            Err(RecvTimeoutError::Timeout | RecvTimeoutError::Disconnected) => return Some(events),
+        }
+    }
+}
+
+fn absolute_paths(paths: impl IntoIterator<Item = PathBuf>) -> BTreeSet<PathBuf> {
+    paths.into_iter().filter_map(|path| path::absolute(path).ok()).collect()
+}
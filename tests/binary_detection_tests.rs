@@ -0,0 +1,35 @@
+use conag::binary_detection::{is_binary, read_prefix, SNIFF_LEN};
+use std::fs::{self, File};
+use tempfile::TempDir;
+
+#[test]
+fn test_text_is_not_binary() {
+    assert!(!is_binary(b"fn main() {\n    println!(\"caf\xc3\xa9\");\n}\n"));
+    assert!(!is_binary(b""));
+}
+
+#[test]
+fn test_nul_bytes_and_magic_numbers_are_binary() {
+    assert!(is_binary(b"some text\x00more text"));
+    assert!(is_binary(b"\x89PNG\r\n\x1a\n"));
+    assert!(is_binary(b"%PDF-1.7\n"));
+    assert!(is_binary(b"\x7fELF\x02\x01\x01"));
+}
+
+#[test]
+fn test_utf16_text_is_not_binary() {
+    assert!(!is_binary(b"\xff\xfeh\x00i\x00"));
+    assert!(!is_binary(b"\xfe\xff\x00h\x00i"));
+    assert!(!is_binary(b"f\x00n\x00 \x00m\x00a\x00i\x00n\x00"));
+}
+
+#[test]
+fn test_read_prefix_reads_at_most_sniff_len() {
+    let temp_dir = TempDir::new().unwrap();
+    let path = temp_dir.path().join("large.txt");
+    fs::write(&path, "a".repeat(SNIFF_LEN * 3)).unwrap();
+
+    let prefix = read_prefix(&mut File::open(&path).unwrap()).unwrap();
+
+    assert_eq!(prefix.len(), SNIFF_LEN);
+}
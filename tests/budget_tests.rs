@@ -0,0 +1,127 @@
+use conag::aggregator::FileContent;
+use conag::budget::{apply_token_budget, BudgetPriority, OmittedFile, TokenBudget};
+use conag::formatter::{render_document, Footer, FormatOptions, Header, MarkdownFormatter};
+use conag::tokenizer::{CharsHeuristic, Tokenizer};
+use std::cell::Cell;
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+fn render(files: &[FileContent], omitted: &[OmittedFile]) -> String {
+    render_document(&MarkdownFormatter, &FormatOptions::default(), &Header::new("Test Project"), None, files, &Footer { omitted, ..Footer::default() })
+}
+
+fn test_files() -> Vec<FileContent> {
+    vec![
+        FileContent::new("src/main.rs", "m".repeat(400)),
+        FileContent::new("README.md", "r".repeat(40)),
+        FileContent::new("tests/test.rs", "t".repeat(200)),
+    ]
+}
+
+fn budget(max_tokens: usize, priority: BudgetPriority) -> TokenBudget {
+    TokenBudget { max_tokens, priority, priority_globs: BTreeMap::new() }
+}
+
+fn paths(files: &[FileContent]) -> Vec<PathBuf> {
+    files.iter().map(|file| file.path.clone()).collect()
+}
+
+#[test]
+fn test_everything_fits_within_budget() {
+    let (kept, omitted) = apply_token_budget(test_files(), &budget(10_000, BudgetPriority::Order), &CharsHeuristic, render).unwrap();
+
+    assert_eq!(kept, test_files());
+    assert!(omitted.is_empty());
+}
+
+#[test]
+fn test_order_priority_keeps_files_in_output_order() {
+    let (kept, omitted) = apply_token_budget(test_files(), &budget(170, BudgetPriority::Order), &CharsHeuristic, render).unwrap();
+
+    assert_eq!(paths(&kept), vec![PathBuf::from("src/main.rs"), PathBuf::from("README.md")]);
+    assert_eq!(paths_of_omitted(&omitted), vec![PathBuf::from("tests/test.rs")]);
+    assert!(CharsHeuristic.count_tokens(&render(&kept, &omitted)) <= 170);
+}
+
+#[test]
+fn test_smallest_first_priority() {
+    let (kept, omitted) = apply_token_budget(test_files(), &budget(130, BudgetPriority::SmallestFirst), &CharsHeuristic, render).unwrap();
+
+    assert_eq!(paths(&kept), vec![PathBuf::from("README.md"), PathBuf::from("tests/test.rs")]);
+    assert_eq!(paths_of_omitted(&omitted), vec![PathBuf::from("src/main.rs")]);
+}
+
+#[test]
+fn test_glob_priority() {
+    let mut token_budget = budget(170, BudgetPriority::Globs);
+    token_budget.priority_globs.insert("tests/**".to_string(), 10);
+    token_budget.priority_globs.insert("src/**".to_string(), 5);
+
+    let (kept, omitted) = apply_token_budget(test_files(), &token_budget, &CharsHeuristic, render).unwrap();
+
+    assert_eq!(paths(&kept), vec![PathBuf::from("README.md"), PathBuf::from("tests/test.rs")]);
+    assert_eq!(paths_of_omitted(&omitted), vec![PathBuf::from("src/main.rs")]);
+}
+
+#[test]
+fn test_omitted_files_are_listed_in_trailer() {
+    let (kept, omitted) = apply_token_budget(test_files(), &budget(170, BudgetPriority::Order), &CharsHeuristic, render).unwrap();
+    let output = render(&kept, &omitted);
+
+    assert!(output.contains("## Omitted Files"));
+    assert!(output.contains(&format!("- tests/test.rs ({} tokens)", omitted[0].tokens)));
+    assert!(!output.contains("ttttt"));
+}
+
+#[test]
+fn test_output_is_not_rendered_again_for_every_dropped_file() {
+    let files: Vec<FileContent> = (0..60).map(|index| FileContent::new(format!("src/file{:03}.rs", index), "x".repeat(200))).collect();
+    let renders = Cell::new(0);
+    let counting_render = |files: &[FileContent], omitted: &[OmittedFile]| {
+        renders.set(renders.get() + 1);
+        render(files, omitted)
+    };
+
+    let (kept, omitted) = apply_token_budget(files.clone(), &budget(2000, BudgetPriority::Order), &CharsHeuristic, counting_render).unwrap();
+
+    assert!(CharsHeuristic.count_tokens(&render(&kept, &omitted)) <= 2000);
+    assert_eq!(kept.len() + omitted.len(), files.len());
+    // One render per file to measure its cost, one for the empty output, one with every file
+    // omitted and one full one
+    assert!(renders.get() <= files.len() + 3, "{} renders", renders.get());
+}
+
+#[test]
+fn test_trailer_lines_of_dropped_files_count_against_the_budget() {
+    let files: Vec<FileContent> = (0..9).map(|index| FileContent::new(format!("src/module_{}.rs", index), "x".repeat(40))).collect();
+    let base_tokens = CharsHeuristic.count_tokens(&render(&[], &[]));
+    let all_omitted: Vec<OmittedFile> = files
+        .iter()
+        .map(|file| {
+            let tokens = CharsHeuristic.count_tokens(&render(std::slice::from_ref(file), &[])) - base_tokens;
+            OmittedFile { path: file.path.clone(), tokens }
+        })
+        .collect();
+    // Keeping a file takes more tokens than its line in the trailer, so this only leaves room for one
+    let max_tokens = CharsHeuristic.count_tokens(&render(&[], &all_omitted)) + 15;
+
+    let (kept, omitted) = apply_token_budget(files.clone(), &budget(max_tokens, BudgetPriority::Order), &CharsHeuristic, render).unwrap();
+
+    assert_eq!(paths(&kept), vec![PathBuf::from("src/module_0.rs")]);
+    assert_eq!(omitted.len(), files.len() - 1);
+    assert!(CharsHeuristic.count_tokens(&render(&kept, &omitted)) <= max_tokens);
+}
+
+#[test]
+fn test_budget_too_small_for_the_trailer_is_an_error() {
+    let files: Vec<FileContent> = (0..9).map(|index| FileContent::new(format!("src/module_{}.rs", index), "x".repeat(40))).collect();
+
+    let err = apply_token_budget(files, &budget(20, BudgetPriority::Order), &CharsHeuristic, render).unwrap_err();
+
+    assert_eq!(err.max_tokens, 20);
+    assert!(err.tokens > 20);
+}
+
+fn paths_of_omitted(omitted: &[OmittedFile]) -> Vec<PathBuf> {
+    omitted.iter().map(|file| file.path.clone()).collect()
+}
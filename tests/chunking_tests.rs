@@ -0,0 +1,128 @@
+use conag::aggregator::FileContent;
+use conag::budget::OmittedFile;
+use conag::chunking::{part_path, remove_stale_parts, split_into_parts, ChunkLimit, PartInfo};
+use conag::formatter::{render_document, Footer, FormatOptions, Header, MarkdownFormatter};
+use conag::tokenizer::{CharsHeuristic, Tokenizer};
+use std::fs;
+use std::path::{Path, PathBuf};
+use tempfile::TempDir;
+
+fn render(files: &[FileContent], part: &PartInfo, footer: &Footer) -> String {
+    render_document(&MarkdownFormatter, &FormatOptions::default(), &Header::new("Test Project"), Some(part), files, footer)
+}
+
+fn byte_limit(max_bytes: usize) -> ChunkLimit {
+    ChunkLimit { max_bytes: Some(max_bytes), max_tokens: None }
+}
+
+fn numbered_lines(count: usize) -> String {
+    (1..=count).map(|line| format!("line {}\n", line)).collect()
+}
+
+#[test]
+fn test_small_output_is_a_single_part() {
+    let files = vec![FileContent::new("a.txt", "a"), FileContent::new("b.txt", "b")];
+
+    let parts = split_into_parts(&files, &Footer::default(), &byte_limit(10_000), &CharsHeuristic, render);
+
+    assert_eq!(parts.len(), 1);
+    assert!(parts[0].contains("Part 1 of 1"));
+    assert!(parts[0].contains("## File: a.txt"));
+    assert!(parts[0].contains("## File: b.txt"));
+}
+
+#[test]
+fn test_parts_split_at_file_boundaries() {
+    let files = vec![
+        FileContent::new("a.txt", "a".repeat(300)),
+        FileContent::new("b.txt", "b".repeat(300)),
+        FileContent::new("c.txt", "c".repeat(300)),
+    ];
+
+    let parts = split_into_parts(&files, &Footer::default(), &byte_limit(800), &CharsHeuristic, render);
+
+    assert_eq!(parts.len(), 2);
+    for part in &parts {
+        assert!(part.len() <= 800);
+        assert!(part.starts_with("# Project: Test Project"));
+    }
+    assert!(parts[0].contains("Part 1 of 2"));
+    assert!(parts[0].contains("- a.txt\n- b.txt\n"));
+    assert!(parts[1].contains("Part 2 of 2"));
+    assert!(parts[1].contains("## File: c.txt"));
+}
+
+#[test]
+fn test_oversized_file_is_split_at_line_boundaries() {
+    let files = vec![FileContent::new("big.txt", numbered_lines(100))];
+
+    let parts = split_into_parts(&files, &Footer::default(), &byte_limit(400), &CharsHeuristic, render);
+
+    assert!(parts.len() > 1);
+    assert!(parts[0].contains("## File: big.txt (lines 1-"));
+    assert!(parts[1].contains("## File: big.txt (continued, lines "));
+    assert!(parts.last().unwrap().contains("line 100\n"));
+    for part in &parts {
+        assert!(part.len() <= 400);
+    }
+    let all_lines: String = parts.concat();
+    for line in 1..=100 {
+        assert_eq!(all_lines.matches(&format!("line {}\n", line)).count(), 1);
+    }
+}
+
+#[test]
+fn test_token_limit() {
+    let files: Vec<FileContent> = (0..10)
+        .map(|index| FileContent::new(format!("file{}.txt", index), "word ".repeat(50)))
+        .collect();
+
+    let limit = ChunkLimit { max_bytes: None, max_tokens: Some(200) };
+    let parts = split_into_parts(&files, &Footer::default(), &limit, &CharsHeuristic, render);
+
+    assert!(parts.len() > 1);
+    for part in &parts {
+        assert!(CharsHeuristic.count_tokens(part) <= 200);
+    }
+}
+
+#[test]
+fn test_trailer_is_appended_to_last_part() {
+    let files = vec![FileContent::new("a.txt", "a".repeat(300)), FileContent::new("b.txt", "b".repeat(300))];
+
+    let omitted = vec![OmittedFile { path: PathBuf::from("c.txt"), tokens: 75 }];
+    let parts = split_into_parts(&files, &Footer { omitted: &omitted, ..Footer::default() }, &byte_limit(500), &CharsHeuristic, render);
+
+    assert!(parts.last().unwrap().ends_with("- c.txt (75 tokens)\n"));
+    assert_eq!(parts.iter().filter(|part| part.contains("## Omitted Files")).count(), 1);
+}
+
+#[test]
+fn test_part_path() {
+    let output_path = Path::new("/out/project_conag_output.md");
+
+    assert_eq!(
+        part_path(output_path, &PartInfo { number: 1, total: 3 }),
+        PathBuf::from("/out/project_conag_output.part01.md")
+    );
+    assert_eq!(
+        part_path(output_path, &PartInfo { number: 7, total: 120 }),
+        PathBuf::from("/out/project_conag_output.part007.md")
+    );
+}
+
+#[test]
+fn test_remove_stale_parts() {
+    let temp_dir = TempDir::new().unwrap();
+    let output_path = temp_dir.path().join("project_conag_output.md");
+    for name in ["project_conag_output.part01.md", "project_conag_output.part02.md", "other.part01.md", "project_conag_output.md"] {
+        fs::write(temp_dir.path().join(name), "").unwrap();
+    }
+
+    remove_stale_parts(&output_path).unwrap();
+
+    assert!(!temp_dir.path().join("project_conag_output.part01.md").exists());
+    assert!(!temp_dir.path().join("project_conag_output.part02.md").exists());
+    assert!(temp_dir.path().join("other.part01.md").exists());
+    assert!(output_path.exists());
+}
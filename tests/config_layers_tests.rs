@@ -0,0 +1,206 @@
+use conag::cli::Cli;
+use conag::config_layers::{find_project_config, ConfigLayer, LayerSource, LayeredConfig};
+use conag::sorting::SortMode;
+use clap::Parser;
+use std::fs;
+use tempfile::TempDir;
+use toml::{Table, Value};
+
+fn layer(source: LayerSource, toml_str: &str) -> ConfigLayer {
+    ConfigLayer::new(source, toml::from_str::<Table>(toml_str).unwrap())
+}
+
+#[test]
+fn test_higher_layers_replace_values() {
+    let layers = vec![
+        ConfigLayer::defaults().unwrap(),
+        layer(LayerSource::Global, r#"
+            output_dir = "/global/output"
+            ignore_patterns = ["*.log"]
+        "#),
+        layer(LayerSource::Project, r#"
+            ignore_patterns = ["*.tmp"]
+            sort = "tree"
+        "#),
+    ];
+
+    let layered = LayeredConfig::from_layers(&layers).unwrap();
+
+    assert_eq!(layered.config.output_dir, "/global/output");
+    assert_eq!(layered.config.ignore_patterns, vec!["*.tmp"]);
+    assert_eq!(layered.config.sort, SortMode::Tree);
+    assert_eq!(layered.config.input_dir, ".");
+    assert_eq!(layered.origins["output_dir"].source, LayerSource::Global);
+    assert_eq!(layered.origins["ignore_patterns[0]"].source, LayerSource::Project);
+    assert_eq!(layered.origins["input_dir"].source, LayerSource::Default);
+}
+
+#[test]
+fn test_extend_appends_to_lists() {
+    let layers = vec![
+        ConfigLayer::defaults().unwrap(),
+        layer(LayerSource::Global, r#"ignore_patterns = ["*.log"]"#),
+        layer(LayerSource::Project, r#"extend_ignore_patterns = ["*.tmp", "*.bak"]"#),
+    ];
+
+    let layered = LayeredConfig::from_layers(&layers).unwrap();
+
+    assert_eq!(layered.config.ignore_patterns, vec!["*.log", "*.tmp", "*.bak"]);
+    assert_eq!(layered.origins["ignore_patterns[0]"].source, LayerSource::Global);
+    assert_eq!(layered.origins["ignore_patterns[1]"].source, LayerSource::Project);
+    assert_eq!(layered.origins["ignore_patterns[2]"].source, LayerSource::Project);
+}
+
+#[test]
+fn test_tables_merge_by_entry() {
+    let layers = vec![
+        ConfigLayer::defaults().unwrap(),
+        layer(LayerSource::Global, r#"
+            [project_specific_ignores]
+            rust = ["target/*"]
+            python = ["*.pyc"]
+        "#),
+        layer(LayerSource::Project, r#"
+            [project_specific_ignores]
+            python = ["__pycache__/*"]
+        "#),
+    ];
+
+    let layered = LayeredConfig::from_layers(&layers).unwrap();
+    let ignores = &layered.config.project_specific_ignores;
+
+    assert_eq!(ignores["rust"], vec!["target/*"]);
+    assert_eq!(ignores["python"], vec!["__pycache__/*"]);
+    assert_eq!(layered.origins["project_specific_ignores.rust[0]"].source, LayerSource::Global);
+    assert_eq!(layered.origins["project_specific_ignores.python[0]"].source, LayerSource::Project);
+}
+
+#[test]
+fn test_env_layer_parses_values_by_type() {
+    let defaults = ConfigLayer::defaults().unwrap();
+    let vars = vec![
+        ("CONAG_IGNORE_PATTERNS".to_string(), "*.log, *.tmp".to_string()),
+        ("CONAG_RESPECT_GITIGNORE".to_string(), "false".to_string()),
+        ("CONAG_OUTPUT_DIR".to_string(), "/env/output".to_string()),
+        ("OTHER_VARIABLE".to_string(), "ignored".to_string()),
+    ];
+    let env_layer = ConfigLayer::from_env_vars(vars, &defaults.values);
+
+    let layered = LayeredConfig::from_layers(&[defaults, env_layer]).unwrap();
+
+    assert_eq!(layered.config.ignore_patterns, vec!["*.log", "*.tmp"]);
+    assert!(!layered.config.respect_gitignore);
+    assert_eq!(layered.config.output_dir, "/env/output");
+    assert_eq!(layered.origins["output_dir"].source, LayerSource::Environment);
+}
+
+#[test]
+fn test_env_layer_only_parses_integer_keys_as_integers() {
+    let defaults = ConfigLayer::defaults().unwrap();
+    let vars = vec![
+        ("CONAG_MAX_TOKENS".to_string(), "5000".to_string()),
+        ("CONAG_FALLBACK_ENCODING".to_string(), "1252".to_string()),
+        ("CONAG_UNKNOWN_KEY".to_string(), "42".to_string()),
+    ];
+
+    let env_layer = ConfigLayer::from_env_vars(vars, &defaults.values);
+
+    assert_eq!(env_layer.values["max_tokens"], Value::Integer(5000));
+    assert_eq!(env_layer.values["fallback_encoding"], Value::String("1252".to_string()));
+    assert_eq!(env_layer.values["unknown_key"], Value::String("42".to_string()));
+}
+
+#[test]
+fn test_cli_layer_has_highest_precedence() {
+    let cli = Cli::parse_from(["conag", "--include-file", "a.txt,b.txt", "--no-gitignore", "--sort", "size"]);
+    let layers = vec![
+        ConfigLayer::defaults().unwrap(),
+        layer(LayerSource::Project, r#"
+            include_file_overrides = ["c.txt"]
+            sort = "tree"
+        "#),
+        ConfigLayer::from_cli(&cli),
+    ];
+
+    let layered = LayeredConfig::from_layers(&layers).unwrap();
+
+    assert_eq!(layered.config.include_file_overrides, vec!["a.txt", "b.txt"]);
+    assert!(!layered.config.respect_gitignore);
+    assert_eq!(layered.config.sort, SortMode::Size);
+    assert_eq!(layered.origins["sort"].source, LayerSource::Cli);
+}
+
+#[test]
+fn test_find_project_config_walks_up() {
+    let temp_dir = TempDir::new().unwrap();
+    let nested = temp_dir.path().join("a").join("b");
+    fs::create_dir_all(&nested).unwrap();
+    fs::write(temp_dir.path().join("conag.toml"), "").unwrap();
+
+    assert_eq!(find_project_config(&nested), Some(temp_dir.path().join("conag.toml")));
+
+    fs::write(temp_dir.path().join(".conag.toml"), "").unwrap();
+    assert_eq!(find_project_config(&nested), Some(temp_dir.path().join(".conag.toml")));
+
+    fs::write(nested.join("conag.toml"), "").unwrap();
+    assert_eq!(find_project_config(&nested), Some(nested.join("conag.toml")));
+}
+
+#[test]
+fn test_find_project_config_stops_at_the_repository_root() {
+    let temp_dir = TempDir::new().unwrap();
+    let repo = temp_dir.path().join("repo");
+    let nested = repo.join("src");
+    fs::create_dir_all(&nested).unwrap();
+    fs::create_dir(repo.join(".git")).unwrap();
+    fs::write(temp_dir.path().join("conag.toml"), "").unwrap();
+
+    assert_eq!(find_project_config(&nested), None);
+
+    fs::write(repo.join("conag.toml"), "").unwrap();
+    assert_eq!(find_project_config(&nested), Some(repo.join("conag.toml")));
+}
+
+#[test]
+fn test_render_with_origin() {
+    let temp_dir = TempDir::new().unwrap();
+    let project_path = temp_dir.path().join(".conag.toml");
+    fs::write(&project_path, "extend_ignore_patterns = [\"*.tmp\"]\n").unwrap();
+    let layers = vec![
+        ConfigLayer::defaults().unwrap(),
+        layer(LayerSource::Global, r#"ignore_patterns = ["*.log"]"#),
+        ConfigLayer::from_file(LayerSource::Project, &project_path).unwrap(),
+    ];
+
+    let layered = LayeredConfig::from_layers(&layers).unwrap();
+    let rendered = layered.render(true);
+
+    assert!(rendered.contains("\"*.log\",  # global\n"));
+    assert!(rendered.contains(&format!("\"*.tmp\",  # project ({})", project_path.display())));
+    assert!(rendered.contains("input_dir = \".\"  # default"));
+    assert!(!layered.render(false).contains('#'));
+}
+
+#[test]
+fn test_shipped_ignores_apply_with_a_project_config_only() {
+    let temp_dir = TempDir::new().unwrap();
+    let home = temp_dir.path().join("home");
+    let project = temp_dir.path().join("project");
+    fs::create_dir_all(&home).unwrap();
+    fs::create_dir_all(project.join(".git/hooks")).unwrap();
+    fs::create_dir_all(project.join("src")).unwrap();
+    fs::write(project.join(".git/HEAD"), "ref: refs/heads/main\n").unwrap();
+    fs::write(project.join(".git/hooks/pre-commit.sample"), "#!/bin/sh\n").unwrap();
+    fs::write(project.join("src/main.rs"), "fn main() {}\n").unwrap();
+    fs::write(project.join("debug.log"), "log\n").unwrap();
+    fs::write(project.join(".conag.toml"), "sort = \"tree\"\n").unwrap();
+
+    assert_cmd::Command::cargo_bin("conag")
+        .unwrap()
+        .current_dir(&project)
+        .env("HOME", &home)
+        .arg("--list")
+        .assert()
+        .success()
+        .stdout("src/main.rs\n");
+}
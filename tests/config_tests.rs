@@ -43,6 +43,7 @@ fn test_should_include_hidden() {
         include_hidden_patterns: vec![".gitignore".to_string(), ".env*".to_string()],
         include_file_overrides: vec![],
         include_dir_overrides: vec![],
+        ..Config::default()
     };
 
     assert!(config.should_include_hidden(".gitignore"));
@@ -63,6 +64,7 @@ fn test_get_ignore_patterns_includes_hidden_files() {
         include_hidden_patterns: vec![],
         include_file_overrides: vec![],
         include_dir_overrides: vec![],
+        ..Config::default()
     };
 
     let patterns = config.get_ignore_patterns();
@@ -81,6 +83,7 @@ fn test_resolve_output_dir() {
         include_hidden_patterns: vec![],
         include_file_overrides: vec![],
         include_dir_overrides: vec![],
+        ..Config::default()
     };
 
     config.resolve_output_dir().unwrap();
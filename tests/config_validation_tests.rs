@@ -0,0 +1,111 @@
+use assert_cmd::Command;
+use conag::config::Config;
+use conag::config_layers::{ConfigLayer, LayerSource, LayeredConfig};
+use conag::config_validation::{suggest_key, validate_config, validate_layer, Location, Severity};
+use conag::ignore_rules::IgnoreRules;
+use predicates::prelude::*;
+use std::fs;
+use std::path::Path;
+use tempfile::TempDir;
+
+fn file_layer(dir: &Path, toml_str: &str) -> ConfigLayer {
+    let path = dir.join("config.toml");
+    fs::write(&path, toml_str).unwrap();
+    ConfigLayer::from_file(LayerSource::Global, &path).unwrap()
+}
+
+#[test]
+fn test_invalid_globs_are_located() {
+    let temp_dir = TempDir::new().unwrap();
+    let layer = file_layer(temp_dir.path(), "ignore_patterns = [\n    \"*.log\",\n    \"[abc\",\n]\n\n[project_specific_ignores]\nrust = [\"tar**get\"]\n");
+
+    let issues = validate_layer(&layer);
+
+    assert_eq!(issues.len(), 2);
+    assert_eq!(issues[0].severity, Severity::Error);
+    assert_eq!(issues[0].key, "ignore_patterns[1]");
+    assert_eq!(issues[0].location, Some(Location { line: 3, column: 5 }));
+    assert!(issues[0].message.contains("'[abc'"));
+    assert_eq!(issues[1].key, "project_specific_ignores.rust[0]");
+    assert_eq!(issues[1].location, Some(Location { line: 7, column: 9 }));
+    assert!(issues[0].to_string().starts_with(&format!("{}:3:5: error: invalid glob pattern", temp_dir.path().join("config.toml").display())));
+}
+
+#[test]
+fn test_unknown_keys_are_warnings_with_suggestions() {
+    let temp_dir = TempDir::new().unwrap();
+    let layer = file_layer(temp_dir.path(), "sort = \"path\"\nignore_pattern = []\nextend_include_hiden_patterns = []\ncompletely_unrelated = 1\n");
+
+    let issues = validate_layer(&layer);
+
+    let messages: Vec<&str> = issues.iter().map(|issue| issue.message.as_str()).collect();
+    assert_eq!(messages, [
+        "unknown key 'completely_unrelated'",
+        "unknown key 'extend_include_hiden_patterns', did you mean 'extend_include_hidden_patterns'?",
+        "unknown key 'ignore_pattern', did you mean 'ignore_patterns'?",
+    ]);
+    assert!(issues.iter().all(|issue| issue.severity == Severity::Warning));
+    assert_eq!(issues[2].location, Some(Location { line: 2, column: 1 }));
+    assert_eq!(suggest_key("max_token"), Some("max_tokens"));
+}
+
+#[test]
+fn test_environment_issues_have_no_location() {
+    let defaults = ConfigLayer::defaults().unwrap();
+    let env_layer = ConfigLayer::from_env_vars(
+        [("CONAG_IGNORE_PATTERNS".to_string(), "*.log,[abc".to_string()), ("CONAG_SORTT".to_string(), "size".to_string())],
+        &defaults.values,
+    );
+    let layered = LayeredConfig::from_layers(&[defaults, env_layer]).unwrap();
+
+    let issues = validate_config(&layered);
+
+    assert_eq!(issues.len(), 2);
+    assert_eq!(issues[0].to_string(), "env: error: invalid glob pattern '[abc' in ignore_patterns[1]: invalid range pattern");
+    assert_eq!(issues[1].to_string(), "env: warning: unknown key 'sortt', did you mean 'sort'?");
+}
+
+#[test]
+fn test_default_config_is_valid() {
+    let layer = ConfigLayer::from_file(LayerSource::Global, Path::new("config/default_config.toml")).unwrap();
+
+    assert_eq!(validate_layer(&layer), []);
+}
+
+#[test]
+fn test_ignore_rules_report_invalid_patterns() {
+    let config = Config { ignore_patterns: vec!["[abc".to_string()], ..Config::default() };
+
+    let err = IgnoreRules::new(&config).err().unwrap();
+
+    assert_eq!(err.to_string(), "Invalid glob pattern '[abc'");
+}
+
+#[test]
+fn test_config_check_command() {
+    let temp_dir = TempDir::new().unwrap();
+    let config_path = temp_dir.path().join("config.toml");
+    let conag = || {
+        let mut command = Command::cargo_bin("conag").unwrap();
+        command.current_dir(temp_dir.path()).args(["--config", config_path.to_str().unwrap(), "config", "check"]);
+        command
+    };
+
+    fs::write(&config_path, "ignore_pattern = [\"*.log\"]\n").unwrap();
+    conag()
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("config.toml:1:1: warning: unknown key 'ignore_pattern', did you mean 'ignore_patterns'?"));
+
+    fs::write(&config_path, "ignore_patterns = [\"[abc\"]\n").unwrap();
+    conag()
+        .assert()
+        .failure()
+        .stdout(predicate::str::contains("config.toml:1:20: error: invalid glob pattern '[abc'"))
+        .stderr(predicate::str::contains("Found 1 errors and 0 warnings"));
+
+    // A normal run stops before reading any files
+    let mut run = Command::cargo_bin("conag").unwrap();
+    run.current_dir(temp_dir.path()).args(["--config", config_path.to_str().unwrap(), "--output", "-"]);
+    run.assert().failure().stdout(predicate::str::is_empty()).stderr(predicate::str::contains("Invalid configuration"));
+}
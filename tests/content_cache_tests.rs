@@ -0,0 +1,196 @@
+use assert_cmd::Command;
+use conag::aggregator::{aggregate_contents_cached, ReadOptions};
+use conag::content_cache::{clear_cache, ContentCache, DiskCacheOptions};
+use conag::encoding::DecodeOptions;
+use predicates::prelude::*;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+use tempfile::TempDir;
+
+fn disk_options(dir: &Path) -> DiskCacheOptions {
+    DiskCacheOptions { dir: dir.to_path_buf(), max_size: 1024 * 1024, hash_contents: false }
+}
+
+fn aggregate(files: &[PathBuf], base_dir: &Path, options: &ReadOptions, cache: &mut ContentCache) -> String {
+    let (contents, _) = aggregate_contents_cached(files, base_dir, options, cache);
+    contents[0].content.clone()
+}
+
+/// Rewrites a file with content of the same size, then sets its modification time.
+fn rewrite(path: &Path, content: &str, modified: SystemTime) {
+    fs::write(path, content).unwrap();
+    fs::File::options().write(true).open(path).unwrap().set_modified(modified).unwrap();
+}
+
+fn cache_file_names(dir: &Path) -> Vec<PathBuf> {
+    fs::read_dir(dir).unwrap().map(|entry| entry.unwrap().path()).collect()
+}
+
+/// Returns the total size of the files in the cache of an input directory.
+fn cache_size(dir: &Path) -> u64 {
+    fs::read_dir(dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .map(|path| if path.is_dir() { cache_size(&path) } else { fs::metadata(path).unwrap().len() })
+        .sum()
+}
+
+#[test]
+fn test_cache_is_reused_by_the_next_run_while_files_are_unchanged() {
+    let project = TempDir::new().unwrap();
+    let cache_dir = TempDir::new().unwrap();
+    let path = project.path().join("main.rs");
+    fs::write(&path, "fn main() {}").unwrap();
+    let modified = fs::metadata(&path).unwrap().modified().unwrap();
+    let files = vec![path.clone()];
+    let options = ReadOptions::default();
+
+    let mut cache = ContentCache::load(&disk_options(cache_dir.path()), project.path(), &options);
+    aggregate(&files, project.path(), &options, &mut cache);
+    cache.save().unwrap();
+    assert_eq!(cache_file_names(cache_dir.path()).len(), 1);
+
+    // Same size and modification time: the cached read is reused
+    rewrite(&path, "fn main(){1}", modified);
+    let mut cache = ContentCache::load(&disk_options(cache_dir.path()), project.path(), &options);
+    assert_eq!(cache.len(), 1);
+    assert_eq!(aggregate(&files, project.path(), &options, &mut cache), "fn main() {}");
+
+    // A new modification time: the file is read again
+    rewrite(&path, "fn main(){1}", modified + Duration::from_secs(1));
+    assert_eq!(aggregate(&files, project.path(), &options, &mut cache), "fn main(){1}");
+}
+
+#[test]
+fn test_files_not_read_by_a_run_stay_cached_until_they_are_deleted() {
+    let project = TempDir::new().unwrap();
+    let cache_dir = TempDir::new().unwrap();
+    let files: Vec<PathBuf> = ["a.rs", "b.rs"].iter().map(|name| project.path().join(name)).collect();
+    fs::write(&files[0], "fn a() {}").unwrap();
+    fs::write(&files[1], "fn b() {}").unwrap();
+    let options = ReadOptions::default();
+    let load = || ContentCache::load(&disk_options(cache_dir.path()), project.path(), &options);
+
+    let mut cache = load();
+    aggregate(&files, project.path(), &options, &mut cache);
+    cache.save().unwrap();
+
+    // A run reading only some files, e.g. with --changed, keeps the others
+    let mut cache = load();
+    fs::write(&files[0], "fn a(x: u8) {}").unwrap();
+    aggregate(&files[..1], project.path(), &options, &mut cache);
+    cache.save().unwrap();
+    let mut cache = load();
+    assert_eq!(cache.len(), 2);
+    assert_eq!(aggregate(&files[1..], project.path(), &options, &mut cache), "fn b() {}");
+
+    fs::remove_file(&files[1]).unwrap();
+    cache.save().unwrap();
+    assert_eq!(load().len(), 1);
+    // Only the content of the remaining file is kept
+    let cache_path = &cache_file_names(cache_dir.path())[0];
+    assert_eq!(cache_file_names(&cache_path.join("content")).len(), 1);
+}
+
+#[test]
+fn test_cache_written_with_other_read_options_is_discarded() {
+    let project = TempDir::new().unwrap();
+    let cache_dir = TempDir::new().unwrap();
+    let path = project.path().join("main.rs");
+    fs::write(&path, "fn main() {}").unwrap();
+    let files = vec![path];
+
+    let mut cache = ContentCache::load(&disk_options(cache_dir.path()), project.path(), &ReadOptions::default());
+    aggregate(&files, project.path(), &ReadOptions::default(), &mut cache);
+    cache.save().unwrap();
+
+    let lossy = ReadOptions { decode: DecodeOptions { lossy: true, ..DecodeOptions::default() }, ..ReadOptions::default() };
+    assert_eq!(ContentCache::load(&disk_options(cache_dir.path()), project.path(), &ReadOptions::default()).len(), 1);
+    assert!(ContentCache::load(&disk_options(cache_dir.path()), project.path(), &lossy).is_empty());
+}
+
+#[test]
+fn test_content_hash_detects_changes() {
+    let project = TempDir::new().unwrap();
+    let cache_dir = TempDir::new().unwrap();
+    let path = project.path().join("main.rs");
+    fs::write(&path, "fn main() {}").unwrap();
+    let modified = fs::metadata(&path).unwrap().modified().unwrap();
+    let files = vec![path.clone()];
+    let options = ReadOptions::default();
+    let disk_options = DiskCacheOptions { hash_contents: true, ..disk_options(cache_dir.path()) };
+
+    let mut cache = ContentCache::load(&disk_options, project.path(), &options);
+    aggregate(&files, project.path(), &options, &mut cache);
+    rewrite(&path, "fn main() {}", modified + Duration::from_secs(1));
+    assert_eq!(aggregate(&files, project.path(), &options, &mut cache), "fn main() {}");
+    rewrite(&path, "fn main(){1}", modified + Duration::from_secs(2));
+    assert_eq!(aggregate(&files, project.path(), &options, &mut cache), "fn main(){1}");
+}
+
+#[test]
+fn test_cache_size_is_limited() {
+    let cache_dir = TempDir::new().unwrap();
+    let projects: Vec<TempDir> = (0..2).map(|_| TempDir::new().unwrap()).collect();
+    let options = ReadOptions::default();
+    let save = |project: &Path, max_size: u64| {
+        let path = project.join("main.rs");
+        fs::write(&path, "fn main() {}").unwrap();
+        let disk_options = DiskCacheOptions { max_size, ..disk_options(cache_dir.path()) };
+        let mut cache = ContentCache::load(&disk_options, project, &options);
+        aggregate(&[path], project, &options, &mut cache);
+        cache.save()
+    };
+
+    // A cache that doesn't fit on its own isn't written
+    assert!(save(projects[0].path(), 10).is_err());
+    assert!(cache_file_names(cache_dir.path()).is_empty());
+
+    // The least recently written cache is removed to make room
+    save(projects[0].path(), 1024 * 1024).unwrap();
+    let first = cache_file_names(cache_dir.path());
+    let size = cache_size(&first[0]);
+    save(projects[1].path(), size * 3 / 2).unwrap();
+    let remaining = cache_file_names(cache_dir.path());
+    assert_eq!(remaining.len(), 1);
+    assert_ne!(remaining, first);
+}
+
+#[test]
+fn test_clear_cache() {
+    let cache_dir = TempDir::new().unwrap();
+    fs::write(cache_dir.path().join("0123456789abcdef.json"), "{}").unwrap();
+
+    assert_eq!(clear_cache(cache_dir.path()).unwrap(), (1, 2));
+    assert!(cache_file_names(cache_dir.path()).is_empty());
+    assert_eq!(clear_cache(&cache_dir.path().join("missing")).unwrap(), (0, 0));
+}
+
+#[test]
+fn test_cache_cli() {
+    let project = TempDir::new().unwrap();
+    fs::write(project.path().join("main.rs"), "fn main() {}").unwrap();
+    let cache_home = TempDir::new().unwrap();
+    let config_dir = TempDir::new().unwrap();
+    let config_path = config_dir.path().join("config.toml");
+    fs::write(&config_path, "").unwrap();
+    let conag = || {
+        let mut command = Command::cargo_bin("conag").unwrap();
+        command.current_dir(project.path()).env("XDG_CACHE_HOME", cache_home.path());
+        command.args(["--config", config_path.to_str().unwrap()]);
+        command
+    };
+
+    conag().args(["--output", "-", "--no-cache"]).assert().success();
+    assert!(!cache_home.path().join("conag").exists());
+    conag().args(["--output", "-"]).assert().success();
+    assert_eq!(cache_file_names(&cache_home.path().join("conag")).len(), 1);
+
+    conag()
+        .args(["cache", "clear"])
+        .assert()
+        .success()
+        .stderr(predicate::str::contains("Removed 1 cache files"));
+    assert!(cache_file_names(&cache_home.path().join("conag")).is_empty());
+}
@@ -0,0 +1,89 @@
+use conag::aggregator::{aggregate_contents, format_output, ReadOptions};
+use conag::encoding::{decode, detect_bomless_utf16, encoding_for_label, DecodeOptions};
+use conag::formatter::{FormatOptions, MarkdownFormatter};
+use std::fs;
+use tempfile::TempDir;
+
+fn with_fallback(label: &str) -> DecodeOptions {
+    DecodeOptions { fallback_encoding: encoding_for_label(label), lossy: false }
+}
+
+#[test]
+fn test_utf8_is_used_as_is() {
+    let decoded = decode("café".as_bytes().to_vec(), &DecodeOptions::default()).unwrap();
+
+    assert_eq!(decoded.content, "café");
+    assert_eq!(decoded.encoding, "UTF-8");
+}
+
+#[test]
+fn test_byte_order_marks_are_detected_and_removed() {
+    let utf16le: Vec<u8> = [0xff, 0xfe].into_iter().chain("hé\n".encode_utf16().flat_map(u16::to_le_bytes)).collect();
+    let utf16be: Vec<u8> = [0xfe, 0xff].into_iter().chain("hé\n".encode_utf16().flat_map(u16::to_be_bytes)).collect();
+    let utf8_bom = b"\xef\xbb\xbfhi".to_vec();
+
+    let le = decode(utf16le, &DecodeOptions::default()).unwrap();
+    let be = decode(utf16be, &DecodeOptions::default()).unwrap();
+    let utf8 = decode(utf8_bom, &DecodeOptions::default()).unwrap();
+
+    assert_eq!((le.content.as_str(), le.encoding.as_str()), ("hé\n", "UTF-16LE"));
+    assert_eq!((be.content.as_str(), be.encoding.as_str()), ("hé\n", "UTF-16BE"));
+    assert_eq!((utf8.content.as_str(), utf8.encoding.as_str()), ("hi", "UTF-8"));
+}
+
+#[test]
+fn test_utf16_without_byte_order_mark_is_detected() {
+    let text = "fn main() {\n    println!(\"hé\");\n}\n";
+    let utf16le: Vec<u8> = text.encode_utf16().flat_map(u16::to_le_bytes).collect();
+    let utf16be: Vec<u8> = text.encode_utf16().flat_map(u16::to_be_bytes).collect();
+
+    let le = decode(utf16le, &DecodeOptions::default()).unwrap();
+    let be = decode(utf16be, &DecodeOptions::default()).unwrap();
+
+    assert_eq!((le.content.as_str(), le.encoding.as_str()), (text, "UTF-16LE"));
+    assert_eq!((be.content.as_str(), be.encoding.as_str()), (text, "UTF-16BE"));
+    assert_eq!(detect_bomless_utf16(b"some text\x00more text"), None);
+    assert_eq!(detect_bomless_utf16(b"\x01\x00\x00\x00\x02\x00\x00\x00"), None);
+}
+
+#[test]
+fn test_fallback_encoding() {
+    let latin1 = b"caf\xe9 \x80".to_vec();
+
+    assert!(decode(latin1.clone(), &DecodeOptions::default()).is_none());
+    let decoded = decode(latin1, &with_fallback("latin1")).unwrap();
+
+    assert_eq!(decoded.content, "café €");
+    assert_eq!(decoded.encoding, "windows-1252");
+}
+
+#[test]
+fn test_lossy_mode_replaces_invalid_sequences() {
+    let options = DecodeOptions { fallback_encoding: None, lossy: true };
+
+    let decoded = decode(b"caf\xe9".to_vec(), &options).unwrap();
+
+    assert_eq!(decoded.content, "caf\u{fffd}");
+    assert_eq!(decoded.encoding, "UTF-8 (lossy)");
+}
+
+#[test]
+fn test_unknown_label() {
+    assert!(encoding_for_label("klingon").is_none());
+    assert!(encoding_for_label(" Windows-1252 ").is_some());
+}
+
+#[test]
+fn test_transcoded_files_note_their_encoding() {
+    let temp_dir = TempDir::new().unwrap();
+    let path = temp_dir.path().join("legacy.sql");
+    fs::write(&path, b"SELECT 'caf\xe9';").unwrap();
+    let options = ReadOptions { decode: with_fallback("windows-1252"), ..ReadOptions::default() };
+
+    let (contents, skipped) = aggregate_contents(&[path], temp_dir.path(), &options);
+    let output = format_output("Test Project", &contents, &MarkdownFormatter, &FormatOptions::default());
+
+    assert!(skipped.is_empty());
+    assert_eq!(contents[0].content, "SELECT 'café';");
+    assert!(output.contains("## File: legacy.sql (encoding: windows-1252)\n"));
+}
@@ -0,0 +1,158 @@
+use assert_cmd::Command;
+use conag::config::Config;
+use conag::explain::{render_explanation, render_table};
+use conag::ignore_rules::{DecisionStep, IgnoreRules, RuleKind, RuleOutcome, apply_ignore_rules, explain_file};
+use predicates::prelude::*;
+use std::collections::{BTreeMap, HashSet};
+use std::fs;
+use std::path::Path;
+use tempfile::TempDir;
+
+fn create_test_file(base: &Path, name: &str, content: &str) {
+    let path = base.join(name);
+    fs::create_dir_all(path.parent().unwrap()).unwrap();
+    fs::write(path, content).unwrap();
+}
+
+fn create_test_config(input_dir: &Path) -> Config {
+    Config {
+        input_dir: input_dir.to_string_lossy().into_owned(),
+        ignore_patterns: vec!["**/*.log".to_string(), "**/build/*".to_string()],
+        include_hidden_patterns: vec![".github".to_string()],
+        include_file_overrides: vec!["build/keep.txt".to_string()],
+        ..Config::default()
+    }
+}
+
+#[test]
+fn test_each_step_decides() {
+    let base = Path::new("/project");
+    let config = create_test_config(base);
+    let ignore_rules = IgnoreRules::new(&config).unwrap();
+    let explain = |file: &str| {
+        explain_file(&ignore_rules, &base.join(file), &config.include_file_overrides, &config.include_dir_overrides, base)
+    };
+
+    let kept = explain("build/keep.txt");
+    assert!(kept.included);
+    assert_eq!(kept.step, DecisionStep::FileOverride);
+    assert_eq!(kept.checks[kept.deciding_rule.unwrap()].pattern, "build/keep.txt");
+
+    let hidden = explain(".env");
+    assert!(!hidden.included);
+    assert_eq!(hidden.step, DecisionStep::HiddenFile);
+    assert_eq!(hidden.checks[hidden.deciding_rule.unwrap()].kind, RuleKind::HiddenFiles);
+
+    let log = explain("debug.log");
+    assert!(!log.included);
+    assert_eq!(log.step, DecisionStep::IgnorePattern);
+    assert_eq!(log.checks[log.deciding_rule.unwrap()].pattern, "**/*.log");
+
+    let source = explain("src/main.rs");
+    assert!(source.included);
+    assert_eq!(source.step, DecisionStep::Default);
+    assert_eq!(source.deciding_rule, None);
+    // Every rule is evaluated, even though none matches
+    assert!(source.checks.iter().filter(|check| check.kind == RuleKind::IgnorePattern).all(|check| check.outcome == RuleOutcome::NoMatch));
+    assert_eq!(source.checks.iter().filter(|check| check.kind == RuleKind::IgnorePattern).count(), 2);
+}
+
+#[test]
+fn test_git_ignore_rules_are_explained() {
+    let temp_dir = TempDir::new().unwrap();
+    let base = temp_dir.path();
+    fs::create_dir(base.join(".git")).unwrap();
+    create_test_file(base, ".gitignore", "*.tmp\n!keep.tmp\n");
+    let config = Config { ignore_patterns: vec![], ..create_test_config(base) };
+    let ignore_rules = IgnoreRules::new(&config).unwrap().with_gitignore(base);
+    let explain = |file: &str| {
+        explain_file(&ignore_rules, &base.join(file), &config.include_file_overrides, &config.include_dir_overrides, base)
+    };
+
+    let ignored = explain("a.tmp");
+    assert!(!ignored.included);
+    assert_eq!(ignored.step, DecisionStep::GitIgnore);
+    let rule = &ignored.checks[ignored.deciding_rule.unwrap()];
+    assert_eq!(rule.pattern, "*.tmp");
+
+    let kept = explain("keep.tmp");
+    assert!(kept.included);
+    assert_eq!(kept.step, DecisionStep::Default);
+    let rule = kept.checks.iter().find(|check| check.kind == RuleKind::GitIgnore).unwrap();
+    assert_eq!((rule.pattern, rule.outcome), ("!keep.tmp", RuleOutcome::Reincluded));
+
+    let rendered = render_explanation(&ignored, &BTreeMap::new(), base);
+    assert!(rendered.starts_with("a.tmp: excluded\nDecided by step 4 of 5 (git ignore): git ignore rule *.tmp from .gitignore\n"));
+}
+
+#[test]
+fn test_explanations_agree_with_the_ignore_rules() {
+    let temp_dir = TempDir::new().unwrap();
+    let base = temp_dir.path();
+    fs::create_dir(base.join(".git")).unwrap();
+    create_test_file(base, ".gitignore", "*.tmp\n!keep.tmp\nbuild/\n");
+    let config = Config { include_dir_overrides: vec!["build".to_string()], ..create_test_config(base) };
+    let ignore_rules = IgnoreRules::new(&config).unwrap().with_gitignore(base);
+    let files: HashSet<_> = ["build/keep.txt", "build/out.txt", ".env", ".github", "debug.log", "a.tmp", "keep.tmp", "src/main.rs"]
+        .iter()
+        .map(|file| base.join(file))
+        .collect();
+
+    let included = apply_ignore_rules(&ignore_rules, &files, &config.include_file_overrides, &config.include_dir_overrides, base);
+
+    for file in &files {
+        let explanation = explain_file(&ignore_rules, file, &config.include_file_overrides, &config.include_dir_overrides, base);
+        assert_eq!(explanation.included, included.contains(file), "{}", file.display());
+    }
+}
+
+#[test]
+fn test_render_table() {
+    let base = Path::new("/project");
+    let config = create_test_config(base);
+    let ignore_rules = IgnoreRules::new(&config).unwrap();
+    let explanations: Vec<_> = ["build/keep.txt", "debug.log", "src/main.rs"]
+        .iter()
+        .map(|file| explain_file(&ignore_rules, &base.join(file), &config.include_file_overrides, &config.include_dir_overrides, base))
+        .collect();
+
+    assert_eq!(
+        render_table(&explanations, base),
+        "DECISION  STEP            RULE                                        PATH\n\
+         included  file override   build/keep.txt (include_file_overrides[0])  build/keep.txt\n\
+         excluded  ignore pattern  **/*.log (ignore_patterns[0])               debug.log\n\
+         included  default                                                     src/main.rs\n"
+    );
+}
+
+#[test]
+fn test_explain_command() {
+    let temp_dir = TempDir::new().unwrap();
+    let base = temp_dir.path();
+    create_test_file(base, "src/main.rs", "fn main() {}\n");
+    create_test_file(base, "debug.log", "log\n");
+    let config_path = base.join("config.toml");
+    fs::write(&config_path, "ignore_patterns = [\"**/*.log\", \"**/config.toml\"]\n").unwrap();
+    let conag = || {
+        let mut command = Command::cargo_bin("conag").unwrap();
+        command.current_dir(base).args(["--config", config_path.to_str().unwrap(), "explain"]);
+        command
+    };
+
+    conag()
+        .arg("debug.log")
+        .assert()
+        .success()
+        .stdout(predicate::str::starts_with(format!(
+            "debug.log: excluded\nDecided by step 3 of 5 (ignore pattern): ignore_patterns[0] **/*.log from global ({})\n",
+            config_path.display()
+        )))
+        .stdout(predicate::str::contains("no match     ignore_patterns[1]"));
+
+    conag()
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("excluded  ignore pattern  **/*.log (ignore_patterns[0])"))
+        .stdout(predicate::str::contains("included  default").and(predicate::str::contains("src/main.rs")))
+        .stderr(predicate::str::contains("1 files included, 2 excluded"));
+}
@@ -0,0 +1,179 @@
+use conag::aggregator::{FileContent, FileSegment, SkipReason, SkippedFile};
+use conag::budget::OmittedFile;
+use conag::chunking::{split_into_parts, ChunkLimit, PartInfo};
+use conag::formatter::{render_document, DocumentWriter, FormatOptions, Footer, Header, JsonFormatter, JsonLinesFormatter, MarkdownFormatter, OutputFormat, XmlFormatter};
+use conag::tokenizer::{CharsHeuristic, Tokenizer};
+use conag::truncation::Truncation;
+use std::path::PathBuf;
+
+fn test_files() -> Vec<FileContent> {
+    vec![
+        FileContent::new("src/main.rs", "fn main() {\n    println!(\"<hi>\");\n}"),
+        FileContent::new("README.md", "# Readme"),
+    ]
+}
+
+fn test_omitted() -> Vec<OmittedFile> {
+    vec![OmittedFile { path: PathBuf::from("big.rs"), tokens: 1200 }]
+}
+
+#[test]
+fn test_markdown_part_index() {
+    let part = PartInfo { number: 2, total: 3 };
+
+    let output = render_document(&MarkdownFormatter, &FormatOptions::default(), &Header::new("Test Project"), Some(&part), &test_files(), &Footer { omitted: &test_omitted(), ..Footer::default() });
+
+    assert!(output.starts_with("# Project: Test Project\n\nPart 2 of 3\n\nFiles in this part:\n- src/main.rs\n- README.md\n\n"));
+    assert!(output.contains("```rust\nfn main() {"));
+    assert!(output.ends_with("## Omitted Files\n\nThe following files were omitted to stay within the token budget:\n\n- big.rs (1200 tokens)\n"));
+}
+
+#[test]
+fn test_xml_format() {
+    let output = render_document(&XmlFormatter, &FormatOptions::default(), &Header::new("A & B"), None, &test_files(), &Footer { omitted: &test_omitted(), ..Footer::default() });
+
+    assert!(output.starts_with("<documents project=\"A &amp; B\">\n<document index=\"1\">\n<source>src/main.rs</source>\n"));
+    assert!(output.contains("<document_content><![CDATA[\nfn main() {\n    println!(\"<hi>\");\n}\n]]></document_content>\n</document>\n"));
+    assert!(output.contains("<document index=\"2\">\n<source>README.md</source>"));
+    assert!(output.ends_with("<omitted_files>\n<file tokens=\"1200\">big.rs</file>\n</omitted_files>\n</documents>\n"));
+}
+
+#[test]
+fn test_xml_content_can_not_end_its_cdata_section() {
+    let files = vec![FileContent::new("src/lib.rs", "let nested = a[b[0]]>c;\n</document_content>\n")];
+
+    let output = render_document(&XmlFormatter, &FormatOptions::default(), &Header::new("Test Project"), None, &files, &Footer::default());
+
+    assert!(output.contains("<![CDATA[\nlet nested = a[b[0]]]]><![CDATA[>c;\n</document_content>\n\n]]></document_content>\n"));
+}
+
+#[test]
+fn test_json_format_is_valid_json() {
+    let mut files = test_files();
+    files[1].segment = Some(FileSegment { start_line: 3, end_line: 4, total_lines: 9 });
+    let part = PartInfo { number: 1, total: 2 };
+
+    let output = render_document(&JsonFormatter, &FormatOptions::default(), &Header::new("Test Project"), Some(&part), &files, &Footer { omitted: &test_omitted(), ..Footer::default() });
+    let value: serde_json::Value = serde_json::from_str(&output).unwrap();
+
+    assert_eq!(value["project"], "Test Project");
+    assert_eq!(value["part"]["total"], 2);
+    assert_eq!(value["files"][0]["path"], "src/main.rs");
+    assert_eq!(value["files"][0]["language"], "rust");
+    assert_eq!(value["files"][0]["content"], files[0].content);
+    assert_eq!(value["files"][1]["lines"]["start"], 3);
+    assert_eq!(value["omitted"][0]["tokens"], 1200);
+}
+
+#[test]
+fn test_json_format_without_files() {
+    let output = render_document(&JsonFormatter, &FormatOptions::default(), &Header::new("Empty"), None, &[], &Footer::default());
+    let value: serde_json::Value = serde_json::from_str(&output).unwrap();
+
+    assert_eq!(value["files"].as_array().unwrap().len(), 0);
+    assert_eq!(value["omitted"].as_array().unwrap().len(), 0);
+}
+
+#[test]
+fn test_jsonl_format_has_one_object_per_line() {
+    let output = render_document(&JsonLinesFormatter, &FormatOptions::default(), &Header::new("Test Project"), None, &test_files(), &Footer { omitted: &test_omitted(), ..Footer::default() });
+    let lines: Vec<serde_json::Value> = output.lines().map(|line| serde_json::from_str(line).unwrap()).collect();
+
+    assert_eq!(lines.len(), 3);
+    assert_eq!(lines[0]["path"], "src/main.rs");
+    assert_eq!(lines[1]["content"], "# Readme");
+    assert_eq!(lines[2]["omitted"], true);
+}
+
+#[test]
+fn test_output_format_extensions() {
+    let extensions: Vec<&str> = [OutputFormat::Markdown, OutputFormat::Plain, OutputFormat::Xml, OutputFormat::Json, OutputFormat::Jsonl]
+        .iter()
+        .map(|format| format.formatter().extension())
+        .collect();
+
+    assert_eq!(extensions, vec!["md", "txt", "xml", "json", "jsonl"]);
+}
+
+#[test]
+fn test_skipped_files_are_listed() {
+    let skipped = vec![SkippedFile { path: PathBuf::from("logo.png"), reason: SkipReason::Binary }];
+    let footer = Footer { skipped: &skipped, ..Footer::default() };
+
+    let markdown = render_document(&MarkdownFormatter, &FormatOptions::default(), &Header::new("Test Project"), None, &[], &footer);
+    let json: serde_json::Value = serde_json::from_str(&render_document(&JsonFormatter, &FormatOptions::default(), &Header::new("Test Project"), None, &[], &footer)).unwrap();
+
+    assert!(markdown.ends_with("## Skipped Files\n\nThe following files were skipped:\n\n- logo.png (binary)\n"));
+    assert_eq!(json["skipped"][0]["reason"], "binary");
+}
+
+#[test]
+fn test_document_writer_matches_rendered_document() {
+    let files = test_files();
+    let skipped = vec![SkippedFile { path: PathBuf::from("logo.png"), reason: SkipReason::Binary }];
+    let footer = Footer { skipped: &skipped, ..Footer::default() };
+    let formats = [OutputFormat::Markdown, OutputFormat::Plain, OutputFormat::Xml, OutputFormat::Json, OutputFormat::Jsonl];
+    for (format, options) in formats.into_iter().flat_map(|format| [(format, FormatOptions::default()), (format, NUMBERED)]) {
+        let formatter = format.formatter();
+        let mut output = Vec::new();
+        let mut document = DocumentWriter::new(formatter.as_ref(), &mut output).with_options(options).with_tokenizer(&CharsHeuristic);
+        document.write_header(&Header::new("project")).unwrap();
+        for file in &files {
+            document.write_file(file).unwrap();
+        }
+        assert_eq!(document.files(), 2);
+        let tokens = document.finish(&footer).unwrap();
+
+        let rendered = render_document(formatter.as_ref(), &options, &Header::new("project"), None, &files, &footer);
+        assert_eq!(String::from_utf8(output).unwrap(), rendered, "{:?} {:?}", format, options);
+        assert!(tokens.abs_diff(CharsHeuristic.count_tokens(&rendered)) <= 3, "{:?} {:?}", format, options);
+    }
+}
+
+const NUMBERED: FormatOptions = FormatOptions { line_numbers: true };
+
+#[test]
+fn test_line_numbers_are_aligned() {
+    let content: String = (1..=10).map(|line| if line == 3 { "\n".to_string() } else { format!("line {}\n", line) }).collect();
+    let output = render_document(&MarkdownFormatter, &NUMBERED, &Header::new("Test Project"), None, &[FileContent::new("notes.txt", content)], &Footer::default());
+
+    assert!(output.contains("\n 1 | line 1\n 2 | line 2\n 3 |\n 4 | line 4\n"));
+    assert!(output.contains("\n10 | line 10\n\n```"));
+}
+
+#[test]
+fn test_line_numbers_follow_segments_and_truncation() {
+    let mut segment = FileContent::new("src/lib.rs", "fn a() {}\nfn b() {}");
+    segment.segment = Some(FileSegment { start_line: 99, end_line: 100, total_lines: 100 });
+    let mut truncated = FileContent::new("big.log", "first\n[… 996 lines elided …]\nsecond to last\nlast\n");
+    truncated.truncation = Some(Truncation { original_bytes: 20000, original_lines: 1000, head_lines: 1, tail_lines: 2 });
+
+    let output = render_document(&MarkdownFormatter, &NUMBERED, &Header::new("Test Project"), None, &[segment.clone(), truncated.clone()], &Footer::default());
+
+    assert!(output.contains("```rust\n 99 | fn a() {}\n100 | fn b() {}\n```"));
+    assert!(output.contains("   1 | first\n     | [… 996 lines elided …]\n 999 | second to last\n1000 | last\n"));
+
+    let json: serde_json::Value = serde_json::from_str(&render_document(&JsonFormatter, &NUMBERED, &Header::new("Test Project"), None, &[segment, truncated], &Footer::default())).unwrap();
+    assert_eq!(json["files"][0]["start_line"], 99);
+    assert_eq!(json["files"][0]["content"], "fn a() {}\nfn b() {}");
+    assert_eq!(json["files"][1]["start_line"], 1);
+}
+
+#[test]
+fn test_line_numbers_continue_across_parts() {
+    let content: String = (1..=30).map(|line| format!("let x{} = {};\n", line, line)).collect();
+    let files = vec![FileContent::new("src/main.rs", content)];
+    let limit = ChunkLimit { max_bytes: Some(400), max_tokens: None };
+    let footer = Footer::default();
+
+    let parts = split_into_parts(&files, &footer, &limit, &CharsHeuristic, |files, part, footer| {
+        render_document(&MarkdownFormatter, &NUMBERED, &Header::new("Test Project"), Some(part), files, footer)
+    });
+
+    assert!(parts.len() > 1);
+    let numbers: Vec<usize> = parts
+        .iter()
+        .flat_map(|part| part.lines().filter_map(|line| line.split_once(" | ").and_then(|(number, _)| number.trim().parse().ok())))
+        .collect();
+    assert_eq!(numbers, (1..=30).collect::<Vec<_>>());
+}
@@ -0,0 +1,283 @@
+use assert_cmd::Command;
+use conag::aggregator::FileContent;
+use conag::git_changes::{attach_diffs, collect_changes, collect_diffs, ChangeSelection, ChangeStatus, RenamedFile};
+use git2::{IndexAddOption, Repository, Signature};
+use predicates::prelude::*;
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+use tempfile::TempDir;
+
+fn commit_all(repo: &Repository, message: &str) {
+    let mut index = repo.index().unwrap();
+    index.add_all(["*"], IndexAddOption::DEFAULT, None).unwrap();
+    index.update_all(["*"], None).unwrap();
+    index.write().unwrap();
+    let tree = repo.find_tree(index.write_tree().unwrap()).unwrap();
+    let signature = Signature::now("Test", "test@example.com").unwrap();
+    let parent = repo.head().ok().map(|head| head.peel_to_commit().unwrap());
+    let parents: Vec<_> = parent.iter().collect();
+    repo.commit(Some("HEAD"), &signature, &signature, message, &tree, &parents).unwrap();
+}
+
+fn stage(repo: &Repository, paths: &[&str]) {
+    let mut index = repo.index().unwrap();
+    for path in paths {
+        if repo.workdir().unwrap().join(path).exists() {
+            index.add_path(Path::new(path)).unwrap();
+        } else {
+            index.remove_path(Path::new(path)).unwrap();
+        }
+    }
+    index.write().unwrap();
+}
+
+/// Creates a repository with a `main` branch, and a feature branch that modifies, adds,
+/// renames and deletes files.
+fn setup_repo() -> (TempDir, Repository) {
+    let temp_dir = TempDir::new().unwrap();
+    let repo = Repository::init(temp_dir.path()).unwrap();
+    let root = temp_dir.path();
+    fs::create_dir_all(root.join("src")).unwrap();
+    fs::write(root.join("src/main.rs"), "fn main() {}\n").unwrap();
+    fs::write(root.join("src/old_name.rs"), "pub fn helper() -> u32 {\n    42\n}\n").unwrap();
+    fs::write(root.join("src/obsolete.rs"), "pub fn unused() {}\n").unwrap();
+    fs::write(root.join("README.md"), "# Project\n").unwrap();
+    commit_all(&repo, "Initial commit");
+    repo.branch("main", &repo.head().unwrap().peel_to_commit().unwrap(), true).unwrap();
+
+    fs::write(root.join("src/main.rs"), "fn main() {\n    println!(\"hi\");\n}\n").unwrap();
+    fs::write(root.join("src/new.rs"), "pub fn new() {}\n").unwrap();
+    fs::rename(root.join("src/old_name.rs"), root.join("src/new_name.rs")).unwrap();
+    fs::remove_file(root.join("src/obsolete.rs")).unwrap();
+    commit_all(&repo, "Feature");
+    (temp_dir, repo)
+}
+
+fn changed_paths(changed: &HashSet<PathBuf>, root: &Path) -> Vec<String> {
+    let mut paths: Vec<String> = changed
+        .iter()
+        .map(|path| path.strip_prefix(root).unwrap().display().to_string())
+        .collect();
+    paths.sort();
+    paths
+}
+
+#[test]
+fn test_changed_since_a_branch() {
+    let (temp_dir, _repo) = setup_repo();
+    fs::write(temp_dir.path().join("README.md"), "# Project\n\nUncommitted\n").unwrap();
+
+    let changes = collect_changes(temp_dir.path(), &ChangeSelection::Since("main".to_string())).unwrap();
+
+    assert_eq!(changed_paths(&changes.changed, temp_dir.path()), ["README.md", "src/main.rs", "src/new.rs", "src/new_name.rs"]);
+    assert_eq!(changes.summary.deleted, [PathBuf::from("src/obsolete.rs")]);
+    assert_eq!(
+        changes.summary.renamed,
+        [RenamedFile { from: PathBuf::from("src/old_name.rs"), to: PathBuf::from("src/new_name.rs") }]
+    );
+}
+
+#[test]
+fn test_staged_and_uncommitted_changes() {
+    let (temp_dir, repo) = setup_repo();
+    let root = temp_dir.path();
+    fs::write(root.join("README.md"), "# Staged\n").unwrap();
+    stage(&repo, &["README.md"]);
+    fs::write(root.join("src/main.rs"), "fn main() {}\n").unwrap();
+    fs::write(root.join("src/untracked.rs"), "\n").unwrap();
+
+    let staged = collect_changes(root, &ChangeSelection::Staged).unwrap();
+    let uncommitted = collect_changes(root, &ChangeSelection::Uncommitted).unwrap();
+
+    assert_eq!(changed_paths(&staged.changed, root), ["README.md"]);
+    assert_eq!(changed_paths(&uncommitted.changed, root), ["README.md", "src/main.rs", "src/untracked.rs"]);
+}
+
+#[test]
+fn test_changes_outside_the_input_directory_are_ignored() {
+    let (temp_dir, _repo) = setup_repo();
+    let input_dir = temp_dir.path().join("src");
+
+    let changes = collect_changes(&input_dir, &ChangeSelection::Since("main".to_string())).unwrap();
+
+    assert_eq!(changed_paths(&changes.changed, &input_dir), ["main.rs", "new.rs", "new_name.rs"]);
+    assert_eq!(changes.summary.deleted, [PathBuf::from("obsolete.rs")]);
+}
+
+#[test]
+fn test_unknown_revision_fails() {
+    let (temp_dir, _repo) = setup_repo();
+
+    let result = collect_changes(temp_dir.path(), &ChangeSelection::Since("no-such-branch".to_string()));
+
+    assert!(result.unwrap_err().to_string().contains("no-such-branch"));
+}
+
+#[test]
+fn test_changed_since_cli() {
+    let (temp_dir, _repo) = setup_repo();
+    let config_dir = TempDir::new().unwrap();
+    let config_path = config_dir.path().join("config.toml");
+    fs::write(&config_path, "respect_gitignore = false\n").unwrap();
+
+    Command::cargo_bin("conag")
+        .unwrap()
+        .current_dir(temp_dir.path())
+        .args(["--config", config_path.to_str().unwrap(), "--output", "-", "--changed-since", "main"])
+        .assert()
+        .success()
+        .stdout(
+            predicate::str::contains("## File: src/new_name.rs")
+                .and(predicate::str::contains("## File: README.md").not())
+                .and(predicate::str::contains("## Deleted and Renamed Files"))
+                .and(predicate::str::contains("- src/obsolete.rs\n"))
+                .and(predicate::str::contains("- src/old_name.rs → src/new_name.rs\n")),
+        );
+}
+
+#[test]
+fn test_collect_diffs() {
+    let (temp_dir, _repo) = setup_repo();
+
+    let diffs = collect_diffs(temp_dir.path(), "main", 3).unwrap();
+
+    let stats: Vec<(String, ChangeStatus, usize, usize)> = diffs
+        .summary
+        .files
+        .iter()
+        .map(|file| (file.path.display().to_string(), file.status, file.insertions, file.deletions))
+        .collect();
+    assert_eq!(
+        stats,
+        [
+            ("src/main.rs".to_string(), ChangeStatus::Modified, 3, 1),
+            ("src/new.rs".to_string(), ChangeStatus::Added, 1, 0),
+            ("src/new_name.rs".to_string(), ChangeStatus::Renamed, 0, 0),
+            ("src/obsolete.rs".to_string(), ChangeStatus::Deleted, 0, 1),
+        ]
+    );
+    assert_eq!(
+        diffs.files[Path::new("src/main.rs")].unified(),
+        "@@ -1 +1,3 @@\n-fn main() {}\n+fn main() {\n+    println!(\"hi\");\n+}\n"
+    );
+    assert!(!diffs.files.contains_key(Path::new("src/obsolete.rs")));
+}
+
+#[test]
+fn test_attach_diffs_keeps_small_files_whole() {
+    let (temp_dir, _repo) = setup_repo();
+    let diffs = collect_diffs(temp_dir.path(), "main", 3).unwrap();
+    let mut contents = vec![
+        FileContent::new("src/main.rs", fs::read_to_string(temp_dir.path().join("src/main.rs")).unwrap()),
+        FileContent::new("src/new.rs", "pub fn new() {}\n"),
+    ];
+
+    attach_diffs(&mut contents, diffs.files, Some(20));
+
+    assert_eq!(contents[0].content, "");
+    assert_eq!(contents[0].diff.as_ref().unwrap().insertions, 3);
+    assert_eq!(contents[1].content, "pub fn new() {}\n");
+    assert!(contents[1].diff.is_some());
+}
+
+#[test]
+fn test_diff_cli() {
+    let (temp_dir, _repo) = setup_repo();
+    let config_dir = TempDir::new().unwrap();
+    let config_path = config_dir.path().join("config.toml");
+    fs::write(&config_path, "respect_gitignore = false\n").unwrap();
+    let run = |format: &str| {
+        let output = Command::cargo_bin("conag")
+            .unwrap()
+            .current_dir(temp_dir.path())
+            .args(["--config", config_path.to_str().unwrap(), "--output", "-", "--diff", "main", "--format", format])
+            .output()
+            .unwrap();
+        assert!(output.status.success());
+        String::from_utf8(output.stdout).unwrap()
+    };
+
+    let markdown = run("markdown");
+    let json: serde_json::Value = serde_json::from_str(&run("json")).unwrap();
+
+    assert!(markdown.contains("## Diff Summary\n\nChanges since main:\n\n- src/main.rs: +3 -1\n"));
+    assert!(markdown.contains("- src/new_name.rs (renamed from src/old_name.rs): +0 -0\n- src/obsolete.rs (deleted): +0 -1\n"));
+    assert!(markdown.contains("4 files changed, 4 insertions(+), 2 deletions(-)\n"));
+    assert!(markdown.contains("## File: src/main.rs (+3 -1)\n\n```diff\n@@ -1 +1,3 @@\n-fn main() {}\n"));
+    assert!(!markdown.contains("## Deleted and Renamed Files"));
+    assert_eq!(json["diff_summary"]["insertions"], 4);
+    let main = json["files"].as_array().unwrap().iter().find(|file| file["path"] == "src/main.rs").unwrap();
+    assert_eq!(main["diff"]["hunks"][0]["new_lines"], 3);
+    assert_eq!(main["diff"]["hunks"][0]["lines"][0], "-fn main() {}");
+    assert_eq!(main["content"], "");
+}
+
+#[test]
+fn test_missing_newline_at_end_of_file_is_marked_once() {
+    let (temp_dir, repo) = setup_repo();
+    fs::write(temp_dir.path().join("README.md"), "# Project\nNo trailing newline").unwrap();
+    commit_all(&repo, "Drop the trailing newline");
+
+    let diffs = collect_diffs(temp_dir.path(), "main", 3).unwrap();
+
+    let readme = &diffs.files[Path::new("README.md")];
+    assert_eq!(readme.unified(), "@@ -1 +1,2 @@\n # Project\n+No trailing newline\n\\ No newline at end of file\n");
+    assert!(!readme.hunks[0].lines.iter().any(|line| line == "\\"));
+}
+
+#[test]
+fn test_pure_rename_has_no_empty_diff_fence() {
+    let (temp_dir, _repo) = setup_repo();
+    let config_dir = TempDir::new().unwrap();
+    let config_path = config_dir.path().join("config.toml");
+    fs::write(&config_path, "respect_gitignore = false\n").unwrap();
+
+    let output = Command::cargo_bin("conag")
+        .unwrap()
+        .current_dir(temp_dir.path())
+        .args(["--config", config_path.to_str().unwrap(), "--output", "-", "--diff", "main"])
+        .output()
+        .unwrap();
+
+    assert!(output.status.success());
+    let markdown = String::from_utf8(output.stdout).unwrap();
+    assert!(markdown.contains("## File: src/new_name.rs"));
+    assert!(!markdown.contains("```diff\n```"));
+}
+
+#[test]
+fn test_diffs_select_the_same_changes_as_collect_changes() {
+    let (temp_dir, _repo) = setup_repo();
+
+    let diffs = collect_diffs(temp_dir.path(), "main", 3).unwrap();
+    let changes = collect_changes(temp_dir.path(), &ChangeSelection::Since("main".to_string())).unwrap();
+
+    assert_eq!(diffs.changes(temp_dir.path()), changes);
+}
+
+#[test]
+fn test_ignored_changes_are_left_out_of_the_summaries() {
+    let (temp_dir, _repo) = setup_repo();
+    let config_dir = TempDir::new().unwrap();
+    let config_path = config_dir.path().join("config.toml");
+    fs::write(&config_path, "respect_gitignore = false\nignore_patterns = [\"src/obsolete.rs\", \"src/new_name.rs\"]\n").unwrap();
+    let run = |mode: &str| {
+        let output = Command::cargo_bin("conag")
+            .unwrap()
+            .current_dir(temp_dir.path())
+            .args(["--config", config_path.to_str().unwrap(), "--output", "-", mode, "main"])
+            .output()
+            .unwrap();
+        assert!(output.status.success());
+        String::from_utf8(output.stdout).unwrap()
+    };
+
+    let changed = run("--changed-since");
+    let diff = run("--diff");
+
+    assert!(!changed.contains("## Deleted and Renamed Files"));
+    assert!(!changed.contains("src/obsolete.rs") && !changed.contains("src/new_name.rs"));
+    assert!(diff.contains("- src/main.rs: +3 -1\n"));
+    assert!(!diff.contains("src/obsolete.rs") && !diff.contains("src/new_name.rs"));
+}
@@ -0,0 +1,133 @@
+use assert_cmd::Command;
+use conag::git_tracked::{list_tracked_files, SubmoduleMode, TrackedOptions};
+use git2::{Repository, Signature};
+use predicates::prelude::*;
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+use tempfile::TempDir;
+
+fn commit_paths(repo: &Repository, paths: &[&str]) -> git2::Oid {
+    let mut index = repo.index().unwrap();
+    for path in paths {
+        index.add_path(Path::new(path)).unwrap();
+    }
+    index.write().unwrap();
+    let tree = repo.find_tree(index.write_tree().unwrap()).unwrap();
+    let signature = Signature::now("Test", "test@example.com").unwrap();
+    repo.commit(Some("HEAD"), &signature, &signature, "Commit", &tree, &[]).unwrap()
+}
+
+fn write(root: &Path, path: &str, content: &str) {
+    let path = root.join(path);
+    fs::create_dir_all(path.parent().unwrap()).unwrap();
+    fs::write(path, content).unwrap();
+}
+
+/// Creates a repository with tracked sources, an untracked build artifact, an untracked
+/// note, and a checked-out submodule in `vendor/lib`.
+fn setup_repo() -> TempDir {
+    let temp_dir = TempDir::new().unwrap();
+    let root = temp_dir.path();
+    let repo = Repository::init(root).unwrap();
+    write(root, "src/main.rs", "fn main() {}\n");
+    write(root, ".gitignore", "target/\n");
+    write(root, "target/debug/app.d", "artifact\n");
+    write(root, "notes.md", "untracked\n");
+
+    let submodule = Repository::init(root.join("vendor/lib")).unwrap();
+    write(&root.join("vendor/lib"), "lib.rs", "pub fn lib() {}\n");
+    let submodule_commit = commit_paths(&submodule, &["lib.rs"]);
+    write(root, ".gitmodules", "[submodule \"vendor/lib\"]\n\tpath = vendor/lib\n\turl = https://example.com/lib.git\n");
+    commit_paths(&repo, &["src/main.rs", ".gitignore", ".gitmodules", "vendor/lib"]);
+    assert_eq!(repo.index().unwrap().get_path(Path::new("vendor/lib"), 0).unwrap().id, submodule_commit);
+    temp_dir
+}
+
+fn relative_paths(files: &HashSet<PathBuf>, root: &Path) -> Vec<String> {
+    let mut paths: Vec<String> = files.iter().map(|path| path.strip_prefix(root).unwrap().display().to_string()).collect();
+    paths.sort();
+    paths
+}
+
+#[test]
+fn test_only_tracked_files_are_listed() {
+    let temp_dir = setup_repo();
+
+    let tracked = list_tracked_files(temp_dir.path(), &TrackedOptions::default()).unwrap();
+
+    assert_eq!(relative_paths(&tracked.files, temp_dir.path()), [".gitignore", ".gitmodules", "src/main.rs"]);
+    assert_eq!(tracked.submodules.len(), 1);
+    assert_eq!(tracked.submodules[0].path, PathBuf::from("vendor/lib"));
+    assert_eq!(tracked.submodules[0].url.as_deref(), Some("https://example.com/lib.git"));
+}
+
+#[test]
+fn test_untracked_files_that_are_not_ignored() {
+    let temp_dir = setup_repo();
+    let options = TrackedOptions { include_untracked: true, ..TrackedOptions::default() };
+
+    let tracked = list_tracked_files(temp_dir.path(), &options).unwrap();
+
+    let paths = relative_paths(&tracked.files, temp_dir.path());
+    assert!(paths.contains(&"notes.md".to_string()));
+    assert!(!paths.iter().any(|path| path.starts_with("target")));
+}
+
+#[test]
+fn test_submodules_are_recursed_into() {
+    let temp_dir = setup_repo();
+    let options = TrackedOptions { submodules: SubmoduleMode::Recurse, ..TrackedOptions::default() };
+
+    let tracked = list_tracked_files(temp_dir.path(), &options).unwrap();
+
+    assert!(relative_paths(&tracked.files, temp_dir.path()).contains(&"vendor/lib/lib.rs".to_string()));
+    assert!(tracked.submodules.is_empty());
+}
+
+#[test]
+fn test_tracked_only_cli() {
+    let temp_dir = setup_repo();
+    let config_dir = TempDir::new().unwrap();
+    let config_path = config_dir.path().join("config.toml");
+    fs::write(&config_path, "ignore_patterns = []\n").unwrap();
+
+    Command::cargo_bin("conag")
+        .unwrap()
+        .current_dir(temp_dir.path())
+        .args(["--config", config_path.to_str().unwrap(), "--output", "-", "--tracked-only"])
+        .assert()
+        .success()
+        .stdout(
+            predicate::str::contains("## File: src/main.rs")
+                .and(predicate::str::contains("notes.md").not())
+                .and(predicate::str::contains("target").not())
+                .and(predicate::str::contains("## File: vendor/lib\n\n```text\nGit submodule\nURL: https://example.com/lib.git\n")),
+        );
+}
+
+#[test]
+fn test_submodule_stubs_go_through_the_ignore_rules_and_sorting() {
+    let temp_dir = setup_repo();
+    write(temp_dir.path(), "zz.rs", "fn zz() {}\n");
+    let config_dir = TempDir::new().unwrap();
+    let config_path = config_dir.path().join("config.toml");
+    fs::write(&config_path, "ignore_patterns = []\n").unwrap();
+    let conag = |extra_args: &[&str]| {
+        let output = Command::cargo_bin("conag")
+            .unwrap()
+            .current_dir(temp_dir.path())
+            .args(["--config", config_path.to_str().unwrap(), "--output", "-", "--tracked-only", "--include-untracked"])
+            .args(extra_args)
+            .output()
+            .unwrap();
+        String::from_utf8(output.stdout).unwrap()
+    };
+
+    let sorted = conag(&["--sort", "tree"]);
+    let stub_position = sorted.find("## File: vendor/lib\n").unwrap();
+    assert!(stub_position < sorted.find("## File: zz.rs\n").unwrap());
+
+    fs::write(&config_path, "ignore_patterns = [\"vendor/**\"]\n").unwrap();
+    assert!(!conag(&[]).contains("vendor/lib"));
+}
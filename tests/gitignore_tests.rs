@@ -0,0 +1,168 @@
+use conag::config::Config;
+use conag::gitignore::GitIgnoreMatcher;
+use conag::ignore_rules::{IgnoreRules, apply_ignore_rules};
+use conag::file_system_ops::list_files;
+use std::fs;
+use std::path::Path;
+use tempfile::TempDir;
+
+fn create_test_file(base: &Path, name: &str, content: &str) {
+    let path = base.join(name);
+    fs::create_dir_all(path.parent().unwrap()).unwrap();
+    fs::write(path, content).unwrap();
+}
+
+fn create_test_repo() -> TempDir {
+    let temp_dir = TempDir::new().unwrap();
+    let base = temp_dir.path();
+
+    fs::create_dir_all(base.join(".git").join("info")).unwrap();
+    create_test_file(base, ".git/info/exclude", "secret.txt\n");
+    create_test_file(base, ".gitignore", "*.tmp\n!keep.tmp\n/anchored.txt\nbuild/\n!build/keep.txt\n");
+    create_test_file(base, "sub/.gitignore", "!*.tmp\n");
+
+    for file in [
+        "main.rs",
+        "a.tmp",
+        "keep.tmp",
+        "anchored.txt",
+        "secret.txt",
+        "build/out.txt",
+        "build/keep.txt",
+        "sub/anchored.txt",
+        "sub/build",
+        "sub/b.tmp",
+    ] {
+        create_test_file(base, file, "content");
+    }
+    temp_dir
+}
+
+#[test]
+fn test_gitignore_semantics() {
+    let temp_dir = create_test_repo();
+    let base = temp_dir.path();
+    let matcher = GitIgnoreMatcher::new(base);
+
+    assert!(!matcher.is_ignored(&base.join("main.rs")));
+    assert!(matcher.is_ignored(&base.join("a.tmp")));
+    // Negation re-includes a file ignored by an earlier pattern
+    assert!(!matcher.is_ignored(&base.join("keep.tmp")));
+    // Anchored patterns only match relative to the .gitignore's directory
+    assert!(matcher.is_ignored(&base.join("anchored.txt")));
+    assert!(!matcher.is_ignored(&base.join("sub/anchored.txt")));
+    // Directory-only patterns don't match files with the same name
+    assert!(matcher.is_ignored(&base.join("build/out.txt")));
+    assert!(!matcher.is_ignored(&base.join("sub/build")));
+    // Files inside an excluded directory cannot be re-included
+    assert!(matcher.is_ignored(&base.join("build/keep.txt")));
+}
+
+#[test]
+fn test_nested_gitignore_takes_precedence() {
+    let temp_dir = create_test_repo();
+    let base = temp_dir.path();
+    let matcher = GitIgnoreMatcher::new(base);
+
+    assert!(!matcher.is_ignored(&base.join("sub/b.tmp")));
+}
+
+#[test]
+fn test_git_info_exclude() {
+    let temp_dir = create_test_repo();
+    let base = temp_dir.path();
+    let matcher = GitIgnoreMatcher::new(base);
+
+    assert!(matcher.is_ignored(&base.join("secret.txt")));
+}
+
+#[test]
+fn test_parent_gitignore_applies_to_subdirectory_input() {
+    let temp_dir = create_test_repo();
+    let base = temp_dir.path();
+    create_test_file(base, "nested/c.tmp", "content");
+    let matcher = GitIgnoreMatcher::new(&base.join("nested"));
+
+    assert!(matcher.is_ignored(&base.join("nested/c.tmp")));
+}
+
+#[test]
+fn test_dot_ignore_overrides_gitignore() {
+    let temp_dir = create_test_repo();
+    let base = temp_dir.path();
+    create_test_file(base, ".ignore", "!a.tmp\n");
+    let matcher = GitIgnoreMatcher::new(base);
+
+    assert!(!matcher.is_ignored(&base.join("a.tmp")));
+}
+
+#[test]
+fn test_apply_ignore_rules_with_gitignore() {
+    let temp_dir = create_test_repo();
+    let base = temp_dir.path();
+    let config = Config::default();
+    let ignore_rules = IgnoreRules::new(&config).unwrap().with_gitignore(base);
+    let files = list_files(base).unwrap();
+
+    let result = apply_ignore_rules(
+        &ignore_rules,
+        &files,
+        &["secret.txt".to_string()],
+        &["build".to_string()],
+        base,
+    );
+
+    assert!(result.contains(&base.join("main.rs")));
+    assert!(result.contains(&base.join("keep.tmp")));
+    assert!(!result.contains(&base.join("a.tmp")));
+    // Config overrides take priority over git's ignore rules
+    assert!(result.contains(&base.join("secret.txt")));
+    assert!(result.contains(&base.join("build/out.txt")));
+}
+
+#[test]
+fn test_apply_ignore_rules_without_gitignore() {
+    let temp_dir = create_test_repo();
+    let base = temp_dir.path();
+    let config = Config { respect_gitignore: false, ..Config::default() };
+    let ignore_rules = IgnoreRules::new(&config).unwrap();
+    let files = list_files(base).unwrap();
+
+    let result = apply_ignore_rules(&ignore_rules, &files, &[], &[], base);
+
+    assert!(result.contains(&base.join("a.tmp")));
+    assert!(result.contains(&base.join("build/out.txt")));
+}
+
+#[test]
+fn test_list_candidate_files_skips_ignored_directories() {
+    let temp_dir = create_test_repo();
+    let base = temp_dir.path();
+    create_test_file(base, "target/debug/app", "content");
+    create_test_file(base, "target/keep.txt", "content");
+    create_test_file(base, "logs/today/app.txt", "content");
+    let config = Config { ignore_patterns: vec!["**/target/**".to_string(), "logs/*".to_string()], ..Config::default() };
+    let file_overrides = ["target/keep.txt".to_string()];
+    let dir_overrides = ["build".to_string()];
+    let ignore_rules = IgnoreRules::new(&config).unwrap();
+
+    let files = ignore_rules.list_candidate_files(&file_overrides, &dir_overrides, base, true).unwrap();
+
+    // Ignored directories are not walked, but the overrides are
+    assert!(!files.contains(&base.join("target/debug/app")));
+    assert!(!files.contains(&base.join("logs/today/app.txt")));
+    assert!(files.contains(&base.join("target/keep.txt")));
+    assert!(files.contains(&base.join("build/out.txt")));
+    // Git's ignore files apply while walking
+    assert!(!files.contains(&base.join("a.tmp")));
+    assert!(files.contains(&base.join("keep.tmp")));
+
+    // The rules select the same files as from the full listing
+    let ignore_rules = ignore_rules.with_gitignore_for(base, &files);
+    let mut result = apply_ignore_rules(&ignore_rules, &files, &file_overrides, &dir_overrides, base);
+    let all_rules = IgnoreRules::new(&config).unwrap().with_gitignore(base);
+    let mut expected = apply_ignore_rules(&all_rules, &list_files(base).unwrap(), &file_overrides, &dir_overrides, base);
+    result.sort();
+    expected.sort();
+    assert_eq!(result, expected);
+}
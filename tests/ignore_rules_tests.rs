@@ -18,6 +18,7 @@ fn create_test_config(
         include_hidden_patterns,
         include_file_overrides: vec![],
         include_dir_overrides: vec![],
+        ..Config::default()
     }
 }
 
@@ -29,7 +30,7 @@ fn test_ignore_rules_creation() {
         HashMap::new(),
         vec![],
     );
-    let ignore_rules = IgnoreRules::new(&config);
+    let ignore_rules = IgnoreRules::new(&config).unwrap();
 
     assert_eq!(ignore_rules.rules.len(), 2);
     assert_eq!(ignore_rules.include_hidden.len(), 0);
@@ -44,7 +45,7 @@ fn test_ignore_hidden_files() {
         HashMap::new(),
         vec![".gitignore".to_string()],
     );
-    let ignore_rules = IgnoreRules::new(&config);
+    let ignore_rules = IgnoreRules::new(&config).unwrap();
     let mut files = HashSet::new();
     let input_dir = PathBuf::from("/test/input");
 
@@ -73,7 +74,7 @@ fn test_ignore_rules_with_hidden_patterns() {
         HashMap::new(),
         vec![".env".to_string()],
     );
-    let ignore_rules = IgnoreRules::new(&config);
+    let ignore_rules = IgnoreRules::new(&config).unwrap();
     let mut files = HashSet::new();
     let input_dir = PathBuf::from("/test/input");
 
@@ -103,7 +104,7 @@ fn test_ignore_file_extension() {
         HashMap::new(),
         vec![],
     );
-    let ignore_rules = IgnoreRules::new(&config);
+    let ignore_rules = IgnoreRules::new(&config).unwrap();
     let mut files = HashSet::new();
     let input_dir = PathBuf::from("/test/input");
 
@@ -131,7 +132,7 @@ fn test_ignore_directory() {
         HashMap::new(),
         vec![],
     );
-    let ignore_rules = IgnoreRules::new(&config);
+    let ignore_rules = IgnoreRules::new(&config).unwrap();
     let mut files = HashSet::new();
     let input_dir = PathBuf::from("/test/input");
 
@@ -163,7 +164,7 @@ fn test_project_specific_ignore_rules() {
         vec![],
     );
     
-    let ignore_rules = IgnoreRules::new(&config);
+    let ignore_rules = IgnoreRules::new(&config).unwrap();
     let mut files = HashSet::new();
     let input_dir = PathBuf::from("/test/input");
 
@@ -192,7 +193,7 @@ fn test_include_directory_override() {
     );
     let include_dir_overrides = vec!["included_dir".to_string()];
     let include_file_overrides = vec!["ignored_dir/ignored_file.txt".to_string()];
-    let ignore_rules = IgnoreRules::new(&config);
+    let ignore_rules = IgnoreRules::new(&config).unwrap();
     let input_dir = PathBuf::from("/test/input");
     let mut files = HashSet::new();
 
@@ -228,7 +229,7 @@ fn test_include_file_override() {
     );
     let include_file_overrides = vec!["file1.txt".to_string()];
     let include_dir_overrides = vec![];
-    let ignore_rules = IgnoreRules::new(&config);
+    let ignore_rules = IgnoreRules::new(&config).unwrap();
     let mut files = HashSet::new();
     let input_dir = PathBuf::from("/test/input");
 
@@ -0,0 +1,101 @@
+use assert_cmd::Command;
+use conag::listing::{ListingTotals, Listing};
+use predicates::prelude::*;
+use std::fs;
+use std::path::{Path, PathBuf};
+use tempfile::TempDir;
+
+fn create_test_file(base: &Path, name: &str, content: &str) {
+    let path = base.join(name);
+    fs::create_dir_all(path.parent().unwrap()).unwrap();
+    fs::write(path, content).unwrap();
+}
+
+#[test]
+fn test_listing_totals() {
+    let temp_dir = TempDir::new().unwrap();
+    let base = temp_dir.path();
+    create_test_file(base, "src/main.rs", "fn main() {}\n");
+    create_test_file(base, "src/lib.rs", "pub mod a;\n");
+    create_test_file(base, "README.md", &"a".repeat(41));
+    let files: Vec<PathBuf> = ["README.md", "src/lib.rs", "src/main.rs"].iter().map(|file| base.join(file)).collect();
+
+    let listing = Listing::new(&files, base).unwrap();
+
+    assert_eq!(listing.files[0].path, PathBuf::from("README.md"));
+    assert_eq!((listing.files[0].size, listing.files[0].estimated_tokens), (41, 11));
+    assert_eq!(listing.files[0].language, "markdown");
+    assert_eq!(listing.totals, ListingTotals { files: 3, size: 65, estimated_tokens: 11 + 3 + 4 });
+    let languages: Vec<(&str, usize)> = listing.languages.iter().map(|(language, totals)| (language.as_str(), totals.files)).collect();
+    assert_eq!(languages, [("markdown", 1), ("rust", 2)]);
+}
+
+#[test]
+fn test_listing_render() {
+    let temp_dir = TempDir::new().unwrap();
+    let base = temp_dir.path();
+    create_test_file(base, "main.rs", "fn main() {}\n");
+    create_test_file(base, "notes.txt", "hi\n");
+    let listing = Listing::new(&[base.join("main.rs"), base.join("notes.txt")], base).unwrap();
+
+    assert_eq!(listing.render(false), "main.rs\nnotes.txt\n");
+    assert_eq!(
+        listing.render(true),
+        "SIZE  TOKENS  LANGUAGE  PATH\n  13       4  rust      main.rs\n   3       1  text      notes.txt\n\n  16       5  total     (2 files)\n  13       4  rust      (1 files)\n   3       1  text      (1 files)\n"
+    );
+
+    let json: serde_json::Value = serde_json::from_str(&listing.render_json()).unwrap();
+    assert_eq!(json["files"][1]["path"], "notes.txt");
+    assert_eq!(json["totals"]["estimated_tokens"], 5);
+    assert_eq!(json["languages"]["rust"]["size"], 13);
+}
+
+#[test]
+fn test_list_flag_writes_no_output() {
+    let temp_dir = TempDir::new().unwrap();
+    let base = temp_dir.path();
+    create_test_file(base, "src/main.rs", "fn main() {}\n");
+    create_test_file(base, "debug.log", "log\n");
+    let output_dir = base.join("out");
+    let config_path = base.join("config.toml");
+    fs::write(
+        &config_path,
+        format!("input_dir = {:?}\noutput_dir = {:?}\nignore_patterns = [\"**/*.log\", \"**/*.toml\"]\n", base, output_dir),
+    )
+    .unwrap();
+    let conag = || {
+        let mut command = Command::cargo_bin("conag").unwrap();
+        command.current_dir(base).args(["--config", config_path.to_str().unwrap(), "--list"]);
+        command
+    };
+
+    conag()
+        .assert()
+        .success()
+        .stdout("src/main.rs\n")
+        .stderr(predicate::str::contains("1 files, 13 bytes, about 4 tokens"));
+    conag()
+        .arg("--json")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("\"path\": \"src/main.rs\"").and(predicate::str::contains("\"language\": \"rust\"")));
+    assert!(!output_dir.exists());
+}
+
+#[test]
+fn test_list_flag_does_not_resolve_the_output_directory() {
+    let temp_dir = TempDir::new().unwrap();
+    let base = temp_dir.path();
+    create_test_file(base, "src/main.rs", "fn main() {}\n");
+    let config_path = base.join("config.toml");
+    fs::write(&config_path, format!("input_dir = {:?}\noutput_dir = \"{{DESKTOP}}/out\"\nignore_patterns = [\"**/*.toml\"]\n", base)).unwrap();
+
+    // Without a home directory, there is no desktop directory either
+    Command::cargo_bin("conag")
+        .unwrap()
+        .env("HOME", base.join("missing-home"))
+        .args(["--config", config_path.to_str().unwrap(), "--list"])
+        .assert()
+        .success()
+        .stdout("src/main.rs\n");
+}
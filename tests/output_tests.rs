@@ -0,0 +1,168 @@
+use assert_cmd::Command;
+use conag::output::{write_output, OutputTarget};
+use predicates::prelude::*;
+use std::fs;
+use std::path::PathBuf;
+use tempfile::TempDir;
+
+fn setup_project() -> (TempDir, PathBuf) {
+    let temp_dir = TempDir::new().unwrap();
+    let project = temp_dir.path().join("project");
+    fs::create_dir_all(project.join("src")).unwrap();
+    fs::write(project.join("src/main.rs"), "fn main() {}\n").unwrap();
+
+    let config_path = temp_dir.path().join("config.toml");
+    let config = format!("output_dir = {:?}\nrespect_gitignore = false\n", temp_dir.path().join("out"));
+    fs::write(&config_path, config).unwrap();
+    (temp_dir, config_path)
+}
+
+#[test]
+fn test_resolve_explicit_output() {
+    let no_default = || -> anyhow::Result<PathBuf> { panic!("the default path should not be needed") };
+
+    assert_eq!(OutputTarget::resolve(Some("-"), false, no_default).unwrap(), OutputTarget::Stdout);
+    assert_eq!(
+        OutputTarget::resolve(Some("ctx.md"), true, no_default).unwrap(),
+        OutputTarget::File(PathBuf::from("ctx.md"))
+    );
+}
+
+#[test]
+fn test_resolve_default_output() {
+    let target = OutputTarget::resolve(None, false, || Ok(PathBuf::from("/out/project_conag_output.md"))).unwrap();
+
+    assert_eq!(target, OutputTarget::File(PathBuf::from("/out/project_conag_output.md")));
+}
+
+#[test]
+fn test_write_output_parts_to_files() {
+    let temp_dir = TempDir::new().unwrap();
+    let output_path = temp_dir.path().join("nested/ctx.md");
+
+    let written = write_output(&OutputTarget::File(output_path.clone()), &["one".to_string(), "two".to_string()]).unwrap();
+
+    assert_eq!(written, vec![temp_dir.path().join("nested/ctx.part01.md"), temp_dir.path().join("nested/ctx.part02.md")]);
+    assert_eq!(fs::read_to_string(&written[1]).unwrap(), "two");
+    assert!(!output_path.exists());
+}
+
+#[test]
+fn test_write_several_parts_to_stdout_fails() {
+    let result = write_output(&OutputTarget::Stdout, &["one".to_string(), "two".to_string()]);
+
+    assert!(result.is_err());
+}
+
+#[test]
+fn test_output_to_stdout() {
+    let (temp_dir, config_path) = setup_project();
+
+    Command::cargo_bin("conag")
+        .unwrap()
+        .current_dir(temp_dir.path().join("project"))
+        .args(["--config", config_path.to_str().unwrap(), "--output", "-"])
+        .assert()
+        .success()
+        .stdout(predicate::str::starts_with("# Project: project").and(predicate::str::contains("fn main() {}")))
+        .stderr(predicate::str::contains("Total tokens:"));
+
+    assert!(!temp_dir.path().join("out").exists());
+}
+
+#[test]
+fn test_quiet_silences_status_messages() {
+    let (temp_dir, config_path) = setup_project();
+    let output_path = temp_dir.path().join("ctx.md");
+
+    Command::cargo_bin("conag")
+        .unwrap()
+        .current_dir(temp_dir.path().join("project"))
+        .args(["--config", config_path.to_str().unwrap(), "--output", output_path.to_str().unwrap(), "--quiet"])
+        .assert()
+        .success()
+        .stdout(predicate::str::is_empty())
+        .stderr(predicate::str::is_empty());
+
+    assert!(fs::read_to_string(&output_path).unwrap().contains("## File: src/main.rs"));
+}
+
+#[test]
+fn test_streamed_and_buffered_output_count_the_same_tokens() {
+    let (temp_dir, config_path) = setup_project();
+    fs::write(temp_dir.path().join("project/src/lib.rs"), "pub fn add(a: u8, b: u8) -> u8 {\n    a + b\n}\n").unwrap();
+    let total_tokens = |args: &[&str]| {
+        let output = Command::cargo_bin("conag")
+            .unwrap()
+            .current_dir(temp_dir.path().join("project"))
+            .args(["--config", config_path.to_str().unwrap(), "--output", "-", "--no-cache"])
+            .args(args)
+            .output()
+            .unwrap();
+        let stderr = String::from_utf8(output.stderr).unwrap();
+        stderr.lines().find(|line| line.starts_with("Total tokens:")).unwrap().to_string()
+    };
+
+    // A token budget needs every file to be read first, so the output is built in memory
+    assert_eq!(total_tokens(&[]), total_tokens(&["--max-tokens", "100000"]));
+}
+
+#[test]
+fn test_run_can_be_called_twice_in_one_process() {
+    use clap::Parser;
+    use conag::cli::{run, Cli};
+
+    let (temp_dir, config_path) = setup_project();
+    let project = temp_dir.path().join("project");
+    fs::write(&config_path, format!("input_dir = {:?}\nrespect_gitignore = false\n", project)).unwrap();
+    let output_path = temp_dir.path().join("ctx.md");
+    let args = [
+        "conag",
+        "--config",
+        config_path.to_str().unwrap(),
+        "--output",
+        output_path.to_str().unwrap(),
+        "--no-cache",
+        "--quiet",
+    ];
+
+    run(Cli::parse_from(args)).unwrap();
+    fs::remove_file(&output_path).unwrap();
+    run(Cli::parse_from(args)).unwrap();
+    assert!(fs::read_to_string(&output_path).unwrap().contains("fn main() {}"));
+}
+
+#[test]
+fn test_tree_marks_files_omitted_by_the_token_budget() {
+    let (temp_dir, config_path) = setup_project();
+    let project = temp_dir.path().join("project");
+    fs::write(project.join("src/large.rs"), "// filler line\n".repeat(2000)).unwrap();
+    fs::write(&config_path, format!("input_dir = {:?}\nrespect_gitignore = false\n", project)).unwrap();
+
+    let output = Command::cargo_bin("conag")
+        .unwrap()
+        .args(["--config", config_path.to_str().unwrap(), "--output", "-"])
+        .args(["--tree", "--max-tokens", "500", "--no-cache", "--quiet"])
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8(output.stdout).unwrap();
+
+    assert!(stdout.contains("large.rs  [skipped: over the token budget]"));
+    assert!(!stdout.contains("// filler line"));
+}
+
+#[test]
+fn test_output_to_a_bare_file_name() {
+    let (temp_dir, config_path) = setup_project();
+    let project = temp_dir.path().join("project");
+    fs::write(&config_path, format!("input_dir = {:?}\nrespect_gitignore = false\n", project)).unwrap();
+
+    Command::cargo_bin("conag")
+        .unwrap()
+        .current_dir(temp_dir.path())
+        .args(["--config", config_path.to_str().unwrap(), "--output", "ctx.md", "--no-cache"])
+        .assert()
+        .success();
+
+    assert!(fs::read_to_string(temp_dir.path().join("ctx.md")).unwrap().contains("fn main() {}"));
+}
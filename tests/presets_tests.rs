@@ -0,0 +1,180 @@
+use assert_cmd::Command;
+use conag::config::Config;
+use conag::config_layers::{ConfigLayer, LayerSource};
+use conag::config_validation::validate_layer;
+use conag::ignore_rules::{IgnoreRules, PatternSource, apply_ignore_rules};
+use conag::presets::{detect_presets, find_preset, PRESETS};
+use predicates::prelude::*;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use tempfile::TempDir;
+
+fn create_test_file(base: &Path, name: &str, content: &str) {
+    let path = base.join(name);
+    fs::create_dir_all(path.parent().unwrap()).unwrap();
+    fs::write(path, content).unwrap();
+}
+
+fn preset_names(dir: &Path) -> Vec<&'static str> {
+    detect_presets(dir).iter().map(|detected| detected.preset.name).collect()
+}
+
+#[test]
+fn test_detect_presets_from_markers() {
+    let temp_dir = TempDir::new().unwrap();
+    let base = temp_dir.path();
+    assert_eq!(preset_names(base), Vec::<&str>::new());
+
+    create_test_file(base, "Cargo.toml", "[package]\n");
+    assert_eq!(preset_names(base), ["rust"]);
+
+    // Polyglot repositories activate every preset found below, in the order of PRESETS
+    create_test_file(base, "services/web/package.json", "{}");
+    create_test_file(base, "tools/App.csproj", "<Project />");
+    create_test_file(base, "Makefile", "all:\n");
+    assert_eq!(preset_names(base), ["rust", "node", "dotnet"]);
+    let detected = detect_presets(base);
+    assert_eq!(detected[1].roots, [PathBuf::from("services/web")]);
+
+    // Hidden directories, dependency directories and deeper directories are not looked into
+    create_test_file(base, ".cache/go.mod", "module x\n");
+    create_test_file(base, "services/web/node_modules/pkg/pyproject.toml", "");
+    create_test_file(base, "a/b/c/d/composer.json", "{}");
+    assert_eq!(preset_names(base), ["rust", "node", "dotnet"]);
+}
+
+#[test]
+fn test_preset_ignores_apply_under_their_marker() {
+    let temp_dir = TempDir::new().unwrap();
+    let base = temp_dir.path();
+    create_test_file(base, "Tool.sln", "");
+    create_test_file(base, "services/web/package.json", "{}");
+    let config = Config { input_dir: base.to_string_lossy().into_owned(), ignore_patterns: vec![], ..Config::default() };
+    let ignore_rules = IgnoreRules::new(&config).unwrap();
+    let files: HashSet<PathBuf> = [
+        "bin/Debug/Tool.dll",
+        "tools/bin/run.rs",
+        "services/web/node_modules/lib/index.js",
+        "services/web/yarn.lock",
+        "yarn.lock",
+    ]
+    .iter()
+    .map(|file| base.join(file))
+    .collect();
+
+    let mut result = apply_ignore_rules(&ignore_rules, &files, &[], &[], base);
+    result.sort();
+
+    assert_eq!(result, [base.join("tools/bin/run.rs"), base.join("yarn.lock")]);
+    assert_eq!(find_preset("node").unwrap().ignores_under(Path::new("services/web"))[0], "services/web/**/node_modules/**");
+}
+
+#[test]
+fn test_presets_are_valid() {
+    let mut names = HashSet::new();
+    for preset in PRESETS {
+        assert!(names.insert(preset.name), "duplicate preset {}", preset.name);
+        assert!(!preset.markers.is_empty() && !preset.ignores.is_empty());
+        for pattern in preset.markers.iter().chain(preset.ignores) {
+            assert!(glob::Pattern::new(pattern).is_ok(), "invalid pattern {} in {}", pattern, preset.name);
+        }
+    }
+    assert_eq!(find_preset("go").unwrap().markers, ["go.mod"]);
+    assert_eq!(find_preset("cobol"), None);
+}
+
+#[test]
+fn test_active_project_types() {
+    let temp_dir = TempDir::new().unwrap();
+    let base = temp_dir.path();
+    create_test_file(base, "go.mod", "module x\n");
+    let config = Config {
+        input_dir: base.to_string_lossy().into_owned(),
+        project_type: Some("python".to_string()),
+        project_types: vec!["go".to_string(), "internal".to_string()],
+        ..Config::default()
+    };
+
+    assert_eq!(config.active_project_types(), ["python", "go", "internal"]);
+    let config = Config { project_types: vec![], detect_project_types: false, ..config };
+    assert_eq!(config.active_project_types(), ["python"]);
+}
+
+#[test]
+fn test_preset_ignores_are_applied() {
+    let temp_dir = TempDir::new().unwrap();
+    let base = temp_dir.path();
+    create_test_file(base, "Cargo.toml", "[package]\n");
+    create_test_file(base, "frontend/package.json", "{}");
+    let config = Config {
+        input_dir: base.to_string_lossy().into_owned(),
+        project_specific_ignores: HashMap::from([("node".to_string(), vec!["**/storybook-static/**".to_string()])]),
+        ..Config::default()
+    };
+    let ignore_rules = IgnoreRules::new(&config).unwrap();
+    let files: HashSet<PathBuf> = [
+        "src/main.rs",
+        "target/debug/app",
+        "Cargo.lock",
+        "frontend/node_modules/react/index.js",
+        "frontend/storybook-static/index.html",
+        "frontend/src/app.ts",
+    ]
+    .iter()
+    .map(|file| base.join(file))
+    .collect();
+
+    let mut result = apply_ignore_rules(&ignore_rules, &files, &[], &[], base);
+    result.sort();
+
+    assert_eq!(result, [base.join("frontend/src/app.ts"), base.join("src/main.rs")]);
+    assert!(ignore_rules.rule_sources.contains(&PatternSource::Preset("rust", 0)));
+    assert!(ignore_rules.rule_sources.contains(&PatternSource::Config("project_specific_ignores.node".to_string(), 0)));
+}
+
+#[test]
+fn test_unknown_project_types_are_warnings() {
+    let temp_dir = TempDir::new().unwrap();
+    let path = temp_dir.path().join("config.toml");
+    fs::write(&path, "project_types = [\"rust\", \"rsut\", \"internal\"]\n\n[project_specific_ignores]\ninternal = [\"gen/**\"]\n").unwrap();
+    let layer = ConfigLayer::from_file(LayerSource::Global, &path).unwrap();
+
+    let issues = validate_layer(&layer);
+
+    assert_eq!(issues.len(), 1);
+    assert_eq!(
+        issues[0].message,
+        "unknown project type 'rsut' in project_types[1], which has no preset and no project_specific_ignores"
+    );
+    assert_eq!(issues[0].location.map(|location| (location.line, location.column)), Some((1, 26)));
+}
+
+#[test]
+fn test_presets_commands() {
+    let temp_dir = TempDir::new().unwrap();
+    let base = temp_dir.path();
+    create_test_file(base, "pyproject.toml", "");
+    let config_path = base.join("config.toml");
+    fs::write(&config_path, "[project_specific_ignores]\npython = [\"**/notebooks/**\"]\n").unwrap();
+    let conag = || {
+        let mut command = Command::cargo_bin("conag").unwrap();
+        command.current_dir(base).args(["--config", config_path.to_str().unwrap(), "presets"]);
+        command
+    };
+
+    conag()
+        .arg("list")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("* python").and(predicate::str::contains("  rust    Cargo.toml")))
+        .stderr(predicate::str::contains("Active project types: python"));
+    conag()
+        .args(["show", "python"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Detected by: pyproject.toml"))
+        .stdout(predicate::str::contains("  **/__pycache__/**"))
+        .stdout(predicate::str::contains("Also ignored, from project_specific_ignores.python:\n  **/notebooks/**\n"));
+    conag().args(["show", "cobol"]).assert().failure().stderr(predicate::str::contains("Unknown preset 'cobol'"));
+}
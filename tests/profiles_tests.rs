@@ -0,0 +1,127 @@
+use assert_cmd::Command;
+use conag::config_layers::{ConfigLayer, LayerSource, LayeredConfig};
+use conag::config_validation::validate_layer;
+use predicates::prelude::*;
+use std::fs;
+use std::path::Path;
+use tempfile::TempDir;
+use toml::Table;
+
+fn layer(source: LayerSource, toml_str: &str) -> ConfigLayer {
+    ConfigLayer::new(source, toml::from_str::<Table>(toml_str).unwrap())
+}
+
+fn create_test_file(base: &Path, name: &str, content: &str) {
+    let path = base.join(name);
+    fs::create_dir_all(path.parent().unwrap()).unwrap();
+    fs::write(path, content).unwrap();
+}
+
+#[test]
+fn test_profiles_layer_over_the_config_files() {
+    let mut layers = vec![
+        ConfigLayer::defaults().unwrap(),
+        layer(LayerSource::Global, r#"
+            ignore_patterns = ["*.log"]
+
+            [profiles.api]
+            extend_ignore_patterns = ["web/**"]
+            sort = "tree"
+
+            [profiles.tests]
+            sort = "size"
+            max_tokens = 5000
+        "#),
+        layer(LayerSource::Project, r#"
+            [profiles.api]
+            extend_ignore_patterns = ["docs/**"]
+            line_numbers = true
+        "#),
+    ];
+    // The project file's definition of a profile replaces the global one
+    let api = ConfigLayer::from_profile("api", &layers).unwrap();
+    let tests = ConfigLayer::from_profile("tests", &layers).unwrap();
+    assert_eq!(api.origin.source, LayerSource::Profile);
+    assert_eq!(api.origin.to_string(), "profile api");
+    layers.extend([api, tests]);
+
+    let layered = LayeredConfig::from_layers(&layers).unwrap();
+
+    assert_eq!(layered.config.ignore_patterns, ["*.log", "docs/**"]);
+    assert!(layered.config.line_numbers);
+    // Later profiles take precedence
+    assert_eq!(layered.config.sort, conag::sorting::SortMode::Size);
+    assert_eq!(layered.config.max_tokens, Some(5000));
+    assert_eq!(layered.origins["ignore_patterns[1]"].profile.as_deref(), Some("api"));
+    assert_eq!(layered.origins["sort"].profile.as_deref(), Some("tests"));
+}
+
+#[test]
+fn test_unknown_profile_is_an_error() {
+    let layers = vec![
+        ConfigLayer::defaults().unwrap(),
+        layer(LayerSource::Global, "[profiles.docs]\nsort = \"tree\"\n\n[profiles.api]\nsort = \"size\"\n"),
+    ];
+
+    let err = ConfigLayer::from_profile("tset", &layers).unwrap_err();
+    assert_eq!(err.to_string(), "Unknown profile 'tset'. The profiles are: api, docs");
+    let err = ConfigLayer::from_profile("docs", &layers[..1]).unwrap_err();
+    assert_eq!(err.to_string(), "Unknown profile 'docs'. No profiles are defined in the config files");
+}
+
+#[test]
+fn test_profile_keys_are_validated() {
+    let temp_dir = TempDir::new().unwrap();
+    let path = temp_dir.path().join("config.toml");
+    fs::write(&path, "sort = \"path\"\n\n[profiles.docs]\nignore_patern = []\nextend_ignore_patterns = [\"[x\"]\n").unwrap();
+    let layer = ConfigLayer::from_file(LayerSource::Global, &path).unwrap();
+
+    let issues = validate_layer(&layer);
+
+    let found: Vec<(&str, Option<(usize, usize)>)> = issues
+        .iter()
+        .map(|issue| (issue.message.as_str(), issue.location.map(|location| (location.line, location.column))))
+        .collect();
+    assert_eq!(
+        found,
+        [
+            ("invalid glob pattern '[x' in profiles.docs.extend_ignore_patterns[0]: invalid range pattern", Some((5, 27))),
+            ("unknown key 'profiles.docs.ignore_patern', did you mean 'ignore_patterns'?", Some((4, 1))),
+        ]
+    );
+}
+
+#[test]
+fn test_profile_flag() {
+    let temp_dir = TempDir::new().unwrap();
+    let base = temp_dir.path().join("project");
+    create_test_file(&base, "src/api.rs", "fn api() {}\n");
+    create_test_file(&base, "tests/api_tests.rs", "#[test]\nfn t() {}\n");
+    create_test_file(&base, "docs/guide.md", "# Guide\n");
+    let output_dir = temp_dir.path().join("out");
+    let config_path = temp_dir.path().join("config.toml");
+    fs::write(
+        &config_path,
+        format!(
+            "input_dir = {:?}\noutput_dir = {:?}\ndetect_project_types = false\n\n[profiles.api]\nextend_ignore_patterns = [\"**/docs/**\", \"**/tests/**\"]\n\n[profiles.tests]\nignore_patterns = [\"**/src/**\"]\n",
+            base, output_dir
+        ),
+    )
+    .unwrap();
+    let conag = || {
+        let mut command = Command::cargo_bin("conag").unwrap();
+        command.current_dir(&base).args(["--config", config_path.to_str().unwrap()]);
+        command
+    };
+
+    conag().args(["--profile", "api", "--list"]).assert().success().stdout("src/api.rs\n");
+    conag().args(["-p", "api,tests", "--list"]).assert().success().stdout("docs/guide.md\ntests/api_tests.rs\n");
+    conag().args(["-p", "api"]).assert().success();
+    let output = fs::read_to_string(output_dir.join("project_api_conag_output.md")).unwrap();
+    assert!(output.contains("src/api.rs") && !output.contains("guide.md"));
+    conag()
+        .args(["-p", "web", "--list"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("Unknown profile 'web'. The profiles are: api, tests"));
+}
@@ -0,0 +1,114 @@
+use conag::sorting::{sort_files, SortMode};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+use tempfile::TempDir;
+
+fn create_test_file(base: &Path, name: &str, content: &str) -> PathBuf {
+    let path = base.join(name);
+    fs::create_dir_all(path.parent().unwrap()).unwrap();
+    fs::write(&path, content).unwrap();
+    path
+}
+
+fn relative_names(files: &[PathBuf], base: &Path) -> Vec<String> {
+    files
+        .iter()
+        .map(|file| file.strip_prefix(base).unwrap().to_string_lossy().into_owned())
+        .collect()
+}
+
+#[test]
+fn test_sort_by_path() {
+    let base = PathBuf::from("/test/input");
+    let mut files = vec![
+        base.join("src/main.rs"),
+        base.join("README.md"),
+        base.join("Cargo.toml"),
+        base.join("src/cli.rs"),
+    ];
+
+    sort_files(&mut files, SortMode::Path, &base);
+
+    assert_eq!(relative_names(&files, &base), vec!["Cargo.toml", "README.md", "src/cli.rs", "src/main.rs"]);
+}
+
+#[test]
+fn test_sort_in_tree_order() {
+    let base = PathBuf::from("/test/input");
+    let mut files = vec![
+        base.join("z.txt"),
+        base.join("a.txt"),
+        base.join("src/lib.rs"),
+        base.join("src/bin/tool.rs"),
+        base.join("docs/guide.md"),
+    ];
+
+    sort_files(&mut files, SortMode::Tree, &base);
+
+    assert_eq!(
+        relative_names(&files, &base),
+        vec!["docs/guide.md", "src/bin/tool.rs", "src/lib.rs", "a.txt", "z.txt"]
+    );
+}
+
+#[test]
+fn test_sort_by_size() {
+    let temp_dir = TempDir::new().unwrap();
+    let base = temp_dir.path();
+    let mut files = vec![
+        create_test_file(base, "large.txt", "a much larger file"),
+        create_test_file(base, "small.txt", "s"),
+        create_test_file(base, "medium.txt", "medium"),
+    ];
+
+    sort_files(&mut files, SortMode::Size, base);
+
+    assert_eq!(relative_names(&files, base), vec!["small.txt", "medium.txt", "large.txt"]);
+}
+
+#[test]
+fn test_sort_by_mtime() {
+    let temp_dir = TempDir::new().unwrap();
+    let base = temp_dir.path();
+    let older = create_test_file(base, "older.txt", "older");
+    let newer = create_test_file(base, "newer.txt", "newer");
+    let now = SystemTime::now();
+    fs::File::options().write(true).open(&older).unwrap().set_modified(now - Duration::from_secs(3600)).unwrap();
+    fs::File::options().write(true).open(&newer).unwrap().set_modified(now).unwrap();
+    let mut files = vec![older, newer];
+
+    sort_files(&mut files, SortMode::Mtime, base);
+
+    assert_eq!(relative_names(&files, base), vec!["newer.txt", "older.txt"]);
+}
+
+#[test]
+fn test_sort_entry_points_first() {
+    let base = PathBuf::from("/test/input");
+    let mut files = vec![
+        base.join("src/cli.rs"),
+        base.join("README.md"),
+        base.join("Cargo.toml"),
+        base.join("src/lib.rs"),
+        base.join("src/main.rs"),
+    ];
+
+    sort_files(&mut files, SortMode::EntryPoints, &base);
+
+    assert_eq!(
+        relative_names(&files, &base),
+        vec!["src/main.rs", "src/lib.rs", "README.md", "Cargo.toml", "src/cli.rs"]
+    );
+}
+
+#[test]
+fn test_sort_is_deterministic() {
+    let base = PathBuf::from("/test/input");
+    let expected = vec![base.join("a/b.rs"), base.join("a/c.rs"), base.join("b.rs")];
+    let mut reversed: Vec<PathBuf> = expected.iter().rev().cloned().collect();
+
+    sort_files(&mut reversed, SortMode::Path, &base);
+
+    assert_eq!(reversed, expected);
+}
@@ -0,0 +1,36 @@
+use conag::tokenizer::{BpeEstimator, CharsHeuristic, Tokenizer, TokenizerKind};
+
+#[test]
+fn test_chars_heuristic() {
+    assert_eq!(CharsHeuristic.count_tokens(""), 0);
+    assert_eq!(CharsHeuristic.count_tokens("abcd"), 1);
+    assert_eq!(CharsHeuristic.count_tokens("abcde"), 2);
+}
+
+#[test]
+fn test_bpe_estimator_counts_words_with_leading_spaces() {
+    assert_eq!(BpeEstimator.count_tokens(""), 0);
+    assert_eq!(BpeEstimator.count_tokens("hello"), 1);
+    assert_eq!(BpeEstimator.count_tokens("hello world"), 2);
+    assert_eq!(BpeEstimator.count_tokens("the quick brown fox"), 4);
+}
+
+#[test]
+fn test_bpe_estimator_splits_identifiers_and_numbers() {
+    assert_eq!(BpeEstimator.count_tokens("getLanguageIdentifier"), 3);
+    assert_eq!(BpeEstimator.count_tokens("1234567"), 3);
+    assert_eq!(BpeEstimator.count_tokens("fn main() {}"), 4);
+}
+
+#[test]
+fn test_bpe_estimator_counts_newline_runs_once() {
+    assert_eq!(BpeEstimator.count_tokens("a\n\n\nb"), 3);
+    assert_eq!(BpeEstimator.count_tokens("a\n        b"), 3);
+}
+
+#[test]
+fn test_tokenizer_kind_selects_implementation() {
+    let text = "some text to count";
+    assert_eq!(TokenizerKind::Chars.tokenizer().count_tokens(text), CharsHeuristic.count_tokens(text));
+    assert_eq!(TokenizerKind::Bpe.tokenizer().count_tokens(text), BpeEstimator.count_tokens(text));
+}
@@ -0,0 +1,91 @@
+use conag::formatter::{render_document, Footer, FormatOptions, Header, JsonFormatter, MarkdownFormatter};
+use conag::tree::{DirectoryTree, TreeMark, TreeOptions};
+use std::path::{Path, PathBuf};
+
+fn test_tree() -> DirectoryTree {
+    let base = Path::new("/project");
+    let all_files: Vec<PathBuf> = [
+        "src/main.rs",
+        "src/lib.rs",
+        "src/util/strings.rs",
+        "target/debug/app",
+        "target/debug/app.d",
+        "README.md",
+        "logo.png",
+    ]
+    .iter()
+    .map(|path| base.join(path))
+    .collect();
+    let included: Vec<PathBuf> = ["src/main.rs", "src/lib.rs", "src/util/strings.rs", "README.md", "logo.png"]
+        .iter()
+        .map(|path| base.join(path))
+        .collect();
+
+    let mut tree = DirectoryTree::from_files("project", base, &all_files, &included);
+    tree.insert(Path::new("logo.png"), TreeMark::Skipped("binary".to_string()));
+    tree
+}
+
+#[test]
+fn test_render_marks_files() {
+    let rendered = test_tree().render(&TreeOptions::default());
+
+    assert_eq!(
+        rendered,
+        "project/\n\
+         ├── src/\n\
+         │   ├── util/\n\
+         │   │   └── strings.rs\n\
+         │   ├── lib.rs\n\
+         │   └── main.rs\n\
+         ├── target/  [ignored]\n\
+         ├── README.md\n\
+         └── logo.png  [skipped: binary]\n"
+    );
+}
+
+#[test]
+fn test_render_without_ignored_files() {
+    let options = TreeOptions { show_ignored: false, ..TreeOptions::default() };
+
+    let rendered = test_tree().render(&options);
+
+    assert!(!rendered.contains("target"));
+    assert!(rendered.contains("├── README.md\n"));
+}
+
+#[test]
+fn test_depth_limit_collapses_directories() {
+    let options = TreeOptions { max_depth: Some(1), ..TreeOptions::default() };
+
+    let rendered = test_tree().render(&options);
+
+    assert!(rendered.contains("├── src/  … 3 files\n"));
+    assert!(!rendered.contains("main.rs"));
+}
+
+#[test]
+fn test_files_per_directory_are_collapsed() {
+    let mut tree = DirectoryTree::new("project");
+    for index in 0..150 {
+        tree.insert(&PathBuf::from(format!("fixtures/case{:03}.json", index)), TreeMark::Included);
+    }
+    let options = TreeOptions { max_files_per_dir: Some(5), ..TreeOptions::default() };
+
+    let rendered = tree.render(&options);
+
+    assert!(rendered.contains("    ├── case004.json\n    └── … 145 more files\n"));
+    assert!(!rendered.contains("case005.json"));
+}
+
+#[test]
+fn test_tree_in_every_format() {
+    let tree = test_tree().render(&TreeOptions::default());
+    let header = Header { tree: Some(&tree), ..Header::new("project") };
+
+    let markdown = render_document(&MarkdownFormatter, &FormatOptions::default(), &header, None, &[], &Footer::default());
+    let json: serde_json::Value = serde_json::from_str(&render_document(&JsonFormatter, &FormatOptions::default(), &header, None, &[], &Footer::default())).unwrap();
+
+    assert!(markdown.starts_with("# Project: project\n\n## Directory Tree\n\n```\nproject/\n"));
+    assert_eq!(json["tree"], tree);
+}
@@ -0,0 +1,124 @@
+use conag::aggregator::{aggregate_contents, ReadOptions, SkipReason};
+use conag::formatter::{render_document, Footer, FormatOptions, Header, JsonFormatter, MarkdownFormatter};
+use conag::truncation::{SizeLimitAction, SizeLimits, Truncation};
+use std::fs;
+use std::path::PathBuf;
+use tempfile::TempDir;
+
+fn numbered_lines(count: usize) -> String {
+    (1..=count).map(|line| format!("line {:03}\n", line)).collect()
+}
+
+fn read_with(files: &[(&str, &[u8])], size_limits: SizeLimits) -> (TempDir, ReadOptions, Vec<PathBuf>) {
+    let temp_dir = TempDir::new().unwrap();
+    let paths = files
+        .iter()
+        .map(|(name, content)| {
+            let path = temp_dir.path().join(name);
+            fs::write(&path, content).unwrap();
+            path
+        })
+        .collect();
+    let options = ReadOptions { size_limits, ..ReadOptions::default() };
+    (temp_dir, options, paths)
+}
+
+fn file_limit(limit: u64, action: SizeLimitAction) -> SizeLimits {
+    SizeLimits { max_file_size: Some(limit), action, max_lines: 5, ..SizeLimits::default() }
+}
+
+#[test]
+fn test_large_files_are_skipped_by_default() {
+    let content = numbered_lines(100);
+    let (temp_dir, options, paths) = read_with(&[("big.txt", content.as_bytes()), ("small.txt", b"ok\n")], file_limit(100, SizeLimitAction::Skip));
+
+    let (contents, skipped) = aggregate_contents(&paths, temp_dir.path(), &options);
+
+    assert_eq!(contents.len(), 1);
+    assert_eq!(skipped[0].reason, SkipReason::TooLarge { size: 900, limit: 100 });
+}
+
+#[test]
+fn test_head_keeps_whole_lines() {
+    let content = numbered_lines(100);
+    let (temp_dir, options, paths) = read_with(&[("big.txt", content.as_bytes())], file_limit(95, SizeLimitAction::Head));
+
+    let (contents, _) = aggregate_contents(&paths, temp_dir.path(), &options);
+
+    assert_eq!(contents[0].content, format!("{}[… 90 lines elided …]\n", numbered_lines(10)));
+    assert_eq!(
+        contents[0].truncation,
+        Some(Truncation { original_bytes: 900, original_lines: 100, head_lines: 10, tail_lines: 0 })
+    );
+}
+
+#[test]
+fn test_head_tail_keeps_both_ends() {
+    let content = numbered_lines(100);
+    let (temp_dir, options, paths) = read_with(&[("big.txt", content.as_bytes())], file_limit(40, SizeLimitAction::HeadTail));
+
+    let (contents, _) = aggregate_contents(&paths, temp_dir.path(), &options);
+
+    assert_eq!(contents[0].content, "line 001\nline 002\n[… 96 lines elided …]\nline 099\nline 100\n");
+}
+
+#[test]
+fn test_lines_keeps_the_first_lines() {
+    let content = numbered_lines(100);
+    let (temp_dir, options, paths) = read_with(&[("big.txt", content.as_bytes())], file_limit(500, SizeLimitAction::Lines));
+
+    let (contents, _) = aggregate_contents(&paths, temp_dir.path(), &options);
+
+    assert_eq!(contents[0].content, format!("{}[… 95 lines elided …]\n", numbered_lines(5)));
+}
+
+#[test]
+fn test_total_size_limit() {
+    let content = numbered_lines(10);
+    let files: [(&str, &[u8]); 3] = [("a.txt", content.as_bytes()), ("b.txt", content.as_bytes()), ("c.txt", content.as_bytes())];
+    let limits = SizeLimits { max_total_size: Some(150), ..SizeLimits::default() };
+    let (temp_dir, options, paths) = read_with(&files, limits);
+
+    let (contents, skipped) = aggregate_contents(&paths, temp_dir.path(), &options);
+
+    assert_eq!(contents.len(), 1);
+    assert_eq!(skipped[0].reason, SkipReason::TotalSizeLimit { size: 90, remaining: 60 });
+    assert_eq!(skipped[1].reason, SkipReason::TotalSizeLimit { size: 90, remaining: 60 });
+}
+
+#[test]
+fn test_utf16_files_are_truncated_on_code_units() {
+    let content: Vec<u8> = [0xff, 0xfe].into_iter().chain(numbered_lines(50).encode_utf16().flat_map(u16::to_le_bytes)).collect();
+    let (temp_dir, options, paths) = read_with(&[("wide.txt", &content)], file_limit(81, SizeLimitAction::HeadTail));
+
+    let (contents, _) = aggregate_contents(&paths, temp_dir.path(), &options);
+
+    assert_eq!(contents[0].encoding, "UTF-16LE");
+    assert_eq!(contents[0].content, "line 001\nline 002\n[… 46 lines elided …]\nline 049\nline 050\n");
+}
+
+#[test]
+fn test_file_holding_only_a_byte_order_mark() {
+    let (temp_dir, options, paths) = read_with(&[("bom.txt", &[0xff, 0xfe])], file_limit(1, SizeLimitAction::HeadTail));
+
+    let (contents, skipped) = aggregate_contents(&paths, temp_dir.path(), &options);
+
+    assert!(skipped.is_empty());
+    assert_eq!(contents[0].encoding, "UTF-16LE");
+    assert_eq!(contents[0].truncation.map(|truncation| (truncation.head_lines, truncation.tail_lines)), Some((0, 0)));
+}
+
+#[test]
+fn test_truncated_files_are_marked_in_the_output() {
+    let content = numbered_lines(100);
+    let (temp_dir, options, paths) = read_with(&[("big.txt", content.as_bytes())], file_limit(95, SizeLimitAction::Head));
+    let (contents, _) = aggregate_contents(&paths, temp_dir.path(), &options);
+    let header = Header::new("project");
+
+    let markdown = render_document(&MarkdownFormatter, &FormatOptions::default(), &header, None, &contents, &Footer::default());
+    let json: serde_json::Value = serde_json::from_str(&render_document(&JsonFormatter, &FormatOptions::default(), &header, None, &contents, &Footer::default())).unwrap();
+
+    assert!(markdown.contains("## File: big.txt (truncated, originally 900 bytes and 100 lines)\n"));
+    assert_eq!(json["files"][0]["truncated"]["original_lines"], 100);
+    assert_eq!(json["files"][0]["truncated"]["head_lines"], 10);
+}
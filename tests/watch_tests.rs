@@ -0,0 +1,188 @@
+use assert_cmd::cargo::cargo_bin;
+use conag::aggregator::{aggregate_contents_cached, ReadOptions};
+use conag::cli::Generation;
+use conag::content_cache::ContentCache;
+use conag::output::OutputTarget;
+use conag::config::Config;
+use conag::watch::{ChangeBatch, PathKind, SourceRules, WatchedPaths};
+use notify::event::{CreateKind, DataChange, ModifyKind, RemoveKind};
+use notify::{Event, EventKind};
+use std::fs;
+use std::io::{BufRead, BufReader};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+use tempfile::TempDir;
+
+fn watched_paths() -> WatchedPaths {
+    WatchedPaths {
+        config_files: [PathBuf::from("/home/user/.config/conag/config.toml")].into(),
+        output: Some(OutputTarget::File(PathBuf::from("/project/out/project_conag_output.md"))),
+        sources: None,
+    }
+}
+
+fn event(kind: EventKind, path: &str) -> Event {
+    Event::new(kind).add_path(PathBuf::from(path))
+}
+
+fn generation(files: usize, total_tokens: usize) -> Generation {
+    Generation {
+        target: OutputTarget::Stdout,
+        written: Vec::new(),
+        files,
+        token_report: Vec::new(),
+        redactions: Vec::new(),
+        skipped: Vec::new(),
+        git_changes: None,
+        omitted: 0,
+        total_tokens,
+    }
+}
+
+#[test]
+fn test_classify_paths() {
+    let watched = watched_paths();
+
+    assert_eq!(watched.classify(Path::new("/project/src/main.rs")), PathKind::Source);
+    assert_eq!(watched.classify(Path::new("/project/out/project_conag_output.part02.md")), PathKind::Ignored);
+    assert_eq!(watched.classify(Path::new("/project/.git/objects/ab/cdef")), PathKind::Ignored);
+    assert_eq!(watched.classify(Path::new("/project/src/.gitignore")), PathKind::RebuildTrigger);
+    assert_eq!(watched.classify(Path::new("/project/.git/info/exclude")), PathKind::RebuildTrigger);
+    assert_eq!(watched.classify(Path::new("/project/.conag.toml")), PathKind::RebuildTrigger);
+    assert_eq!(watched.classify(Path::new("/home/user/.config/conag/config.toml")), PathKind::RebuildTrigger);
+}
+
+#[test]
+fn test_ignored_files_are_not_sources() {
+    let temp_dir = TempDir::new().unwrap();
+    let root = temp_dir.path();
+    fs::write(root.join(".gitignore"), "build/\n").unwrap();
+    let config = Config {
+        ignore_patterns: vec!["target/**".to_string()],
+        include_file_overrides: vec!["target/keep.rs".to_string()],
+        ..Config::default()
+    };
+    let watched = WatchedPaths { sources: Some(SourceRules::new(&config, root).unwrap()), ..watched_paths() };
+    let mut batch = ChangeBatch::default();
+
+    batch.record(&event(EventKind::Modify(ModifyKind::Data(DataChange::Content)), root.join("target/debug/app.d").to_str().unwrap()), &watched);
+    batch.record(&event(EventKind::Create(CreateKind::File), root.join("build/out.o").to_str().unwrap()), &watched);
+    assert!(batch.is_empty());
+
+    assert_eq!(watched.classify(&root.join("src/main.rs")), PathKind::Source);
+    assert_eq!(watched.classify(&root.join("target/keep.rs")), PathKind::Source);
+}
+
+#[test]
+fn test_events_are_merged() {
+    let watched = watched_paths();
+    let mut batch = ChangeBatch::default();
+
+    batch.record(&event(EventKind::Create(CreateKind::File), "/project/new.rs"), &watched);
+    batch.record(&event(EventKind::Modify(ModifyKind::Data(DataChange::Content)), "/project/new.rs"), &watched);
+    batch.record(&event(EventKind::Modify(ModifyKind::Data(DataChange::Content)), "/project/lib.rs"), &watched);
+    batch.record(&event(EventKind::Remove(RemoveKind::File), "/project/lib.rs"), &watched);
+    batch.record(&event(EventKind::Create(CreateKind::File), "/project/tmp.swp"), &watched);
+    batch.record(&event(EventKind::Remove(RemoveKind::File), "/project/tmp.swp"), &watched);
+    batch.record(&event(EventKind::Modify(ModifyKind::Data(DataChange::Content)), "/project/out/project_conag_output.part01.md"), &watched);
+
+    assert_eq!(batch.added, [PathBuf::from("/project/new.rs")].into());
+    assert!(batch.modified.is_empty());
+    assert_eq!(batch.removed, [PathBuf::from("/project/lib.rs")].into());
+    assert!(batch.rebuild_triggers.is_empty());
+}
+
+#[test]
+fn test_summary() {
+    let watched = watched_paths();
+    let mut batch = ChangeBatch::default();
+    for file in ["a.rs", "b.rs", "c.rs", "d.rs"] {
+        batch.record(&event(EventKind::Modify(ModifyKind::Data(DataChange::Content)), &format!("/project/{}", file)), &watched);
+    }
+    let mut rebuild = ChangeBatch::default();
+    rebuild.record(&event(EventKind::Modify(ModifyKind::Data(DataChange::Content)), "/project/.gitignore"), &watched);
+
+    assert_eq!(
+        batch.summary(Path::new("/project"), &generation(12, 3400)),
+        "Regenerated after changes to a.rs (modified), b.rs (modified), c.rs (modified) and 1 more: 12 files, 3400 tokens"
+    );
+    assert_eq!(
+        rebuild.summary(Path::new("/project"), &generation(10, 3000)),
+        "Rebuilt ignore rules and config after changes to .gitignore: 10 files, 3000 tokens"
+    );
+}
+
+#[test]
+fn test_cached_contents_are_reused_until_invalidated() {
+    let temp_dir = TempDir::new().unwrap();
+    let path = temp_dir.path().join("main.rs");
+    fs::write(&path, "fn main() {}").unwrap();
+    let files = vec![path.clone()];
+    let mut cache = ContentCache::new();
+
+    aggregate_contents_cached(&files, temp_dir.path(), &ReadOptions::default(), &mut cache);
+    // Change the file without changing its size or modification time, so only invalidating it is noticed
+    let modified = fs::metadata(&path).unwrap().modified().unwrap();
+    fs::write(&path, "fn main(){1}").unwrap();
+    fs::File::options().write(true).open(&path).unwrap().set_modified(modified).unwrap();
+    let (cached, _) = aggregate_contents_cached(&files, temp_dir.path(), &ReadOptions::default(), &mut cache);
+    assert!(cache.invalidate(&path));
+    let (reread, _) = aggregate_contents_cached(&files, temp_dir.path(), &ReadOptions::default(), &mut cache);
+
+    assert_eq!(cached[0].content, "fn main() {}");
+    assert_eq!(reread[0].content, "fn main(){1}");
+}
+
+/// Polls until the condition holds, failing the test after a timeout.
+fn wait_for(mut condition: impl FnMut() -> bool) {
+    let start = Instant::now();
+    while !condition() {
+        assert!(start.elapsed() < Duration::from_secs(20), "timed out waiting for the output");
+        thread::sleep(Duration::from_millis(50));
+    }
+}
+
+#[test]
+fn test_watch_regenerates_the_output() {
+    let temp_dir = TempDir::new().unwrap();
+    let project = temp_dir.path().join("project");
+    fs::create_dir_all(project.join("src")).unwrap();
+    fs::write(project.join("src/main.rs"), "fn main() {}\n").unwrap();
+    let config_path = temp_dir.path().join("config.toml");
+    fs::write(&config_path, "respect_gitignore = false\n").unwrap();
+    let output_path = project.join("context.md");
+
+    let mut child = Command::new(cargo_bin("conag"))
+        .current_dir(&project)
+        .args(["--config", config_path.to_str().unwrap(), "--output", output_path.to_str().unwrap()])
+        .args(["--watch", "--debounce", "50"])
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let read_output = || fs::read_to_string(&output_path).unwrap_or_default();
+    let stderr = Arc::new(Mutex::new(String::new()));
+    let child_stderr = BufReader::new(child.stderr.take().unwrap());
+    let stderr_lines = Arc::clone(&stderr);
+    thread::spawn(move || {
+        for line in child_stderr.lines().map_while(Result::ok) {
+            stderr_lines.lock().unwrap().push_str(&format!("{}\n", line));
+        }
+    });
+
+    wait_for(|| read_output().contains("fn main() {}"));
+    // Give the watcher time to start after the first generation
+    thread::sleep(Duration::from_millis(500));
+    fs::write(project.join("src/main.rs"), "fn main() { watched(); }\n").unwrap();
+    wait_for(|| read_output().contains("watched()"));
+    // The summary is logged after the output is written
+    wait_for(|| stderr.lock().unwrap().contains("Regenerated"));
+    child.kill().unwrap();
+    child.wait().unwrap();
+
+    let stderr = stderr.lock().unwrap();
+    assert!(!read_output().contains("## File: context.md"));
+    assert!(stderr.contains("Regenerated after changes to src/main.rs (modified): 1 files"), "{}", stderr);
+}